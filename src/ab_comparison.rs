@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::json_report::Status;
+use crate::runtime_comparison::geometric_mean;
+
+/// Tests which run faster than this are too noisy to report as slower or faster on their own
+const MIN_RUN_TIME: Duration = Duration::from_millis(10);
+
+/// How a test went with one of the two C0 distributions
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub status: Status,
+    /// What it did, if it got to run
+    pub behavior: Option<String>,
+    pub output: Option<String>,
+    pub run_time: Option<Duration>,
+    pub artifact_size: Option<u64>
+}
+
+/// How many percent slower or larger a test can get with the candidate before it's reported
+pub struct Thresholds {
+    pub slowdown: f64,
+    pub size: f64
+}
+
+/// How a test differs between the baseline and the candidate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delta {
+    /// Passed with the baseline, but not with the candidate
+    Regressed,
+    /// Didn't pass with the baseline, but did with the candidate
+    Fixed,
+    /// Ended up the same either way, but did something else or printed something else
+    Changed,
+    /// How many times longer it ran with the candidate, past --slowdown-threshold
+    Slower(f64),
+    /// How many times longer it ran with the baseline, past --slowdown-threshold
+    Faster(f64),
+    /// How many times larger its artifact got with the candidate, past --size-threshold
+    Larger(f64),
+    Smaller(f64)
+}
+
+/// How a test differs between the baseline and the candidate, if it does at all
+pub fn deltas(baseline: &Outcome, candidate: &Outcome, thresholds: &Thresholds) -> Vec<Delta> {
+    let mut deltas = Vec::new();
+    if baseline.status == Status::Passed && candidate.status.is_failure() {
+        deltas.push(Delta::Regressed);
+    }
+    else if baseline.status.is_failure() && candidate.status == Status::Passed {
+        deltas.push(Delta::Fixed);
+    }
+    else if baseline.status == candidate.status && baseline.behavior.is_some() && candidate.behavior.is_some()
+        && (baseline.behavior != candidate.behavior || baseline.output != candidate.output)
+    {
+        deltas.push(Delta::Changed);
+    }
+
+    if let (Some(before), Some(after)) = (baseline.run_time, candidate.run_time) {
+        if before >= MIN_RUN_TIME && after >= MIN_RUN_TIME {
+            let (before, after) = (before.as_secs_f64(), after.as_secs_f64());
+            if after / before > 1. + thresholds.slowdown / 100. {
+                deltas.push(Delta::Slower(after / before));
+            }
+            else if before / after > 1. + thresholds.slowdown / 100. {
+                deltas.push(Delta::Faster(before / after));
+            }
+        }
+    }
+
+    if let (Some(before), Some(after)) = (baseline.artifact_size, candidate.artifact_size) {
+        if before > 0 && after > 0 {
+            let (before, after) = (before as f64, after as f64);
+            if after / before > 1. + thresholds.size / 100. {
+                deltas.push(Delta::Larger(after / before));
+            }
+            else if before / after > 1. + thresholds.size / 100. {
+                deltas.push(Delta::Smaller(before / after));
+            }
+        }
+    }
+
+    deltas
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match &outcome.behavior {
+        Some(behavior) => format!("{} ({})", outcome.status, behavior),
+        None => outcome.status.to_string()
+    }
+}
+
+/// Prints every test which differs between the two distributions, grouped by how it
+/// differs, then how the run times compare overall. Only tests which have an outcome
+/// from both are compared. Returns how many tests regressed with the candidate
+pub fn print_report(
+    baseline: &BTreeMap<String, Outcome>,
+    candidate: &BTreeMap<String, Outcome>,
+    thresholds: &Thresholds) -> usize
+{
+    let pairs: Vec<(&String, &Outcome, &Outcome)> = baseline.iter()
+        .filter_map(|(name, before)| Some((name, before, candidate.get(name)?)))
+        .collect();
+    let mut found: Vec<(&String, &Outcome, &Outcome, Delta)> = Vec::new();
+    for (name, before, after) in pairs.iter() {
+        for delta in deltas(before, after, thresholds) {
+            found.push((name, before, after, delta));
+        }
+    }
+
+    let section = |title: &str, wanted: fn(Delta) -> bool, line: &dyn Fn(&Outcome, &Outcome, Delta) -> String| {
+        let lines: Vec<String> = found.iter()
+            .filter(|(_, _, _, delta)| wanted(*delta))
+            .map(|(name, before, after, delta)| format!("  {}: {}", name, line(before, after, *delta)))
+            .collect();
+        if !lines.is_empty() {
+            println!("\n{} ({}):\n{}", title, lines.len(), lines.join("\n"));
+        }
+        lines.len()
+    };
+
+    let statuses = |before: &Outcome, after: &Outcome, _| format!("{} → {}", describe(before), describe(after));
+    let times = |before: &Outcome, after: &Outcome, delta| match delta {
+        Delta::Slower(ratio) | Delta::Faster(ratio) => format!("{:.1} → {:.1} ms ({:.2}x)",
+            before.run_time.unwrap_or_default().as_secs_f64() * 1000.,
+            after.run_time.unwrap_or_default().as_secs_f64() * 1000., ratio),
+        _ => String::new()
+    };
+    let sizes = |before: &Outcome, after: &Outcome, delta| match delta {
+        Delta::Larger(ratio) | Delta::Smaller(ratio) => format!("{} → {} bytes ({:.2}x)",
+            before.artifact_size.unwrap_or_default(), after.artifact_size.unwrap_or_default(), ratio),
+        _ => String::new()
+    };
+
+    let regressions = section("❌ Regressed with the candidate", |delta| delta == Delta::Regressed, &statuses);
+    section("✅ Fixed by the candidate", |delta| delta == Delta::Fixed, &statuses);
+    section("🔀 Behaved differently", |delta| delta == Delta::Changed, &|before, after, delta| {
+        if before.behavior == after.behavior { format!("{}, with other output", describe(after)) } else { statuses(before, after, delta) }
+    });
+    section("🐢 Slower with the candidate", |delta| matches!(delta, Delta::Slower(_)), &times);
+    section("🐇 Faster with the candidate", |delta| matches!(delta, Delta::Faster(_)), &times);
+    section("📦 Larger with the candidate", |delta| matches!(delta, Delta::Larger(_)), &sizes);
+    section("📦 Smaller with the candidate", |delta| matches!(delta, Delta::Smaller(_)), &sizes);
+
+    let run_times: Vec<(Duration, Duration)> = pairs.iter()
+        .filter_map(|(_, before, after)| Some((before.run_time?, after.run_time?)))
+        .collect();
+    println!("\nCompared {} tests", pairs.len());
+    if !run_times.is_empty() {
+        let total = |times: Vec<Duration>| times.iter().sum::<Duration>().as_secs_f64();
+        let before = total(run_times.iter().map(|(before, _)| *before).collect());
+        let after = total(run_times.iter().map(|(_, after)| *after).collect());
+        let ratios: Vec<f64> = run_times.iter()
+            .filter(|(before, after)| !before.is_zero() && !after.is_zero())
+            .map(|(before, after)| after.as_secs_f64() / before.as_secs_f64())
+            .collect();
+        print!("CPU time of {} tests: {:.1} → {:.1} ms", run_times.len(), before * 1000., after * 1000.);
+        if before > 0. {
+            print!(" ({:.2}x total", after / before);
+            match geometric_mean(&ratios) {
+                Some(mean) => println!(", {:.2}x geometric mean per test)", mean),
+                None => println!(")")
+            }
+        }
+        else {
+            println!();
+        }
+    }
+
+    regressions
+}
+
+#[cfg(test)]
+mod ab_comparison_tests {
+    use super::*;
+
+    fn outcome(status: Status, behavior: &str, run_time: u64, artifact_size: u64) -> Outcome {
+        Outcome {
+            status,
+            behavior: Some(behavior.to_string()),
+            output: Some(String::new()),
+            run_time: Some(Duration::from_millis(run_time)),
+            artifact_size: Some(artifact_size)
+        }
+    }
+
+    #[test]
+    fn test() {
+        let thresholds = Thresholds { slowdown: 20., size: 10. };
+        let passed = outcome(Status::Passed, "return 0", 100, 1000);
+        assert_eq!(deltas(&passed, &passed, &thresholds), vec![]);
+
+        let failed = outcome(Status::Failed, "segfault", 100, 1000);
+        assert_eq!(deltas(&passed, &failed, &thresholds), vec![Delta::Regressed]);
+        assert_eq!(deltas(&failed, &passed, &thresholds), vec![Delta::Fixed]);
+
+        let noisy = Outcome { output: Some(String::from("warning")), ..passed.clone() };
+        assert_eq!(deltas(&passed, &noisy, &thresholds), vec![Delta::Changed]);
+
+        let slow = outcome(Status::Passed, "return 0", 200, 1200);
+        assert_eq!(deltas(&passed, &slow, &thresholds), vec![Delta::Slower(2.), Delta::Larger(1.2)]);
+        assert_eq!(deltas(&slow, &passed, &thresholds), vec![Delta::Faster(2.), Delta::Smaller(1.2)]);
+
+        // Too quick to say whether it really got slower
+        let quick = outcome(Status::Passed, "return 0", 2, 1000);
+        let slower = outcome(Status::Passed, "return 0", 6, 1000);
+        assert_eq!(deltas(&quick, &slower, &thresholds), vec![]);
+
+        // Skipped tests didn't get to pass or fail
+        let skipped = Outcome { status: Status::Skipped, behavior: None, output: None, run_time: None, artifact_size: None };
+        assert_eq!(deltas(&passed, &skipped, &thresholds), vec![]);
+    }
+}
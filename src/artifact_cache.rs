@@ -0,0 +1,400 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Context, Result};
+
+use crate::executer::{Artifact, Compilation, Executer, ExecuterProperties, TestRun};
+use crate::fingerprint;
+use crate::options::Options;
+use crate::spec::*;
+use crate::storage::Storage;
+
+/// An executer which keeps what it compiles in --cache-dir, so tests whose sources,
+/// options and toolchain haven't changed since are run without compiling them again
+/// (see --artifact-cache and 'prime-cache'). Artifacts are found by hashing all of
+/// these, and the toolchain only by its contents. Failed compilations aren't kept,
+/// since they may have only run out of time
+pub struct ArtifactCache {
+    executer: Box<dyn Executer>,
+    /// Where this toolchain's artifacts are, with the settings which affect them
+    dir: PathBuf,
+    work_dir: PathBuf,
+    /// Where artifacts are shared with other runs (see --storage), if anywhere
+    storage: Option<Arc<dyn Storage>>
+}
+
+/// What is kept next to an artifact
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedArtifact {
+    /// e.g. '.bc0', which some executers need to run it
+    extension: String,
+    compiler_output: String,
+    notes: Vec<String>
+}
+
+/// What priming the cache did for a test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Primed {
+    AlreadyCached,
+    Compiled,
+    /// It didn't compile, or couldn't be compiled at all
+    Failed,
+    /// The executer doesn't compile tests
+    NotNeeded
+}
+
+impl ArtifactCache {
+    /// Cached artifacts are copied into 'work_dir' to be run
+    pub fn new(executer: Box<dyn Executer>, options: &Options, work_dir: &Path) -> Result<ArtifactCache> {
+        let toolchain = fingerprint::toolchain_contents_key(&*executer).context("Couldn't fingerprint the toolchain")?;
+        let settings = format!("{} {}", toolchain, options.no_debug_info);
+        let dir = ArtifactCache::path(options).join(&format!("{:x}", Sha256::digest(settings.as_bytes()))[..16]);
+        ArtifactCache::in_dir(executer, dir, work_dir)
+    }
+
+    fn in_dir(executer: Box<dyn Executer>, dir: PathBuf, work_dir: &Path) -> Result<ArtifactCache> {
+        fs::create_dir_all(&dir).context(format!("Couldn't create '{}'", dir.display()))?;
+        Ok(ArtifactCache { executer, dir, work_dir: work_dir.to_path_buf(), storage: None })
+    }
+
+    /// Shares artifacts through 'storage' too: ones which aren't in the cache
+    /// directory are fetched from it, and new ones are pushed to it
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> ArtifactCache {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn path(options: &Options) -> PathBuf {
+        options.cache_dir().join("artifacts")
+    }
+
+    /// Compiles every test which isn't cached yet, sharing compilations between
+    /// tests with the same sources and options. Returns how many ended up each way
+    pub fn prime(&self, tests: &[&TestInfo]) -> HashMap<Primed, usize> {
+        let mut seen = HashSet::new();
+        let unique: Vec<&TestInfo> = tests.iter().copied()
+            .filter(|test| match fingerprint::compilation_key(&test.execution) {
+                Ok(key) => seen.insert(key),
+                Err(_) => true
+            })
+            .collect();
+
+        let counts = Mutex::new(HashMap::new());
+        unique.par_iter().for_each(|test| {
+            let primed = self.prime_test(&test.execution).unwrap_or_else(|error| {
+                eprintln!("⚠: couldn't compile '{}': {:#}", test.name(), error);
+                Primed::Failed
+            });
+            *counts.lock().unwrap().entry(primed).or_insert(0) += 1;
+        });
+
+        counts.into_inner().unwrap()
+    }
+
+    fn prime_test(&self, test: &TestExecutionInfo) -> Result<Primed> {
+        let key = match self.key(test)? {
+            Some(key) => key,
+            None => return Ok(Primed::NotNeeded)
+        };
+        if self.dir.join(format!("{}.json", key)).is_file() || self.download(&key)? {
+            return Ok(Primed::AlreadyCached)
+        }
+
+        Ok(match self.compile_and_store(test, &key)? {
+            Compilation::NotNeeded => Primed::NotNeeded,
+            Compilation::Failed(_) => Primed::Failed,
+            Compilation::Compiled(_) => Primed::Compiled
+        })
+    }
+
+    /// What a test's artifact is cached under: its sources and options, and
+    /// the command which compiles it, since the executer may add its own
+    /// options (e.g. --save-files). 'None' if the executer doesn't compile tests
+    fn key(&self, test: &TestExecutionInfo) -> Result<Option<String>> {
+        let command = match self.executer.compile_command(test) {
+            Some(command) => command,
+            None => return Ok(None)
+        };
+        // Where this runner keeps its work directory (e.g. with --tmpfs) and toolchain doesn't
+        // change what's compiled, and would keep other runs from finding its artifacts
+        let toolchain = self.executer.toolchain();
+        let command: Vec<String> = command.iter().map(|arg| portable_arg(arg, &self.work_dir, &toolchain)).collect();
+        let key = format!("{} {:?}", fingerprint::compilation_key(test)?, command);
+        Ok(Some(format!("{:x}", Sha256::digest(key.as_bytes()))))
+    }
+
+    fn compile_and_store(&self, test: &TestExecutionInfo, key: &str) -> Result<Compilation> {
+        let compilation = self.executer.compile(test)?;
+        if let Compilation::Compiled(artifact) = &compilation {
+            if let Err(error) = self.store(key, artifact) {
+                eprintln!("⚠: couldn't cache '{}': {:#}", artifact.path.display(), error);
+            }
+        }
+        Ok(compilation)
+    }
+
+    /// Copies an artifact into the cache. Files are written under temporary
+    /// names and then renamed, so other runs never see half of one
+    fn store(&self, key: &str, artifact: &Artifact) -> Result<()> {
+        let temp = self.temp_path();
+
+        fs::copy(&artifact.path, &temp).context(format!("Couldn't copy it to '{}'", temp.display()))?;
+        fs::rename(&temp, self.dir.join(key)).context("Couldn't move it into the cache")?;
+
+        let cached = CachedArtifact {
+            extension: artifact.path.extension().map_or(String::new(), |extension| format!(".{}", extension.to_string_lossy())),
+            compiler_output: artifact.compiler_output.clone(),
+            notes: artifact.notes.clone()
+        };
+        fs::write(&temp, serde_json::to_vec(&cached)?).context(format!("Couldn't write '{}'", temp.display()))?;
+        fs::rename(&temp, self.dir.join(format!("{}.json", key))).context("Couldn't move it into the cache")?;
+
+        // The description goes last, since it's what says an artifact is there
+        if let Some(storage) = &self.storage {
+            for file in [key.to_string(), format!("{}.json", key)].iter() {
+                storage.put(&self.dir.join(file), &self.storage_key(file))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies an artifact which another run shared into the cache directory,
+    /// returning whether there was one
+    fn download(&self, key: &str) -> Result<bool> {
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => return Ok(false)
+        };
+
+        let temp = self.temp_path();
+        for file in [key.to_string(), format!("{}.json", key)].iter() {
+            if !storage.get(&self.storage_key(file), &temp)? {
+                return Ok(false)
+            }
+            fs::rename(&temp, self.dir.join(file)).context("Couldn't move it into the cache")?;
+        }
+        Ok(true)
+    }
+
+    /// Where a file in the cache directory is kept in the storage
+    fn storage_key(&self, file: &str) -> String {
+        format!("artifacts/{}/{}", self.dir.file_name().unwrap_or_default().to_string_lossy(), file)
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        self.dir.join(format!(".{}-{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    /// Copies a cached artifact into the work directory, so that it can be
+    /// deleted after running like any other
+    fn fetch(&self, key: &str) -> Result<Option<Artifact>> {
+        static COPY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let json_path = self.dir.join(format!("{}.json", key));
+        let json = match fs::read(&json_path) {
+            Ok(json) => json,
+            Err(error) if error.kind() == ErrorKind::NotFound && self.download(key)? =>
+                fs::read(&json_path).context(format!("Couldn't read '{}'", json_path.display()))?,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error).context(format!("Couldn't read '{}'", json_path.display()))
+        };
+        let cached: CachedArtifact = serde_json::from_slice(&json).context(format!("Invalid '{}'", json_path.display()))?;
+
+        let path = self.work_dir.join(format!("cached{}{}", COPY_COUNTER.fetch_add(1, Ordering::Relaxed), cached.extension));
+        fs::copy(self.dir.join(key), &path).context(format!("Couldn't copy the cached artifact to '{}'", path.display()))?;
+
+        let mut artifact = Artifact::new(path, cached.compiler_output);
+        artifact.notes = cached.notes;
+        Ok(Some(artifact))
+    }
+}
+
+impl Executer for ArtifactCache {
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
+        // Tests whose sources can't be read fail to compile as usual
+        let key = match self.key(test) {
+            Ok(Some(key)) => key,
+            _ => return self.executer.compile(test)
+        };
+
+        match self.fetch(&key) {
+            Ok(Some(artifact)) => return Ok(Compilation::Compiled(artifact)),
+            Ok(None) => (),
+            Err(error) => eprintln!("⚠: not using a cached artifact: {:#}", error)
+        }
+        self.compile_and_store(test, &key)
+    }
+
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun> {
+        self.executer.execute(test, artifact)
+    }
+
+    fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        self.executer.compile_command(test)
+    }
+
+    fn exit_code(&self, code: i32) -> Option<Behavior> {
+        self.executer.exit_code(code)
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        self.executer.toolchain()
+    }
+
+    fn properties(&self) -> ExecuterProperties {
+        self.executer.properties()
+    }
+}
+
+/// A compile command's argument, with the work directory replaced by a placeholder
+/// and the toolchain's binaries by their names (the cache's directory already goes
+/// by the toolchain's contents)
+fn portable_arg(arg: &CString, work_dir: &Path, toolchain: &[PathBuf]) -> String {
+    let arg = arg.to_string_lossy();
+    if let Some(binary) = toolchain.iter().find(|binary| binary.as_os_str() == arg.as_ref()) {
+        return binary.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+    arg.replace(work_dir.to_string_lossy().as_ref(), "<work dir>")
+}
+
+#[cfg(test)]
+mod artifact_cache_tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::test_util::TempDir;
+
+    /// Compiles a source by copying it, and counts how often it did
+    struct CopyExecuter {
+        work_dir: PathBuf,
+        compiles: Arc<AtomicUsize>
+    }
+
+    impl Executer for CopyExecuter {
+        fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
+            let id = self.compiles.fetch_add(1, Ordering::SeqCst);
+            if test.compiler_options.iter().any(|option| option == "--fail") {
+                return Ok(Compilation::Failed(String::from("error")))
+            }
+
+            let path = self.work_dir.join(format!("a.out{}.bc0", id));
+            fs::copy(&test.sources[0], &path)?;
+            let mut artifact = Artifact::new(path, String::from("warning"));
+            artifact.notes.push(String::from("note"));
+            Ok(Compilation::Compiled(artifact))
+        }
+
+        fn execute(&self, _test: &TestExecutionInfo, _artifact: Option<&Artifact>) -> Result<TestRun> {
+            Ok(TestRun::new(String::new(), None, Behavior::Return(Some(0))))
+        }
+
+        fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+            let output = self.work_dir.join("a.out");
+            let args = ["/opt/c0/bin/cc0", "-o", &output.to_string_lossy()].iter().map(|arg| arg.to_string())
+                .chain(test.compiler_options.iter().cloned())
+                .map(|arg| CString::new(arg).unwrap())
+                .collect();
+            Some(args)
+        }
+
+        fn toolchain(&self) -> Vec<PathBuf> {
+            vec![PathBuf::from("/opt/c0/bin/cc0")]
+        }
+
+        fn properties(&self) -> ExecuterProperties {
+            ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "copy" }
+        }
+    }
+
+    fn test_info(source: &Path, compiler_options: Vec<String>) -> TestExecutionInfo {
+        let sources = vec![source.to_path_buf()];
+        TestExecutionInfo { compiler_options, ..TestExecutionInfo::new(sources, Arc::from(Path::new("/tests"))) }
+    }
+
+    #[test]
+    fn test() -> Result<()> {
+        let base = TempDir::new("artifact-cache");
+        let source = base.join("a.c0");
+        fs::write(&source, "int main() { return 0; }")?;
+
+        let compiles = Arc::new(AtomicUsize::new(0));
+        let executer = CopyExecuter { work_dir: base.to_path_buf(), compiles: compiles.clone() };
+        let cache = ArtifactCache::in_dir(Box::new(executer), base.join("cache"), &base)?;
+
+        // Artifacts are only compiled once, and keep what the compiler said
+        for _ in 0..2 {
+            match cache.compile(&test_info(&source, Vec::new()))? {
+                Compilation::Compiled(artifact) => {
+                    assert_eq!(fs::read_to_string(&artifact.path)?, "int main() { return 0; }");
+                    assert_eq!(artifact.path.extension().unwrap(), "bc0");
+                    assert_eq!((artifact.compiler_output.as_str(), artifact.notes.as_slice()), ("warning", &[String::from("note")][..]));
+                },
+                _ => panic!("expected an artifact")
+            }
+        }
+        assert_eq!(compiles.load(Ordering::SeqCst), 1);
+
+        // Changing the sources or options compiles it again, and failures aren't kept
+        fs::write(&source, "int main() { return 1; }")?;
+        let failing = test_info(&source, vec![String::from("--fail")]);
+        let (a, b) = (TestInfo::new(test_info(&source, Vec::new()), Vec::new()), TestInfo::new(failing.clone(), Vec::new()));
+        let counts = cache.prime(&[&a, &a, &b]);
+        assert_eq!((counts[&Primed::Compiled], counts[&Primed::Failed]), (1, 1));
+        assert_eq!(cache.prime(&[&a, &b])[&Primed::AlreadyCached], 1);
+        assert!(matches!(cache.compile(&failing)?, Compilation::Failed(_)));
+        assert_eq!(compiles.load(Ordering::SeqCst), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage() -> Result<()> {
+        let base = TempDir::new("artifact-storage");
+        let source = base.join("a.c0");
+        fs::write(&source, "int main() { return 0; }")?;
+        let storage = crate::storage::open(&base.join("storage").to_string_lossy())?;
+
+        // Another machine's cache, with the same settings, gets what this one compiled
+        let compiles = Arc::new(AtomicUsize::new(0));
+        for machine in ["a", "b"].iter() {
+            let work_dir = base.join(machine);
+            fs::create_dir_all(&work_dir)?;
+            let executer = CopyExecuter { work_dir: work_dir.clone(), compiles: compiles.clone() };
+            let cache = ArtifactCache::in_dir(Box::new(executer), work_dir.join("cache"), &work_dir)?
+                .with_storage(storage.clone());
+            match cache.compile(&test_info(&source, Vec::new()))? {
+                Compilation::Compiled(artifact) => assert_eq!(fs::read_to_string(&artifact.path)?, "int main() { return 0; }"),
+                _ => panic!("expected an artifact")
+            }
+        }
+        assert_eq!(compiles.load(Ordering::SeqCst), 1);
+        assert!(base.join("b/cache").read_dir()?.count() == 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_work_dirs() -> Result<()> {
+        let base = TempDir::new("artifact-work-dirs");
+        let source = base.join("a.c0");
+        fs::write(&source, "int main() { return 0; }")?;
+
+        // e.g. /dev/shm/c0check-<pid> with --tmpfs, which is different on every run
+        let key = |work_dir: &str| -> Result<Option<String>> {
+            let work_dir = base.join(work_dir);
+            fs::create_dir_all(&work_dir)?;
+            let executer = CopyExecuter { work_dir: work_dir.clone(), compiles: Arc::default() };
+            ArtifactCache::in_dir(Box::new(executer), base.join("cache"), &work_dir)?.key(&test_info(&source, Vec::new()))
+        };
+        assert!(key("first")?.is_some());
+        assert_eq!(key("first")?, key("second")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use anyhow::{bail, Context, Result};
+
+use crate::json_report::Status;
+use crate::schema;
+
+/// Changes whenever the meaning of existing fields does. Baselines
+/// from older versions are upgraded when they're loaded (see 'migrate')
+const SCHEMA_VERSION: u32 = 2;
+
+/// A record of a previous run, which later runs can be compared against
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Baseline {
+    version: u32,
+    /// Name of the executer the run used
+    pub executer: String,
+    /// The run's --meta tags
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+    /// Keyed by test name
+    pub tests: BTreeMap<String, BaselineEntry>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    /// Size in bytes of the compiled executable or bytecode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_size: Option<u64>,
+    /// Smallest memory limit in bytes the test passed with under --gc-stress
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_memory: Option<u64>,
+    /// How the test ended up. Baselines from before outcomes were recorded don't have it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Status>,
+    /// What the test actually did, as written in specs, if it was run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub behavior: Option<String>
+}
+
+/// A test which ended up differently than in the baseline
+pub struct OutcomeChange<'a> {
+    pub test: &'a str,
+    /// 'None' if the test is new
+    pub old: Option<Status>,
+    pub new: Status,
+    pub old_behavior: Option<&'a str>,
+    pub new_behavior: Option<&'a str>
+}
+
+impl OutcomeChange<'_> {
+    /// Whether the test got worse, i.e. it fails now but didn't, or fails differently
+    pub fn is_regression(&self) -> bool {
+        self.new.is_failure()
+    }
+}
+
+/// A test whose compiled artifact or memory use grew past the threshold
+pub struct SizeRegression<'a> {
+    pub test: &'a str,
+    pub old_size: u64,
+    pub new_size: u64
+}
+
+impl Baseline {
+    pub fn new(executer: &str) -> Baseline {
+        Baseline { version: SCHEMA_VERSION, executer: executer.to_string(), meta: BTreeMap::new(), tests: BTreeMap::new() }
+    }
+
+    pub fn load(path: &Path) -> Result<Baseline> {
+        schema::read(path, "baseline", SCHEMA_VERSION, migrate)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(path).context(format!("Couldn't create baseline '{}'", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Gets the entry for a test, creating it if needed
+    pub fn entry(&mut self, test: String) -> &mut BaselineEntry {
+        self.tests.entry(test).or_default()
+    }
+
+    /// Makes sure this baseline can be compared against a run with the given executer
+    pub fn check_executer(&self, executer: &str) -> Result<()> {
+        if self.executer != executer {
+            bail!("Baseline was recorded with '{}', not '{}'", self.executer, executer)
+        }
+
+        Ok(())
+    }
+
+    /// Whether tests' outcomes were recorded, so they can be compared
+    pub fn has_outcomes(&self) -> bool {
+        self.tests.values().any(|entry| entry.outcome.is_some())
+    }
+
+    /// Finds tests which passed, failed, timed out, etc. in 'current' but didn't
+    /// in this baseline, or which failed with a different behavior. Tests which
+    /// are new in 'current' count as changed unless they passed
+    pub fn outcome_changes<'a>(&'a self, current: &'a Baseline) -> Vec<OutcomeChange<'a>> {
+        if !self.has_outcomes() {
+            return Vec::new()
+        }
+
+        current.tests.iter().filter_map(|(test, entry)| {
+            let new = entry.outcome?;
+            let old_entry = self.tests.get(test);
+            let old = old_entry.and_then(|entry| entry.outcome);
+            let old_behavior = old_entry.and_then(|entry| entry.behavior.as_deref());
+            let new_behavior = entry.behavior.as_deref();
+
+            let changed = match old {
+                Some(old) => old != new || (new.is_failure() && old_behavior != new_behavior),
+                None => new != Status::Passed
+            };
+            if !changed {
+                return None
+            }
+
+            Some(OutcomeChange { test, old, new, old_behavior, new_behavior })
+        }).collect()
+    }
+
+    /// Tests which failed the same way in 'current' as in this baseline,
+    /// so they needn't be reported again
+    pub fn known_failures(&self, current: &Baseline) -> HashSet<String> {
+        let changed: HashSet<&str> = self.outcome_changes(current).iter().map(|change| change.test).collect();
+        current.tests.iter()
+            .filter(|(test, entry)| match (self.tests.get(*test).and_then(|entry| entry.outcome), entry.outcome) {
+                (Some(_), Some(new)) => new.is_failure() && !changed.contains(test.as_str()),
+                _ => false
+            })
+            .map(|(test, _)| test.clone())
+            .collect()
+    }
+
+    /// Finds tests whose artifacts are more than 'threshold' percent
+    /// larger in 'current' than in this baseline
+    pub fn size_regressions<'a>(&self, current: &'a Baseline, threshold: f64) -> Vec<SizeRegression<'a>> {
+        self.regressions(current, threshold, |entry| entry.artifact_size)
+    }
+
+    /// Finds tests which need more than 'threshold' percent more
+    /// memory to pass in 'current' than in this baseline
+    pub fn memory_regressions<'a>(&self, current: &'a Baseline, threshold: f64) -> Vec<SizeRegression<'a>> {
+        self.regressions(current, threshold, |entry| entry.min_memory)
+    }
+
+    fn regressions<'a>(
+        &self,
+        current: &'a Baseline,
+        threshold: f64,
+        size: impl Fn(&BaselineEntry) -> Option<u64>) -> Vec<SizeRegression<'a>>
+    {
+        current.tests.iter().filter_map(|(test, entry)| {
+            let new_size = size(entry)?;
+            let old_size = size(self.tests.get(test)?)?;
+
+            if new_size as f64 > old_size as f64 * (1. + threshold / 100.) {
+                Some(SizeRegression { test, old_size, new_size })
+            }
+            else {
+                None
+            }
+        }).collect()
+    }
+}
+
+/// Upgrades a baseline from the given version to the next
+fn migrate(version: u32, _baseline: &mut Map<String, Value>) -> Result<()> {
+    match version {
+        // Version 1 didn't say which version it was, but is otherwise the same
+        1 => Ok(()),
+        _ => bail!("There is no version {} of baselines", version)
+    }
+}
+
+/// Prints each change in outcome, with the icon of the new one
+pub fn print_changes(changes: &[OutcomeChange]) {
+    println!("\nChanges from the baseline:\n");
+    for change in changes.iter() {
+        let icon = match change.new {
+            Status::Passed => "✅",
+            Status::Failed => "❌",
+            Status::Timeout => "⌛",
+            Status::Error => "⛔",
+            Status::Skipped => "⏭️",
+            Status::NotRun => "🛑",
+            Status::ExpectedFailure => "❎",
+            Status::UnexpectedPass => "❗"
+        };
+        let describe = |status: Status, behavior: Option<&str>| match behavior {
+            Some(behavior) if status.is_failure() => format!("{} ({})", status, behavior),
+            _ => status.to_string()
+        };
+
+        match change.old {
+            Some(old) => println!("{} {}: {} → {}", icon, change.test,
+                describe(old, change.old_behavior), describe(change.new, change.new_behavior)),
+            None => println!("{} {}: new test, {}", icon, change.test, describe(change.new, change.new_behavior))
+        }
+    }
+}
+
+#[cfg(test)]
+mod baseline_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_size_regressions() {
+        let mut old = Baseline::new("cc0");
+        old.entry("a.c0".to_string()).artifact_size = Some(1000);
+        old.entry("b.c0".to_string()).artifact_size = Some(1000);
+        old.entry("c.c0".to_string()).artifact_size = Some(1000);
+
+        let mut new = Baseline::new("cc0");
+        new.entry("a.c0".to_string()).artifact_size = Some(1050);
+        new.entry("b.c0".to_string()).artifact_size = Some(1200);
+        new.entry("d.c0".to_string()).artifact_size = Some(5000);
+
+        let regressions = old.size_regressions(&new, 10.);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].test, "b.c0");
+        assert_eq!((regressions[0].old_size, regressions[0].new_size), (1000, 1200));
+    }
+
+    #[test]
+    fn test_memory_regressions() {
+        let mut old = Baseline::new("cc0");
+        old.entry("gc.c0".to_string()).min_memory = Some(1 << 20);
+        old.entry("a.c0".to_string()).artifact_size = Some(1000);
+
+        let mut new = Baseline::new("cc0");
+        new.entry("gc.c0".to_string()).min_memory = Some(2 << 20);
+        new.entry("a.c0".to_string()).artifact_size = Some(1000);
+
+        let regressions = old.memory_regressions(&new, 10.);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].test, "gc.c0");
+        assert!(old.size_regressions(&new, 10.).is_empty());
+    }
+
+    #[test]
+    fn test_versions() -> Result<()> {
+        let dir = TempDir::new("baseline");
+        let path = dir.join("baseline.json");
+
+        // Baselines from before they had versions still load
+        fs::write(&path, r#"{ "executer": "cc0", "tests": { "a.c0": { "outcome": "failed", "behavior": "return 4" } } }"#)?;
+        let baseline = Baseline::load(&path)?;
+        assert_eq!(baseline.version, SCHEMA_VERSION);
+        assert_eq!(baseline.tests["a.c0"].outcome, Some(Status::Failed));
+
+        baseline.save(&path)?;
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        assert_eq!(json["version"], SCHEMA_VERSION);
+
+        fs::write(&path, r#"{ "version": 100, "executer": "cc0", "tests": {} }"#)?;
+        assert!(format!("{:#}", Baseline::load(&path).unwrap_err()).contains("newer c0check"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_outcome_changes() {
+        let record = |baseline: &mut Baseline, test: &str, outcome: Status, behavior: &str| {
+            let entry = baseline.entry(test.to_string());
+            entry.outcome = Some(outcome);
+            entry.behavior = Some(behavior.to_string());
+        };
+
+        let mut old = Baseline::new("cc0");
+        record(&mut old, "known.c0", Status::Failed, "return 4");
+        record(&mut old, "fixed.c0", Status::Failed, "return 4");
+        record(&mut old, "broken.c0", Status::Passed, "return 0");
+        record(&mut old, "different.c0", Status::Failed, "return 4");
+
+        let mut new = Baseline::new("cc0");
+        record(&mut new, "known.c0", Status::Failed, "return 4");
+        record(&mut new, "fixed.c0", Status::Passed, "return 0");
+        record(&mut new, "broken.c0", Status::Timeout, "infloop");
+        record(&mut new, "different.c0", Status::Failed, "segfault");
+        record(&mut new, "new.c0", Status::Passed, "return 0");
+        record(&mut new, "new-failure.c0", Status::Error, "error");
+
+        let changes = old.outcome_changes(&new);
+        let changed: Vec<(&str, bool)> = changes.iter().map(|change| (change.test, change.is_regression())).collect();
+        assert_eq!(changed, vec![("broken.c0", true), ("different.c0", true), ("fixed.c0", false), ("new-failure.c0", true)]);
+
+        let known = old.known_failures(&new);
+        assert_eq!(known.into_iter().collect::<Vec<_>>(), vec![String::from("known.c0")]);
+
+        // Baselines without outcomes can't be compared
+        assert!(Baseline::new("cc0").outcome_changes(&new).is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::Result;
+
+use crate::spec::TestInfo;
+use crate::suite_config::SuiteConfig;
+
+/// How many tests in a suite failed, compared to how many its suite.toml allows
+pub struct SuiteBudget {
+    pub suite: String,
+    pub failures: usize,
+    pub allowed: usize
+}
+
+impl SuiteBudget {
+    pub fn exceeded(&self) -> bool {
+        self.failures > self.allowed
+    }
+}
+
+/// Counts the failed tests in each suite. Only suites which
+/// had failures or allow some are included
+pub fn check_budgets<'a>(tests: impl Iterator<Item = &'a TestInfo>, failed: &[&TestInfo]) -> Result<Vec<SuiteBudget>> {
+    let mut budgets: BTreeMap<Arc<Path>, SuiteBudget> = BTreeMap::new();
+
+    for test in tests {
+        let dir = &test.execution.directory;
+        if !budgets.contains_key(dir) {
+            let budget = SuiteBudget {
+                suite: dir.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                failures: 0,
+                allowed: SuiteConfig::load(dir)?.allowed_failures()
+            };
+            budgets.insert(dir.clone(), budget);
+        }
+    }
+
+    for test in failed.iter() {
+        if let Some(budget) = budgets.get_mut(&test.execution.directory) {
+            budget.failures += 1;
+        }
+    }
+
+    Ok(budgets.into_values()
+        .filter(|budget| budget.failures > 0 || budget.allowed > 0)
+        .collect())
+}
+
+pub fn print_budgets(budgets: &[SuiteBudget]) {
+    println!("\nSuite failure budgets:\n");
+
+    for budget in budgets.iter() {
+        if budget.exceeded() {
+            println!("🚫 {}: {} failures, {} allowed", budget.suite, budget.failures, budget.allowed);
+        }
+        else {
+            println!("✅ {}: {} of {} allowed failures", budget.suite, budget.failures, budget.allowed);
+        }
+    }
+}
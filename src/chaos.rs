@@ -0,0 +1,101 @@
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::options::Options;
+
+/// The longest delay before a test is dispatched, in milliseconds
+const MAX_DELAY_MS: u64 = 50;
+
+/// Perturbs the order tests run in, and delays each one by a small random
+/// amount, to shake out tests which depend on each other or on timing.
+/// Everything is derived from the seed and the tests' names, so the same
+/// seed gives the same order and delays no matter how threads are scheduled.
+/// --shuffle only changes the order
+pub struct Chaos {
+    seed: u64,
+    delays: bool
+}
+
+impl Chaos {
+    /// 'None' without --chaos or --shuffle. Without a seed, the seed is random
+    pub fn new(options: &Options) -> Option<Chaos> {
+        if !options.chaos && !options.shuffle {
+            return None
+        }
+
+        let seed = options.chaos_seed.or(options.seed).unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            mix(now.as_nanos() as u64 ^ process::id() as u64)
+        });
+        Some(Chaos { seed, delays: options.chaos })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Whether tests are delayed as well as shuffled
+    pub fn delays(&self) -> bool {
+        self.delays
+    }
+
+    /// Puts items in a random order, going by a name for each one
+    pub fn shuffle<T>(&self, items: &mut [T], name: impl Fn(&T) -> String) {
+        items.sort_by_cached_key(|item| self.hash(&name(item), 0));
+    }
+
+    /// Waits a random amount of time before a test is dispatched
+    pub fn delay(&self, name: &str) {
+        if self.delays {
+            thread::sleep(self.delay_for(name));
+        }
+    }
+
+    fn delay_for(&self, name: &str) -> Duration {
+        Duration::from_millis(self.hash(name, 1) % (MAX_DELAY_MS + 1))
+    }
+
+    /// Hashes a name together with the seed, with 'salt' keeping
+    /// the order and the delays independent of each other
+    fn hash(&self, name: &str, salt: u64) -> u64 {
+        // FNV-1a, which unlike std's hasher is the same in every build
+        let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        mix(hash ^ mix(self.seed ^ salt))
+    }
+}
+
+/// SplitMix64's finalizer, which spreads similar inputs across all 64 bits
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod chaos_tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let names: Vec<String> = (0..20).map(|i| format!("basic/test{}.c0", i)).collect();
+        let shuffled = |seed: u64| {
+            let mut names = names.clone();
+            Chaos { seed, delays: false }.shuffle(&mut names, String::clone);
+            names
+        };
+
+        // The same seed always gives the same order, and it's still the same tests
+        assert_eq!(shuffled(1), shuffled(1));
+        assert_ne!(shuffled(1), shuffled(2));
+        let mut sorted = shuffled(1);
+        sorted.sort();
+        assert_eq!(sorted, { let mut names = names.clone(); names.sort(); names });
+
+        let chaos = Chaos { seed: 1, delays: true };
+        assert_eq!(chaos.delay_for("basic/a.c0"), chaos.delay_for("basic/a.c0"));
+        assert!(names.iter().all(|name| chaos.delay_for(name) <= Duration::from_millis(MAX_DELAY_MS)));
+    }
+}
@@ -1,62 +1,617 @@
 use std::fmt::{self, Display};
-use anyhow::Result;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::Duration;
+use anyhow::{bail, Result};
+use serde::Serialize;
 
 use crate::executer::*;
 use crate::spec::*;
+use crate::options::Options;
+use crate::suppressions::Suppressions;
+use crate::host_tools;
+use crate::matchers::{self, MatchContext, OutputMatcher, Subject};
+use crate::unified_diff;
 
-/// Runs the given test case using the given execution strategy
-pub fn run_test(executer: &dyn Executer, test: &TestInfo) -> Result<TestResult> {
-    let properties = executer.properties();
-    
-    // See if any behaviors apply
-    let behaviors: Vec<Behavior> = test.specs.iter()
-        .filter_map(|spec| find_behavior(spec, &properties))
-        .collect();
-    
-    if behaviors.is_empty() {
-        return Ok(TestResult::Success)
+/// Tests expected to 'infloop' have to use at least this fraction
+/// of their CPU time limit, with --check-infloop-cpu
+const MIN_INFLOOP_CPU: f64 = 0.9;
+
+/// Runs tests and checks the results against their specs
+pub struct Checker {
+    suppressions: Suppressions,
+    images: ImageComparison,
+    /// The time limit of tests which don't have their own, if
+    /// --check-infloop-cpu checks how much CPU time 'infloop' tests use
+    infloop_time: Option<u64>,
+    /// Whether each test's notes say how its specs were evaluated (see 'trace_specs')
+    trace_specs: bool
+}
+
+/// How images created by tests are compared against their golden copies
+struct ImageComparison {
+    /// How many percent each pixel's color can be off by,
+    /// unless the assertion gives its own tolerance
+    tolerance: u32,
+    /// Where images highlighting the differences go
+    diff_dir: PathBuf
+}
+
+impl ImageComparison {
+    fn context<'a>(&'a self, dir: &'a Path, tolerance: Option<u32>) -> MatchContext<'a> {
+        MatchContext { dir, tolerance, image_tolerance: self.tolerance, diff_dir: &self.diff_dir }
+    }
+}
+
+impl Checker {
+    pub fn new(options: &Options) -> Result<Checker> {
+        let suppressions = match &options.suppressions {
+            Some(path) => Suppressions::load(path)?,
+            None => Suppressions::default()
+        };
+
+        let images = ImageComparison {
+            tolerance: options.image_tolerance,
+            diff_dir: options.image_diff_dir().to_path_buf()
+        };
+
+        let infloop_time = if options.check_infloop_cpu { Some(options.test_time) } else { None };
+
+        Ok(Checker { suppressions, images, infloop_time, trace_specs: options.trace_specs })
+    }
+
+    /// Runs the given test case using the given execution strategy
+    pub fn run_test(&self, executer: &dyn Executer, test: &TestInfo) -> Result<TestOutcome> {
+        if let Some((reason, message)) = skip_reason(executer, test) {
+            let outcome = TestOutcome { result: TestResult::Skipped(reason, message), run: None, notes: Vec::new() };
+            return Ok(self.trace(executer, test, outcome))
+        }
+        if !needs_run(executer, test) {
+            let outcome = TestOutcome { result: TestResult::Success, run: None, notes: Vec::new() };
+            return Ok(self.trace(executer, test, outcome))
+        }
+
+        let compilation = catch_panic(|| executer.compile(&test.execution))?;
+        self.run_compiled_test(executer, test, &compilation)
+    }
+
+    /// Like 'run_test', but reuses the result of compiling a test with
+    /// the same sources and compiler options
+    pub fn run_compiled_test(&self, executer: &dyn Executer, test: &TestInfo, compilation: &Compilation) -> Result<TestOutcome> {
+        let outcome = self.check_compiled_test(executer, test, compilation)?;
+        if !expects_failure(&executer.properties(), test) {
+            return Ok(self.trace(executer, test, outcome))
+        }
+
+        let result = match outcome.result {
+            TestResult::Mismatch(failure) => TestResult::ExpectedFailure(failure),
+            TestResult::Success => TestResult::UnexpectedPass,
+            result => result
+        };
+        Ok(self.trace(executer, test, TestOutcome { result, ..outcome }))
     }
-    
-    let (output, actual) = executer.run_test(&test.execution)?;
 
-    match behaviors.iter().find(|&&behavior| behavior != actual) {
-        Some(&expected) => Ok(TestResult::Mismatch(Failure { expected, actual, output })),
-        None => Ok(TestResult::Success)
-    } 
+    /// Adds how the test's specs were evaluated to its notes with --trace-specs,
+    /// whether or not it was run
+    fn trace(&self, executer: &dyn Executer, test: &TestInfo, mut outcome: TestOutcome) -> TestOutcome {
+        if self.trace_specs {
+            outcome.notes.push(trace_specs(test, &executer.properties()));
+        }
+        outcome
+    }
+
+    fn check_compiled_test(&self, executer: &dyn Executer, test: &TestInfo, compilation: &Compilation) -> Result<TestOutcome> {
+        if let Some((reason, message)) = skip_reason(executer, test) {
+            return Ok(TestOutcome { result: TestResult::Skipped(reason, message), run: None, notes: Vec::new() })
+        }
+
+        let (behaviors, assertions) = applicable_specs(test, &executer.properties());
+        if behaviors.is_empty() && assertions.is_empty() {
+            return Ok(TestOutcome { result: TestResult::Success, run: None, notes: Vec::new() })
+        }
+
+        // Output which is thrown away can't be checked
+        if test.execution.no_capture {
+            if let Some(assertion) = assertions.iter().find(|assertion| reads_output(assertion)) {
+                bail!("'{}' can't be checked, since @no_capture discards the output", assertion)
+            }
+        }
+
+        // A test which shouldn't compile has already failed if it did, so it isn't run
+        if let Compilation::Compiled(artifact) = compilation {
+            if behaviors.iter().any(|behavior| matches!(behavior, Behavior::CompileError)) {
+                let failure = Failure::UnexpectedlyCompiled { output: artifact.compiler_output.clone(), location: None };
+                return Ok(TestOutcome { result: TestResult::Mismatch(failure.locate(test, &executer.properties())), run: None, notes: Vec::new() })
+            }
+        }
+
+        // Files the test should write are deleted before running it, so
+        // that ones left over from an earlier run don't count, and afterwards
+        let dir = &*test.execution.directory;
+        let created_files: Vec<PathBuf> = assertions.iter().filter_map(|assertion| match assertion {
+            Assertion::Creates { file, .. } => Some(dir.join(file)),
+            _ => None
+        }).collect();
+        remove_files(&created_files);
+
+        let outcome = catch_panic(|| compilation.run(executer, &test.execution).map(|mut run| {
+            // A test which expects this very exit code gets it as it is
+            if let Behavior::UnknownExit(code) = run.behavior {
+                if !behaviors.iter().any(|behavior| matches!(behavior, Behavior::Exit(expected) if *expected == code)) {
+                    run.behavior = exit_code_behavior(executer, &test.execution, code).unwrap_or(run.behavior);
+                }
+            }
+            run.behavior = diagnose(run.behavior, &run.output);
+
+            // Get rid of known noise before looking at the output
+            run.output = self.suppressions.apply(&run.output);
+            run.compiler_output = run.compiler_output.map(|output| self.suppressions.apply(&output));
+
+            let infloop_time = self.infloop_time.map(|time| test.execution.run_limits.time.unwrap_or(time));
+            let result = match check_run(&behaviors, &assertions, &run, dir, &self.images, infloop_time) {
+                TestResult::Mismatch(failure) => TestResult::Mismatch(failure.locate(test, &executer.properties())),
+                result => result
+            };
+            TestOutcome { result, run: Some(run), notes: Vec::new() }
+        }));
+
+        remove_files(&created_files);
+        outcome
+    }
+}
+
+/// What a runtime's exit code means for a test, from its suite.toml or else the executer
+fn exit_code_behavior(executer: &dyn Executer, test: &TestExecutionInfo, code: i32) -> Option<Behavior> {
+    test.exit_codes.get(executer.properties().name)
+        .and_then(|codes| codes.get(&code).copied())
+        .or_else(|| executer.exit_code(code))
+}
+
+/// Tells apart the ways an aborted or failed program can have been stopped by the
+/// C0 runtime, going by the last line it printed, e.g. 'c0rt: assertion failed'
+/// or 'a.c0:5.3-5.20: @requires annotation failed'. Anything else stays as it is
+pub fn diagnose(behavior: Behavior, output: &str) -> Behavior {
+    let ending = match behavior {
+        Behavior::Abort => Ending::Aborted,
+        Behavior::Failure => Ending::Exited,
+        _ => return behavior
+    };
+
+    let last = output.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default();
+    let failure = if last.contains("annotation failed") {
+        RuntimeFailure::Contract
+    }
+    else if last.contains("assert failed") || last.contains("assertion failed") {
+        RuntimeFailure::Assert
+    }
+    else if last.starts_with("Error: ") {
+        RuntimeFailure::ErrorCall
+    }
+    else {
+        return behavior
+    };
+
+    Behavior::Failed(failure, Some(ending))
+}
+
+/// Turns a panic while handling a test into an error for that test, so
+/// that a bug in c0check (or an odd test) doesn't end the whole run
+pub fn catch_panic<T>(run: impl FnOnce() -> Result<T>) -> Result<T> {
+    // Nothing is left half-updated on a panic: results
+    // are only shared through mutexes once a test is done
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("<no message>");
+            bail!("c0check panicked: {}", message)
+        }
+    }
+}
+
+/// Checks if any of a test's specs apply to an executer, i.e. if
+/// the test has to be run at all
+pub fn needs_run(executer: &dyn Executer, test: &TestInfo) -> bool {
+    let (behaviors, assertions) = applicable_specs(test, &executer.properties());
+    !behaviors.is_empty() || !assertions.is_empty()
+}
+
+/// Checks if a test only expects a compile error from an executer,
+/// so it never has to be run
+pub fn expects_compile_error(executer: &dyn Executer, test: &TestInfo) -> bool {
+    let (behaviors, _) = applicable_specs(test, &executer.properties());
+    !behaviors.is_empty() && behaviors.iter().all(|behavior| matches!(behavior, Behavior::CompileError))
+}
+
+/// Checks if a test is only expected to return from an executer,
+/// i.e. it runs without failing or being stopped
+pub fn runs_cleanly(executer: &dyn Executer, test: &TestInfo) -> bool {
+    let (behaviors, _) = applicable_specs(test, &executer.properties());
+    !behaviors.is_empty() && behaviors.iter().all(|behavior| matches!(behavior, Behavior::Return(_) | Behavior::ReturnIn(_)))
+}
+
+/// Why a test shouldn't be run with an executer: one of its 'skip' specs applies,
+/// or none of its specs do because of tools this machine doesn't have. Returns
+/// the reason's code along with a message for people
+pub fn skip_reason(executer: &dyn Executer, test: &TestInfo) -> Option<(SkipReason, String)> {
+    skip_reason_for(&executer.properties(), test)
+}
+
+/// Like 'skip_reason', but only needs to know what the executer is like
+pub fn skip_reason_for(properties: &ExecuterProperties, test: &TestInfo) -> Option<(SkipReason, String)> {
+    let skip = test.specs.iter().find_map(|spec| match find_consequent(spec, properties, &test.execution.compiler_options) {
+        Some(Spec::Skip(reason)) => Some((SkipReason::Spec, reason.clone())),
+        _ => None
+    });
+    let (behaviors, assertions) = applicable_specs(test, properties);
+    if skip.is_some() || !behaviors.is_empty() || !assertions.is_empty() {
+        return skip
+    }
+
+    let mut missing: Vec<String> = test.specs.iter().flat_map(|spec| missing_tools(spec, properties, &test.execution.compiler_options)).collect();
+    missing.sort();
+    missing.dedup();
+    if missing.is_empty() {
+        None
+    }
+    else {
+        Some((SkipReason::MissingTool, format!("requires {}, which this machine doesn't have", missing.join(", "))))
+    }
+}
+
+/// The missing tools mentioned by the first predicate which stops a spec from applying
+fn missing_tools(spec: &Spec, properties: &ExecuterProperties, compiler_options: &[String]) -> Vec<String> {
+    let mut spec = spec;
+    while let Spec::Implication(predicate, consequent) = spec {
+        if !properties.matches_predicate(predicate, compiler_options) {
+            let mut tools = Vec::new();
+            host_tools::required_tools(predicate, &mut tools);
+            return tools.into_iter().filter(|tool| !host_tools::is_available(tool)).collect()
+        }
+        spec = consequent;
+    }
+
+    Vec::new()
+}
+
+/// Whether one of a test's 'xfail' specs applies to an executer, i.e. the
+/// test is known to fail with it, and only passing is worth reporting
+pub fn expects_failure(properties: &ExecuterProperties, test: &TestInfo) -> bool {
+    test.specs.iter().any(|spec| {
+        let mut spec = spec;
+        while let Spec::Implication(predicate, consequent) = spec {
+            if !properties.matches_predicate(predicate, &test.execution.compiler_options) {
+                return false
+            }
+            spec = consequent;
+        }
+        matches!(spec, Spec::ExpectedFailure(_))
+    })
+}
+
+/// The behaviors a test's specs expect from an executer
+pub fn expected_behaviors(executer: &dyn Executer, test: &TestInfo) -> Vec<Behavior> {
+    applicable_specs(test, &executer.properties()).0
+}
+
+/// Finds the behaviors and assertions of a test which apply to an executer
+pub fn applicable_specs<'a>(test: &'a TestInfo, properties: &ExecuterProperties) -> (Vec<Behavior>, Vec<&'a Assertion>) {
+    let mut behaviors: Vec<Behavior> = Vec::new();
+    let mut assertions: Vec<&Assertion> = Vec::new();
+    for spec in test.specs.iter() {
+        match find_consequent(spec, properties, &test.execution.compiler_options) {
+            Some(Spec::Behavior(behavior)) => behaviors.push(*behavior),
+            Some(Spec::Assertion(assertion)) => assertions.push(assertion),
+            _ => ()
+        }
+    }
+
+    (behaviors, assertions)
+}
+
+/// Compares what happened when running a test against its specs
+fn check_run(
+    behaviors: &[Behavior],
+    assertions: &[&Assertion],
+    run: &TestRun,
+    dir: &Path,
+    images: &ImageComparison,
+    infloop_time: Option<u64>) -> TestResult
+{
+    let actual = run.behavior;
+
+    if let Some(&expected) = behaviors.iter().find(|&&behavior| behavior != actual) {
+        return TestResult::Mismatch(Failure::Behavior { expected, actual, output: run.output.clone(), location: None })
+    }
+
+    if let (Behavior::InfiniteLoop, Some(limit), Some(cpu_time)) = (actual, infloop_time, run.run_time) {
+        if cpu_time.as_secs_f64() < limit as f64 * MIN_INFLOOP_CPU {
+            return TestResult::Mismatch(Failure::TerminatedEarly { cpu_time, limit, output: run.output.clone(), location: None })
+        }
+    }
+
+    // Skipped tests never produced any output to check
+    if matches!(actual, Behavior::Skipped) {
+        return TestResult::Skipped(SkipReason::Unsupported, run.output.clone())
+    }
+
+    for assertion in assertions.iter() {
+        if let Err(output) = check_assertion(assertion, run, dir, images) {
+            return TestResult::Mismatch(match assertion {
+                Assertion::Expects(file) => Failure::OutputMismatch { expected: file.clone(), diff: output, location: None },
+                _ => Failure::Assertion { assertion: (*assertion).clone(), output, location: None }
+            })
+        }
+    }
+
+    TestResult::Success
+}
+
+/// The result of a test along with what happened while running it
+pub struct TestOutcome {
+    pub result: TestResult,
+    /// 'None' if the test wasn't run, since none of its specs
+    /// applied or it compiled when it shouldn't have
+    pub run: Option<TestRun>,
+    /// Notes about the test rather than its run (e.g. --trace-specs),
+    /// which it gets even if it wasn't run
+    pub notes: Vec<String>
 }
 
 /// Test cases either succeed or have a mismatch between the expected
-/// behavior and the actual behavior
+/// behavior and the actual behavior, unless a spec skips them
 pub enum TestResult {
     Success,
-    Mismatch(Failure)
+    Mismatch(Failure),
+    /// The test wasn't run, e.g. because a 'skip' spec applied. Holds why, and a message
+    Skipped(SkipReason, String),
+    /// An 'xfail' spec applied and the test failed, as it was known to. Holds how it failed
+    ExpectedFailure(Failure),
+    /// An 'xfail' spec applied, but the test passed
+    UnexpectedPass
+}
+
+/// Why a test wasn't run, as a code which the JSON report and the summary group tests by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// One of its 'skip' specs applied
+    Spec,
+    /// Its specs only apply with tools this machine doesn't have (see 'requires')
+    MissingTool,
+    /// The executer can't run it, e.g. coin with C1 sources
+    Unsupported,
+    /// Its before hook, or its suite's, failed
+    HookFailed,
+    /// A test it depends on didn't pass (see '@depends')
+    DependencyFailed
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Spec => write!(f, "spec"),
+            SkipReason::MissingTool => write!(f, "missing-tool"),
+            SkipReason::Unsupported => write!(f, "unsupported"),
+            SkipReason::HookFailed => write!(f, "hook-failed"),
+            SkipReason::DependencyFailed => write!(f, "dependency-failed")
+        }
+    }
 }
 
 /// Contains all information from a failed test run,
 /// including stdout/stderr from the compiler or program
-/// (depending on which stage failed)
-pub struct Failure {
-    pub expected: Behavior,
-    pub actual: Behavior, 
-    pub output: String
+/// (depending on which stage failed), and where the
+/// spec which wasn't satisfied was written, if it's known
+pub enum Failure {
+    /// The test didn't behave as expected
+    Behavior { expected: Behavior, actual: Behavior, output: String, location: Option<SpecLocation> },
+    /// The test behaved as expected, but its output did not
+    /// satisfy an assertion
+    Assertion { assertion: Assertion, output: String, location: Option<SpecLocation> },
+    /// The test should have had a compile error, but compiled. Holds
+    /// the compiler output, since the test wasn't run
+    UnexpectedlyCompiled { output: String, location: Option<SpecLocation> },
+    /// The test was stopped as if it ran out of time, but used much less CPU
+    /// time than its limit (in seconds), so it didn't really loop
+    TerminatedEarly { cpu_time: Duration, limit: u64, output: String, location: Option<SpecLocation> },
+    /// The output wasn't the same as the file an 'expects' assertion names.
+    /// Holds a unified diff from the file to the output
+    OutputMismatch { expected: String, diff: String, location: Option<SpecLocation> }
 }
 
 impl Failure {
     pub fn is_timeout(&self) -> bool {
-        self.actual == Behavior::InfiniteLoop
-    }    
+        matches!(self, Failure::Behavior { actual: Behavior::InfiniteLoop, .. })
+    }
+
+    pub fn location(&self) -> Option<&SpecLocation> {
+        match self {
+            Failure::Behavior { location, .. } | Failure::Assertion { location, .. } | Failure::UnexpectedlyCompiled { location, .. }
+                | Failure::TerminatedEarly { location, .. } | Failure::OutputMismatch { location, .. } => location.as_ref()
+        }
+    }
+
+    /// The spec which wasn't satisfied, as it would be written
+    fn spec(&self) -> String {
+        match self {
+            Failure::Behavior { expected, .. } => expected.to_string(),
+            Failure::Assertion { assertion, .. } => assertion.to_string(),
+            Failure::UnexpectedlyCompiled { .. } => Behavior::CompileError.to_string(),
+            Failure::TerminatedEarly { .. } => Behavior::InfiniteLoop.to_string(),
+            Failure::OutputMismatch { expected, .. } => Assertion::Expects(expected.clone()).to_string()
+        }
+    }
+
+    /// Points the failure at the first of the test's specs which
+    /// applies to the executer and expects what wasn't satisfied
+    fn locate(mut self, test: &TestInfo, properties: &ExecuterProperties) -> Failure {
+        let expected = self.spec();
+        let found = test.origin.as_ref().and_then(|origin| {
+            let index = test.specs.iter().position(|spec| {
+                find_consequent(spec, properties, &test.execution.compiler_options).is_some_and(|consequent| consequent.to_string() == expected)
+            })?;
+            origin.location(index)
+        });
+
+        match &mut self {
+            Failure::Behavior { location, .. } | Failure::Assertion { location, .. } | Failure::UnexpectedlyCompiled { location, .. }
+                | Failure::TerminatedEarly { location, .. } | Failure::OutputMismatch { location, .. } => *location = found
+        }
+        self
+    }
+}
+
+/// Checks if a test satisfies an assertion. If it doesn't, returns the
+/// output the assertion was about, or how the output or a file differs from
+/// what an output matcher expected
+fn check_assertion(assertion: &Assertion, run: &TestRun, dir: &Path, images: &ImageComparison) -> Result<(), String> {
+    let subject = assertion_subject(assertion, run);
+    let satisfied = match assertion {
+        Assertion::Warns(text) => subject.contains(text.as_str()),
+        // Only the compiler's diagnostics count, e.g. 'a.c0:1.1-1.2:warning: ...',
+        // not any line which happens to mention a warning
+        Assertion::NoWarnings => !subject.lines().any(|line| line.contains("warning:")),
+        Assertion::Prints { pattern, regex: None } => subject.contains(pattern.as_str()),
+        Assertion::Prints { pattern, regex: Some(regex) } => {
+            let subject = Subject { name: "the output", contents: subject.as_bytes(), path: None };
+            return regex.check(&subject, pattern, &images.context(dir, None))
+        },
+        Assertion::RawExit(_) | Assertion::RawSignal(_) => {
+            let expected = match assertion {
+                Assertion::RawExit(code) => RawStatus::Exited(*code),
+                Assertion::RawSignal(signal) => RawStatus::Signaled(*signal),
+                _ => unreachable!()
+            };
+            return match run.raw_status {
+                Some(status) if status == expected => Ok(()),
+                Some(status) => Err(format!("The program {}\n{}", status, subject)),
+                None => Err(format!("The program wasn't run\n{}", subject))
+            }
+        },
+        Assertion::OutputSize(limit) => {
+            let size = run.output.len() as u64;
+            return if size < *limit {
+                Ok(())
+            }
+            else {
+                Err(format!("The output was {} bytes, but it should be less than {}", size, limit))
+            }
+        },
+        Assertion::Checks(patterns) => {
+            let mut rest = subject;
+            patterns.iter().all(|pattern| match rest.find(pattern.as_str()) {
+                Some(pos) => {
+                    rest = &rest[pos + pattern.len()..];
+                    true
+                },
+                None => false
+            })
+        },
+        Assertion::Output { matcher, expected, tolerance } => {
+            let subject = Subject { name: "the output", contents: run.output.as_bytes(), path: None };
+            let matcher = matchers::find(matcher).expect("the parser only accepts known matchers");
+            return matcher.check(&subject, expected, &images.context(dir, *tolerance))
+        },
+        Assertion::Creates { file, golden, matcher, tolerance } => {
+            let path = dir.join(file);
+            let contents = fs::read(&path).map_err(|_| format!("'{}' wasn't created", file))?;
+            let name = format!("'{}'", file);
+            let subject = Subject { name: &name, contents: &contents, path: Some(&path) };
+            let matcher = match matcher {
+                Some(matcher) => matchers::find(matcher).expect("the parser only accepts known matchers"),
+                None => matchers::default_for(golden)
+            };
+            return matcher.check(&subject, golden, &images.context(dir, *tolerance))
+        },
+        Assertion::Expects(file) => {
+            let expected = fs::read(dir.join(file)).map_err(|e| format!("Couldn't read '{}': {}", file, e))?;
+            let expected = String::from_utf8_lossy(&expected);
+            return if expected == run.output {
+                Ok(())
+            }
+            else {
+                Err(unified_diff::diff(file, "the output", &expected, &run.output))
+            }
+        }
+    };
+
+    if satisfied {
+        Ok(())
+    }
+    else {
+        Err(subject.to_string())
+    }
+}
+
+fn remove_files(files: &[PathBuf]) {
+    for file in files.iter() {
+        let _ = fs::remove_file(file);
+    }
+}
+
+/// Whether an assertion is about the program's output, rather than
+/// the compiler's, its exit status, or files it writes
+fn reads_output(assertion: &Assertion) -> bool {
+    matches!(assertion, Assertion::Checks(_) | Assertion::Output { .. } | Assertion::OutputSize(_)
+        | Assertion::Expects(_) | Assertion::Prints { .. })
 }
 
-/// Finds the behavior a given spec prescribes. This basically just involves
-/// checking if the execution strategy has the properties that the spec
+/// Gets the output which an assertion is about. Warnings come from the
+/// compiler, unless the executer doesn't have a separate compilation step
+fn assertion_subject<'a>(assertion: &Assertion, run: &'a TestRun) -> &'a str {
+    match assertion {
+        Assertion::Warns(_) | Assertion::NoWarnings =>
+            run.compiler_output.as_deref().unwrap_or(&run.output),
+        Assertion::Checks(_) | Assertion::Output { .. } | Assertion::OutputSize(_) | Assertion::Creates { .. }
+            | Assertion::Expects(_) | Assertion::Prints { .. } | Assertion::RawExit(_) | Assertion::RawSignal(_) => &run.output
+    }
+}
+
+/// Describes how each of a test's specs was evaluated: the properties each
+/// predicate looked at, whether it held, and what the spec expects if it applied
+fn trace_specs(test: &TestInfo, properties: &ExecuterProperties) -> String {
+    let mut trace = format!("Specs evaluated for {}:", properties.name);
+    for spec in test.specs.iter() {
+        trace += &format!("\n  {}", spec);
+
+        let mut spec = spec;
+        while let Spec::Implication(predicate, consequent) = spec {
+            let mut consulted = Vec::new();
+            let holds = properties.trace_predicate(predicate, &test.execution.compiler_options, &mut consulted);
+            trace += &format!("\n    '{}' is {} ({})", predicate, holds, consulted.join(", "));
+            if !holds {
+                break
+            }
+            spec = consequent;
+        }
+
+        match spec {
+            Spec::Behavior(behavior) => trace += &format!("\n    applies: expects {}", behavior),
+            Spec::Assertion(assertion) => trace += &format!("\n    applies: expects {}", assertion),
+            Spec::Skip(reason) => trace += &format!("\n    applies: skips the test ({})", reason),
+            Spec::ExpectedFailure(spec) => trace += &format!("\n    applies: expects {}, which is known to fail", spec),
+            Spec::Implication(..) => trace += "\n    doesn't apply"
+        }
+    }
+
+    trace
+}
+
+/// Finds the behavior or assertion a given spec prescribes. This basically just
+/// involves checking if the execution strategy has the properties that the spec
 /// needs (e.g. a garbage collected executor can run tests which require 
-/// garbage collection)
-fn find_behavior(spec: &Spec, properties: &ExecuterProperties) -> Option<Behavior> {
+/// garbage collection), or the test's compiler options do for 'opt(<flag>)'
+fn find_consequent<'a>(spec: &'a Spec, properties: &ExecuterProperties, compiler_options: &[String]) -> Option<&'a Spec> {
     match spec {
-        Spec::Behavior(b) => Some(*b),
+        Spec::Behavior(_) | Spec::Assertion(_) | Spec::Skip(_) => Some(spec),
+        Spec::ExpectedFailure(spec) => find_consequent(spec, properties, compiler_options),
         Spec::Implication(predicate, consequent) => {
-            if properties.matches_predicate(predicate) {
-                find_behavior(consequent, properties)
+            if properties.matches_predicate(predicate, compiler_options) {
+                find_consequent(consequent, properties, compiler_options)
             }
             else {
                 None
@@ -67,11 +622,247 @@ fn find_behavior(spec: &Spec, properties: &ExecuterProperties) -> Option<Behavio
 
 impl Display for Failure {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.output.is_empty() {
-            write!(f, "expected {}, got {}", self.expected, self.actual)
+        let output = match self {
+            Failure::Behavior { expected, actual, output, .. } => {
+                write!(f, "expected {}, got {}", expected, actual)?;
+                output
+            },
+            Failure::Assertion { assertion, output, .. } => {
+                write!(f, "expected {}", assertion)?;
+                output
+            },
+            Failure::UnexpectedlyCompiled { output, .. } => {
+                write!(f, "expected {}, but it compiled", Behavior::CompileError)?;
+                output
+            },
+            Failure::TerminatedEarly { cpu_time, limit, output, .. } => {
+                write!(f, "expected {}, but it was terminated early after {:.2}s of its {}s CPU time",
+                    Behavior::InfiniteLoop, cpu_time.as_secs_f64(), limit)?;
+                output
+            },
+            Failure::OutputMismatch { expected, diff, .. } => {
+                write!(f, "the output differs from '{}'", expected)?;
+                diff
+            }
+        };
+        if let Some(location) = self.location() {
+            write!(f, " (spec at {})", location)?;
+        }
+
+        if output.is_empty() {
+            Ok(())
         }
         else {
-            write!(f, "expected {}, got {}\n{}", self.expected, self.actual, self.output)
+            write!(f, "\n{}", output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod checker_tests {
+    use super::*;
+    use crate::matchers::Pattern;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(|| Ok(5)).unwrap(), 5);
+        assert!(catch_panic(|| -> Result<()> { bail!("oops") }).is_err());
+
+        let error = catch_panic(|| -> Result<()> { panic!("bad {}", "path") }).unwrap_err();
+        assert_eq!(error.to_string(), "c0check panicked: bad path");
+    }
+
+    #[test]
+    fn test_terminated_early() {
+        let images = ImageComparison { tolerance: 0, diff_dir: PathBuf::new() };
+        let check = |cpu_time: f64, infloop_time: Option<u64>| {
+            let mut run = TestRun::new(String::new(), None, Behavior::InfiniteLoop);
+            run.run_time = Some(Duration::from_secs_f64(cpu_time));
+            check_run(&[Behavior::InfiniteLoop], &[], &run, Path::new("."), &images, infloop_time)
+        };
+
+        assert!(matches!(check(9.5, Some(10)), TestResult::Success));
+        assert!(matches!(check(0.01, None), TestResult::Success));
+
+        match check(0.01, Some(10)) {
+            TestResult::Mismatch(failure) => {
+                assert!(!failure.is_timeout());
+                assert_eq!(failure.to_string(), "expected infloop, but it was terminated early after 0.01s of its 10s CPU time");
+            },
+            _ => panic!("terminated early, but passed")
+        }
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let assert = diagnose(Behavior::Abort, "checking...\nc0rt: assertion failed: n >= 0\n");
+        assert_eq!(assert.to_string(), "assert");
+        assert_eq!(assert, Behavior::Abort);
+        assert_ne!(assert, Behavior::Failure);
+        assert_eq!(assert, Behavior::Failed(RuntimeFailure::Assert, None));
+        assert_ne!(assert, Behavior::Failed(RuntimeFailure::Contract, None));
+
+        let contract = diagnose(Behavior::Abort, "a.c0:5.3-5.20: @requires annotation failed\n");
+        assert_eq!(contract.to_string(), "contract");
+
+        let error = diagnose(Behavior::Failure, "Error: not implemented\n");
+        assert_eq!(error.to_string(), "error()");
+        assert_eq!(error, Behavior::Failure);
+        assert_ne!(error, Behavior::Abort);
+
+        // Only the runtime's last words count
+        assert_eq!(diagnose(Behavior::Abort, "assertion failed\nsomething else").to_string(), "abort");
+        assert_eq!(diagnose(Behavior::Segfault, "Error: oops").to_string(), "segfault");
+    }
+
+    #[test]
+    fn test_exit() {
+        let images = ImageComparison { tolerance: 0, diff_dir: PathBuf::new() };
+        let run = TestRun::new(String::new(), None, Behavior::UnknownExit(3));
+        assert!(matches!(check_run(&[Behavior::Exit(3)], &[], &run, Path::new("."), &images, None), TestResult::Success));
+
+        match check_run(&[Behavior::Exit(4)], &[], &run, Path::new("."), &images, None) {
+            TestResult::Mismatch(failure) => assert_eq!(failure.to_string(), "expected exit 4, got unknown-exit 3"),
+            _ => panic!("exited with another code, but passed")
+        }
+    }
+
+    #[test]
+    fn test_skipped() {
+        let images = ImageComparison { tolerance: 0, diff_dir: PathBuf::new() };
+        let run = TestRun::new(String::from("coin can't run C1 code"), None, Behavior::Skipped);
+        match check_run(&[Behavior::Return(Some(0))], &[], &run, Path::new("."), &images, None) {
+            TestResult::Skipped(reason, message) => {
+                assert_eq!(reason, SkipReason::Unsupported);
+                assert_eq!(message, "coin can't run C1 code");
+            },
+            _ => panic!("the executer couldn't run it, but it wasn't skipped")
+        }
+
+        assert_eq!(SkipReason::DependencyFailed.to_string(), "dependency-failed");
+        assert_eq!(serde_json::to_value(SkipReason::MissingTool).unwrap(), "missing-tool");
+    }
+
+    #[test]
+    fn test_prints() {
+        let images = ImageComparison { tolerance: 0, diff_dir: PathBuf::new() };
+        let run = TestRun::new(String::from("c0rt: assertion failed: n >= 0\n"), None, Behavior::Abort);
+        let check = |pattern: &str, regex: bool| {
+            let prints = Assertion::Prints { pattern: String::from(pattern), regex: regex.then(|| Pattern::compile(pattern).unwrap()) };
+            matches!(check_run(&[Behavior::Abort], &[&prints], &run, Path::new("."), &images, None), TestResult::Success)
+        };
+
+        assert!(check("assertion failed", false));
+        assert!(!check("n < 0", false));
+        assert!(check("^c0rt: .* n >= [0-9]", true));
+        assert!(!check("^assertion", true));
+    }
+
+    #[test]
+    fn test_no_warnings() {
+        let images = ImageComparison { tolerance: 0, diff_dir: PathBuf::new() };
+        let check = |compiler_output: &str| {
+            let run = TestRun::new(String::new(), Some(String::from(compiler_output)), Behavior::Return(Some(0)));
+            matches!(check_run(&[Behavior::Return(Some(0))], &[&Assertion::NoWarnings], &run, Path::new("."), &images, None),
+                TestResult::Success)
+        };
+
+        assert!(check(""));
+        assert!(check("Compiling warnings.c0\n"));
+        assert!(!check("warnings.c0:1.1-1.2:warning: mock warning\n"));
+    }
+
+    #[test]
+    fn test_expected_output() {
+        let dir = TempDir::new("expects");
+        fs::write(dir.join("a.c0.expect"), "1\n2\n3\n").unwrap();
+
+        let images = ImageComparison { tolerance: 0, diff_dir: PathBuf::new() };
+        let expects = Assertion::Expects(String::from("a.c0.expect"));
+        let check = |output: &str| {
+            let run = TestRun::new(String::from(output), None, Behavior::Return(Some(0)));
+            check_run(&[Behavior::Return(Some(0))], &[&expects], &run, &dir, &images, None)
+        };
+
+        assert!(matches!(check("1\n2\n3\n"), TestResult::Success));
+        match check("1\n3\n") {
+            TestResult::Mismatch(failure @ Failure::OutputMismatch { .. }) => assert_eq!(failure.to_string(),
+                "the output differs from 'a.c0.expect'\n--- a.c0.expect\n+++ the output\n@@ -1,3 +1,2 @@\n 1\n-2\n 3"),
+            _ => panic!("the output was different, but it didn't mismatch")
         }
+
+    }
+
+    #[test]
+    fn test_trace_specs() {
+        use crate::parse_spec::{self, ParseOptions};
+
+        let (_, specs) = parse_spec::parse("!safe => gc => segfault; safe, !coin => return 0", ParseOptions { require_test_marker: false }).unwrap();
+        let test = TestInfo::new(TestExecutionInfo::for_source("/tests/a.c0"), specs);
+        let properties = ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "cc0" };
+
+        let trace = trace_specs(&test, &properties);
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines[0], "Specs evaluated for cc0:");
+        assert_eq!(lines[2], "    '!safe' is false (safe = true)");
+        assert_eq!(lines[3], "    doesn't apply");
+        assert_eq!(lines[5], "    'safe, !coin' is true (safe = true, coin = false)");
+        assert_eq!(lines[6], "    applies: expects return 0");
+    }
+
+    #[test]
+    fn test_expects_failure() {
+        use crate::parse_spec::{self, ParseOptions};
+
+        let (_, specs) = parse_spec::parse("xfail safe => return 0; !safe => segfault", ParseOptions { require_test_marker: false }).unwrap();
+        let test = TestInfo::new(TestExecutionInfo::for_source("/tests/a.c0"), specs);
+        let safe = ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "cc0" };
+        let unsafe_ = ExecuterProperties { safe: false, ..safe };
+
+        assert!(expects_failure(&safe, &test));
+        assert!(!expects_failure(&unsafe_, &test));
+        assert!(matches!(applicable_specs(&test, &safe).0.as_slice(), [Behavior::Return(Some(0))]));
+        assert!(trace_specs(&test, &safe).contains("applies: expects return 0, which is known to fail"));
+    }
+
+    #[test]
+    fn test_opt() {
+        use crate::parse_spec::{self, ParseOptions};
+
+        let (_, specs) = parse_spec::parse("opt(-d) => abort; !opt(-d) => return 0", ParseOptions { require_test_marker: false }).unwrap();
+        let test_with = |compiler_options: &[&str]| {
+            let compiler_options = compiler_options.iter().map(|option| option.to_string()).collect();
+            TestInfo::new(TestExecutionInfo { compiler_options, ..TestExecutionInfo::for_source("/tests/a.c0") }, specs.clone())
+        };
+        let properties = ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "cc0" };
+
+        let (debug, plain) = (test_with(&["-d"]), test_with(&["-lconio"]));
+        assert!(matches!(applicable_specs(&debug, &properties).0.as_slice(), [Behavior::Abort]));
+        assert!(matches!(applicable_specs(&plain, &properties).0.as_slice(), [Behavior::Return(Some(0))]));
+        assert!(trace_specs(&debug, &properties).contains("'opt(-d)' is true (opt(-d) = true)"));
+    }
+
+    #[test]
+    fn test_locate() {
+        use crate::parse_spec::{self, ParseOptions};
+
+        let line = "//test !safe => segfault; safe => return 0; output exact \"hi\"";
+        let (_, specs, spans) = parse_spec::parse_with_spans(line, ParseOptions { require_test_marker: true }).unwrap();
+        let test = TestInfo {
+            origin: Some(SpecOrigin::new(PathBuf::from("/tests/a.c0"), 1, spans)),
+            ..TestInfo::new(TestExecutionInfo::for_source("/tests/a.c0"), specs)
+        };
+        let properties = ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "cc0" };
+
+        let failure = Failure::Behavior { expected: Behavior::Return(Some(0)), actual: Behavior::Segfault, output: String::new(), location: None };
+        assert_eq!(failure.locate(&test, &properties).to_string(), "expected return 0, got segfault (spec at a.c0:1:27-42)");
+
+        let assertion = Assertion::Output { matcher: String::from("exact"), expected: String::from("hi"), tolerance: None };
+        let failure = Failure::Assertion { assertion, output: String::from("bye"), location: None };
+        assert_eq!(failure.locate(&test, &properties).to_string(), "expected output exact \"hi\" (spec at a.c0:1:45-61)\nbye");
+
+        let failure = Failure::UnexpectedlyCompiled { output: String::new(), location: None };
+        assert!(failure.locate(&test, &properties).location().is_none());
     }
 }
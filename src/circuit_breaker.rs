@@ -0,0 +1,148 @@
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+
+/// Stops a run once enough tests in a row end in errors (rather than
+/// failures), since that usually means the executer itself is broken,
+/// e.g. its binary was replaced partway through. Otherwise every
+/// remaining test would be reported with the same error.
+///
+/// It also stops a run once enough tests have failed (see --max-failures),
+/// for when the compiler being tested is obviously broken
+pub struct CircuitBreaker {
+    /// How many errors in a row trip the breaker. 0 means it never trips
+    limit: usize,
+    /// How many failures in all trip the breaker. 0 means they never do
+    max_failures: usize,
+    state: Mutex<BreakerState>
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_errors: usize,
+    failures: usize,
+    tripped: Option<Trip>
+}
+
+/// Why a run was stopped
+#[derive(Clone)]
+pub enum Trip {
+    /// Too many tests in a row had errors
+    Errors { errors: usize, last_error: String },
+    /// Enough tests failed or timed out
+    Failures { failures: usize, last_failure: String }
+}
+
+impl Trip {
+    /// A short reason for the summary, e.g. 'after 25 errors in a row'
+    pub fn reason(&self) -> String {
+        match self {
+            Trip::Errors { errors, .. } => format!("after {} errors in a row", errors),
+            Trip::Failures { failures, .. } => format!("after {} failures", failures)
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(limit: usize, max_failures: usize) -> CircuitBreaker {
+        CircuitBreaker { limit, max_failures, state: Mutex::new(BreakerState::default()) }
+    }
+
+    /// Records a test which ran, i.e. passed or failed
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_errors = 0;
+    }
+
+    /// Records a test which had an error, returning true if this tripped the breaker
+    pub fn record_error(&self, error: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_errors += 1;
+
+        if self.limit > 0 && state.consecutive_errors >= self.limit && state.tripped.is_none() {
+            state.tripped = Some(Trip::Errors { errors: state.consecutive_errors, last_error: error.to_string() });
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Records a test which failed or timed out, returning true if this tripped the breaker
+    pub fn record_failure(&self, failure: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_errors = 0;
+        state.failures += 1;
+
+        if self.max_failures > 0 && state.failures >= self.max_failures && state.tripped.is_none() {
+            state.tripped = Some(Trip::Failures { failures: state.failures, last_failure: failure.to_string() });
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    /// Whether tests should stop being run
+    pub fn is_tripped(&self) -> bool {
+        self.state.lock().unwrap().tripped.is_some()
+    }
+
+    pub fn trip(&self) -> Option<Trip> {
+        self.state.lock().unwrap().tripped.clone()
+    }
+}
+
+impl Display for Trip {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Trip::Errors { errors, last_error } =>
+                write!(f, "Stopped testing after {} tests in a row had errors, which usually means \
+                    the toolchain is broken (e.g. a binary is missing or was replaced). The last error was:\n{}",
+                    errors, last_error),
+            Trip::Failures { failures, last_failure } =>
+                write!(f, "Stopped testing once {} tests had failed (see --max-failures and --fail-fast). The last failure was:\n{}",
+                    failures, last_failure)
+        }
+    }
+}
+
+#[cfg(test)]
+mod circuit_breaker_tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let breaker = CircuitBreaker::new(3, 0);
+        assert!(!breaker.record_error("a"));
+        assert!(!breaker.record_error("b"));
+        breaker.record_success();
+        assert!(!breaker.record_error("c"));
+        assert!(!breaker.record_error("d"));
+        assert!(!breaker.is_tripped());
+
+        assert!(breaker.record_error("e"));
+        assert!(breaker.is_tripped());
+        assert!(matches!(breaker.trip(), Some(Trip::Errors { last_error, .. }) if last_error == "e"));
+
+        // It only trips once, and stays tripped
+        assert!(!breaker.record_error("f"));
+        breaker.record_success();
+        assert!(breaker.is_tripped());
+
+        let breaker = CircuitBreaker::new(0, 0);
+        for _ in 0..100 {
+            breaker.record_error("error");
+            breaker.record_failure("failure");
+        }
+        assert!(!breaker.is_tripped());
+
+        // Failures needn't be in a row, and reset the errors in a row
+        let breaker = CircuitBreaker::new(2, 2);
+        assert!(!breaker.record_failure("a"));
+        assert!(!breaker.record_error("b"));
+        breaker.record_success();
+        assert!(!breaker.record_error("c"));
+        assert!(breaker.record_failure("d"));
+        assert!(matches!(breaker.trip(), Some(Trip::Failures { failures: 2, .. })));
+    }
+}
@@ -0,0 +1,42 @@
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use serde::Serialize;
+use anyhow::{Context, Result};
+
+use crate::executer::Executer;
+use crate::spec::TestInfo;
+
+/// An entry of a compile_commands.json, as used by clangd and other tools
+#[derive(Serialize)]
+struct CompileCommand {
+    directory: String,
+    arguments: Vec<String>,
+    file: String,
+    /// Not part of the format, but lets scripts find a particular test
+    test: String
+}
+
+/// Writes the command which compiles each test to a compile_commands.json
+/// style file. Returns how many commands were written
+pub fn write(path: &Path, executer: &dyn Executer, tests: &[TestInfo]) -> Result<usize> {
+    // CC0 is run from our working directory
+    let directory = env::current_dir()?.to_string_lossy().into_owned();
+
+    let commands: Vec<CompileCommand> = tests.iter().filter_map(|test| {
+        let command = executer.compile_command(&test.execution)?;
+        Some(CompileCommand {
+            directory: directory.clone(),
+            arguments: command.iter().map(|arg| arg.to_string_lossy().into_owned()).collect(),
+            file: test.execution.sources.last().map(|source| source.to_string_lossy().into_owned()).unwrap_or_default(),
+            test: test.name()
+        })
+    }).collect();
+
+    let file = File::create(path).context(format!("Couldn't create '{}'", path.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &commands)
+        .context(format!("Couldn't write '{}'", path.display()))?;
+
+    Ok(commands.len())
+}
@@ -0,0 +1,127 @@
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use anyhow::Result;
+
+use crate::executer::{Artifact, Compilation, Executer, ExecuterProperties, TestRun};
+use crate::spec::*;
+
+/// An executer which only compiles so many tests at once (see --compile-jobs).
+/// CC0 and GCC take far more memory than running most tests, so this keeps
+/// the compilers from thrashing the machine while every job can still run tests
+pub struct CompileLimit {
+    executer: Box<dyn Executer>,
+    limit: usize,
+    compiling: Mutex<usize>,
+    freed: Condvar
+}
+
+/// A compilation which is allowed to run. Its place is given up when
+/// it's dropped, even if the compiler panicked
+struct Slot<'a>(&'a CompileLimit);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self.0.compiling.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+impl CompileLimit {
+    /// There has to be room for at least one compilation
+    pub fn new(executer: Box<dyn Executer>, limit: usize) -> CompileLimit {
+        assert!(limit > 0, "at least one test has to be able to compile");
+        CompileLimit { executer, limit, compiling: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    fn acquire(&self) -> Slot<'_> {
+        let mut compiling = self.compiling.lock().unwrap();
+        while *compiling >= self.limit {
+            compiling = self.freed.wait(compiling).unwrap();
+        }
+        *compiling += 1;
+        Slot(self)
+    }
+}
+
+impl Executer for CompileLimit {
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
+        let _slot = self.acquire();
+        self.executer.compile(test)
+    }
+
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun> {
+        self.executer.execute(test, artifact)
+    }
+
+    fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        self.executer.compile_command(test)
+    }
+
+    fn exit_code(&self, code: i32) -> Option<Behavior> {
+        self.executer.exit_code(code)
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        self.executer.toolchain()
+    }
+
+    fn properties(&self) -> ExecuterProperties {
+        self.executer.properties()
+    }
+}
+
+#[cfg(test)]
+mod compile_limit_tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Remembers the most compilations it had going at once
+    #[derive(Default)]
+    struct SlowExecuter {
+        compiling: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>
+    }
+
+    impl Executer for SlowExecuter {
+        fn compile(&self, _test: &TestExecutionInfo) -> Result<Compilation> {
+            let now = self.compiling.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            self.compiling.fetch_sub(1, Ordering::SeqCst);
+            Ok(Compilation::NotNeeded)
+        }
+
+        fn execute(&self, _test: &TestExecutionInfo, _artifact: Option<&Artifact>) -> Result<TestRun> {
+            Ok(TestRun::new(String::new(), None, Behavior::Return(Some(0))))
+        }
+
+        fn toolchain(&self) -> Vec<PathBuf> {
+            Vec::new()
+        }
+
+        fn properties(&self) -> ExecuterProperties {
+            ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "slow" }
+        }
+    }
+
+    #[test]
+    fn test() {
+        let executer = SlowExecuter::default();
+        let most = executer.most.clone();
+        let limit = CompileLimit::new(Box::new(executer), 2);
+        let test = TestExecutionInfo::new(Vec::new(), Arc::from(Path::new("/tests")));
+
+        thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| limit.compile(&test).unwrap());
+            }
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(*limit.compiling.lock().unwrap(), 0);
+    }
+}
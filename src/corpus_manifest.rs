@@ -0,0 +1,129 @@
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+
+use crate::fingerprint;
+
+/// Expected hashes of the files in a test corpus, in the format
+/// 'sha256sum' produces. Paths are relative to the test directory
+pub struct CorpusManifest {
+    entries: Vec<(PathBuf, String)>
+}
+
+/// A file which doesn't match the manifest
+pub enum CorpusProblem {
+    Missing(PathBuf),
+    Unreadable(PathBuf, String),
+    Modified { path: PathBuf, expected: String, actual: String }
+}
+
+impl CorpusManifest {
+    pub fn load(path: &Path) -> Result<CorpusManifest> {
+        let text = fs::read_to_string(path).context(format!("Couldn't read '{}'", path.display()))?;
+        CorpusManifest::parse(&text).context(format!("In corpus manifest '{}'", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<CorpusManifest> {
+        let mut entries = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue
+            }
+
+            // Lines look like '<hash>  <path>', or '<hash> *<path>' for files hashed in binary mode
+            let (hash, path) = match line.find(' ') {
+                Some(pos) => (&line[..pos], &line[pos + 1..]),
+                None => bail!("Line {}: expected '<hash>  <path>'", i + 1)
+            };
+            let path = path.strip_prefix(|c| c == ' ' || c == '*').unwrap_or(path);
+
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("Line {}: '{}' isn't a SHA-256 hash", i + 1, hash)
+            }
+            if path.is_empty() {
+                bail!("Line {}: missing file name", i + 1)
+            }
+
+            entries.push((PathBuf::from(path), hash.to_ascii_lowercase()));
+        }
+
+        Ok(CorpusManifest { entries })
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks every file in the manifest against the files in 'test_dir'
+    pub fn verify(&self, test_dir: &Path) -> Vec<CorpusProblem> {
+        let mut problems = Vec::new();
+
+        for (path, expected) in self.entries.iter() {
+            let full_path = test_dir.join(path);
+            match fingerprint::file_hash(&full_path) {
+                Ok(actual) if actual == *expected => (),
+                Ok(actual) => problems.push(CorpusProblem::Modified { 
+                    path: path.clone(), 
+                    expected: expected.clone(), 
+                    actual 
+                }),
+                Err(_) if !full_path.exists() => problems.push(CorpusProblem::Missing(path.clone())),
+                Err(e) => problems.push(CorpusProblem::Unreadable(path.clone(), format!("{:#}", e)))
+            }
+        }
+
+        problems
+    }
+}
+
+impl Display for CorpusProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorpusProblem::Missing(path) => write!(f, "{} is missing", path.display()),
+            CorpusProblem::Unreadable(path, msg) => write!(f, "{} couldn't be read: {}", path.display(), msg),
+            CorpusProblem::Modified { path, expected, actual } => 
+                write!(f, "{} has been modified (expected hash {}, got {})", path.display(), expected, actual)
+        }
+    }
+}
+
+#[cfg(test)]
+mod corpus_manifest_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let manifest = CorpusManifest::parse(&format!("{}  a/b.c0\n\n{} *c d.c0\n", hash, hash.to_uppercase()))?;
+        assert_eq!(manifest.entries, vec![
+            (PathBuf::from("a/b.c0"), hash.to_string()),
+            (PathBuf::from("c d.c0"), hash.to_string())
+        ]);
+
+        assert!(CorpusManifest::parse("abc  a.c0").is_err());
+        assert!(CorpusManifest::parse(hash).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let dir = TempDir::new("corpus");
+        fs::write(dir.join("empty.c0"), "")?;
+        fs::write(dir.join("changed.c0"), "int main() { return 0; }")?;
+
+        // SHA-256 of the empty string
+        let empty_hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let manifest = CorpusManifest::parse(&format!(
+            "{0}  empty.c0\n{0}  changed.c0\n{0}  missing.c0\n", empty_hash))?;
+
+        let problems = manifest.verify(&dir);
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(&problems[0], CorpusProblem::Modified { path, .. } if path == Path::new("changed.c0")));
+        assert!(matches!(&problems[1], CorpusProblem::Missing(path) if path == Path::new("missing.c0")));
+
+        Ok(())
+    }
+}
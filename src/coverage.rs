@@ -0,0 +1,159 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+
+use crate::fingerprint;
+use crate::launcher::run_tool;
+use crate::options::{CoverageKind, Options};
+use crate::spec::TestExecutionInfo;
+
+/// Merging profile data can take a while for big toolchains
+const MERGE_TIMEOUT: u64 = 3600;
+
+/// Collects profile data from an instrumented toolchain. Each test gets its
+/// own profile data, so tests don't overwrite each other's counters and the
+/// data for a single test can be looked at later
+pub struct Coverage {
+    kind: CoverageKind,
+    /// Absolute path to --coverage-dir
+    dir: PathBuf
+}
+
+impl Coverage {
+    pub fn new(options: &Options) -> Result<Option<Coverage>> {
+        let kind = match options.coverage {
+            Some(kind) => kind,
+            None => return Ok(None)
+        };
+
+        let dir = options.coverage_dir();
+        fs::create_dir_all(dir).context(format!("Couldn't create '{}'", dir.display()))?;
+        let dir = fs::canonicalize(dir)?;
+
+        Ok(Some(Coverage { kind, dir }))
+    }
+
+    /// Where the profile data of each test goes
+    fn raw_dir(&self) -> PathBuf {
+        match self.kind {
+            CoverageKind::Gcov => self.dir.join("gcov"),
+            CoverageKind::Llvm => self.dir.join("llvm")
+        }
+    }
+
+    /// Removes the profile data from previous runs
+    pub fn clear(&self) -> Result<()> {
+        let raw_dir = self.raw_dir();
+        if raw_dir.exists() {
+            fs::remove_dir_all(&raw_dir).context(format!("Couldn't remove '{}'", raw_dir.display()))?;
+        }
+        fs::create_dir_all(&raw_dir).context(format!("Couldn't create '{}'", raw_dir.display()))?;
+        Ok(())
+    }
+
+    /// Environment variables ('NAME=value') which make instrumented
+    /// programs run for a test write their profile data to its own place
+    pub fn environment(&self, test: &TestExecutionInfo) -> Vec<CString> {
+        let test_dir = self.raw_dir().join(test_label(test));
+        let vars = match self.kind {
+            // Files go to <prefix>/<absolute path of the .gcda file at build time>
+            CoverageKind::Gcov => vec![
+                format!("GCOV_PREFIX={}", test_dir.display()),
+                String::from("GCOV_PREFIX_STRIP=0")
+            ],
+            // The profiling runtime fills in the process ID
+            CoverageKind::Llvm => vec![format!("LLVM_PROFILE_FILE={}/%p.profraw", test_dir.display())]
+        };
+
+        vars.into_iter().map(|var| CString::new(var).unwrap()).collect()
+    }
+
+    /// Merges the profile data from every test. Returns where the merged data is
+    pub fn merge(&self) -> Result<PathBuf> {
+        let mut test_dirs: Vec<PathBuf> = fs::read_dir(self.raw_dir())?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .collect();
+        test_dirs.sort();
+
+        match self.kind {
+            CoverageKind::Llvm => {
+                let merged = self.dir.join("merged.profdata");
+                let mut args = vec![path_arg("merge"), path_arg("-sparse"), path_arg("-o"), path_arg(&merged)];
+                for dir in test_dirs.iter() {
+                    for entry in fs::read_dir(dir)?.filter_map(Result::ok) {
+                        args.push(path_arg(entry.path()));
+                    }
+                }
+
+                run_merge_tool("llvm-profdata", &args)?;
+                Ok(merged)
+            },
+            CoverageKind::Gcov => {
+                // gcov-tool only merges two directories at a time
+                let merged = self.dir.join("merged");
+                if merged.exists() {
+                    fs::remove_dir_all(&merged)?;
+                }
+
+                let mut dirs = test_dirs.iter();
+                let first = match dirs.next() {
+                    Some(first) => first,
+                    None => bail!("No tests produced coverage data")
+                };
+                copy_dir(first, &merged)?;
+
+                let next_merged = self.dir.join("merged.next");
+                for dir in dirs {
+                    run_merge_tool("gcov-tool", &[
+                        path_arg("merge"), path_arg(&merged), path_arg(dir), path_arg("-o"), path_arg(&next_merged)
+                    ])?;
+                    fs::remove_dir_all(&merged)?;
+                    fs::rename(&next_merged, &merged)?;
+                }
+
+                Ok(merged)
+            }
+        }
+    }
+}
+
+/// A name for a test which is unique and readable enough
+/// to find the profile data of a particular test
+fn test_label(test: &TestExecutionInfo) -> String {
+    let stem = test.sources.last()
+        .and_then(|source| source.file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    format!("{}-{}", stem, &fingerprint::test_key(test)[..12])
+}
+
+fn run_merge_tool(tool: &str, args: &[CString]) -> Result<()> {
+    let (success, output) = run_tool(path_arg(tool), args, MERGE_TIMEOUT, u64::MAX)?;
+    if !success {
+        bail!("'{}' failed to merge coverage data:\n{}", tool, output)
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        }
+        else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn path_arg<P: AsRef<Path>>(path: P) -> CString {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(path.as_ref().as_os_str().as_bytes()).unwrap()
+}
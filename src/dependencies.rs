@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{bail, Result};
+
+use crate::spec::TestInfo;
+
+/// Which tests depend on which (see '@depends'), and the order that
+/// makes them run after their dependencies. Tests only depend on
+/// tests from their own suite, e.g. one which writes a file that
+/// another one reads, and each stage only depends on earlier ones
+pub struct DependencyGraph<'a> {
+    stages: Vec<Vec<&'a TestInfo>>,
+    dependencies: HashMap<String, Vec<&'a TestInfo>>,
+    /// Tests which depend on each other in a cycle, or on tests which do
+    stuck: Vec<&'a TestInfo>
+}
+
+impl<'a> DependencyGraph<'a> {
+    /// Dependencies which aren't in 'tests' (e.g. because they passed last
+    /// time and --rerun-failed left them out) are left out of the graph
+    pub fn new(tests: &'a [TestInfo]) -> DependencyGraph<'a> {
+        let mut dependencies: HashMap<String, Vec<&'a TestInfo>> = HashMap::new();
+        for test in tests.iter().filter(|test| !test.execution.depends.is_empty()) {
+            dependencies.insert(test.name(), find_dependencies(test, tests));
+        }
+
+        // A test's stage is one after the latest stage of its dependencies
+        let mut stage_of: HashMap<String, usize> = HashMap::new();
+        let mut stages: Vec<Vec<&'a TestInfo>> = Vec::new();
+        let mut left: Vec<&'a TestInfo> = tests.iter().collect();
+        let mut stuck = Vec::new();
+        while !left.is_empty() {
+            let (ready, waiting): (Vec<&TestInfo>, Vec<&TestInfo>) = left.into_iter().partition(|test| {
+                match dependencies.get(&test.name()) {
+                    Some(dependencies) => dependencies.iter().all(|dependency| stage_of.contains_key(&dependency.name())),
+                    None => true
+                }
+            });
+
+            // Only a cycle leaves tests waiting forever. They're run last
+            // and don't pass, since each one waits on one which hasn't run
+            if ready.is_empty() {
+                stuck = waiting.clone();
+                stages.push(waiting);
+                break
+            }
+
+            for test in ready.iter() {
+                stage_of.insert(test.name(), stages.len());
+            }
+            stages.push(ready);
+            left = waiting;
+        }
+
+        DependencyGraph { stages, dependencies, stuck }
+    }
+
+    /// Groups of tests to run one after another. The tests in a stage can run in parallel
+    pub fn stages(&self) -> &[Vec<&'a TestInfo>] {
+        &self.stages
+    }
+
+    /// Whether the test depends on another one, or another one depends on it
+    pub fn is_linked(&self, test: &TestInfo) -> bool {
+        let name = test.name();
+        self.dependencies.contains_key(&name) || self.dependencies.values().flatten().any(|dependency| dependency.name() == name)
+    }
+
+    /// The tests which have to pass before the given one runs
+    pub fn dependencies(&self, test: &TestInfo) -> &[&'a TestInfo] {
+        match self.dependencies.get(&test.name()) {
+            Some(dependencies) => dependencies,
+            None => &[]
+        }
+    }
+}
+
+/// Checks that every dependency of the tests from a suite is in the suite,
+/// and that no tests depend on each other
+pub fn check_suite(dir: &Path, tests: &[TestInfo]) -> Result<()> {
+    for test in tests.iter() {
+        for dependency in test.execution.depends.iter() {
+            if !tests.iter().any(|other| local_name(other) == *dependency) {
+                bail!("'{}' depends on '{}', which isn't a test in '{}'", test.name(), dependency, dir.display())
+            }
+        }
+    }
+
+    let graph = DependencyGraph::new(tests);
+    if !graph.stuck.is_empty() {
+        let names: Vec<String> = graph.stuck.iter().map(|test| local_name(test)).collect();
+        bail!("Tests in '{}' depend on each other in a cycle: {}", dir.display(), names.join(", "))
+    }
+
+    Ok(())
+}
+
+/// What '@depends' calls a test: its sources and compiler options as written
+/// in sources.test, e.g. 'writer.c0' or 'lib.c0 writer.c0 -d'
+pub fn local_name(test: &TestInfo) -> String {
+    let execution = &test.execution;
+    let mut name: Vec<String> = execution.sources.iter()
+        .map(|source| source.strip_prefix(&execution.directory).unwrap_or(source).to_string_lossy().into_owned())
+        .collect();
+
+    name.extend(execution.compiler_options.iter().cloned());
+    name.join(" ")
+}
+
+fn find_dependencies<'a>(test: &TestInfo, tests: &'a [TestInfo]) -> Vec<&'a TestInfo> {
+    tests.iter()
+        .filter(|other| other.execution.directory == test.execution.directory)
+        .filter(|other| test.execution.depends.contains(&local_name(other)))
+        .collect()
+}
+
+#[cfg(test)]
+mod dependencies_tests {
+    use super::*;
+    use crate::spec::*;
+
+    fn test_named(name: &str, depends: &[&str]) -> TestInfo {
+        let depends = depends.iter().map(|name| name.to_string()).collect();
+        TestInfo::new(TestExecutionInfo { depends, ..TestExecutionInfo::for_source(Path::new("/tests/files").join(name)) }, Vec::new())
+    }
+
+    fn stage_names(graph: &DependencyGraph) -> Vec<Vec<String>> {
+        graph.stages().iter().map(|stage| stage.iter().map(|test| local_name(test)).collect()).collect()
+    }
+
+    #[test]
+    fn test() {
+        let tests = vec![
+            test_named("reader.c0", &["writer.c0"]),
+            test_named("writer.c0", &[]),
+            test_named("other.c0", &[]),
+            test_named("cleanup.c0", &["reader.c0", "writer.c0"])
+        ];
+        let graph = DependencyGraph::new(&tests);
+        assert_eq!(stage_names(&graph), vec![
+            vec!["writer.c0", "other.c0"],
+            vec!["reader.c0"],
+            vec!["cleanup.c0"]
+        ]);
+        assert_eq!(graph.dependencies(&tests[0]).len(), 1);
+        assert!(graph.is_linked(&tests[1]) && !graph.is_linked(&tests[2]));
+        assert!(check_suite(Path::new("/tests/files"), &tests).is_ok());
+
+        // Dependencies which aren't being run don't hold tests back
+        let graph = DependencyGraph::new(&tests[..1]);
+        assert_eq!(stage_names(&graph), vec![vec!["reader.c0"]]);
+
+        let tests = vec![test_named("reader.c0", &["writer.c0"])];
+        assert!(check_suite(Path::new("/tests/files"), &tests).is_err());
+
+        let tests = vec![test_named("a.c0", &["b.c0"]), test_named("b.c0", &["a.c0"])];
+        assert!(check_suite(Path::new("/tests/files"), &tests).is_err());
+    }
+}
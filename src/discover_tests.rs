@@ -1,57 +1,274 @@
 use std::{fs::{self, File}, io::BufReader};
 use std::io::prelude::*;
-use std::path::Path;
-use std::sync::Arc;
-use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use nix::libc;
+use anyhow::{anyhow, bail, Context, Result};
 
 use crate::parse_spec::{self, ParseOptions};
 use crate::spec::*;
+use crate::suite_config::SuiteConfig;
+use crate::lit;
+use crate::dependencies;
+use crate::options::{Options, SpecConflicts};
+use crate::test_filter::TestFilter;
+
+/// Problems found while discovering tests, e.g. malformed '//test' lines.
+/// Discovery carries on without the tests they affect, and --dry-run fails if there are any
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Prints a problem found during discovery, and remembers it for --dry-run
+fn warn(warning: String) {
+    eprintln!("⚠: {}", warning);
+    WARNINGS.lock().unwrap().push(warning);
+}
+
+/// The problems found since the last call, in the order they were found
+pub fn take_warnings() -> Vec<String> {
+    std::mem::take(&mut *WARNINGS.lock().unwrap())
+}
+
+/// What discovery leaves out, e.g. suites which can't run on a headless machine
+pub struct Exclusions<'a> {
+    /// Names of test directories which aren't loaded at all (see --exclude-dir)
+    pub dirs: &'a [String],
+    /// Tests which are dropped once they are loaded (see --exclude)
+    pub tests: &'a [TestFilter],
+    /// Which spec wins when sources.test and a '//test' line disagree,
+    /// or whether the suite is left out
+    pub spec_conflicts: SpecConflicts
+}
+
+impl<'a> Default for Exclusions<'a> {
+    fn default() -> Exclusions<'a> {
+        Exclusions { dirs: &[], tests: &[], spec_conflicts: SpecConflicts::Sources }
+    }
+}
+
+impl<'a> Exclusions<'a> {
+    pub fn new(options: &'a Options) -> Exclusions<'a> {
+        Exclusions { dirs: &options.exclude_dir, tests: &options.exclude, spec_conflicts: options.spec_conflicts }
+    }
+
+    fn excludes_dir(&self, path: &Path) -> bool {
+        match path.file_name().and_then(OsStr::to_str) {
+            Some(name) => self.dirs.iter().any(|dir| dir == name),
+            None => false
+        }
+    }
+}
 
 /// Discovers all CC0 test cases in all subdirectories of 'base'.
 /// This assumes base contains directories which contain test cases.
 /// If a subdirectory contains 'sources.test', then that file will be
-/// read to discover test cases.
-pub fn discover(base: &Path) -> Result<Vec<TestInfo>> {
-    let paths = fs::read_dir(base)
+/// read to discover test cases. Directories and files reached through
+/// more than one symlink are only loaded once
+pub fn discover(base: &Path, exclusions: &Exclusions) -> Result<Vec<TestInfo>> {
+    let canonical_base = fs::canonicalize(base)
+        .context(format!("Couldn't open the root test directory '{}'", base.display()))?;
+    let mut paths: Vec<PathBuf> = fs::read_dir(base)
         .context(format!("Couldn't open the root test directory '{}'", base.display()))?
-        .filter_map(Result::ok);
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+
+    // Real directories come first, so they are the ones loaded instead of symlinks to them
+    paths.sort_by_key(|path| (is_symlink(path), path.clone()));
 
     let mut tests = Vec::new();
+    let mut loaded: HashMap<PathBuf, PathBuf> = HashMap::new();
 
     for path in paths {
-        let path = path.path();
-        if path.is_dir() {
-            match discover_directory(&path) {
-                Ok(new_tests) => tests.extend(new_tests.into_iter()),
-                Err(e) => eprintln!("⚠: skipping '{}': {:#}", path.display(), e)
+        let canonical = match fs::canonicalize(&path) {
+            Ok(canonical) => canonical,
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+                warn(format!("skipping '{}': it is part of a symlink cycle", path.display()));
+                continue
+            },
+            Err(e) => {
+                if is_symlink(&path) {
+                    warn(format!("skipping '{}': {}", path.display(), e));
+                }
+                continue
             }
+        };
+
+        if !canonical.is_dir() || exclusions.excludes_dir(&path) {
+            continue
+        }
+
+        if canonical_base.starts_with(&canonical) {
+            warn(format!("skipping '{}': it is a symlink cycle back to '{}'", path.display(), canonical.display()));
+            continue
+        }
+
+        if let Some(first) = loaded.get(&canonical) {
+            warn(format!("skipping '{}': it is the same directory as '{}'", path.display(), first.display()));
+            continue
+        }
+        loaded.insert(canonical, path.clone());
+
+        match discover_directory(&path, exclusions.spec_conflicts) {
+            Ok(new_tests) => tests.extend(new_tests),
+            Err(e) => warn(format!("skipping '{}': {:#}", path.display(), e))
         }
     }
 
+    let mut tests = dedup_tests(tests);
+    tests.retain(|test| !exclusions.tests.iter().any(|filter| filter.matches(test)));
     Ok(tests)
 }
 
-/// Loads all test cases inside a directory
-fn discover_directory(dir: &Path) -> Result<Vec<TestInfo>> {
+/// Describes what 'discover' looked at in 'base', one line per entry, to
+/// explain why it found no tests, e.g. when given the wrong directory
+pub fn describe_scan(base: &Path, exclusions: &Exclusions) -> Vec<String> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(base) {
+        Ok(entries) => entries.filter_map(Result::ok).map(|entry| entry.path()).collect(),
+        Err(e) => return vec![format!("{}: {}", base.display(), e)]
+    };
+    paths.sort();
+    if !paths.iter().any(|path| path.is_dir()) {
+        return vec![format!("{}: has no test directories, e.g. '{}'", base.display(), base.join("basic").display())]
+    }
+
+    let mut lines: Vec<String> = paths.iter().map(|path| {
+        if !path.is_dir() {
+            format!("{}: not a directory", path.display())
+        }
+        else if exclusions.excludes_dir(path) {
+            format!("{}: left out by --exclude-dir", path.display())
+        }
+        else if path.join("sources.test").is_file() {
+            format!("{}: has a sources.test, but no tests were loaded from it", path.display())
+        }
+        else {
+            let sources = fs::read_dir(path).map(|entries| entries.filter_map(Result::ok)
+                .filter(|entry| matches!(entry.path().extension().and_then(OsStr::to_str), Some("c0") | Some("c1")))
+                .count());
+            match sources {
+                Ok(0) => format!("{}: has no sources.test, .c0 or .c1 files", path.display()),
+                Ok(count) => format!("{}: has {} .c0 and .c1 files, but no tests were loaded from them", path.display(), count),
+                Err(e) => format!("{}: {}", path.display(), e)
+            }
+        }
+    }).collect();
+
+    if !exclusions.tests.is_empty() {
+        lines.push(String::from("--exclude may have left out every test"));
+    }
+    lines
+}
+
+/// Removes tests which are the same as another test once symlinks in their
+/// sources are resolved, e.g. when several suites link to a shared file.
+/// The test whose sources aren't symlinks is kept if there is one
+fn dedup_tests(tests: Vec<TestInfo>) -> Vec<TestInfo> {
+    let identities: Vec<_> = tests.iter().map(canonical_identity).collect();
+
+    let mut kept: HashMap<&(Vec<PathBuf>, Vec<String>), usize> = HashMap::new();
+    for (i, identity) in identities.iter().enumerate() {
+        let through_symlink = |i: usize| identity.0.iter()
+            .zip(tests[i].execution.sources.iter())
+            .any(|(canonical, source)| canonical != Path::new(source));
+
+        match kept.get(identity) {
+            Some(&first) if through_symlink(first) && !through_symlink(i) => { kept.insert(identity, i); },
+            Some(_) => (),
+            None => { kept.insert(identity, i); }
+        }
+    }
+
+    for (i, identity) in identities.iter().enumerate() {
+        let first = kept[identity];
+        if first != i {
+            warn(format!("skipping '{}': it is the same test as '{}'", tests[i].name(), tests[first].name()));
+        }
+    }
+
+    tests.into_iter().zip(identities.iter()).enumerate()
+        .filter(|(i, (_, identity))| kept[identity] == *i)
+        .map(|(_, (test, _))| test)
+        .collect()
+}
+
+/// What makes a test unique: its sources, with symlinks resolved, and its compiler options
+fn canonical_identity(test: &TestInfo) -> (Vec<PathBuf>, Vec<String>) {
+    let sources = test.execution.sources.iter()
+        .map(|source| fs::canonicalize(source).unwrap_or_else(|_| PathBuf::from(source)))
+        .collect();
+
+    (sources, test.execution.compiler_options.clone())
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false)
+}
+
+/// Finds the tests in a source file's directory which use that file.
+/// The file itself can be a symlink, but its directory is resolved
+pub fn tests_for_file(path: &Path) -> Result<Vec<TestInfo>> {
+    let file_name = path.file_name().context("The test file has no name")?;
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new(".")
+    };
+    let dir = fs::canonicalize(dir).context(format!("Couldn't find '{}'", path.display()))?;
+    let path = dir.join(file_name);
+    if !path.exists() {
+        bail!("Couldn't find '{}'", path.display())
+    }
+
+    let tests: Vec<TestInfo> = discover_directory(&dir, SpecConflicts::Sources)?.into_iter()
+        .filter(|test| test.execution.sources.contains(&path))
+        .collect();
+
+    if tests.is_empty() {
+        bail!("No tests use '{}'", path.display())
+    }
+    Ok(tests)
+}
+
+/// Loads all test cases inside a directory, applying
+/// the directory's suite.toml if it has one
+fn discover_directory(dir: &Path, spec_conflicts: SpecConflicts) -> Result<Vec<TestInfo>> {
+    let suite = SuiteConfig::load(dir)?;
+    let compilation_limits = suite.compilation_limits()?;
+    let exit_codes = Arc::new(suite.exit_codes().context(format!("in '{}'", dir.join("suite.toml").display()))?);
+
     let sources_test_path = dir.join("sources.test");
 
     // Try to look for sources.test
-    match File::open(sources_test_path).ok() {
-        Some(sources_test) => read_sources_file(dir, sources_test),
-        None => read_test_files(dir)
+    let mut tests = match File::open(sources_test_path).ok() {
+        Some(sources_test) => reconcile_test_lines(read_sources_file(dir, sources_test)?, spec_conflicts)?,
+        None => read_test_files(dir)?
+    };
+
+    // Limits from the spec take priority over the suite's
+    for test in tests.iter_mut() {
+        let execution = &mut test.execution;
+        execution.compilation_limits = execution.compilation_limits.or(compilation_limits);
+        execution.exit_codes = exit_codes.clone();
+        add_expected_output(test);
     }
+
+    dependencies::check_suite(dir, &tests)?;
+
+    Ok(tests)
 }
 
 /// Parses a 'sources.test' 
-fn read_sources_file(dir: &Path, sources_test: File) -> Result<Vec<TestInfo>> {
+pub fn read_sources_file(dir: &Path, sources_test: File) -> Result<Vec<TestInfo>> {
     let reader = BufReader::new(sources_test);
     let lines = reader.lines();
     let mut tests = Vec::new();
 
-    let directory = Arc::<str>::from(dir.to_str().unwrap());
+    let directory = Arc::<Path>::from(dir);
 
     for (line, lineno) in lines.zip(1usize..) {
-        let line = line?;
+        let line = line.context(format!("Couldn't read sources.test on line {}", lineno))?;
 
         if line.trim().is_empty() {
             continue
@@ -61,15 +278,14 @@ fn read_sources_file(dir: &Path, sources_test: File) -> Result<Vec<TestInfo>> {
             .split_once('~')
             .ok_or_else(|| anyhow!("sources.test is missing '~' on line {}", lineno))?;
 
-        let specs = parse_spec::parse(spec, ParseOptions { require_test_marker: false })
-            .context(format!("in sources.test on line {}", lineno))?;
+        let (annotations, specs, spans) = parse_spec::parse_with_spans(spec, ParseOptions { require_test_marker: false })
+            .map_err(|e| anyhow!("{}", e.render(&dir.join("sources.test"), lineno, spec)))?;
 
-        let mut sources: Vec<String> = Vec::new();
+        let mut sources: Vec<PathBuf> = Vec::new();
         let mut compiler_options: Vec<String> = Vec::new();
         for arg in cmdline.split_ascii_whitespace() {
             if !arg.starts_with('-') && ([".c0", ".c1", ".h0", ".h1"].iter().any(|&ext| arg.ends_with(ext))) {
-                let path = dir.join(arg);
-                sources.push(path.into_os_string().into_string().expect("Invalid path character"));
+                sources.push(dir.join(arg));
             }
             else {
                 compiler_options.push(String::from(arg));
@@ -80,9 +296,19 @@ fn read_sources_file(dir: &Path, sources_test: File) -> Result<Vec<TestInfo>> {
             execution: TestExecutionInfo {
                 sources,
                 compiler_options,
-                directory: directory.clone()
+                directory: directory.clone(),
+                compilation_limits: annotations.compilation_limits,
+                run_limits: annotations.run_limits,
+                hooks: annotations.hooks,
+                exit_codes: Arc::default(),
+                link_options: Vec::new(),
+                depends: annotations.depends,
+                slow: annotations.slow,
+                no_capture: annotations.no_capture
             },
-            specs
+            specs,
+            tags: annotations.tags,
+            origin: Some(SpecOrigin::new(dir.join("sources.test"), lineno, spans))
         };
 
         tests.push(test)
@@ -91,51 +317,124 @@ fn read_sources_file(dir: &Path, sources_test: File) -> Result<Vec<TestInfo>> {
     Ok(tests)
 }
 
+/// Compares each test in sources.test which runs a single file on its own with
+/// the file's '//test' line, if it has one, since otherwise the line would be
+/// silently ignored. Conflicts are settled as --spec-conflicts says
+fn reconcile_test_lines(mut tests: Vec<TestInfo>, spec_conflicts: SpecConflicts) -> Result<Vec<TestInfo>> {
+    let mut conflicts = Vec::new();
+
+    for test in tests.iter_mut() {
+        let source = match (test.execution.sources.as_slice(), test.execution.compiler_options.is_empty()) {
+            ([source], true) => source.clone(),
+            _ => continue
+        };
+
+        // Files which can't be read or parsed fail when the test runs
+        let (annotations, specs, origin) = match read_test_lines(&source) {
+            Ok(lines) => match parse_test_lines(&source, &lines) {
+                Ok(result) => result,
+                Err(_) => continue
+            },
+            _ => continue
+        };
+
+        let mut inline = test.clone();
+        inline.specs = specs;
+        inline.origin = Some(origin);
+        inline.execution.compilation_limits = annotations.compilation_limits;
+        inline.execution.run_limits = annotations.run_limits;
+        inline.execution.hooks = annotations.hooks;
+        inline.execution.depends = annotations.depends;
+        inline.execution.slow = annotations.slow;
+        inline.execution.no_capture = annotations.no_capture;
+        inline.tags = annotations.tags;
+        if inline.spec_line() == test.spec_line() {
+            continue
+        }
+
+        let conflict = format!("'{}' is '{}' in sources.test, but its //test line is '{}'",
+            test.name(), test.spec_line(), inline.spec_line());
+        match spec_conflicts {
+            SpecConflicts::Sources => warn(format!("{}, so sources.test is used", conflict)),
+            SpecConflicts::Inline => {
+                warn(format!("{}, so the //test line is used", conflict));
+                *test = inline;
+            },
+            SpecConflicts::Error => conflicts.push(conflict)
+        }
+    }
+
+    if !conflicts.is_empty() {
+        bail!("sources.test and //test lines disagree (see --spec-conflicts):\n{}", conflicts.join("\n"))
+    }
+    Ok(tests)
+}
+
 /// Loads all .c0, .c1 test files in the given directory
-fn read_test_files(dir: &Path) -> Result<Vec<TestInfo>> {
+pub fn read_test_files(dir: &Path) -> Result<Vec<TestInfo>> {
     let test_paths = fs::read_dir(dir)
         .context(format!("Couldn't open a test directory '{}'", dir.display()))?
         .filter_map(Result::ok);
 
     let mut tests = Vec::new();
-    let directory = Arc::<str>::from(dir.to_str().unwrap());
+    let directory = Arc::<Path>::from(dir);
 
     for test in test_paths {
         let path = test.path();
 
         // Check if its a c0 or c1 file and open it if it is
-        match path.extension().map(|ext| ext.to_str().expect("Invalid path character")) {
+        match path.extension().and_then(OsStr::to_str) {
             Some("c0") | Some("c1") => (),
             _ => continue
         };
 
-        let file = match File::open(&path) {
-            Ok(file) => file,
-            Err(_) => continue
+        let spec_lines = match read_test_lines(&path) {
+            Ok(lines) if lines.is_empty() => { warn(format!("file '{}' is empty", path.display())); continue },
+            Ok(lines) => lines,
+            Err(e) => { warn(format!("skipping '{}': {:#}", path.display(), e)); continue }
         };
 
-        // Read spec line
-        let reader = BufReader::new(file);
-        let spec_line = match reader.lines().next() {
-            Some(Ok(line)) => line,
-            Some(Err(_)) => continue,
-            None => { eprintln!("⚠: file '{}' is empty", path.display()); continue }
-        };
-
-        // Parse spec line
-        let specs: Specs = match parse_spec::parse(&spec_line, ParseOptions { require_test_marker: true }) {
-            Ok(specs) => specs,
-            Err(parse_spec::SpecParseError::NotSpec) => continue,
-            Err(e) => { eprintln!("⚠: skipping '{}': {:#}", path.display(), e); continue }
+        // Parse the spec lines, or the spec file next to the test
+        let spec_file = spec_file_path(&path);
+        let (annotations, specs, origin) = match parse_test_lines(&path, &spec_lines) {
+            Ok((annotations, specs, origin)) => {
+                if spec_file.exists() {
+                    warn(format!("ignoring '{}', since '{}' has a //test line", spec_file.display(), path.display()));
+                }
+                (annotations, specs, origin)
+            },
+            Err((_, parse_spec::SpecParseError::NotSpec)) if spec_file.exists() => match read_spec_file(&spec_file) {
+                Ok((annotations, specs, spans)) => (annotations, specs, SpecOrigin::new(spec_file, 1, spans)),
+                Err(e) => { warn(format!("{:#}", e)); continue }
+            },
+            Err((_, parse_spec::SpecParseError::NotSpec)) => {
+                // It might be from another harness instead
+                match lit::read_lit_tests(&path, &directory) {
+                    Ok(lit_tests) => tests.extend(lit_tests),
+                    Err(e) => warn(format!("skipping '{}': {:#}", path.display(), e))
+                }
+                continue
+            },
+            Err((line, e)) => { warn(e.render(&path, line, &spec_lines[line - 1])); continue }
         };
 
         let test = TestInfo {
             execution: TestExecutionInfo {
-                sources: vec![String::from(test.path().to_str().expect("Invalid character in path"))],
+                sources: vec![path],
                 compiler_options: Vec::new(),
-                directory: directory.clone()
+                directory: directory.clone(),
+                compilation_limits: annotations.compilation_limits,
+                run_limits: annotations.run_limits,
+                hooks: annotations.hooks,
+                exit_codes: Arc::default(),
+                link_options: Vec::new(),
+                depends: annotations.depends,
+                slow: annotations.slow,
+                no_capture: annotations.no_capture
             },
-            specs
+            specs,
+            tags: annotations.tags,
+            origin: Some(origin)
         };
 
         tests.push(test)
@@ -144,19 +443,270 @@ fn read_test_files(dir: &Path) -> Result<Vec<TestInfo>> {
     Ok(tests)
 }
 
+/// Where the spec of a test file which can't have a '//test' line
+/// (e.g. because it's generated) goes: 'foo.c0' has 'foo.c0.spec'
+pub fn spec_file_path(test_file: &Path) -> PathBuf {
+    let mut path = test_file.as_os_str().to_os_string();
+    path.push(".spec");
+    PathBuf::from(path)
+}
+
+/// Where the expected output of a test file goes: 'foo.c0' has 'foo.c0.expect'
+pub fn expect_file_path(test_file: &Path) -> PathBuf {
+    let mut path = test_file.as_os_str().to_os_string();
+    path.push(".expect");
+    PathBuf::from(path)
+}
+
+/// Makes a test with one source expect the output in the source's
+/// '.expect' file, if it has one and the test doesn't already expect a file
+fn add_expected_output(test: &mut TestInfo) {
+    let expect_file = match test.execution.sources.as_slice() {
+        [source] => expect_file_path(source),
+        _ => return
+    };
+    let already_expects = test.specs.iter().any(|spec| matches!(spec, Spec::Assertion(Assertion::Expects(_))));
+    if already_expects || !expect_file.is_file() {
+        return
+    }
+
+    if let Some(name) = expect_file.file_name() {
+        test.specs.push(Spec::Assertion(Assertion::Expects(name.to_string_lossy().into_owned())));
+    }
+}
+
+/// Parses a spec file, whose first line is a spec written as in sources.test
+fn read_spec_file(path: &Path) -> Result<(Annotations, Specs, Vec<Range<usize>>)> {
+    let line = match read_first_line(path).context(format!("Couldn't read '{}'", path.display()))? {
+        Some(line) => line,
+        None => bail!("'{}' is empty", path.display())
+    };
+
+    parse_spec::parse_with_spans(&line, ParseOptions { require_test_marker: false })
+        .map_err(|e| anyhow!("{}", e.render(path, 1, &line)))
+}
+
+/// Reads the lines with a file's spec: its first line, and any '//test' lines
+/// right after it, since long specs can be split over several. Empty if the file is
+fn read_test_lines(path: &Path) -> Result<Vec<String>> {
+    let file = File::open(path).context("Couldn't open it")?;
+    let mut reader = BufReader::new(file);
+    let mut lines = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line).context("Couldn't read it")?;
+        let line = String::from_utf8_lossy(&line);
+        let is_test_line = line.trim_start().starts_with("//test");
+        if line.is_empty() || (!lines.is_empty() && !is_test_line) {
+            break
+        }
+
+        lines.push(line.trim_end_matches(['\n', '\r']).to_string());
+        if !is_test_line {
+            break
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Parses the '//test' lines of a file, which make up one spec together. Fails with the
+/// line the error is on, starting from 1, or 'NotSpec' if the first isn't a '//test' line
+fn parse_test_lines(path: &Path, lines: &[String]) -> Result<(Annotations, Specs, SpecOrigin), (usize, parse_spec::SpecParseError)> {
+    let mut annotations = Annotations::default();
+    let mut specs = Vec::new();
+    let mut spans = Vec::new();
+    for (line, lineno) in lines.iter().zip(1usize..) {
+        let (line_annotations, line_specs, line_spans) = parse_spec::parse_with_spans(line, ParseOptions { require_test_marker: true })
+            .map_err(|e| (lineno, e))?;
+        annotations.merge(line_annotations);
+        specs.extend(line_specs);
+        spans.extend(line_spans.into_iter().map(|span| (lineno, span)));
+    }
+
+    Ok((annotations, specs, SpecOrigin { file: path.to_path_buf(), spans }))
+}
+
+/// Reads the line with a file's spec. Student code isn't always UTF-8
+/// (e.g. comments saved as Latin-1), which doesn't matter for the spec
+fn read_first_line(path: &Path) -> Result<Option<String>> {
+    let file = File::open(path).context("Couldn't open it")?;
+    let mut line = Vec::new();
+    BufReader::new(file).read_until(b'\n', &mut line).context("Couldn't read it")?;
+
+    if line.is_empty() {
+        return Ok(None)
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+}
+
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod discover_tests {
     use super::*;
+    use crate::test_util::TempDir;
 
     use std::env;
 
     #[test]
     fn test() -> Result<()> {
         let testdir = env::var("C0_HOME")?;
-        let tests = discover(&Path::new(&format!("{}/tests/", testdir)))?;
+        let tests = discover(Path::new(&format!("{}/tests/", testdir)), &Exclusions::default())?;
 
         assert_eq!(tests.len(), 3761);
 
         Ok(())
     }
+
+    #[test]
+    fn test_symlinks() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let base = TempDir::new("symlinks");
+        fs::create_dir_all(base.join("real"))?;
+        fs::create_dir_all(base.join("shared"))?;
+        fs::write(base.join("real/a.c0"), "//test return 0\n")?;
+        fs::write(base.join("shared/b.c0"), "//test return 1\n")?;
+
+        // A suite linked under another name, a file linked into another suite,
+        // a link back to the root, and two links to each other
+        symlink("real", base.join("alias"))?;
+        symlink("../shared/b.c0", base.join("real/b.c0"))?;
+        symlink(".", base.join("root"))?;
+        symlink("loop2", base.join("loop1"))?;
+        symlink("loop1", base.join("loop2"))?;
+
+        let mut names: Vec<String> = discover(&base, &Exclusions::default())?.iter().map(TestInfo::name).collect();
+        names.sort();
+        assert_eq!(names, vec!["real/a.c0", "shared/b.c0"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_files() -> Result<()> {
+        let dir = TempDir::new("spec-files");
+        fs::write(dir.join("generated.c0"), "int main() { return 3; }\n")?;
+        fs::write(dir.join("generated.c0.spec"), "@compile_timeout(60) return 3\n")?;
+        fs::write(dir.join("marked.c0"), "//test return 1\nint main() { return 1; }\n")?;
+        fs::write(dir.join("marked.c0.spec"), "return 2\n")?;
+        fs::write(dir.join("broken.c0"), "int main() { return 0; }\n")?;
+        fs::write(dir.join("broken.c0.spec"), "return =>\n")?;
+
+        let mut tests = read_test_files(&dir)?;
+        tests.sort_by_key(TestInfo::name);
+        let specs: Vec<String> = tests.iter().map(TestInfo::spec_line).collect();
+        assert_eq!(specs, vec!["@compile_timeout(60) return 3", "return 1"]);
+        assert!(take_warnings().iter().any(|warning| warning.contains("broken.c0")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expect_files() -> Result<()> {
+        let dir = TempDir::new("expect-files");
+        fs::write(dir.join("sidecar.c0"), "//test return 0\nint main() { return 0; }\n")?;
+        fs::write(dir.join("sidecar.c0.expect"), "hi\n")?;
+        fs::write(dir.join("named.c0"), "//test return 0; expects \"golden.txt\"\nint main() { return 0; }\n")?;
+        fs::write(dir.join("named.c0.expect"), "hi\n")?;
+        fs::write(dir.join("plain.c0"), "//test return 0\nint main() { return 0; }\n")?;
+
+        let mut tests = discover_directory(&dir, SpecConflicts::Sources)?;
+        tests.sort_by_key(TestInfo::name);
+        let specs: Vec<String> = tests.iter().map(TestInfo::spec_line).collect();
+        assert_eq!(specs, vec!["return 0; expects \"golden.txt\"", "return 0", "return 0; expects \"sidecar.c0.expect\""]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_lines() -> Result<()> {
+        let dir = TempDir::new("spec-lines");
+        fs::write(dir.join("a.c0"), "//test @compile_timeout(5) safe => segfault\n//test @compile_timeout(60) !safe => return 0\n//test output exact \"hi\"\n// not a spec\n//test return 1\n")?;
+
+        let tests = discover_directory(&dir, SpecConflicts::Sources)?;
+        assert_eq!(tests[0].spec_line(), "@compile_timeout(60) safe => segfault; !safe => return 0; output exact \"hi\"");
+        let lines: Vec<usize> = (0..3).map(|spec| tests[0].origin.as_ref().unwrap().location(spec).unwrap().line).collect();
+        assert_eq!(lines, vec![1, 2, 3]);
+
+        // Errors say which line they're on
+        fs::write(dir.join("a.c0"), "//test return 0\n//test retrn 1\n")?;
+        let lines = read_test_lines(&dir.join("a.c0"))?;
+        assert!(matches!(parse_test_lines(&dir.join("a.c0"), &lines), Err((2, _))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_scan() -> Result<()> {
+        let base = TempDir::new("describe-scan");
+        fs::write(base.join("a.c0"), "//test return 0\n")?;
+        assert_eq!(describe_scan(&base, &Exclusions::default()),
+            vec![format!("{}: has no test directories, e.g. '{}'", base.display(), base.join("basic").display())]);
+
+        fs::create_dir_all(base.join("docs"))?;
+        fs::create_dir_all(base.join("img"))?;
+        let dirs = vec![String::from("img")];
+        let lines = describe_scan(&base, &Exclusions { dirs: &dirs, ..Exclusions::default() });
+        assert_eq!(lines, vec![
+            format!("{}: not a directory", base.join("a.c0").display()),
+            format!("{}: has no sources.test, .c0 or .c1 files", base.join("docs").display()),
+            format!("{}: left out by --exclude-dir", base.join("img").display())
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spec_conflicts() -> Result<()> {
+        let dir = TempDir::new("spec-conflicts");
+        fs::write(dir.join("a.c0"), "//test return 1\nint main() { return 1; }\n")?;
+        fs::write(dir.join("b.c0"), "//test return 2\nint main() { return 2; }\n")?;
+        fs::write(dir.join("sources.test"), "return 1 ~ a.c0\nreturn 3 ~ b.c0\nreturn 4 ~ b.c0 -d\n")?;
+
+        let specs = |spec_conflicts| -> Result<Vec<String>> {
+            Ok(discover_directory(&dir, spec_conflicts)?.iter().map(TestInfo::spec_line).collect())
+        };
+        assert_eq!(specs(SpecConflicts::Sources)?, vec!["return 1", "return 3", "return 4"]);
+        assert_eq!(specs(SpecConflicts::Inline)?, vec!["return 1", "return 2", "return 4"]);
+        let error = format!("{:#}", specs(SpecConflicts::Error).unwrap_err());
+        assert!(error.contains("'return 3' in sources.test, but its //test line is 'return 2'"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclusions() -> Result<()> {
+        let base = TempDir::new("exclusions");
+        for (suite, file) in [("img", "draw.c0"), ("basic", "ok.c0"), ("basic", "slow.c0")].iter() {
+            fs::create_dir_all(base.join(suite))?;
+            fs::write(base.join(suite).join(file), "//test return 0\n")?;
+        }
+
+        let dirs = vec![String::from("img")];
+        let tests = vec!["basic/slow*".parse()?];
+        let names: Vec<String> = discover(&base, &Exclusions { dirs: &dirs, tests: &tests, ..Exclusions::default() })?.iter().map(TestInfo::name).collect();
+        assert_eq!(names, vec!["basic/ok.c0"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_utf8() -> Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let base = TempDir::new("non-utf8");
+        let suite = base.join(OsStr::from_bytes(b"caf\xe9"));
+        fs::create_dir_all(&suite)?;
+        fs::write(suite.join(OsStr::from_bytes(b"na\xefve.c0")), b"//test return 0\n// \xe9t\xe9\n")?;
+        fs::write(suite.join("lit.c0"), b"// RUN: %cc0 %s\n// r\xe9sum\xe9\n")?;
+
+        let tests = discover(&base, &Exclusions::default())?;
+        assert_eq!(tests.len(), 2);
+        assert!(tests.iter().all(|test| test.execution.sources[0].starts_with(&suite)));
+
+        Ok(())
+    }
 }
\ No newline at end of file
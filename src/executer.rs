@@ -1,6 +1,16 @@
+use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use anyhow::Result;
 
 use crate::spec::*;
+use crate::launcher::Execution;
+use crate::host_tools;
 
 pub struct ExecuterProperties {
     pub libraries: bool,
@@ -11,29 +21,232 @@ pub struct ExecuterProperties {
 }
 
 impl ExecuterProperties {
-    /// Checks if the given predicate is true for this executer
-    pub fn matches_predicate(&self, predicate: &ImplementationPredicate) -> bool {
+    /// Checks if the given predicate is true for this executer,
+    /// running a test with the given compiler options
+    pub fn matches_predicate(&self, predicate: &ImplementationPredicate, compiler_options: &[String]) -> bool {
+        self.trace_predicate(predicate, compiler_options, &mut Vec::new())
+    }
+
+    /// Like 'matches_predicate', but also records the value of each
+    /// property it looked at (e.g. "gc = true"), for --trace-specs
+    pub fn trace_predicate(&self, predicate: &ImplementationPredicate, compiler_options: &[String], consulted: &mut Vec<String>) -> bool {
         use ImplementationPredicate::*;
-        match predicate {
+        let value = match predicate {
+            False => return false,
+            Not(p) => return !self.trace_predicate(p, compiler_options, consulted),
+            And(p1, p2) => return self.trace_predicate(p1, compiler_options, consulted) && self.trace_predicate(p2, compiler_options, consulted),
+            Or(p1, p2) => return self.trace_predicate(p1, compiler_options, consulted) || self.trace_predicate(p2, compiler_options, consulted),
+
             Library => self.libraries,
             Typechecked => self.typechecked,
             GarbageCollected => self.garbage_collected,
             Safe => self.safe,
-            False => false,
             ImplementationName(name) => self.name == name,
-    
-            Not(p) => !self.matches_predicate(p),
-            And(p1, p2) => self.matches_predicate(p1) && self.matches_predicate(p2),
-            Or(p1, p2) => self.matches_predicate(p1) || self.matches_predicate(p2),
+            Requires(tool) => host_tools::is_available(tool),
+            Opt(flag) => compiler_options.iter().any(|option| option == flag)
+        };
+        consulted.push(format!("{} = {}", predicate, value));
+        value
+    }
+}
+
+/// Everything observed from running a test
+pub struct TestRun {
+    /// Output from the test program, or from the compiler
+    /// if compilation failed
+    pub output: String,
+    /// Output from a successful compilation (e.g. warnings).
+    /// 'None' if the executer doesn't compile tests separately
+    pub compiler_output: Option<String>,
+    pub behavior: Behavior,
+    /// Size in bytes of the compiled executable or bytecode, if there was one
+    pub artifact_size: Option<u64>,
+    /// CPU time spent running the test program, if it was run
+    pub run_time: Option<Duration>,
+    /// How the test program's process ended, if it was run
+    pub raw_status: Option<RawStatus>,
+    /// Anything noteworthy that doesn't affect the test result
+    pub notes: Vec<String>
+}
+
+impl TestRun {
+    /// A run which stopped because the test didn't compile
+    pub fn compile_error(output: String) -> TestRun {
+        TestRun::new(output, None, Behavior::CompileError)
+    }
+
+    pub fn new(output: String, compiler_output: Option<String>, behavior: Behavior) -> TestRun {
+        TestRun { output, compiler_output, behavior, artifact_size: None, run_time: None, raw_status: None, notes: Vec::new() }
+    }
+
+    pub fn from_execution(execution: Execution, compiler_output: Option<String>) -> TestRun {
+        TestRun {
+            run_time: Some(execution.cpu_time),
+            raw_status: Some(execution.status),
+            ..TestRun::new(execution.output, compiler_output, execution.behavior)
+        }
+    }
+}
+
+/// The result of the compilation step of a test
+pub enum Compilation {
+    /// The executer doesn't compile tests separately
+    NotNeeded,
+    /// Compilation failed, contains the compiler output
+    Failed(String),
+    Compiled(Artifact)
+}
+
+impl Compilation {
+    /// Runs a test using this compilation result. The same result can be
+    /// used to run any number of tests with the same sources and options
+    pub fn run<E: Executer + ?Sized>(&self, executer: &E, test: &TestExecutionInfo) -> Result<TestRun> {
+        match self {
+            Compilation::NotNeeded => executer.execute(test, None),
+            Compilation::Failed(output) => Ok(TestRun::compile_error(output.clone())),
+            Compilation::Compiled(artifact) => {
+                let run = executer.execute(test, Some(artifact))?;
+
+                let mut notes = artifact.notes.clone();
+                notes.extend(run.notes);
+                Ok(TestRun {
+                    compiler_output: Some(artifact.compiler_output.clone()),
+                    artifact_size: artifact.size,
+                    notes,
+                    ..run
+                })
+            }
         }
-    }    
+    }
+}
+
+/// A compiled executable or bytecode file. It is deleted
+/// once the artifact is dropped
+pub struct Artifact {
+    pub path: PathBuf,
+    /// Output from the compiler (e.g. warnings)
+    pub compiler_output: String,
+    /// Size in bytes of the file
+    pub size: Option<u64>,
+    /// Anything noteworthy found while compiling
+    pub notes: Vec<String>,
+    /// Other files or directories the compiler created alongside
+    /// the artifact, which should be deleted with it
+    pub extra_files: Vec<PathBuf>,
+    /// Which member of an executer pool compiled it, so it runs there too
+    pub pool_member: Option<usize>
+}
+
+impl Artifact {
+    pub fn new(path: PathBuf, compiler_output: String) -> Artifact {
+        let size = fs::metadata(&path).ok().map(|metadata| metadata.len());
+        Artifact { path, compiler_output, size, notes: Vec::new(), extra_files: Vec::new(), pool_member: None }
+    }
+
+    pub fn path_cstring(&self) -> CString {
+        CString::new(self.path.as_os_str().as_bytes()).unwrap()
+    }
+}
+
+/// Whether artifacts which can't be deleted are left alone (see --cleanup-failures)
+static IGNORE_CLEANUP_FAILURES: AtomicBool = AtomicBool::new(false);
+
+/// Artifacts which couldn't be deleted when they were dropped, to try again at the end of the run
+static FAILED_DELETIONS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Leaves artifacts which can't be deleted alone, without a warning or trying again
+pub fn ignore_cleanup_failures() {
+    IGNORE_CLEANUP_FAILURES.store(true, Ordering::Relaxed);
+}
+
+fn delete(path: &Path) -> io::Result<()> {
+    if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) }
+}
+
+/// Tries again to delete the artifacts which couldn't be deleted when they were
+/// dropped, e.g. because an NFS server still had them open. Returns the ones
+/// which still can't be, with why. Files which are gone by now were deleted
+pub fn retry_cleanup() -> Vec<(PathBuf, io::Error)> {
+    let failed = std::mem::take(&mut *FAILED_DELETIONS.lock().unwrap());
+    failed.into_iter()
+        .filter_map(|path| match delete(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Some((path, e)),
+            _ => None
+        })
+        .collect()
+}
+
+impl Drop for Artifact {
+    fn drop(&mut self) {
+        let ignore = IGNORE_CLEANUP_FAILURES.load(Ordering::Relaxed);
+        for path in std::iter::once(&self.path).chain(self.extra_files.iter()) {
+            // Something else (e.g. the test itself) may have deleted it already
+            if let Err(e) = delete(path) {
+                if !ignore && e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("❗ Couldn't delete '{}', so it's tried again at the end: {:#}", path.display(), e);
+                    FAILED_DELETIONS.lock().unwrap().push(path.clone());
+                }
+            }
+        }
+    }
 }
 
 pub trait Executer: Send + Sync {
-    /// How to run a test. 
-    /// Returns (Test output, Test actual behavior)
-    fn run_test(&self, test: &TestExecutionInfo) -> Result<(String, Behavior)>;
+    /// Compiles a test, if this executer needs to. The result only
+    /// depends on the test's sources and compiler options
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation>;
+
+    /// Runs a test. 'artifact' is the result of compiling it, if the
+    /// executer compiles tests
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun>;
+
+    /// The command (program followed by arguments) which compiles a test,
+    /// if this executer compiles tests. The output file name is made up
+    fn compile_command(&self, _test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        None
+    }
+
+    /// The behavior an exit code of this executer's runtime means, besides 0
+    /// (main returned) and 1 (the program failed, e.g. a contract). Codes
+    /// which don't have one, or an entry in suite.toml, are an 'unknown-exit'
+    fn exit_code(&self, _code: i32) -> Option<Behavior> {
+        None
+    }
+
+    /// The binaries this executer runs, so that cached
+    /// results can be thrown out when they change
+    fn toolchain(&self) -> Vec<PathBuf>;
 
     /// Gets the properties of this executer
     fn properties(&self) -> ExecuterProperties;
 }
+
+#[cfg(test)]
+mod executer_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_retry_cleanup() -> Result<()> {
+        let dir = TempDir::new("cleanup");
+        fs::create_dir_all(dir.join("a.out.dSYM"))?;
+        fs::write(dir.join("a.out"), "")?;
+
+        // As if the first attempts failed, e.g. on NFS. One of them is gone since
+        let paths = [dir.join("a.out"), dir.join("a.out.dSYM"), dir.join("gone.bc0")];
+        FAILED_DELETIONS.lock().unwrap().extend(paths.iter().cloned());
+        let failures = retry_cleanup();
+        assert!(!failures.iter().any(|(path, _)| paths.contains(path)));
+        assert!(fs::read_dir(&dir)?.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_deleted() {
+        // An artifact which is already gone isn't tried again
+        let dir = TempDir::new("deleted");
+        let path = dir.join("gone.bc0");
+        drop(Artifact::new(path.clone(), String::new()));
+        assert!(!FAILED_DELETIONS.lock().unwrap().contains(&path));
+    }
+}
@@ -0,0 +1,178 @@
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use anyhow::{anyhow, Context, Error, Result};
+
+use crate::executer::{Artifact, Compilation, Executer, ExecuterProperties, TestRun};
+use crate::spec::*;
+
+/// A member of an executer pool (see --pool): how many tests it takes relative
+/// to the others, and the C0 distribution it uses if not --c0-home
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolMember {
+    pub weight: usize,
+    pub c0_home: Option<PathBuf>
+}
+
+impl FromStr for PoolMember {
+    type Err = Error;
+
+    /// Parses '<weight>' or '<weight>:<c0-home>'
+    fn from_str(member: &str) -> Result<PoolMember> {
+        let (weight, c0_home) = match member.split_once(':') {
+            Some((weight, c0_home)) => (weight, Some(PathBuf::from(c0_home))),
+            None => (member, None)
+        };
+
+        match weight.trim().parse::<usize>() {
+            Ok(weight) if weight > 0 => Ok(PoolMember { weight, c0_home }),
+            Ok(_) => Err(anyhow!("Pool members need a weight of at least 1")),
+            Err(e) => Err(e).context(format!("Invalid weight '{}', which should be written as <weight>[:<c0-home>]", weight))
+        }
+    }
+}
+
+/// Several executers of the same kind which share a run. Each test goes to
+/// the member with the fewest tests running for its weight, and runs on the
+/// same member it was compiled on
+pub struct ExecuterPool {
+    members: Vec<(Box<dyn Executer>, usize)>,
+    /// How many compilations and runs each member is busy with
+    running: Mutex<Vec<usize>>
+}
+
+/// A member's share of 'running', which is given back when it's dropped,
+/// even if running the test panicked
+struct Busy<'a> {
+    pool: &'a ExecuterPool,
+    member: usize
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.pool.running.lock().unwrap()[self.member] -= 1;
+    }
+}
+
+impl ExecuterPool {
+    /// Takes each executer with its weight. There has to be at least one
+    pub fn new(members: Vec<(Box<dyn Executer>, usize)>) -> ExecuterPool {
+        assert!(!members.is_empty(), "an executer pool needs members");
+        let running = Mutex::new(vec![0; members.len()]);
+        ExecuterPool { members, running }
+    }
+
+    /// Runs part of a test on a member, picking the least loaded one if it doesn't matter which
+    fn on_member<T>(&self, member: Option<usize>, run: impl FnOnce(usize, &dyn Executer) -> T) -> T {
+        let busy = {
+            let mut running = self.running.lock().unwrap();
+            let weights: Vec<usize> = self.members.iter().map(|(_, weight)| *weight).collect();
+            let member = member.unwrap_or_else(|| least_loaded(&running, &weights));
+            running[member] += 1;
+            Busy { pool: self, member }
+        };
+
+        run(busy.member, &*self.members[busy.member].0)
+    }
+}
+
+impl Executer for ExecuterPool {
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
+        self.on_member(None, |member, executer| match executer.compile(test)? {
+            Compilation::Compiled(mut artifact) => {
+                artifact.pool_member = Some(member);
+                Ok(Compilation::Compiled(artifact))
+            },
+            compilation => Ok(compilation)
+        })
+    }
+
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun> {
+        let member = artifact.and_then(|artifact| artifact.pool_member);
+        self.on_member(member, |_, executer| executer.execute(test, artifact))
+    }
+
+    fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        self.members[0].0.compile_command(test)
+    }
+
+    fn exit_code(&self, code: i32) -> Option<Behavior> {
+        self.members[0].0.exit_code(code)
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        self.members.iter().flat_map(|(executer, _)| executer.toolchain()).collect()
+    }
+
+    fn properties(&self) -> ExecuterProperties {
+        self.members[0].0.properties()
+    }
+}
+
+/// The member which would have the fewest tests for its weight with one more
+fn least_loaded(running: &[usize], weights: &[usize]) -> usize {
+    (0..running.len())
+        .min_by(|&a, &b| ((running[a] + 1) * weights[b]).cmp(&((running[b] + 1) * weights[a])))
+        .expect("an executer pool needs members")
+}
+
+#[cfg(test)]
+mod executer_pool_tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    /// Says which member it is in the output of every run
+    struct NamedExecuter(&'static str);
+
+    impl Executer for NamedExecuter {
+        fn compile(&self, _test: &TestExecutionInfo) -> Result<Compilation> {
+            let path = std::env::temp_dir().join(format!("c0check-pool-{}-{}", self.0, std::process::id()));
+            fs::write(&path, self.0)?;
+            Ok(Compilation::Compiled(Artifact::new(path, String::new())))
+        }
+
+        fn execute(&self, _test: &TestExecutionInfo, _artifact: Option<&Artifact>) -> Result<TestRun> {
+            Ok(TestRun::new(String::from(self.0), None, Behavior::Return(Some(0))))
+        }
+
+        fn toolchain(&self) -> Vec<PathBuf> {
+            vec![PathBuf::from(self.0)]
+        }
+
+        fn properties(&self) -> ExecuterProperties {
+            ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "named" }
+        }
+    }
+
+    #[test]
+    fn test() {
+        assert_eq!("2".parse::<PoolMember>().unwrap(), PoolMember { weight: 2, c0_home: None });
+        assert_eq!("1:/opt/c0".parse::<PoolMember>().unwrap(), PoolMember { weight: 1, c0_home: Some(PathBuf::from("/opt/c0")) });
+        assert!("0".parse::<PoolMember>().is_err());
+        assert!("/opt/c0".parse::<PoolMember>().is_err());
+
+        // Heavier members take more tests
+        assert_eq!(least_loaded(&[0, 0], &[1, 2]), 1);
+        assert_eq!(least_loaded(&[1, 1], &[1, 2]), 1);
+        assert_eq!(least_loaded(&[0, 3], &[1, 2]), 0);
+
+        // Tests run on the member which compiled them
+        let pool = ExecuterPool::new(vec![(Box::new(NamedExecuter("a")), 1), (Box::new(NamedExecuter("b")), 3)]);
+        let test = TestExecutionInfo::new(Vec::new(), Arc::from(Path::new("/tests")));
+        let compilation = pool.compile(&test).unwrap();
+        pool.running.lock().unwrap()[1] = 5;
+        let run = compilation.run(&pool, &test).unwrap();
+        assert_eq!(run.output, "b");
+        let run = pool.execute(&test, None).unwrap();
+        assert_eq!(run.output, "a");
+        assert_eq!(pool.toolchain(), vec![PathBuf::from("a"), PathBuf::from("b")]);
+
+        // A member isn't left busy by a panic
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pool.on_member(Some(0), |_, _| panic!("oops"))));
+        assert!(panicked.is_err());
+        assert_eq!(pool.running.lock().unwrap()[0], 0);
+    }
+}
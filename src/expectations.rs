@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use anyhow::{Context, Result};
+
+use crate::checker;
+use crate::executer::ExecuterProperties;
+use crate::spec::{Assertion, Behavior, TestInfo};
+
+/// Changes whenever the meaning of existing fields does
+const SCHEMA_VERSION: u32 = 1;
+
+/// What a test's specs come down to for one executer
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Expectation {
+    /// Both are empty if no specs apply, so the test isn't run
+    pub behaviors: Vec<String>,
+    pub assertions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip: Option<String>
+}
+
+impl Expectation {
+    pub fn new(test: &TestInfo, properties: &ExecuterProperties) -> Expectation {
+        let (behaviors, assertions) = checker::applicable_specs(test, properties);
+        Expectation {
+            behaviors: behaviors.iter().map(Behavior::to_string).collect(),
+            assertions: assertions.into_iter().map(Assertion::to_string).collect(),
+            skip: checker::skip_reason_for(properties, test).map(|(_, message)| message)
+        }
+    }
+}
+
+/// Every test's expectations for each executer, by test name and then executer name.
+/// Everything is sorted, so that snapshots of the same tests are identical
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    version: u32,
+    tests: BTreeMap<String, BTreeMap<&'static str, Expectation>>
+}
+
+impl Snapshot {
+    pub fn new(tests: &[TestInfo], executers: &[ExecuterProperties]) -> Snapshot {
+        let tests = tests.iter().map(|test| {
+            let expectations = executers.iter()
+                .map(|properties| (properties.name, Expectation::new(test, properties)))
+                .collect();
+            (test.name(), expectations)
+        }).collect();
+
+        Snapshot { version: SCHEMA_VERSION, tests }
+    }
+
+    /// Writes the snapshot as JSON to the given file, or prints it
+    pub fn write(&self, path: Option<&Path>) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)? + "\n";
+        match path {
+            Some(path) => fs::write(path, json).context(format!("Couldn't write '{}'", path.display())),
+            None => {
+                print!("{}", json);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod expectations_tests {
+    use super::*;
+    use crate::implementations::properties_of;
+    use crate::options::ExecuterKind;
+    use crate::parse_spec::{self, ParseOptions};
+    use crate::spec::*;
+
+    #[test]
+    fn test() {
+        let (_, specs) = parse_spec::parse("//test cc0_c0vm => skip \"slow\"; gc => return 0; output exact \"hi\"; !gc => infloop",
+            ParseOptions { require_test_marker: true }).unwrap();
+        let test = TestInfo::new(TestExecutionInfo::for_source("/tests/basic/a.c0"), specs);
+
+        let executers = [properties_of(&ExecuterKind::CC0), properties_of(&ExecuterKind::C0VM), properties_of(&ExecuterKind::Coin)];
+        let snapshot = Snapshot::new(&[test], &executers);
+        let expectations = &snapshot.tests["basic/a.c0"];
+        assert_eq!(expectations["cc0"], Expectation {
+            behaviors: vec![String::from("return 0")],
+            assertions: vec![String::from("output exact \"hi\"")],
+            skip: None
+        });
+        assert_eq!(expectations["cc0_c0vm"].skip.as_deref(), Some("slow"));
+        assert_eq!(expectations["coin"].behaviors, vec!["infloop"]);
+
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["version"], SCHEMA_VERSION);
+        assert!(json["tests"]["basic/a.c0"]["cc0"].get("skip").is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use regex::Regex;
+
+use crate::spec::TestInfo;
+
+/// Tests which failed in effectively the same way
+pub struct FailureGroup<'a> {
+    pub tests: Vec<&'a TestInfo>,
+    /// The failure of the first test in the group
+    pub message: String
+}
+
+/// Groups failures whose messages only differ in what's specific to their
+/// test, e.g. file names and source positions, in order of first appearance
+pub fn group_failures<'a>(failures: impl Iterator<Item = (&'a TestInfo, String)>) -> Vec<FailureGroup<'a>> {
+    let normalizer = Normalizer::new();
+    let mut groups: Vec<FailureGroup<'a>> = Vec::new();
+    let mut group_indices: HashMap<String, usize> = HashMap::new();
+
+    for (test, message) in failures {
+        let signature = normalizer.signature(test, &message);
+        match group_indices.get(&signature) {
+            Some(&i) => groups[i].tests.push(test),
+            None => {
+                group_indices.insert(signature, groups.len());
+                groups.push(FailureGroup { tests: vec![test], message });
+            }
+        }
+    }
+
+    groups
+}
+
+struct Normalizer {
+    /// Source positions from cc0 ('3.4-3.9') or gcc (':3:4'), or of specs (':3:4-9')
+    positions: Regex,
+    addresses: Regex
+}
+
+impl Normalizer {
+    fn new() -> Normalizer {
+        Normalizer {
+            positions: Regex::new(r":?\d+\.\d+-\d+\.\d+|:\d+:\d+-\d+|:\d+(:\d+)?").unwrap(),
+            addresses: Regex::new(r"0x[0-9a-fA-F]+").unwrap()
+        }
+    }
+
+    /// A failure message with the test's file names, source positions, and addresses taken out
+    fn signature(&self, test: &TestInfo, message: &str) -> String {
+        let mut signature = message.to_string();
+        for source in test.execution.sources.iter() {
+            signature = signature.replace(&*source.to_string_lossy(), "<file>");
+            if let Some(name) = source.file_name() {
+                signature = signature.replace(&*name.to_string_lossy(), "<file>");
+            }
+        }
+
+        let signature = self.positions.replace_all(&signature, "<pos>");
+        self.addresses.replace_all(&signature, "<address>").into_owned()
+    }
+}
+
+/// Prints each group, with the message once for groups of more than one test
+pub fn print_groups(groups: &[FailureGroup], icon: &str, verb: &str) {
+    for group in groups.iter() {
+        match group.tests.as_slice() {
+            [test] => println!("{} {}\n{}", icon, test, group.message),
+            tests => {
+                println!("{} {} tests {} with: {}", icon, tests.len(), verb, group.message.trim_end());
+                for test in tests.iter() {
+                    println!("    {}", test);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod failure_groups_tests {
+    use super::*;
+    use std::path::Path;
+    use crate::spec::*;
+
+    fn test_named(name: &str) -> TestInfo {
+        TestInfo::new(TestExecutionInfo::for_source(Path::new("/tests").join(name)), Vec::new())
+    }
+
+    #[test]
+    fn test_group_failures() {
+        let (a, b, c) = (test_named("a.c0"), test_named("b.c0"), test_named("c.c0"));
+        let failures = vec![
+            (&a, String::from("expected return 0, got error\n/tests/a.c0:3.4-3.9:error: undefined reference to c0_string_join")),
+            (&b, String::from("expected return 1, got return 2 (spec at sources.test:2:1-8)")),
+            (&c, String::from("expected return 0, got error\nc.c0:10.1-10.20:error: undefined reference to c0_string_join"))
+        ];
+
+        let groups = group_failures(failures.into_iter());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].tests.len(), 2);
+        assert!(groups[0].message.contains("a.c0"));
+        assert_eq!(groups[1].tests.len(), 1);
+
+        let failures = vec![
+            (&a, String::from("expected return 1, got return 2 (spec at sources.test:1:1-8)")),
+            (&b, String::from("expected return 1, got return 2 (spec at sources.test:12:10-17)"))
+        ];
+        assert_eq!(group_failures(failures.into_iter()).len(), 1);
+    }
+}
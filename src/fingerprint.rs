@@ -0,0 +1,169 @@
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use anyhow::{Result, Context};
+
+use crate::executer::Executer;
+use crate::spec::TestExecutionInfo;
+
+/// Hashes everything the compilation result of a test depends on: its
+/// source files (both paths and contents), compiler and link options,
+/// and compilation limits. Tests with the same key can share a compilation
+pub fn compilation_key(test: &TestExecutionInfo) -> Result<String> {
+    relative_compilation_key(test, Path::new(""))
+}
+
+/// Like 'compilation_key', but with the sources' paths taken relative to 'base'
+/// (the test directory), so that the same tests checked out elsewhere get the same key
+pub fn relative_compilation_key(test: &TestExecutionInfo, base: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+
+    for source in test.sources.iter() {
+        let contents = fs::read(source).context(format!("Couldn't read '{}'", source.display()))?;
+        let path = source.strip_prefix(base).unwrap_or(source);
+        // Length prefixes keep adjacent fields from running together
+        hash_field(&mut hasher, path.as_os_str().as_bytes());
+        hash_field(&mut hasher, &contents);
+    }
+
+    hasher.update(b"options");
+    for option in test.link_options.iter().chain(test.compiler_options.iter()) {
+        hash_field(&mut hasher, option.as_bytes());
+    }
+
+    let limits = test.compilation_limits;
+    hash_field(&mut hasher, format!("{:?} {:?}", limits.time, limits.memory).as_bytes());
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hashes what identifies a test: the paths of its sources and its compiler
+/// options. Unlike 'compilation_key', it doesn't change when the sources do
+pub fn test_key(test: &TestExecutionInfo) -> String {
+    let mut hasher = Sha256::new();
+    for source in test.sources.iter() {
+        hash_field(&mut hasher, source.as_os_str().as_bytes());
+    }
+
+    hasher.update(b"options");
+    for option in test.compiler_options.iter() {
+        hash_field(&mut hasher, option.as_bytes());
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Fingerprints the binaries an executer runs by their names and contents alone, so that
+/// reinstalling or copying the same toolchain (e.g. on CI) gives the same key
+pub fn toolchain_contents_key(executer: &dyn Executer) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(executer.properties().name);
+
+    for path in executer.toolchain() {
+        let name = path.file_name().unwrap_or_else(|| path.as_os_str());
+        hash_field(&mut hasher, name.as_bytes());
+        hash_field(&mut hasher, file_hash(&path)?.as_bytes());
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Gets the SHA-256 hash of a file's contents as a hex string
+pub fn file_hash(path: &Path) -> Result<String> {
+    let contents = fs::read(path).context(format!("Couldn't read '{}'", path.display()))?;
+    Ok(format!("{:x}", Sha256::digest(&contents)))
+}
+
+fn hash_field(hasher: &mut Sha256, field: &[u8]) {
+    hasher.update((field.len() as u64).to_le_bytes());
+    hasher.update(field);
+}
+
+#[cfg(test)]
+mod fingerprint_tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::executer::{Artifact, Compilation, ExecuterProperties, TestRun};
+    use crate::spec::ResourceLimits;
+    use crate::test_util::TempDir;
+
+    /// An executer which is only good for fingerprinting its toolchain
+    struct Toolchain(PathBuf);
+
+    impl Executer for Toolchain {
+        fn compile(&self, _test: &TestExecutionInfo) -> Result<Compilation> {
+            Ok(Compilation::NotNeeded)
+        }
+
+        fn execute(&self, _test: &TestExecutionInfo, _artifact: Option<&Artifact>) -> Result<TestRun> {
+            anyhow::bail!("This executer can't run tests")
+        }
+
+        fn toolchain(&self) -> Vec<PathBuf> {
+            vec![self.0.clone()]
+        }
+
+        fn properties(&self) -> ExecuterProperties {
+            ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "cc0" }
+        }
+    }
+
+    fn test_info(sources: Vec<PathBuf>, compiler_options: Vec<String>) -> TestExecutionInfo {
+        TestExecutionInfo { compiler_options, ..TestExecutionInfo::new(sources, Arc::from(Path::new(""))) }
+    }
+
+    #[test]
+    fn test() {
+        let dir = TempDir::new("fingerprint");
+        let source = dir.join("a.c0");
+        let source_name = source.clone();
+
+        fs::File::create(&source).unwrap().write_all(b"int main() { return 0; }").unwrap();
+        let key = compilation_key(&test_info(vec![source_name.clone()], vec![])).unwrap();
+        assert_eq!(key, compilation_key(&test_info(vec![source_name.clone()], vec![])).unwrap());
+        assert_ne!(key, compilation_key(&test_info(vec![source_name.clone()], vec!["-d".to_string()])).unwrap());
+
+        let limited = TestExecutionInfo {
+            compilation_limits: ResourceLimits { time: Some(5), memory: None },
+            ..test_info(vec![source_name.clone()], vec![])
+        };
+        assert_ne!(key, compilation_key(&limited).unwrap());
+
+        // Changing the file changes the key, but not the test's identity
+        let id = test_key(&test_info(vec![source_name.clone()], vec![]));
+        fs::File::create(&source).unwrap().write_all(b"int main() { return 1; }").unwrap();
+        assert_ne!(key, compilation_key(&test_info(vec![source_name.clone()], vec![])).unwrap());
+        assert_eq!(id, test_key(&test_info(vec![source_name.clone()], vec![])));
+        assert_ne!(id, test_key(&test_info(vec![source_name], vec!["-d".to_string()])));
+
+        assert!(compilation_key(&test_info(vec![dir.join("missing.c0")], vec![])).is_err());
+
+    }
+
+    #[test]
+    fn test_toolchain() -> Result<()> {
+        let dir = TempDir::new("toolchain");
+        let cc0 = Toolchain(dir.join("cc0"));
+        fs::write(&cc0.0, "v1")?;
+        let contents_key = toolchain_contents_key(&cc0)?;
+
+        // Reinstalling the same binary only changes its modification time
+        fs::File::options().write(true).open(&cc0.0)?.set_modified(UNIX_EPOCH + Duration::from_secs(1))?;
+        assert_eq!(contents_key, toolchain_contents_key(&cc0)?);
+
+        // Nor does copying it somewhere else, e.g. another runner's C0_HOME
+        let copy = Toolchain(dir.join("copy").join("cc0"));
+        fs::create_dir_all(dir.join("copy"))?;
+        fs::copy(&cc0.0, &copy.0)?;
+        assert_eq!(contents_key, toolchain_contents_key(&copy)?);
+
+        fs::write(&cc0.0, "v2")?;
+        assert_ne!(contents_key, toolchain_contents_key(&cc0)?);
+
+        Ok(())
+    }
+}
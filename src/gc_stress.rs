@@ -0,0 +1,120 @@
+use rayon::prelude::*;
+use anyhow::{anyhow, Result};
+
+use crate::checker::{self, Checker, TestOutcome, TestResult};
+use crate::executer::Executer;
+use crate::hooks::HookRunner;
+use crate::spec::*;
+
+/// Memory limits are found to within this many bytes,
+/// or to within 2% if that's larger
+const MIN_PRECISION: u64 = 256 * 1024;
+
+/// The smallest memory limit a test passed with
+pub struct MemoryResult<'a> {
+    pub test: &'a TestInfo,
+    pub min_memory: Result<u64>
+}
+
+/// Whether any of a test's specs depend on garbage collection
+pub fn is_gc_test(test: &TestInfo) -> bool {
+    test.specs.iter().any(spec_mentions_gc)
+}
+
+fn spec_mentions_gc(spec: &Spec) -> bool {
+    match spec {
+        Spec::Implication(predicate, consequent) => predicate_mentions_gc(predicate) || spec_mentions_gc(consequent),
+        Spec::ExpectedFailure(consequent) => spec_mentions_gc(consequent),
+        _ => false
+    }
+}
+
+fn predicate_mentions_gc(predicate: &ImplementationPredicate) -> bool {
+    use ImplementationPredicate::*;
+    match predicate {
+        GarbageCollected => true,
+        Not(p) => predicate_mentions_gc(p),
+        And(p, q) | Or(p, q) => predicate_mentions_gc(p) || predicate_mentions_gc(q),
+        _ => false
+    }
+}
+
+/// Finds the smallest memory limit each test passes with
+pub fn stress_tests<'a>(
+    checker: &Checker,
+    hooks: &HookRunner,
+    executer: &dyn Executer,
+    tests: &[&'a TestInfo],
+    max_memory: u64) -> Vec<MemoryResult<'a>>
+{
+    tests.par_iter().map(|&test| {
+        let max_memory = test.execution.run_limits.memory.unwrap_or(max_memory);
+        let (min_memory, _) = hooks.around_test(test, || find_min_memory(checker, executer, test, max_memory));
+        let min_memory = min_memory.unwrap_or_else(|| Err(anyhow!("Its before hook failed")));
+
+        match &min_memory {
+            Ok(memory) => eprintln!("🧹 {}: {}", test.name(), format_memory(*memory)),
+            Err(error) => eprintln!("⛔ {}: {:#}", test.name(), error)
+        }
+
+        MemoryResult { test, min_memory }
+    }).collect()
+}
+
+/// Binary searches for the smallest memory limit (up to 'max_memory', which
+/// it should pass with) that a test passes with. The test is only compiled once
+fn find_min_memory(checker: &Checker, executer: &dyn Executer, test: &TestInfo, max_memory: u64) -> Result<u64> {
+    let compilation = checker::catch_panic(|| executer.compile(&test.execution))?;
+    let mut probe = test.clone();
+
+    // The test passes with 'high' but not with 'low'
+    let (mut low, mut high) = (0, max_memory);
+    while high - low > MIN_PRECISION.max(high / 50) {
+        let middle = low + (high - low) / 2;
+        probe.execution.run_limits.memory = Some(middle);
+
+        // Running out of memory can also show up as an error, e.g. if the runtime can't even start
+        match checker.run_compiled_test(executer, &probe, &compilation) {
+            Ok(TestOutcome { result: TestResult::Success, .. }) => high = middle,
+            _ => low = middle
+        }
+    }
+
+    Ok(high)
+}
+
+pub fn print_report(results: &[MemoryResult]) {
+    println!("\nGC stress (smallest memory limit each test passed with):\n");
+
+    for result in results.iter() {
+        match &result.min_memory {
+            Ok(memory) => println!("🧹 {}: {}", result.test.name(), format_memory(*memory)),
+            Err(error) => println!("⛔ {}: {:#}", result.test.name(), error)
+        }
+    }
+}
+
+pub fn format_memory(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024. * 1024.))
+}
+
+#[cfg(test)]
+mod gc_stress_tests {
+    use super::*;
+    use crate::parse_spec::{self, ParseOptions};
+
+    fn test_with_spec(spec: &str) -> TestInfo {
+        let (_, specs) = parse_spec::parse(spec, ParseOptions { require_test_marker: false }).unwrap();
+        TestInfo::new(TestExecutionInfo::for_source("/tests/gc/a.c0"), specs)
+    }
+
+    #[test]
+    fn test_is_gc_test() {
+        assert!(is_gc_test(&test_with_spec("gc => return 0")));
+        assert!(is_gc_test(&test_with_spec("safe => !gc => infloop; return 0")));
+        assert!(is_gc_test(&test_with_spec("cc0 or gc => return 0")));
+        assert!(is_gc_test(&test_with_spec("xfail gc => return 0")));
+        assert!(!is_gc_test(&test_with_spec("safe => return 0")));
+        assert!(!is_gc_test(&test_with_spec("return 0")));
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+use serde::Deserialize;
+use anyhow::{bail, Error, Result};
+
+use crate::launcher::run_shell;
+use crate::options::Options;
+use crate::spec::TestInfo;
+use crate::suite_config::SuiteConfig;
+
+/// Shell commands to run before and after something, e.g. to generate
+/// large input files or start a server for a test to connect to
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub before: Option<String>,
+    pub after: Option<String>
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HookKind {
+    Before,
+    After
+}
+
+/// A hook which couldn't be run or exited unsuccessfully
+#[derive(Debug)]
+pub struct HookFailure {
+    /// Which hook it was, e.g. "before hook of suite 'basic'"
+    pub hook: String,
+    pub error: Error
+}
+
+/// Runs hooks with the same resource limits as compilation
+pub struct HookRunner {
+    time: u64,
+    memory: u64
+}
+
+impl Hooks {
+    pub fn get(&self, kind: HookKind) -> Option<&str> {
+        match kind {
+            HookKind::Before => self.before.as_deref(),
+            HookKind::After => self.after.as_deref()
+        }
+    }
+}
+
+impl HookRunner {
+    pub fn new(options: &Options) -> HookRunner {
+        HookRunner { time: options.compilation_time, memory: options.compilation_mem }
+    }
+
+    /// Runs a hook's command from the given directory
+    pub fn run(&self, hook: String, command: &str, dir: &Path) -> Result<(), HookFailure> {
+        let result = run_shell(command, dir, self.time, self.memory).and_then(|(success, output)| {
+            let output = output.trim_end();
+            match (success, output.is_empty()) {
+                (true, _) => Ok(()),
+                (false, true) => bail!("'{}' failed", command),
+                (false, false) => bail!("'{}' failed:\n{}", command, output)
+            }
+        });
+
+        result.map_err(|error| HookFailure { hook, error })
+    }
+
+    /// Runs a test between its before and after hooks. The
+    /// test isn't run at all if its before hook fails
+    pub fn around_test<T>(&self, test: &TestInfo, run: impl FnOnce() -> T) -> (Option<T>, Option<HookFailure>) {
+        let hooks = &test.execution.hooks;
+        let dir = &*test.execution.directory;
+        let run_hook = |kind: HookKind| match hooks.get(kind) {
+            Some(command) => self.run(format!("{} hook of {}", kind, test.name()), command, dir),
+            None => Ok(())
+        };
+
+        if let Err(failure) = run_hook(HookKind::Before) {
+            return (None, Some(failure))
+        }
+
+        let result = run();
+        (Some(result), run_hook(HookKind::After).err())
+    }
+
+    /// Runs the before or after hook of each suite, returning the failures
+    /// along with the directory of the suite they came from
+    pub fn run_suite_hooks(&self, suites: &[(Arc<Path>, Hooks)], kind: HookKind) -> Vec<(Arc<Path>, HookFailure)> {
+        let mut failures = Vec::new();
+
+        for (dir, hooks) in suites.iter() {
+            if let Some(command) = hooks.get(kind) {
+                let name = dir.file_name().unwrap_or_default().to_string_lossy();
+                if let Err(failure) = self.run(format!("{} hook of suite '{}'", kind, name), command, dir) {
+                    failures.push((dir.clone(), failure));
+                }
+            }
+        }
+
+        failures
+    }
+}
+
+/// Loads the hooks from the suite.toml of each directory that tests came from
+pub fn suite_hooks(tests: &[TestInfo]) -> Result<Vec<(Arc<Path>, Hooks)>> {
+    let mut seen: HashSet<&Path> = HashSet::new();
+    let mut suites = Vec::new();
+
+    for test in tests.iter() {
+        let dir = &test.execution.directory;
+        if seen.insert(dir) {
+            let config = SuiteConfig::load(dir)?;
+            if *config.hooks() != Hooks::default() {
+                suites.push((dir.clone(), config.hooks().clone()));
+            }
+        }
+    }
+
+    Ok(suites)
+}
+
+impl Display for HookKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HookKind::Before => write!(f, "before"),
+            HookKind::After => write!(f, "after")
+        }
+    }
+}
+
+impl Display for HookFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:#}", self.hook, self.error)
+    }
+}
+
+#[cfg(test)]
+mod hooks_tests {
+    use super::*;
+    use crate::spec::TestExecutionInfo;
+    use crate::test_util::TempDir;
+
+    fn test_with_hooks(dir: &Path, hooks: Hooks) -> TestInfo {
+        TestInfo::new(TestExecutionInfo { hooks, ..TestExecutionInfo::for_source(dir.join("a.c0")) }, Vec::new())
+    }
+
+    #[test]
+    fn test_around_test() -> Result<()> {
+        let dir = TempDir::new("hooks");
+        let runner = HookRunner { time: 10, memory: 1024 * 1024 * 1024 };
+
+        // Hooks run from the test's directory
+        let test = test_with_hooks(&dir, Hooks {
+            before: Some(String::from("echo hi > input.txt")),
+            after: Some(String::from("rm input.txt"))
+        });
+        let (result, failure) = runner.around_test(&test, || dir.join("input.txt").exists());
+        assert_eq!(result, Some(true));
+        assert!(failure.is_none());
+        assert!(!dir.join("input.txt").exists());
+
+        // A failing before hook stops the test from running
+        let test = test_with_hooks(&dir, Hooks { before: Some(String::from("echo oops; exit 1")), after: None });
+        let (result, failure) = runner.around_test(&test, || ());
+        assert!(result.is_none());
+        let failure = failure.unwrap().to_string();
+        assert!(failure.starts_with("before hook of"));
+        assert!(failure.contains("oops"));
+
+        // A failing after hook doesn't affect the result
+        let test = test_with_hooks(&dir, Hooks { before: None, after: Some(String::from("false")) });
+        let (result, failure) = runner.around_test(&test, || 5);
+        assert_eq!(result, Some(5));
+        assert!(failure.is_some());
+
+        Ok(())
+    }
+}
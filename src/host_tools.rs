@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Mutex;
+
+use crate::launcher::run_tool;
+use crate::spec::*;
+
+/// Asking pkg-config about a library should be quick
+const PKG_CONFIG_TIMEOUT: u64 = 10;
+
+/// Which tools 'requires(<tool>)' predicates have asked about, and whether
+/// this machine has them. Each tool is only probed once per run
+static AVAILABLE: Mutex<BTreeMap<String, bool>> = Mutex::new(BTreeMap::new());
+
+/// Whether this machine has a tool: a program in $PATH, or a library pkg-config knows
+pub fn is_available(tool: &str) -> bool {
+    if let Some(&available) = AVAILABLE.lock().unwrap().get(tool) {
+        return available
+    }
+
+    let available = probe(tool);
+    AVAILABLE.lock().unwrap().insert(tool.to_string(), available);
+    available
+}
+
+/// Probes every tool the tests' specs require up front, returning the ones which are missing
+pub fn probe_tests(tests: &[TestInfo]) -> Vec<String> {
+    let mut tools = Vec::new();
+    for test in tests.iter() {
+        for spec in test.specs.iter() {
+            let mut spec = spec;
+            while let Spec::Implication(predicate, consequent) = spec {
+                required_tools(predicate, &mut tools);
+                spec = consequent;
+            }
+        }
+    }
+
+    tools.sort();
+    tools.dedup();
+    tools.into_iter().filter(|tool| !is_available(tool)).collect()
+}
+
+/// Collects the tools a predicate mentions in 'requires(<tool>)'
+pub fn required_tools(predicate: &ImplementationPredicate, tools: &mut Vec<String>) {
+    use ImplementationPredicate::*;
+    match predicate {
+        Requires(tool) => tools.push(tool.clone()),
+        Not(p) => required_tools(p, tools),
+        And(p1, p2) | Or(p1, p2) => {
+            required_tools(p1, tools);
+            required_tools(p2, tools);
+        },
+        _ => ()
+    }
+}
+
+fn probe(tool: &str) -> bool {
+    if in_path(tool) {
+        return true
+    }
+
+    let args = [CString::new("--exists").unwrap(), match CString::new(tool) {
+        Ok(tool) => tool,
+        Err(_) => return false
+    }];
+    matches!(run_tool(CString::new("pkg-config").unwrap(), &args, PKG_CONFIG_TIMEOUT, u64::MAX), Ok((true, _)))
+}
+
+fn in_path(program: &str) -> bool {
+    let path = match env::var_os("PATH") {
+        Some(path) => path,
+        None => return false
+    };
+
+    env::split_paths(&path).any(|dir| match fs::metadata(dir.join(program)) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false
+    })
+}
+
+#[cfg(test)]
+mod host_tools_tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert!(is_available("sh"));
+        assert!(!is_available("c0check-no-such-tool"));
+
+        let mut tools = Vec::new();
+        required_tools(&ImplementationPredicate::And(
+            Box::new(ImplementationPredicate::Safe),
+            Box::new(ImplementationPredicate::Not(Box::new(ImplementationPredicate::Requires(String::from("gcc")))))), &mut tools);
+        assert_eq!(tools, vec![String::from("gcc")]);
+    }
+}
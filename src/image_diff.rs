@@ -0,0 +1,129 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use anyhow::{bail, Result};
+
+use crate::launcher::run_tool;
+
+/// Comparing big images can take a while
+const COMPARE_TIMEOUT: u64 = 60;
+
+/// Whether a file should be compared as an image rather than byte for byte
+pub fn is_image(path: &str) -> bool {
+    path.to_ascii_lowercase().ends_with(".png")
+}
+
+/// Compares two images pixel by pixel using ImageMagick's 'compare', so
+/// that differences in how they are encoded (e.g. between libpng versions)
+/// don't matter. Each pixel's color can be off by 'tolerance' percent.
+/// Returns how many pixels differ by more than that. If there are any,
+/// an image highlighting them is left at 'diff'
+pub fn compare_images(actual: &Path, expected: &Path, tolerance: u32, diff: &Path) -> Result<u64> {
+    let fuzz = format!("{}%", tolerance);
+    let args = [
+        path_arg("-metric"), path_arg("AE"), path_arg("-fuzz"), path_arg(&fuzz),
+        path_arg(actual), path_arg(expected), path_arg(diff)
+    ];
+    let (_, output) = run_tool(path_arg("compare"), &args, COMPARE_TIMEOUT, u64::MAX)?;
+
+    // 'compare' prints the number of differing pixels, or an error
+    // message if the images can't be compared (e.g. if their sizes differ)
+    let count = output.split_whitespace().next()
+        .and_then(|count| count.parse::<f64>().ok())
+        .map(|count| count as u64);
+    match count {
+        Some(0) => {
+            let _ = fs::remove_file(diff);
+            Ok(0)
+        },
+        Some(count) => Ok(count),
+        None => bail!("{}", output.trim())
+    }
+}
+
+fn path_arg<P: AsRef<Path>>(path: P) -> CString {
+    CString::new(path.as_ref().as_os_str().as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod image_diff_tests {
+    use super::*;
+    use crate::host_tools;
+    use crate::test_util::TempDir;
+
+    /// Encodes an RGB image as a PNG, with its pixel data stored uncompressed
+    fn png(width: u32, pixels: &[[u8; 3]]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for row in pixels.chunks(width as usize) {
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+
+        // A zlib stream of one stored deflate block
+        let mut zlib = vec![0x78, 0x01, 1];
+        zlib.extend((raw.len() as u16).to_le_bytes());
+        zlib.extend((!(raw.len() as u16)).to_le_bytes());
+        zlib.extend(&raw);
+        let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), &byte| {
+            let a = (a + byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        zlib.extend(((b << 16) | a).to_be_bytes());
+
+        let mut header = Vec::new();
+        header.extend(width.to_be_bytes());
+        header.extend((pixels.len() as u32 / width).to_be_bytes());
+        header.extend([8, 2, 0, 0, 0]);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+        for (kind, data) in [(b"IHDR", header), (b"IDAT", zlib), (b"IEND", Vec::new())] {
+            png.extend((data.len() as u32).to_be_bytes());
+            let chunk: Vec<u8> = kind.iter().chain(data.iter()).copied().collect();
+            png.extend(&chunk);
+            png.extend(crc32(&chunk).to_be_bytes());
+        }
+        png
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        !bytes.iter().fold(!0u32, |crc, &byte| {
+            (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 })
+        })
+    }
+
+    #[test]
+    fn test_is_image() {
+        assert!(is_image("golden/out.PNG"));
+        assert!(!is_image("out.png.txt"));
+    }
+
+    #[test]
+    fn test_compare_images() -> Result<()> {
+        if !host_tools::is_available("compare") {
+            eprintln!("Skipping, since ImageMagick's 'compare' isn't installed");
+            return Ok(())
+        }
+
+        let dir = TempDir::new("image-diff");
+        let (expected, actual, diff) = (dir.join("expected.png"), dir.join("actual.png"), dir.join("diff.png"));
+        let white = [255, 255, 255];
+        fs::write(&expected, png(2, &[white, white, white, [0, 0, 0]]))?;
+
+        // A pixel a little off is within 5%, but not within 0%
+        fs::write(&actual, png(2, &[white, [250, 250, 250], white, [0, 0, 0]]))?;
+        assert_eq!(compare_images(&actual, &expected, 5, &diff)?, 0);
+        assert!(!diff.exists());
+        assert_eq!(compare_images(&actual, &expected, 0, &diff)?, 1);
+        assert!(diff.exists());
+
+        // A pixel which is another color altogether is beyond it
+        fs::write(&actual, png(2, &[white, white, white, [255, 0, 0]]))?;
+        assert_eq!(compare_images(&actual, &expected, 5, &diff)?, 1);
+
+        // Images of different sizes can't be compared
+        fs::write(&actual, png(1, &[white, white]))?;
+        assert!(compare_images(&actual, &expected, 5, &diff).is_err());
+        Ok(())
+    }
+}
@@ -1,96 +1,227 @@
 #![allow(non_upper_case_globals)]
 
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::fs;
-use std::env;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::sync::atomic::{self, AtomicUsize};
-use std::ffi::CString;
+use std::ffi::{CString, OsStr};
 use std::os::unix::ffi::OsStrExt;
 use anyhow::{Result, Context};
  
 use crate::spec::*;
-use crate::executer::{Executer, ExecuterProperties};
+use crate::executer::{Artifact, Compilation, Executer, ExecuterProperties, TestRun};
 use crate::launcher::*;
 use crate::options::*;
+use crate::coverage::Coverage;
 
 pub struct CC0Executer {
     cc0_path: CString,
+    work_dir: PathBuf,
 
     cc0_memory: u64,
     cc0_time: u64,
 
     test_memory: u64,
-    test_time: u64
+    test_time: u64,
+
+    save_files: Option<PathBuf>,
+    check_c: Option<Vec<CString>>,
+    /// A lock for each file cc0 writes generated C to with --save-files, which is
+    /// named after the test's last source, so tests which share it take turns
+    generated_files: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+
+    debug_info: bool,
+    coverage: Option<Coverage>,
+    /// Whether cc0 leaves out its safety checks (--unsafe)
+    unsafe_mode: bool
 }
 
 impl CC0Executer {
-    pub fn new(options: &Options) -> Result<CC0Executer> {
-        let cc0_path = make_cstr_path(options.c0_home.clone(), &["bin", "cc0"])?;
+    pub fn new(options: &Options, work_dir: &Path) -> Result<CC0Executer> {
+        let cc0_path = make_cstr_path(options.c0_home().to_path_buf(), &["bin", "cc0"])?;
+
+        let save_files = match &options.save_files {
+            Some(dir) => {
+                fs::create_dir_all(dir).context(format!("Couldn't create '{}'", dir.display()))?;
+                Some(fs::canonicalize(dir)?)
+            },
+            None => None
+        };
+
+        let check_c = options.check_c.as_ref().map(|command| {
+            command.split_ascii_whitespace().map(str_to_cstring).collect()
+        });
 
         Ok(CC0Executer {
             cc0_path,
+            work_dir: work_dir.to_path_buf(),
 
             cc0_memory: options.compilation_mem,
             cc0_time: options.compilation_time,
 
             test_memory: options.test_memory,
-            test_time: options.test_time
+            test_time: options.test_time,
+
+            save_files,
+            check_c,
+            generated_files: Mutex::default(),
+
+            debug_info: !options.no_debug_info,
+            coverage: Coverage::new(options)?,
+            unsafe_mode: false
         })
     }
+
+    /// Uses a different cc0 binary than the one in C0_HOME
+    pub fn with_cc0(mut self, cc0: &Path) -> Result<CC0Executer> {
+        self.cc0_path = make_cstr_path(cc0.to_path_buf(), &[])?;
+        Ok(self)
+    }
+
+    /// Compiles tests without safety checks (e.g. array bounds), so only
+    /// specs which hold when the executer isn't 'safe' apply
+    pub fn with_unsafe(mut self) -> CC0Executer {
+        self.unsafe_mode = true;
+        self
+    }
+
+    /// Moves the C code CC0 generated for a test into the --save-files
+    /// directory, and checks it if requested. Returns notes about
+    /// the generated code
+    fn save_generated_c(&self, test: &TestExecutionInfo, save_dir: &Path, id: usize) -> Result<Vec<String>> {
+        // CC0 names the generated code after the last source file
+        let last_source = test.sources.last().context("Test has no source files")?;
+        let generated = PathBuf::from(format!("{}.c", last_source.display()));
+
+        let suite = test.directory.file_name().unwrap_or_default().to_string_lossy();
+        let source = last_source.file_name().unwrap_or_default().to_string_lossy();
+        let saved = save_dir.join(format!("{}-{}-{}.c", suite, source, id));
+
+        // The save directory might be on a different file system, so rename() won't work
+        fs::copy(&generated, &saved).context(format!("Couldn't save '{}'", generated.display()))?;
+        fs::remove_file(&generated).context(format!("Couldn't remove '{}'", generated.display()))?;
+
+        let command = match &self.check_c {
+            Some(command) if !command.is_empty() => command,
+            _ => return Ok(Vec::new())
+        };
+
+        let mut args: Vec<CString> = command[1..].to_vec();
+        args.push(path_to_cstring(&saved));
+
+        let (success, output) = run_tool(&command[0], &args, self.cc0_time, self.cc0_memory)?;
+        if success && output.trim().is_empty() {
+            Ok(Vec::new())
+        }
+        else {
+            Ok(vec![format!("Generated C '{}' {}:\n{}",
+                saved.display(),
+                if success { "has warnings" } else { "doesn't compile" },
+                output.trim_end())])
+        }
+    }
 }
 
-impl Executer for CC0Executer {
-    fn run_test(&self, test: &TestExecutionInfo) -> Result<(String, Behavior)> {
+impl CC0Executer {
+    /// Arguments to CC0 for compiling a test into 'out_file'
+    fn compile_args(&self, test: &TestExecutionInfo, out_file: &Path) -> Vec<CString> {
         let mut args: Vec<CString> = Vec::new();
+        args.extend(test.link_options.iter().map(string_to_cstring));
         args.extend(test.compiler_options.iter().map(string_to_cstring));
-        args.extend(test.sources.iter().map(string_to_cstring));
-        
+        args.extend(test.sources.iter().map(|source| path_to_cstring(source)));
+        args.push(str_to_cstring("-vo"));
+        args.push(path_to_cstring(out_file));
+
+        if self.save_files.is_some() {
+            args.push(str_to_cstring("--save-files"));
+        }
+
+        if self.unsafe_mode {
+            args.push(str_to_cstring("--unsafe"));
+        }
+
+        args
+    }
+}
+
+impl Executer for CC0Executer {
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
         // Global counter to come up with unique names for output files
         static test_counter: AtomicUsize = AtomicUsize::new(0);
 
-        let out_file: CString = {
-            let current_dir = env::current_dir().unwrap();
-            let next_id = test_counter.fetch_add(1, atomic::Ordering::Relaxed);
-            str_to_cstring(&format!("{}/a.out{}", current_dir.display(), next_id))
-        };
-        args.push(str_to_cstring("-vo"));
-        args.push(out_file.clone());
+        let next_id = test_counter.fetch_add(1, atomic::Ordering::Relaxed);
+        let out_file = self.work_dir.join(format!("a.out{}", next_id));
+        let args = self.compile_args(test, &out_file);
 
-        let compilation_result = compile(&self.cc0_path, &args, self.cc0_time, self.cc0_memory)?;
-        if let Err(output) = compilation_result {
-            return Ok((output, Behavior::CompileError))
-        }
-        
-        let exec_result = execute(test, &out_file, self.test_time, self.test_memory);
-        if let Err(e) = fs::remove_file(Path::new(&out_file.to_str().unwrap())) {
-            eprintln!("❗ Couldn't delete a.out file: {:#}", e);
+        // Held until the generated C has been moved out of the way
+        let generated_file = match (&self.save_files, test.sources.last()) {
+            (Some(_), Some(last_source)) => Some(self.generated_files.lock().unwrap().entry(last_source.clone()).or_default().clone()),
+            _ => None
+        };
+        let _turn = generated_file.as_ref().map(|lock| lock.lock().unwrap_or_else(PoisonError::into_inner));
+
+        let compilation_result = compile(
+            &self.cc0_path,
+            &args,
+            &coverage_env(&self.coverage, test),
+            test.compilation_limits.time.unwrap_or(self.cc0_time),
+            test.compilation_limits.memory.unwrap_or(self.cc0_memory))?;
+
+        let mut notes = Vec::new();
+        if let (Ok(_), Some(save_dir)) = (&compilation_result, &self.save_files) {
+            match self.save_generated_c(test, save_dir, next_id) {
+                Ok(new_notes) => notes.extend(new_notes),
+                Err(e) => notes.push(format!("Couldn't check generated C: {:#}", e))
+            }
         }
 
+        let compiler_output = match compilation_result {
+            Ok(output) => output,
+            Err(output) => return Ok(Compilation::Failed(output))
+        };
+
+        let mut artifact = Artifact::new(out_file, compiler_output);
+        artifact.notes = notes;
+
         // Remove debugging symbol directory on MacOS 
-        if cfg!(target_os = "macos") {
-            let dsym_str = format!("{}.dSYM", out_file.to_str().unwrap());
-            let dsym_dir = Path::new(&dsym_str);
-            if let Err(e) = fs::remove_dir_all(dsym_dir) {
-                eprintln!("❗ Couldn't delete .dSYM directory: {:#}", e);
-            }
+        if cfg!(target_os = "macos") && self.debug_info {
+            artifact.extra_files.push(PathBuf::from(format!("{}.dSYM", artifact.path.display())));
         }
 
-        exec_result
+        Ok(Compilation::Compiled(artifact))
+    }
+
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun> {
+        let artifact = artifact.context("cc0 tests have to be compiled before running")?;
+        let execution = execute(
+            test, 
+            artifact.path_cstring(), 
+            &coverage_env(&self.coverage, test), 
+            &self.work_dir, 
+            test.run_limits.time.unwrap_or(self.test_time), 
+            test.run_limits.memory.unwrap_or(self.test_memory))?;
+        Ok(TestRun::from_execution(execution, None))
+    }
+
+    fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        let mut command = vec![self.cc0_path.clone()];
+        command.extend(self.compile_args(test, &self.work_dir.join("a.out")));
+        Some(command)
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        vec![cstring_to_path(&self.cc0_path)]
     }
 
     fn properties(&self) -> ExecuterProperties {
-        ExecuterProperties {
-            libraries: true,
-            garbage_collected: true,
-            safe: true,
-            typechecked: true,
-            name: "cc0"
-        }
+        ExecuterProperties { safe: !self.unsafe_mode, ..properties_of(&ExecuterKind::CC0) }
     }
 }
 
 pub struct C0VMExecuter {
     cc0_path: CString,
+    work_dir: PathBuf,
 
     cc0_memory: u64,
     cc0_time: u64,
@@ -98,16 +229,19 @@ pub struct C0VMExecuter {
     c0vm_path: CString,
 
     test_memory: u64,
-    test_time: u64
+    test_time: u64,
+
+    coverage: Option<Coverage>
 }
 
 impl C0VMExecuter {
-    pub fn new(options: &Options) -> Result<C0VMExecuter> {
-        let cc0_path = make_cstr_path(options.c0_home.clone(), &["bin", "cc0"])?;
-        let c0vm_path = make_cstr_path(options.c0_home.clone(), &["vm", "c0vm"])?;
+    pub fn new(options: &Options, work_dir: &Path) -> Result<C0VMExecuter> {
+        let cc0_path = make_cstr_path(options.c0_home().to_path_buf(), &["bin", "cc0"])?;
+        let c0vm_path = make_cstr_path(options.c0_home().to_path_buf(), &["vm", "c0vm"])?;
 
         Ok(C0VMExecuter {
             cc0_path,
+            work_dir: work_dir.to_path_buf(),
 
             cc0_memory: options.compilation_mem,
             cc0_time: options.compilation_time,
@@ -115,105 +249,179 @@ impl C0VMExecuter {
             c0vm_path,
 
             test_memory: options.test_memory,
-            test_time: options.test_time
+            test_time: options.test_time,
+
+            coverage: Coverage::new(options)?
         })
-    }    
+    }
+
+    /// Uses a different cc0 binary than the one in C0_HOME
+    pub fn with_cc0(mut self, cc0: &Path) -> Result<C0VMExecuter> {
+        self.cc0_path = make_cstr_path(cc0.to_path_buf(), &[])?;
+        Ok(self)
+    }
 }
 
-impl Executer for C0VMExecuter {
-    fn run_test(&self, test: &TestExecutionInfo) -> Result<(String, Behavior)> {
-        // Compile test case
+impl C0VMExecuter {
+    /// Arguments to CC0 for compiling a test into bytecode in 'out_file'
+    fn compile_args(&self, test: &TestExecutionInfo, out_file: &Path) -> Vec<CString> {
         let mut args: Vec<CString> = Vec::new();
+        args.extend(test.link_options.iter().map(string_to_cstring));
         args.extend(test.compiler_options.iter().map(string_to_cstring));
-        args.extend(test.sources.iter().map(string_to_cstring));
-        
+        args.extend(test.sources.iter().map(|source| path_to_cstring(source)));
+        args.push(str_to_cstring("-vbo"));
+        args.push(path_to_cstring(out_file));
+        args
+    }
+}
+
+impl Executer for C0VMExecuter {
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
         static test_counter: AtomicUsize = AtomicUsize::new(0);
         
-        let out_file: CString = {
-            let current_dir = env::current_dir().unwrap();
+        let out_file = {
             let next_id = test_counter.fetch_add(1, atomic::Ordering::Relaxed);
-            str_to_cstring(&format!("{}/a.out{}.bc0", current_dir.display(), next_id))
+            self.work_dir.join(format!("a.out{}.bc0", next_id))
         };
-        args.push(str_to_cstring("-vbo"));
-        args.push(out_file.clone());
+        let args = self.compile_args(test, &out_file);
 
         let compilation_result = 
             compile(
                 &self.cc0_path, 
                 &args,
-                self.cc0_time,
-                self.cc0_memory)?;
+                &coverage_env(&self.coverage, test),
+                test.compilation_limits.time.unwrap_or(self.cc0_time),
+                test.compilation_limits.memory.unwrap_or(self.cc0_memory))?;
         
-        if let Err(output) = compilation_result {
-            return Ok((output, Behavior::CompileError))
-        }
+        Ok(match compilation_result {
+            Ok(output) => Compilation::Compiled(Artifact::new(out_file, output)),
+            Err(output) => Compilation::Failed(output)
+        })
+    }
 
-        // Run test case
-        let exec_result = 
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun> {
+        let artifact = artifact.context("c0vm tests have to be compiled before running")?;
+        let execution = 
             execute_with_args(
                 test, 
                 &self.c0vm_path, 
-                &[out_file.as_ref()], 
-                self.test_time, 
-                self.test_memory);
-        
-        if let Err(e) = fs::remove_file(out_file.to_str().unwrap()) {
-            eprintln!("❗ Couldn't delete bc0 file: {:#}", e);
-        }
+                &[artifact.path_cstring()], 
+                &coverage_env(&self.coverage, test),
+                &self.work_dir,
+                test.run_limits.time.unwrap_or(self.test_time), 
+                test.run_limits.memory.unwrap_or(self.test_memory))?;
+
+        Ok(TestRun::from_execution(execution, None))
+    }
 
-        exec_result
+    fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        let mut command = vec![self.cc0_path.clone()];
+        command.extend(self.compile_args(test, &self.work_dir.join("a.out.bc0")));
+        Some(command)
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        vec![cstring_to_path(&self.cc0_path), cstring_to_path(&self.c0vm_path)]
     }
 
     fn properties(&self) -> ExecuterProperties {
-        ExecuterProperties {
-            libraries: true,
-            garbage_collected: false,
-            safe: true,
-            typechecked: true,
-            name: "cc0_c0vm"
-        }
+        properties_of(&ExecuterKind::C0VM)
     }
 }
 
 pub struct CoinExecuter {
     coin_path: CString,
+    work_dir: PathBuf,
 
     test_time: u64,
-    test_memory: u64
+    test_memory: u64,
+
+    coverage: Option<Coverage>
 }
 
 impl CoinExecuter {
-    pub fn new(options: &Options) -> Result<CoinExecuter> {
+    pub fn new(options: &Options, work_dir: &Path) -> Result<CoinExecuter> {
         // .bin is necessary since coin-exec is missing
         // #!/bin/sh at the top in master for now
-        let coin_path = make_cstr_path(options.c0_home.clone(), &["bin", "coin-exec.bin"])?;
+        let coin_path = make_cstr_path(options.c0_home().to_path_buf(), &["bin", "coin-exec.bin"])?;
         
         Ok(CoinExecuter {
             coin_path,
+            work_dir: work_dir.to_path_buf(),
 
             test_time: options.test_time,
-            test_memory: options.test_memory
+            test_memory: options.test_memory,
+
+            coverage: Coverage::new(options)?
         })
     }
 }
 
 impl Executer for CoinExecuter {
-    fn run_test(&self, test: &TestExecutionInfo) -> Result<(String, Behavior)> {
+    fn compile(&self, _test: &TestExecutionInfo) -> Result<Compilation> {
+        // No need to compile tests for the C0in-trepter
+        Ok(Compilation::NotNeeded)
+    }
+
+    fn execute(&self, test: &TestExecutionInfo, _artifact: Option<&Artifact>) -> Result<TestRun> {
         // Check if it uses C1, if so then skip the test
-        if test.sources.iter().any(|source| source.ends_with(".c1")) {
-            return Ok(("<C1 test skipped>".to_string(), Behavior::Skipped))
+        if test.sources.iter().any(|source| source.extension() == Some(OsStr::new("c1"))) {
+            return Ok(TestRun::new("coin can't run C1 code".to_string(), None, Behavior::Skipped))
         }
 
-        // No need to compile tests for the C0in-trepter
         let mut args: Vec<CString> = Vec::new();
+        args.extend(test.link_options.iter().map(string_to_cstring));
         args.extend(test.compiler_options.iter().map(string_to_cstring));
-        args.extend(test.sources.iter().map(string_to_cstring));
+        args.extend(test.sources.iter().map(|source| path_to_cstring(source)));
+
+        let execution = execute_with_args(
+            test, 
+            &self.coin_path, 
+            &args, 
+            &coverage_env(&self.coverage, test),
+            &self.work_dir, 
+            test.run_limits.time.unwrap_or(self.test_time), 
+            test.run_limits.memory.unwrap_or(self.test_memory))?;
+        Ok(TestRun::from_execution(execution, None))
+    }
 
-        execute_with_args(test, &self.coin_path, &args, self.test_time, self.test_memory)
+    /// coin exits with 2 if the program doesn't compile,
+    /// and with 4 if it failed at runtime
+    fn exit_code(&self, code: i32) -> Option<Behavior> {
+        match code {
+            2 => Some(Behavior::CompileError),
+            4 => Some(Behavior::Failure),
+            _ => None
+        }
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        vec![cstring_to_path(&self.coin_path)]
     }
 
     fn properties(&self) -> ExecuterProperties {
-        ExecuterProperties {
+        properties_of(&ExecuterKind::Coin)
+    }
+}
+
+/// What specs can ask about each kind of executer, which doesn't need a C0 distribution
+pub fn properties_of(kind: &ExecuterKind) -> ExecuterProperties {
+    match kind {
+        ExecuterKind::CC0 => ExecuterProperties {
+            libraries: true,
+            garbage_collected: true,
+            safe: true,
+            typechecked: true,
+            name: "cc0"
+        },
+        ExecuterKind::C0VM => ExecuterProperties {
+            libraries: true,
+            garbage_collected: false,
+            safe: true,
+            typechecked: true,
+            name: "cc0_c0vm"
+        },
+        ExecuterKind::Coin => ExecuterProperties {
             libraries: true,
             garbage_collected: false,
             safe: true,
@@ -223,6 +431,11 @@ impl Executer for CoinExecuter {
     }
 }
 
+/// Environment variables for collecting coverage data while running a test
+fn coverage_env(coverage: &Option<Coverage>, test: &TestExecutionInfo) -> Vec<CString> {
+    coverage.as_ref().map(|coverage| coverage.environment(test)).unwrap_or_default()
+}
+
 fn make_cstr_path(mut base: PathBuf, path: &[&str]) -> Result<CString> {
     base.extend(path.iter());
 
@@ -234,6 +447,14 @@ fn str_to_cstring(s: &str) -> CString {
     CString::new(s.as_bytes()).unwrap()        
 }
 
+fn cstring_to_path(path: &CString) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(path.as_bytes()))
+}
+
+fn path_to_cstring(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
 fn string_to_cstring(s: &String) -> CString {
     CString::new(s.as_bytes()).unwrap()        
 }
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result};
+
+use crate::checker::{self, SkipReason};
+use crate::executer::Executer;
+use crate::spec::{Behavior, TestInfo};
+
+/// Changes whenever a field is renamed or removed, or its meaning changes.
+/// Adding fields doesn't change it, so readers should ignore ones they don't know
+const SCHEMA_VERSION: u32 = 1;
+
+/// What was recorded about a test as it ran, for the report
+pub struct RecordedRun {
+    /// 'None' if the test wasn't run, e.g. because it was expected
+    /// to have a compile error and didn't, or it was cached
+    pub behavior: Option<Behavior>,
+    pub output: Option<String>,
+    pub compiler_output: Option<String>,
+    /// How long compiling and running the test took, including its hooks
+    pub duration: Option<Duration>,
+    /// Whether it passed last time, and so wasn't run again
+    pub cached: bool
+}
+
+/// How a test ended up. Baselines (see 'baseline') keep these too
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Passed,
+    Failed,
+    Timeout,
+    Error,
+    /// The test wasn't run, e.g. because a 'skip' spec applied. Its
+    /// report's 'skip_reason' says why
+    Skipped,
+    /// The run was stopped before it got to the test
+    NotRun,
+    /// An 'xfail' spec applied and it failed
+    #[serde(rename = "xfail")]
+    ExpectedFailure,
+    /// An 'xfail' spec applied, but it passed
+    #[serde(rename = "xpass")]
+    UnexpectedPass
+}
+
+impl Status {
+    /// Whether the test failed, timed out, or had an error
+    pub fn is_failure(self) -> bool {
+        matches!(self, Status::Failed | Status::Timeout | Status::Error)
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Passed => write!(f, "passed"),
+            Status::Failed => write!(f, "failed"),
+            Status::Timeout => write!(f, "timeout"),
+            Status::Error => write!(f, "error"),
+            Status::Skipped => write!(f, "skipped"),
+            Status::NotRun => write!(f, "not-run"),
+            Status::ExpectedFailure => write!(f, "xfail"),
+            Status::UnexpectedPass => write!(f, "xpass")
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    version: u32,
+    executer: &'a str,
+    /// The run's --meta tags
+    meta: &'a BTreeMap<String, String>,
+    /// The C0 distribution of each suite which didn't use --c0-home, by suite name
+    toolchains: &'a BTreeMap<String, String>,
+    tests: &'a [TestReport]
+}
+
+/// Everything about how a test went. Behaviors are written as they are in specs,
+/// e.g. "return 0", and times are in seconds
+#[derive(Serialize)]
+pub struct TestReport {
+    name: String,
+    /// The name of the directory the test is in
+    suite: String,
+    sources: Vec<String>,
+    compiler_options: Vec<String>,
+    /// From the 'owners' in the suite's suite.toml
+    owners: Vec<String>,
+    status: Status,
+    /// Why a skipped test wasn't run, e.g. "spec" or "dependency-failed"
+    skip_reason: Option<SkipReason>,
+    cached: bool,
+    /// The behaviors whose specs applied to the executer
+    expected: Vec<String>,
+    actual: Option<String>,
+    output: Option<String>,
+    compiler_output: Option<String>,
+    /// Why the test failed or had an error
+    message: Option<String>,
+    duration: Option<f64>,
+    /// CPU time spent running the test program
+    run_time: Option<f64>
+}
+
+impl TestReport {
+    pub fn new(
+        executer: &dyn Executer,
+        test: &TestInfo,
+        owners: &[String],
+        status: Status,
+        message: Option<String>,
+        run: Option<&RecordedRun>,
+        run_time: Option<Duration>) -> TestReport
+    {
+        TestReport {
+            name: test.name(),
+            suite: test.execution.directory.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            sources: test.execution.sources.iter().map(|source| source.to_string_lossy().into_owned()).collect(),
+            compiler_options: test.execution.compiler_options.clone(),
+            owners: owners.to_vec(),
+            status,
+            skip_reason: None,
+            cached: matches!(run, Some(RecordedRun { cached: true, .. })),
+            expected: checker::expected_behaviors(executer, test).iter().map(Behavior::to_string).collect(),
+            actual: run.and_then(|run| run.behavior).map(|behavior| behavior.to_string()),
+            output: run.and_then(|run| run.output.clone()),
+            compiler_output: run.and_then(|run| run.compiler_output.clone()),
+            message,
+            duration: run.and_then(|run| run.duration).map(|duration| duration.as_secs_f64()),
+            run_time: run_time.map(|time| time.as_secs_f64())
+        }
+    }
+
+    pub fn with_skip_reason(self, skip_reason: Option<SkipReason>) -> TestReport {
+        TestReport { skip_reason, ..self }
+    }
+}
+
+/// Writes the results of a run as JSON, for tools which would
+/// otherwise have to read c0check's output
+pub fn write(
+    path: &Path,
+    executer_name: &str,
+    meta: &BTreeMap<String, String>,
+    toolchains: &BTreeMap<String, String>,
+    tests: &[TestReport]) -> Result<()>
+{
+    let report = Report { version: SCHEMA_VERSION, executer: executer_name, meta, toolchains, tests };
+    write_json(path, &report)
+}
+
+/// The top of a split report (see 'write_split')
+#[derive(Serialize)]
+struct Index<'a> {
+    version: u32,
+    executer: &'a str,
+    meta: &'a BTreeMap<String, String>,
+    toolchains: &'a BTreeMap<String, String>,
+    suites: Vec<SuiteEntry>
+}
+
+/// Where a suite's tests are in a split report, and how they went
+#[derive(Serialize)]
+struct SuiteEntry {
+    name: String,
+    /// Relative to the index
+    file: String,
+    tests: usize,
+    /// How many tests ended up with each status
+    statuses: BTreeMap<String, usize>
+}
+
+#[derive(Serialize)]
+struct SuiteReport<'a> {
+    version: u32,
+    executer: &'a str,
+    suite: &'a str,
+    tests: Vec<&'a TestReport>
+}
+
+/// Like 'write', but for runs too large to read as one file: 'dir' gets an
+/// index.json with the run's details and how each suite went, and each suite's
+/// tests go in suites/<suite>.json, so readers only load the suites they need
+pub fn write_split(
+    dir: &Path,
+    executer_name: &str,
+    meta: &BTreeMap<String, String>,
+    toolchains: &BTreeMap<String, String>,
+    tests: &[TestReport]) -> Result<()>
+{
+    // Suites from an earlier report in the same directory would be left dangling
+    let suites_dir = dir.join("suites");
+    match fs::remove_dir_all(&suites_dir) {
+        Err(error) if error.kind() != ErrorKind::NotFound =>
+            return Err(error).context(format!("Couldn't delete '{}'", suites_dir.display())),
+        _ => ()
+    }
+    fs::create_dir_all(&suites_dir).context(format!("Couldn't create '{}'", suites_dir.display()))?;
+
+    let mut suites: BTreeMap<&str, Vec<&TestReport>> = BTreeMap::new();
+    for test in tests.iter() {
+        suites.entry(&test.suite).or_default().push(test);
+    }
+
+    let mut entries = Vec::new();
+    for (suite, tests) in suites {
+        let file = format!("suites/{}.json", suite);
+        let mut statuses = BTreeMap::new();
+        for test in tests.iter() {
+            *statuses.entry(test.status.to_string()).or_insert(0) += 1;
+        }
+        entries.push(SuiteEntry { name: suite.to_string(), file: file.clone(), tests: tests.len(), statuses });
+
+        let report = SuiteReport { version: SCHEMA_VERSION, executer: executer_name, suite, tests };
+        write_json(&dir.join(file), &report)?;
+    }
+
+    let index = Index { version: SCHEMA_VERSION, executer: executer_name, meta, toolchains, suites: entries };
+    write_json(&dir.join("index.json"), &index)
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let file = File::create(path).context(format!("Couldn't create '{}'", path.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), value)
+        .context(format!("Couldn't write '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod json_report_tests {
+    use super::*;
+    use std::fs;
+    use crate::test_util::TempDir;
+
+    fn report(name: &str, suite: &str, status: Status) -> TestReport {
+        TestReport {
+            name: format!("{}/{}", suite, name),
+            suite: suite.to_string(),
+            sources: Vec::new(),
+            compiler_options: Vec::new(),
+            owners: Vec::new(),
+            status,
+            skip_reason: None,
+            cached: false,
+            expected: Vec::new(),
+            actual: None,
+            output: None,
+            compiler_output: None,
+            message: None,
+            duration: None,
+            run_time: None
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let report = TestReport {
+            sources: vec![String::from("/tests/basic/a.c0")],
+            owners: vec![String::from("@alice")],
+            expected: vec![String::from("return 0")],
+            ..report("a.c0", "basic", Status::NotRun)
+        };
+
+        let dir = TempDir::new("report");
+        let path = dir.join("report.json");
+        let meta: BTreeMap<String, String> = vec![(String::from("branch"), String::from("main"))].into_iter().collect();
+        let toolchains: BTreeMap<String, String> = vec![(String::from("legacy"), String::from("/opt/c0-2019"))].into_iter().collect();
+        write(&path, "cc0", &meta, &toolchains, &[report]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_eq!(json["version"], SCHEMA_VERSION);
+        assert_eq!(json["executer"], "cc0");
+        assert_eq!(json["meta"]["branch"], "main");
+        assert_eq!(json["toolchains"]["legacy"], "/opt/c0-2019");
+        assert_eq!(json["tests"][0]["status"], "not-run");
+        assert_eq!(json["tests"][0]["owners"][0], "@alice");
+        assert_eq!(json["tests"][0]["expected"][0], "return 0");
+        assert!(json["tests"][0]["actual"].is_null());
+    }
+
+    #[test]
+    fn test_write_split() -> Result<()> {
+        let reports = vec![
+            report("a.c0", "basic", Status::Passed),
+            report("b.c0", "basic", Status::Failed),
+            report("c.c0", "images", Status::Passed)
+        ];
+
+        let dir = TempDir::new("split-report");
+        fs::create_dir_all(dir.join("suites"))?;
+        fs::write(dir.join("suites/old.json"), "{}")?;
+        write_split(&dir, "cc0", &BTreeMap::new(), &BTreeMap::new(), &reports)?;
+        let read = |file: &str| -> Result<serde_json::Value> { Ok(serde_json::from_str(&fs::read_to_string(dir.join(file))?)?) };
+
+        let index = read("index.json")?;
+        assert_eq!(index["version"], SCHEMA_VERSION);
+        assert_eq!(index["suites"][0]["name"], "basic");
+        assert_eq!(index["suites"][0]["tests"], 2);
+        assert_eq!(index["suites"][0]["statuses"]["failed"], 1);
+        assert_eq!(index["suites"][1]["file"], "suites/images.json");
+
+        let basic = read("suites/basic.json")?;
+        assert_eq!(basic["tests"][1]["name"], "basic/b.c0");
+        assert_eq!(basic["tests"].as_array().unwrap().len(), 2);
+        assert!(!dir.join("suites/old.json").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use anyhow::{bail, Context, Result};
+
+use crate::options::Options;
+use crate::spec::TestInfo;
+
+/// The tests which didn't pass in the last run, so that --rerun-failed
+/// can run just those while working on a fix
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LastFailures {
+    /// The test directory of the run, since test names are relative to it
+    test_dir: PathBuf,
+    tests: BTreeSet<String>
+}
+
+impl LastFailures {
+    pub fn new<'a>(test_dir: &Path, tests: impl Iterator<Item = &'a TestInfo>) -> LastFailures {
+        LastFailures { test_dir: test_dir.to_path_buf(), tests: tests.map(TestInfo::name).collect() }
+    }
+
+    /// Where the last failures are kept
+    pub fn path(options: &Options) -> PathBuf {
+        options.cache_dir().join("last-failures.json")
+    }
+
+    pub fn load(path: &Path) -> Result<LastFailures> {
+        let file = File::open(path).context("Couldn't find which tests failed last time. Has there been a run yet?")?;
+        serde_json::from_reader(BufReader::new(file))
+            .context(format!("Invalid list of failures '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!("Couldn't create '{}'", parent.display()))?;
+        }
+
+        let file = File::create(path).context(format!("Couldn't create '{}'", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tests.is_empty()
+    }
+
+    /// Keeps only the tests which failed last time. Ones which
+    /// have been removed or renamed since are left out
+    pub fn filter(&self, test_dir: &Path, tests: Vec<TestInfo>) -> Result<Vec<TestInfo>> {
+        if self.test_dir != test_dir {
+            bail!("The last run was of '{}', not '{}'", self.test_dir.display(), test_dir.display())
+        }
+
+        Ok(tests.into_iter().filter(|test| self.tests.contains(&test.name())).collect())
+    }
+}
+
+#[cfg(test)]
+mod last_failures_tests {
+    use super::*;
+    use crate::spec::*;
+    use crate::test_util::TempDir;
+
+    fn test_named(name: &str) -> TestInfo {
+        TestInfo::new(TestExecutionInfo::for_source(Path::new("/tests").join(name)), Vec::new())
+    }
+
+    #[test]
+    fn test() {
+        let dir = TempDir::new("last-failures");
+        let path = dir.join("last-failures.json");
+        let failed = test_named("b.c0");
+        LastFailures::new(Path::new("/tests"), std::iter::once(&failed)).save(&path).unwrap();
+
+        let last = LastFailures::load(&path).unwrap();
+        assert_eq!(last.len(), 1);
+
+        let tests = vec![test_named("a.c0"), test_named("b.c0"), test_named("c.c0")];
+        let rerun = last.filter(Path::new("/tests"), tests).unwrap();
+        assert_eq!(rerun.iter().map(TestInfo::name).collect::<Vec<_>>(), vec![failed.name()]);
+
+        assert!(last.filter(Path::new("/other"), Vec::new()).is_err());
+    }
+}
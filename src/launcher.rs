@@ -1,156 +1,474 @@
 #![allow(non_upper_case_globals)]
 
-use std::{io::Read, os::unix::prelude::FromRawFd, process};
-use std::os::unix::io::RawFd;
+use std::{io::{self, Read, Write}, os::unix::prelude::FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
 use std::env;
-use std::fs::{self, File};
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
 use std::path::Path;
-use std::sync::atomic::{self, AtomicUsize};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::convert::Infallible;
 use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Duration;
 
-use nix::unistd::{self, ForkResult};
+use nix::unistd::{self, ForkResult, Pid};
 use nix::sys::wait::{self, WaitStatus};
 use nix::sys::signal::Signal;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
 use nix::libc::{self, STDOUT_FILENO, STDERR_FILENO};
 
 use anyhow::{Context, Result, anyhow, bail};
+use thiserror::Error;
 
 use crate::spec::*;
 
 const CC0_GCC_FAILURE_CODE: i32 = 2;
-const EXEC_FAILURE_CODE: i32 = 100;
-const RUST_PANIC_CODE: i32 = 101;
+pub const EXEC_FAILURE_CODE: i32 = 100;
+pub const RUST_PANIC_CODE: i32 = 101;
+/// exec failed, but only because the system was busy
+pub const EXEC_CONTENTION_CODE: i32 = 102;
 
+/// What's recorded before the end of stderr of a test program run with '@no_capture'
+pub const DISCARDED_OUTPUT: &str = "<stdout discarded because of @no_capture>";
+
+/// How much of the end of stderr is kept for tests with '@no_capture', which
+/// is enough for the runtime's last message, e.g. 'c0rt: assertion failed'
+const STDERR_TAIL: usize = 4096;
+
+/// Whether output is copied to stderr as it's read (see --serial)
+static STREAM_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Shows the output of every program c0check runs as it comes, as well as
+/// recording it. Only makes sense when one program runs at a time
+pub fn stream_output() {
+    STREAM_OUTPUT.store(true, atomic::Ordering::Relaxed);
+}
+
+/// Whether programs are started with 'spawn' rather than fork() (see --paranoid)
+static PARANOID: AtomicBool = AtomicBool::new(false);
+
+/// Starts every program with std::process::Command, which prepares everything before
+/// fork()ing and only runs async-signal-safe code in the child, and records output
+/// through close-on-exec pipes, so that other threads' pipes don't leak into it
+pub fn paranoid() {
+    PARANOID.store(true, atomic::Ordering::Relaxed);
+}
+
+/// The system temporarily couldn't start a process, e.g. because it ran out of
+/// processes, file descriptors, or memory. Trying again later usually works
+#[derive(Debug, Error)]
+#[error("the system was too busy to start the program")]
+pub struct Contention;
+
+/// Whether an error came from the system being busy (see 'Contention')
+/// rather than from the program or test
+pub fn is_contention(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Contention>() || matches!(
+        cause.downcast_ref::<nix::Error>().and_then(|error| error.as_errno()),
+        Some(errno) if is_contention_errno(errno)))
+}
+
+fn is_contention_errno(errno: Errno) -> bool {
+    matches!(errno, Errno::EAGAIN | Errno::ENOMEM | Errno::EMFILE | Errno::ENFILE | Errno::ETXTBSY)
+}
+
+/// Runs CC0 with the given arguments. Returns the compiler output
+/// (which may contain warnings) if compilation succeeded, or the
+/// error messages if it failed
 pub fn compile<CC0Path: AsRef<CStr>, Arg: AsRef<CStr>>(
     cc0: CC0Path, 
     args: &[Arg],
+    env: &[CString],
+    timeout: u64,
+    memory: u64) -> Result<Result<String, String>> 
+{
+    let (status, output) = run_program(cc0, args, env, None, timeout, memory).context("when spawning CC0")?;
+
+    match status {
+        WaitStatus::Exited(_, 0) => Ok(Ok(output)),
+        WaitStatus::Exited(_, 1) => Ok(Err(output)),
+        WaitStatus::Exited(_, CC0_GCC_FAILURE_CODE) => Err(anyhow!("CC0 failed to invoke GCC")).context(output),
+        WaitStatus::Exited(_, EXEC_FAILURE_CODE) => Err(anyhow!("Failed to exec cc0")).context(output),
+        WaitStatus::Exited(_, EXEC_CONTENTION_CODE) => Err(anyhow!(Contention)).context("Failed to exec cc0"),
+        WaitStatus::Exited(_, RUST_PANIC_CODE) => Err(anyhow!("CC0 process panic'd")).context(output),
+        WaitStatus::Signaled(_, Signal::SIGXCPU, _) => Err(anyhow!("CC0 timed out")).context(output),
+        status => Err(anyhow!("CC0 unexpectedly failed: {:?}", status)).context(output)
+    }
+}
+
+/// Runs a helper program (looked up in $PATH) with the given resource limits.
+/// Returns (whether it exited successfully, everything it printed)
+pub fn run_tool<Program: AsRef<CStr>, Arg: AsRef<CStr>>(
+    program: Program,
+    args: &[Arg],
     timeout: u64,
-    memory: u64) -> Result<Result<(), String>> 
+    memory: u64) -> Result<(bool, String)>
+{
+    let name = program.as_ref().to_string_lossy().into_owned();
+    let (status, output) = run_program(program, args, &[], None, timeout, memory)
+        .context(format!("when spawning '{}'", name))?;
+
+    tool_result(&name, status, output)
+}
+
+/// Runs a shell command from the given directory, with the given resource limits.
+/// Returns (whether it exited successfully, everything it printed)
+pub fn run_shell(command: &str, dir: &Path, timeout: u64, memory: u64) -> Result<(bool, String)> {
+    let shell = CString::new("sh").unwrap();
+    let args = [CString::new("-c").unwrap(), CString::new(command).context("Invalid character in command")?];
+    let (status, output) = run_program(shell, &args, &[], Some(dir), timeout, memory)
+        .context(format!("when spawning '{}'", command))?;
+
+    tool_result(command, status, output)
+}
+
+fn tool_result(name: &str, status: WaitStatus, output: String) -> Result<(bool, String)> {
+    match status {
+        WaitStatus::Exited(_, 0) => Ok((true, output)),
+        WaitStatus::Exited(_, EXEC_FAILURE_CODE) => Err(anyhow!("Failed to exec '{}'", name)).context(output),
+        WaitStatus::Exited(_, EXEC_CONTENTION_CODE) => Err(anyhow!(Contention)).context(format!("Failed to exec '{}'", name)),
+        WaitStatus::Signaled(_, Signal::SIGXCPU, _) => Err(anyhow!("'{}' timed out", name)).context(output),
+        _ => Ok((false, output))
+    }
+}
+
+/// Runs a program with the given arguments, extra environment
+/// variables ('NAME=value'), working directory, and resource limits
+/// Returns (How it exited, everything it printed)
+fn run_program<Program: AsRef<CStr>, Arg: AsRef<CStr>>(
+    program: Program,
+    args: &[Arg],
+    env: &[CString],
+    dir: Option<&Path>,
+    timeout: u64,
+    memory: u64) -> Result<(WaitStatus, String)>
 {
     // Create argv
-    let mut argv = vec![cc0.as_ref()];
+    let mut argv = vec![program.as_ref()];
     argv.extend(args.iter().map(|arg| arg.as_ref()));
 
+    if PARANOID.load(atomic::Ordering::Relaxed) {
+        let (read_pipe, write_pipe) = checked_pipe().context("When creating a pipe to record output")?;
+        let env: Vec<&CStr> = env.iter().map(|var| var.as_ref()).collect();
+        let child = spawn(&argv, &env, false, dir, (Stdio::from(write_pipe.try_clone()?), Stdio::from(write_pipe)), timeout, memory)?;
+        let output = read_output(read_pipe).unwrap_or_else(|_| "<couldn't read output>".to_string());
+        let status = wait::waitpid(child, None).context("Failed to wait() for child process")?;
+
+        return Ok((status, output))
+    }
+
+    // The child of a multithreaded process mustn't allocate, since another
+    // thread may have held the allocator's lock when it forked, so everything
+    // exec needs is made beforehand
+    let envp = child_environment(env);
+    let envp: Vec<&CStr> = envp.iter().map(|var| var.as_ref()).collect();
+    let (argv, envp) = (exec_array(&argv), exec_array(&envp));
+    let dir = dir.map(|dir| CString::new(dir.as_os_str().as_bytes())).transpose().context("Invalid directory")?;
+
     // Create a pipe to record stdout and stderr from the subprocess
-    let (read_pipe, write_pipe) = unistd::pipe().context("When creating a pipe to record CC0 output")?;
+    let (read_pipe, write_pipe) = unistd::pipe().context("When creating a pipe to record output")?;
 
-    match unsafe { unistd::fork().context("when spawning CC0")? } {
-        ForkResult::Child => {
+    match unsafe { unistd::fork()? } {
+        ForkResult::Child => exec_child(|| {
             unistd::close(read_pipe).unwrap();
             redirect_output(write_pipe);
             set_resource_limits(memory, timeout);
+            if let Some(dir) = &dir {
+                change_dir(dir)?;
+            }
 
-            let _ = unistd::execvp(cc0.as_ref(), &argv);
-            unsafe { libc::_exit(EXEC_FAILURE_CODE); }
-        },
+            unsafe { libc::execvpe(program.as_ref().as_ptr(), argv.as_ptr(), envp.as_ptr()) };
+            Err(nix::Error::Sys(Errno::last()))
+        }),
 
         ForkResult::Parent { child } => {
-            let output = read_from_pipe(read_pipe, write_pipe).unwrap_or("<couldn't read output>".to_string());
-            let status = wait::waitpid(child, None).expect("Failed to wait() for compiler process");
+            let output = read_from_pipe(read_pipe, write_pipe).unwrap_or_else(|_| "<couldn't read output>".to_string());
+            let status = wait::waitpid(child, None).context("Failed to wait() for child process")?;
             
-            match status {
-                WaitStatus::Exited(_, 0) => Ok(Ok(())),
-                WaitStatus::Exited(_, 1) => Ok(Err(output)),
-                WaitStatus::Exited(_, CC0_GCC_FAILURE_CODE) => Err(anyhow!("CC0 failed to invoke GCC")).context(output),
-                WaitStatus::Exited(_, EXEC_FAILURE_CODE) => Err(anyhow!("Failed to exec cc0")).context(output),
-                WaitStatus::Exited(_, RUST_PANIC_CODE) => Err(anyhow!("CC0 process panic'd")).context(output),
-                WaitStatus::Signaled(_, Signal::SIGXCPU, _) => Err(anyhow!("CC0 timed out")).context(output),
-                status => Err(anyhow!("CC0 unexpectedly failed: {:?}", status)).context(output)
-            }
+            Ok((status, output))
         }
     }
 }
 
-pub fn execute<Executable: AsRef<CStr>>(info: &TestExecutionInfo, executable: Executable, timeout: u64, memory: u64) -> Result<(String, Behavior)> {
-    execute_with_args::<Executable, &CStr>(info, executable, &[], timeout, memory)
+/// What happened when running a test program
+pub struct Execution {
+    pub output: String,
+    pub behavior: Behavior,
+    /// How the process ended, before it was classified as a behavior
+    pub status: RawStatus,
+    /// CPU time (user and system) used by the program
+    pub cpu_time: Duration
 }
 
+pub fn execute<Executable: AsRef<CStr>>(
+    info: &TestExecutionInfo, 
+    executable: Executable, 
+    env: &[CString],
+    work_dir: &Path,
+    timeout: u64, 
+    memory: u64) -> Result<Execution> 
+{
+    execute_with_args::<Executable, &CStr>(info, executable, &[], env, work_dir, timeout, memory)
+}
+
+/// Runs a test program. Its result file is written to 'work_dir'. The
+/// program only gets the environment variables ('NAME=value') in 'env'
 pub fn execute_with_args<Executable: AsRef<CStr>, Arg: AsRef<CStr>>(
     info: &TestExecutionInfo, 
     executable: Executable, 
     args: &[Arg], 
+    env: &[CString],
+    work_dir: &Path,
     timeout: u64,
-    memory: u64) -> Result<(String, Behavior)> 
+    memory: u64) -> Result<Execution> 
 {
     static test_counter: AtomicUsize = AtomicUsize::new(0);
 
     let result_file: String = {
-        let current_dir = env::current_dir().unwrap();
         let next_id = test_counter.fetch_add(1, atomic::Ordering::Relaxed);
-        format!("{}/c0_result{}", current_dir.display(), next_id)
+        format!("{}/c0_result{}", work_dir.display(), next_id)
     };
 
     let result_env = CString::new(format!("C0_RESULT_FILE={}", result_file)).unwrap();
+    let mut envp = vec![result_env.as_ref()];
+    envp.extend(env.iter().map(|var| var.as_ref()));
 
     let mut argv = vec![executable.as_ref()];
     argv.extend(args.iter().map(|arg| arg.as_ref()));
 
-    let (read_pipe, write_pipe) = unistd::pipe().context("When creating a pipe to record test output")?;
-
-    match unsafe { unistd::fork().context("when spawning test process")? } {
-        ForkResult::Child => {
-            unistd::close(read_pipe).unwrap();
-            redirect_output(write_pipe);
-            set_resource_limits(memory, timeout);
-            env::set_current_dir(Path::new(&*info.directory)).expect("Couldn't change to the test directory");
+    let (output, child) = if PARANOID.load(atomic::Ordering::Relaxed) {
+        let (read_pipe, write_pipe) = checked_pipe().context("When creating a pipe to record test output")?;
+        let stdout = if info.no_capture { Stdio::null() } else { Stdio::from(write_pipe.try_clone()?) };
+        let child = spawn(&argv, &envp, true, Some(&info.directory), (stdout, Stdio::from(write_pipe)), timeout, memory)
+            .context("when spawning test process")?;
+        let output = if info.no_capture { read_tail(read_pipe)? } else { read_output(read_pipe)? };
+        (output, child)
+    }
+    else {
+        let (read_pipe, write_pipe) = unistd::pipe().context("When creating a pipe to record test output")?;
+        let output_target = if info.no_capture {
+            let null = OpenOptions::new().write(true).open("/dev/null").context("Couldn't open /dev/null")?;
+            OutputTarget::StderrOnly(null, read_pipe, write_pipe)
+        }
+        else {
+            OutputTarget::Pipe(read_pipe, write_pipe)
+        };
 
-            let _ = unistd::execve(executable.as_ref(), &argv, &[&result_env]).unwrap_err();
-            // Couldn't exec
-            process::exit(EXEC_FAILURE_CODE);
-        },
+        // As in 'run_program', the child doesn't allocate
+        let (exec_argv, exec_envp) = (exec_array(&argv), exec_array(&envp));
+        let dir = CString::new(info.directory.as_os_str().as_bytes()).context("Invalid test directory")?;
 
-        ForkResult::Parent { child } => {
-            let output = read_from_pipe(read_pipe, write_pipe)?;
-            let status = wait::waitpid(child, None).expect("Failed to wait() for test program");
-
-            // Read C0_RESULT_FILE, which consists of a null byte
-            // followed by an i32 exit status, which is the 
-            // return value from C0's main()
-            let result = match fs::read(&result_file) {
-                Ok(result) => {
-                    fs::remove_file(Path::new(&result_file))
-                        .context("when removing test program result file")?;
-
-                    if result.len() == 5 {
-                        let bytes = [result[1], result[2], result[3], result[4]];
-                        Some(i32::from_ne_bytes(bytes))
-                    }
-                    else {
-                        None
+        match unsafe { unistd::fork().context("when spawning test process")? } {
+            ForkResult::Child => exec_child(|| {
+                match &output_target {
+                    OutputTarget::Pipe(read_pipe, write_pipe) => {
+                        unistd::close(*read_pipe).unwrap();
+                        redirect_output(*write_pipe);
+                    },
+                    OutputTarget::StderrOnly(null, read_pipe, write_pipe) => {
+                        unistd::close(*read_pipe).unwrap();
+                        unistd::dup2(null.as_raw_fd(), STDOUT_FILENO).expect("Couldn't redirect stdout");
+                        unistd::dup2(*write_pipe, STDERR_FILENO).expect("Couldn't redirect stderr");
                     }
                 }
-                Err(_) => None
-            };
-            
-            let behavior = match status {
-                WaitStatus::Exited(_, 0) => 
-                    if let Some(exit_code) = result {
-                        Behavior::Return(Some(exit_code))
+                set_resource_limits(memory, timeout);
+                change_dir(&dir)?;
+
+                unsafe { libc::execve(executable.as_ref().as_ptr(), exec_argv.as_ptr(), exec_envp.as_ptr()) };
+                Err(nix::Error::Sys(Errno::last()))
+            }),
+
+            ForkResult::Parent { child } => {
+                let output = match output_target {
+                    OutputTarget::Pipe(read_pipe, write_pipe) => read_from_pipe(read_pipe, write_pipe)?,
+                    OutputTarget::StderrOnly(_, read_pipe, write_pipe) => {
+                        unistd::close(write_pipe).context("Couldn't close the write end of the pipe")?;
+                        read_tail(unsafe { File::from_raw_fd(read_pipe) })?
                     }
-                    else {
-                        bail!("C0 program exited succesfully, but no return value was written")
-                    },
-                WaitStatus::Exited(_, 1) => Behavior::Failure,
-                // Coin only. Hopefully other exit codes don't conflict
-                WaitStatus::Exited(_, 2) => Behavior::CompileError,
-                WaitStatus::Exited(_, 4) => Behavior::Failure,
-                WaitStatus::Exited(_, EXEC_FAILURE_CODE) => return Err(anyhow!("Failed to exec the test program")).context(output),
-                WaitStatus::Exited(_, RUST_PANIC_CODE) => return Err(anyhow!("Test program process panic'd")).context(output),
-                WaitStatus::Exited(_, status) => return Err(anyhow!("Unexpected program exit status '{}'", status)).context(output),
-                
-                WaitStatus::Signaled(_, signal, _) => match signal {
-                    Signal::SIGSEGV => Behavior::Segfault,
-                    Signal::SIGXCPU => Behavior::InfiniteLoop,
-                    Signal::SIGFPE => Behavior::DivZero,
-                    Signal::SIGABRT => Behavior::Abort,
-                    other => return Err(anyhow!("Program exited with unexpected signal '{}'", other)).context(output)
-                }
-                status => return Err(anyhow!("Program unexpectedly failed: {:?}", status)).context(output)
-            };
+                };
+                (output, child)
+            }
+        }
+    };
+    let (status, cpu_time) = wait_with_usage(child).context("Failed to wait() for test program")?;
+
+    // Read C0_RESULT_FILE, which consists of a null byte
+    // followed by an i32 exit status, which is the 
+    // return value from C0's main()
+    let result = match fs::read(&result_file) {
+        Ok(result) => {
+            fs::remove_file(Path::new(&result_file))
+                .context("when removing test program result file")?;
+
+            if result.len() == 5 {
+                let bytes = [result[1], result[2], result[3], result[4]];
+                Some(i32::from_ne_bytes(bytes))
+            }
+            else {
+                None
+            }
+        }
+        Err(_) => None
+    };
+    
+    let behavior = match status {
+        WaitStatus::Exited(_, 0) => 
+            if let Some(exit_code) = result {
+                Behavior::Return(Some(exit_code))
+            }
+            else {
+                bail!("C0 program exited succesfully, but no return value was written")
+            },
+        WaitStatus::Exited(_, 1) => Behavior::Failure,
+        WaitStatus::Exited(_, EXEC_FAILURE_CODE) => return Err(anyhow!("Failed to exec the test program")).context(output),
+        WaitStatus::Exited(_, EXEC_CONTENTION_CODE) => return Err(anyhow!(Contention)).context("Failed to exec the test program"),
+        WaitStatus::Exited(_, RUST_PANIC_CODE) => return Err(anyhow!("Test program process panic'd")).context(output),
+        // The executer decides what its runtime's other codes mean
+        WaitStatus::Exited(_, status) => Behavior::UnknownExit(status),
+        
+        WaitStatus::Signaled(_, signal, _) => match signal {
+            Signal::SIGSEGV => Behavior::Segfault,
+            Signal::SIGXCPU => Behavior::InfiniteLoop,
+            Signal::SIGFPE => Behavior::DivZero,
+            Signal::SIGABRT => Behavior::Abort,
+            other => Behavior::UnknownSignal(other)
+        }
+        status => return Err(anyhow!("Program unexpectedly failed: {:?}", status)).context(output)
+    };
+
+    let status = match status {
+        WaitStatus::Signaled(_, signal, _) => RawStatus::Signaled(signal),
+        WaitStatus::Exited(_, code) => RawStatus::Exited(code),
+        _ => unreachable!("other statuses are errors")
+    };
 
-            Ok((output, behavior))
-        },
+    Ok(Execution { output, behavior, status, cpu_time })
+}
+
+/// Waits for a child process, also returning how much CPU time it used
+fn wait_with_usage(child: Pid) -> Result<(WaitStatus, Duration)> {
+    let mut status: libc::c_int = 0;
+    let mut usage = MaybeUninit::<libc::rusage>::zeroed();
+
+    if unsafe { libc::wait4(child.as_raw(), &mut status, 0, usage.as_mut_ptr()) } < 0 {
+        return Err(nix::Error::last().into())
     }
+
+    let usage = unsafe { usage.assume_init() };
+    let to_duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+    let cpu_time = to_duration(usage.ru_utime) + to_duration(usage.ru_stime);
+
+    Ok((WaitStatus::from_raw(child, status)?, cpu_time))
+}
+
+/// Runs the code between fork() and exec() in a child process. The child
+/// must never return into the rest of c0check, where a caught panic would
+/// leave it running tests alongside its parent, so it exits if exec fails
+fn exec_child(exec: impl FnOnce() -> nix::Result<Infallible>) -> ! {
+    let code = match panic::catch_unwind(AssertUnwindSafe(exec)) {
+        Ok(Err(nix::Error::Sys(errno))) if is_contention_errno(errno) => EXEC_CONTENTION_CODE,
+        Ok(_) => EXEC_FAILURE_CODE,
+        Err(_) => RUST_PANIC_CODE
+    };
+
+    unsafe { libc::_exit(code) }
+}
+
+/// The environment of a program started by 'run_program': c0check's own,
+/// with the 'NAME=value' variables in 'env' added or replacing its own
+fn child_environment(env: &[CString]) -> Vec<CString> {
+    let name = |var: &[u8]| var.split(|&b| b == b'=').next().unwrap_or_default().to_vec();
+    let replaced: Vec<Vec<u8>> = env.iter().map(|var| name(var.as_bytes())).collect();
+
+    let inherited = env::vars_os()
+        .filter(|(name, _)| !replaced.iter().any(|replaced| replaced.as_slice() == name.as_bytes()))
+        .filter_map(|(name, value)| CString::new([name.as_bytes(), b"=", value.as_bytes()].concat()).ok());
+    inherited.chain(env.iter().cloned()).collect()
+}
+
+/// The null-terminated array of pointers which exec takes for argv or envp
+fn exec_array(strings: &[&CStr]) -> Vec<*const libc::c_char> {
+    strings.iter().map(|s| s.as_ptr()).chain(std::iter::once(std::ptr::null())).collect()
+}
+
+/// chdir(), which unlike std::env::set_current_dir() doesn't allocate
+fn change_dir(dir: &CStr) -> nix::Result<()> {
+    if unsafe { libc::chdir(dir.as_ptr()) } < 0 {
+        return Err(nix::Error::Sys(Errno::last()))
+    }
+    Ok(())
+}
+
+/// Starts a program with std::process::Command (see 'paranoid'). 'argv' starts with the
+/// program, which is looked up in $PATH, and 'env' has 'NAME=value' variables, which are
+/// the only ones it gets if 'clear_env' is set. Failing to exec is an error here, rather
+/// than an exit code. The program has to be waited for, e.g. with 'wait_with_usage'
+fn spawn(
+    argv: &[&CStr],
+    env: &[&CStr],
+    clear_env: bool,
+    dir: Option<&Path>,
+    (stdout, stderr): (Stdio, Stdio),
+    timeout: u64,
+    memory: u64) -> Result<Pid>
+{
+    let os_str = |s: &CStr| OsStr::from_bytes(s.to_bytes()).to_os_string();
+    let mut command = Command::new(os_str(argv[0]));
+    command.args(argv[1..].iter().map(|arg| os_str(arg)))
+        .stdout(stdout)
+        .stderr(stderr);
+    if clear_env {
+        command.env_clear();
+    }
+    for var in env.iter() {
+        let var = var.to_bytes();
+        let split = var.iter().position(|&b| b == b'=').unwrap_or(var.len());
+        command.env(OsStr::from_bytes(&var[..split]), OsStr::from_bytes(var.get(split + 1..).unwrap_or_default()));
+    }
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+
+    let (mem_limit, time_limit) = resource_limits(memory, timeout);
+    // setrlimit() is async-signal-safe, and the limits are made before fork()
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &mem_limit) < 0 || libc::setrlimit(libc::RLIMIT_CPU, &time_limit) < 0 {
+                return Err(io::Error::last_os_error())
+            }
+            Ok(())
+        });
+    }
+
+    // The command, which holds the parent's copies of the output pipe, is dropped on return
+    let name = argv[0].to_string_lossy();
+    match command.spawn() {
+        Ok(child) => Ok(Pid::from_raw(child.id() as libc::pid_t)),
+        Err(e) => match e.raw_os_error().map(Errno::from_i32) {
+            Some(errno) if is_contention_errno(errno) => Err(anyhow!(Contention)).context(format!("Failed to exec '{}'", name)),
+            _ => Err(anyhow!(e)).context(format!("Failed to exec '{}'", name))
+        }
+    }
+}
+
+/// A pipe whose ends are closed on exec, returned as (read end, write end)
+fn checked_pipe() -> Result<(File, File)> {
+    let (read_pipe, write_pipe) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+    Ok(unsafe { (File::from_raw_fd(read_pipe), File::from_raw_fd(write_pipe)) })
+}
+
+/// Where a test program's output goes: the read and write ends of a pipe
+/// to record it, or for tests with '@no_capture', /dev/null for stdout and
+/// a pipe for stderr, whose end is kept
+enum OutputTarget {
+    Pipe(RawFd, RawFd),
+    StderrOnly(File, RawFd, RawFd)
 }
 
 /// Redirects stdout and stderr to the given file descriptor
@@ -162,19 +480,67 @@ fn redirect_output(target_file: RawFd) {
 /// Reads output from the given pipe set. Consumes the read and write pipes
 fn read_from_pipe(read_pipe: RawFd, write_pipe: RawFd) -> Result<String> {
     // Capture CC0 output
-    unistd::close(write_pipe).expect("Couldn't close write pipe");
-    
+    unistd::close(write_pipe).context("Couldn't close the write end of the pipe")?;
+
+    // File::from_raw_fd will close the read pipe for us
+    read_output(unsafe { File::from_raw_fd(read_pipe) })
+}
+
+/// Reads everything from a pipe until every write end of it is closed
+fn read_output(mut pipe: File) -> Result<String> {
     const PIPE_CAPACITY: usize = 65536;
     let mut bytes: Vec<u8> = Vec::with_capacity(PIPE_CAPACITY);
 
-    unsafe { File::from_raw_fd(read_pipe).read_to_end(&mut bytes)? }; 
-    // File::from_raw_fd will close the read pipe for us
+    if STREAM_OUTPUT.load(atomic::Ordering::Relaxed) {
+        let mut buffer = [0; 4096];
+        loop {
+            let n = pipe.read(&mut buffer)?;
+            if n == 0 {
+                break
+            }
+            // Losing the copy doesn't lose the recorded output
+            let _ = io::stderr().write_all(&buffer[..n]);
+            bytes.extend_from_slice(&buffer[..n]);
+        }
+    }
+    else {
+        pipe.read_to_end(&mut bytes)?;
+    }
 
     let output = String::from_utf8_lossy(&bytes).to_string();
     Ok(output)
 }
 
+/// Reads everything from a pipe like 'read_output', but only keeps the last
+/// STDERR_TAIL bytes, after DISCARDED_OUTPUT
+fn read_tail(mut pipe: File) -> Result<String> {
+    let mut tail: Vec<u8> = Vec::with_capacity(2 * STDERR_TAIL);
+    let mut buffer = [0; 4096];
+    loop {
+        let n = pipe.read(&mut buffer)?;
+        if n == 0 {
+            break
+        }
+        tail.extend_from_slice(&buffer[..n]);
+        if tail.len() > STDERR_TAIL {
+            tail.drain(..tail.len() - STDERR_TAIL);
+        }
+    }
+
+    Ok(format!("{}\n{}", DISCARDED_OUTPUT, String::from_utf8_lossy(&tail)))
+}
+
 fn set_resource_limits(memory: u64, time: u64) {
+    let (mem_limit, time_limit) = resource_limits(memory, time);
+
+    unsafe {
+        assert!(libc::setrlimit(libc::RLIMIT_AS, &mem_limit) >= 0);
+        assert!(libc::setrlimit(libc::RLIMIT_CPU, &time_limit) >= 0);
+    }
+}
+
+/// The (memory, CPU time) limits for a program
+fn resource_limits(memory: u64, time: u64) -> (libc::rlimit, libc::rlimit) {
     let mem_limit = libc::rlimit {
         rlim_cur: memory,
         rlim_max: memory
@@ -197,34 +563,92 @@ fn set_resource_limits(memory: u64, time: u64) {
         rlim_max: time.saturating_add(5)
     };
 
-    unsafe {
-        assert!(libc::setrlimit(libc::RLIMIT_AS, &mem_limit) >= 0);
-        assert!(libc::setrlimit(libc::RLIMIT_CPU, &time_limit) >= 0);
-    }
+    (mem_limit, time_limit)
 }
 
 #[cfg(test)]
 mod compile_tests {
     use super::*;
+    use std::path::PathBuf;
     use std::sync::Arc;
 
     const TEST_MEM: u64 = 4 * 1024 * 1024 * 1024;
 
     #[test]
     fn test() -> Result<()> {
-        let test = TestInfo {
-            execution: TestExecutionInfo {
-                compiler_options: vec![],
-                sources: vec!["test_resources/test.c0".to_string()],
-                directory: Arc::from("./")
-            },
-            specs: vec![]
-        };
+        let execution = TestExecutionInfo::new(vec![PathBuf::from("test_resources/test.c0")], Arc::from(Path::new("./")));
+        let test = TestInfo::new(execution, vec![]);
 
         let args = [CString::new("test_resources/test.c0").unwrap()];
-        compile(CString::new("bin/cc0")?, &args, 5, TEST_MEM)?.map_err(|e| anyhow!(e))?;
-        assert_eq!(execute(&test.execution, &CString::new("a.out").unwrap(), 5, TEST_MEM)?.1, Behavior::Return(Some(0)));
+        compile(CString::new("bin/cc0")?, &args, &[], 5, TEST_MEM)?.map_err(|e| anyhow!(e))?;
+        assert_eq!(execute(&test.execution, CString::new("a.out").unwrap(), &[], Path::new("."), 5, TEST_MEM)?.behavior, Behavior::Return(Some(0)));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod spawn_tests {
+    use super::*;
 
+    const TEST_MEM: u64 = 512 * 1024 * 1024;
+
+    fn run(script: &str, env: &[&str], clear_env: bool) -> Result<(WaitStatus, String)> {
+        let argv = [CString::new("sh")?, CString::new("-c")?, CString::new(script)?];
+        let argv: Vec<&CStr> = argv.iter().map(|arg| arg.as_ref()).collect();
+        let env = env.iter().map(|var| CString::new(*var)).collect::<Result<Vec<_>, _>>()?;
+        let env: Vec<&CStr> = env.iter().map(|var| var.as_ref()).collect();
+
+        let (read_pipe, write_pipe) = checked_pipe()?;
+        let child = spawn(&argv, &env, clear_env, Some(Path::new("/")),
+            (Stdio::from(write_pipe.try_clone()?), Stdio::from(write_pipe)), 5, TEST_MEM)?;
+        let output = read_output(read_pipe)?;
+        let (status, _) = wait_with_usage(child)?;
+        Ok((status, output))
+    }
+
+    #[test]
+    fn test() -> Result<()> {
+        let (status, output) = run("echo out; echo err >&2; pwd; exit 3", &[], false)?;
+        assert!(matches!(status, WaitStatus::Exited(_, 3)));
+        assert_eq!(output, "out\nerr\n/\n");
+
+        let (_, output) = run("echo $GREETING; ulimit -t", &["GREETING=a=b"], false)?;
+        assert_eq!(output, "a=b\n5\n");
+        let (_, output) = run("echo ${HOME:-none}", &[], true)?;
+        assert_eq!(output, "none\n");
+
+        let argv = [CString::new("/nonexistent/program")?];
+        let argv: Vec<&CStr> = argv.iter().map(|arg| arg.as_ref()).collect();
+        assert!(spawn(&argv, &[], false, None, (Stdio::null(), Stdio::null()), 5, TEST_MEM).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fork() -> Result<()> {
+        // Variables are added to c0check's own environment, or replace them
+        let args = [CString::new("-c")?, CString::new("echo $GREETING $HOME; pwd; test -n \"$PATH\"")?];
+        let env = [CString::new("GREETING=hi")?, CString::new("HOME=/home/c0")?];
+        let (status, output) = run_program(CString::new("sh")?, &args, &env, Some(Path::new("/")), 5, TEST_MEM)?;
+        assert!(matches!(status, WaitStatus::Exited(_, 0)), "{}", output);
+        assert_eq!(output, "hi /home/c0\n/\n");
+
+        let (status, _) = run_program(CString::new("sh")?, &args, &env, Some(Path::new("/nonexistent")), 5, TEST_MEM)?;
+        assert!(matches!(status, WaitStatus::Exited(_, EXEC_FAILURE_CODE)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_tail() -> Result<()> {
+        // The runtime's last message is kept even after a lot of output
+        let (read_pipe, mut write_pipe) = checked_pipe()?;
+        write_pipe.write_all(&[b'.'; 2 * STDERR_TAIL])?;
+        write_pipe.write_all(b"\nc0rt: assertion failed\n")?;
+        drop(write_pipe);
+
+        let output = read_tail(read_pipe)?;
+        assert!(output.starts_with(DISCARDED_OUTPUT) && output.ends_with("\nc0rt: assertion failed\n"));
+        assert_eq!(output.len(), DISCARDED_OUTPUT.len() + 1 + STDERR_TAIL);
         Ok(())
     }
 }
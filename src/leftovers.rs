@@ -0,0 +1,156 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use anyhow::{Context, Result};
+
+use crate::spec::TestInfo;
+
+/// How far a file's modification time can be outside a test's run and still be
+/// blamed on it, if no test was running then, since file systems keep times
+/// less precisely than the clock
+const MTIME_SLACK: Duration = Duration::from_millis(10);
+
+/// What was in each suite directory before the run, so that files which
+/// tests (or their hooks) leave behind can be found afterwards
+pub struct Snapshot {
+    /// Paths relative to each suite directory
+    suites: BTreeMap<Arc<Path>, HashSet<PathBuf>>
+}
+
+/// A file or directory which wasn't in a suite directory before the run
+pub struct Leftover {
+    pub path: PathBuf,
+    /// The tests from its suite which were running when it was last written
+    pub suspects: Vec<String>
+}
+
+impl Snapshot {
+    pub fn take(tests: &[TestInfo]) -> Snapshot {
+        let mut suites = BTreeMap::new();
+        for test in tests.iter() {
+            let dir = &test.execution.directory;
+            if !suites.contains_key(dir) {
+                let mut entries = HashSet::new();
+                walk(dir, Path::new(""), &mut |path| { entries.insert(path.to_path_buf()); true });
+                suites.insert(dir.clone(), entries);
+            }
+        }
+
+        Snapshot { suites }
+    }
+
+    /// Everything which is in a suite directory now but wasn't before, with the tests which
+    /// may have written it. New directories are listed rather than everything in them.
+    /// 'windows' says when each test was running
+    pub fn leftovers(&self, windows: &[(&TestInfo, SystemTime, SystemTime)]) -> Vec<Leftover> {
+        let mut leftovers = Vec::new();
+        for (dir, before) in self.suites.iter() {
+            walk(dir, Path::new(""), &mut |path| {
+                if before.contains(path) {
+                    return true
+                }
+
+                let path = dir.join(path);
+                let written = fs::symlink_metadata(&path).and_then(|metadata| metadata.modified()).ok();
+                let running = |slack: Duration| windows.iter()
+                    .filter(|(test, start, end)| test.execution.directory == *dir && match written {
+                        Some(written) => written + slack >= *start && written <= *end + slack,
+                        None => false
+                    })
+                    .map(|(test, _, _)| test.name())
+                    .collect::<Vec<_>>();
+                let mut suspects = running(Duration::from_secs(0));
+                if suspects.is_empty() {
+                    suspects = running(MTIME_SLACK);
+                }
+                leftovers.push(Leftover { path, suspects });
+                false
+            });
+        }
+
+        leftovers
+    }
+}
+
+/// Calls 'visit' with the path of everything under 'dir' relative to 'root', going into
+/// directories (but not symlinks to them) when it returns true
+fn walk(root: &Path, dir: &Path, visit: &mut dyn FnMut(&Path) -> bool) {
+    let entries = match fs::read_dir(root.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+
+    let mut paths: Vec<(PathBuf, bool)> = entries.filter_map(Result::ok)
+        .map(|entry| (dir.join(entry.file_name()), entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false)))
+        .collect();
+    paths.sort();
+    for (path, is_dir) in paths {
+        if visit(&path) && is_dir {
+            walk(root, &path, visit);
+        }
+    }
+}
+
+/// Deletes a leftover file or directory
+pub fn remove(leftover: &Leftover) -> Result<()> {
+    let path = &leftover.path;
+    let removed = if fs::symlink_metadata(path)?.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+    removed.context(format!("Couldn't delete '{}'", path.display()))
+}
+
+/// Lists the leftovers along with which tests may have left them
+pub fn print_report(leftovers: &[Leftover], test_dir: &Path, removed: bool) {
+    println!("\n🧹 Files the tests left in their directories{} ({}):\n",
+        if removed { ", which were deleted" } else { "" }, leftovers.len());
+    for leftover in leftovers.iter() {
+        let path = leftover.path.strip_prefix(test_dir).unwrap_or(&leftover.path);
+        let blame = match leftover.suspects.as_slice() {
+            [] => String::from("written when none of its suite's tests were running, e.g. by a hook"),
+            [test] => format!("written while {} was running", test),
+            tests => format!("written while {} were running", tests.join(", "))
+        };
+        println!("  {}: {}", path.display(), blame);
+    }
+}
+
+#[cfg(test)]
+mod leftovers_tests {
+    use super::*;
+    use crate::spec::TestExecutionInfo;
+    use crate::test_util::TempDir;
+
+    fn test_in(dir: &Path, source: &str) -> TestInfo {
+        TestInfo::new(TestExecutionInfo::for_source(dir.join(source)), Vec::new())
+    }
+
+    #[test]
+    fn test() -> Result<()> {
+        let dir = TempDir::new("leftovers");
+        fs::create_dir_all(dir.join("inputs"))?;
+        fs::write(dir.join("a.c0"), "//test return 0\n")?;
+        fs::write(dir.join("b.c0"), "//test return 0\n")?;
+        let tests = vec![test_in(&dir, "a.c0"), test_in(&dir, "b.c0")];
+        let snapshot = Snapshot::take(&tests);
+
+        let start = SystemTime::now();
+        fs::write(dir.join("out.txt"), "a")?;
+        fs::create_dir_all(dir.join("inputs/tmp/nested"))?;
+        let end = SystemTime::now();
+
+        let later = end + Duration::from_secs(3600);
+        let windows = vec![(&tests[0], start, end), (&tests[1], later, later)];
+        let leftovers = snapshot.leftovers(&windows);
+        let paths: Vec<&Path> = leftovers.iter().map(|leftover| leftover.path.strip_prefix(&dir).unwrap()).collect();
+        assert_eq!(paths, vec![Path::new("inputs/tmp"), Path::new("out.txt")]);
+        assert_eq!(leftovers[1].suspects, vec![tests[0].name()]);
+
+        for leftover in leftovers.iter() {
+            remove(leftover)?;
+        }
+        assert!(snapshot.leftovers(&windows).is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+use serde::Serialize;
+use anyhow::Result;
+
+use crate::spec::{Spec, TestInfo};
+
+/// What c0check knows about a test before running it
+#[derive(Debug, Serialize)]
+pub struct TestListing {
+    /// The name the test has in results, baselines, and --filter
+    pub name: String,
+    pub sources: Vec<PathBuf>,
+    pub compiler_options: Vec<String>,
+    /// The tests from '@depends' which have to pass first
+    pub depends: Vec<String>,
+    /// The tags from '@tags', for --tag and --skip-tag
+    pub tags: Vec<String>,
+    pub specs: Vec<String>
+}
+
+impl TestListing {
+    pub fn new(test: &TestInfo) -> TestListing {
+        TestListing {
+            name: test.name(),
+            sources: test.execution.sources.clone(),
+            compiler_options: test.execution.compiler_options.clone(),
+            depends: test.execution.depends.clone(),
+            tags: test.tags.clone(),
+            specs: test.specs.iter().map(Spec::to_string).collect()
+        }
+    }
+}
+
+/// Prints the tests as JSON, one object per test, for other tools
+pub fn print_json(tests: &[TestInfo]) -> Result<()> {
+    let listings: Vec<TestListing> = tests.iter().map(TestListing::new).collect();
+    println!("{}", serde_json::to_string_pretty(&listings)?);
+    Ok(())
+}
+
+/// Prints the tests for people to read
+pub fn print(tests: &[TestInfo]) {
+    for test in tests.iter() {
+        let listing = TestListing::new(test);
+        println!("{}", listing.name);
+
+        let sources: Vec<String> = listing.sources.iter().map(|source| source.display().to_string()).collect();
+        println!("    sources: {}", sources.join(" "));
+        if !listing.compiler_options.is_empty() {
+            println!("    compiler options: {}", listing.compiler_options.join(" "));
+        }
+        if !listing.depends.is_empty() {
+            println!("    depends on: {}", listing.depends.join(", "));
+        }
+        if !listing.tags.is_empty() {
+            println!("    tags: {}", listing.tags.join(", "));
+        }
+        for spec in listing.specs.iter() {
+            println!("    spec: {}", spec);
+        }
+    }
+}
+
+#[cfg(test)]
+mod list_tests_tests {
+    use super::*;
+    use crate::parse_spec::{self, ParseOptions};
+    use std::path::Path;
+    use std::sync::Arc;
+    use crate::spec::*;
+
+    #[test]
+    fn test() {
+        let (_, specs) = parse_spec::parse("//test safe => segfault; return 0", ParseOptions { require_test_marker: true }).unwrap();
+        let test = TestInfo {
+            execution: TestExecutionInfo {
+                compiler_options: vec![String::from("-d")],
+                depends: vec![String::from("setup.c0")],
+                ..TestExecutionInfo::new(vec![PathBuf::from("/tests/basic/lib.c0"), PathBuf::from("/tests/basic/main.c0")], Arc::from(Path::new("/tests/basic")))
+            },
+            specs,
+            tags: vec![String::from("gc")],
+            origin: None
+        };
+
+        let listing = TestListing::new(&test);
+        assert_eq!(listing.name, "basic/lib.c0 basic/main.c0 -d");
+        assert_eq!(listing.specs, vec!["safe => segfault", "return 0"]);
+
+        let json = serde_json::to_value(&listing).unwrap();
+        assert_eq!(json["compiler_options"], serde_json::json!(["-d"]));
+        assert_eq!(json["depends"], serde_json::json!(["setup.c0"]));
+        assert_eq!(json["tags"], serde_json::json!(["gc"]));
+    }
+}
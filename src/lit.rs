@@ -0,0 +1,164 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{bail, Context, Result};
+
+use crate::spec::*;
+
+/// Reads tests from LLVM lit-style directives in a test file which has no
+/// '//test' line. Each '// RUN:' line becomes a test, so existing tests from
+/// other harnesses can be used without rewriting them. Only a subset of lit
+/// is understood:
+///
+///```text
+/// // RUN: %cc0 [<options>] [<sources>] %s                 -> return *
+/// // RUN: not %cc0 [<options>] [<sources>] %s             -> error
+/// // RUN: [not] %cc0 ... %s | FileCheck %s                -> also checks the output
+/// // CHECK: <text>
+///```
+///
+/// Any '%<tool>' substitution stands for the executer being tested. Output
+/// from the program (or the compiler, for 'not') has to contain each
+/// '// CHECK:' line in order. Returns no tests if there are no RUN lines
+pub fn read_lit_tests(path: &Path, directory: &Arc<Path>) -> Result<Vec<TestInfo>> {
+    let contents = fs::read(path).context(format!("Couldn't read '{}'", path.display()))?;
+    let contents = String::from_utf8_lossy(&contents);
+    let (runs, checks) = find_directives(&contents);
+
+    runs.iter().map(|command| {
+        let (execution, specs) = parse_run_line(command, &checks, path, directory)
+            .context(format!("In 'RUN: {}'", command))?;
+        Ok(TestInfo::new(execution, specs))
+    }).collect()
+}
+
+/// Collects the RUN commands and CHECK patterns in a file. RUN lines can be
+/// continued onto the next line with a trailing backslash
+fn find_directives(contents: &str) -> (Vec<String>, Vec<String>) {
+    let mut runs: Vec<String> = Vec::new();
+    let mut checks: Vec<String> = Vec::new();
+    let mut continued = false;
+
+    for line in contents.lines() {
+        let comment = match line.trim_start().strip_prefix("//") {
+            Some(comment) => comment.trim_start(),
+            None => continue
+        };
+
+        if let Some(command) = comment.strip_prefix("RUN:") {
+            let command = command.trim();
+            let (command, continues) = match command.strip_suffix('\\') {
+                Some(command) => (command.trim_end(), true),
+                None => (command, false)
+            };
+
+            match runs.last_mut() {
+                Some(last) if continued => {
+                    last.push(' ');
+                    last.push_str(command);
+                },
+                _ => runs.push(command.to_string())
+            }
+            continued = continues;
+        }
+        else if let Some(pattern) = comment.strip_prefix("CHECK:") {
+            checks.push(pattern.trim().to_string());
+        }
+    }
+
+    (runs, checks)
+}
+
+fn parse_run_line(command: &str, checks: &[String], path: &Path, directory: &Arc<Path>) -> Result<(TestExecutionInfo, Specs)> {
+    let (command, file_check) = match command.split_once('|') {
+        Some((command, pipe)) => {
+            if !pipe.trim_start().starts_with("FileCheck") {
+                bail!("Only FileCheck can be piped to")
+            }
+            (command, true)
+        },
+        None => (command, false)
+    };
+
+    let mut words = command.split_ascii_whitespace().peekable();
+    let expect_failure = words.next_if_eq(&"not").is_some();
+
+    match words.next() {
+        Some(tool) if tool.starts_with('%') && tool != "%s" && tool != "%t" => (),
+        Some(tool) => bail!("Unsupported command '{}'", tool),
+        None => bail!("Empty RUN line")
+    }
+
+    let mut sources: Vec<PathBuf> = Vec::new();
+    let mut compiler_options: Vec<String> = Vec::new();
+    while let Some(word) = words.next() {
+        match word {
+            "%s" => sources.push(path.to_path_buf()),
+            // The harness decides where compiled tests go
+            "-o" => { words.next(); },
+            option if option.starts_with('-') => compiler_options.push(option.to_string()),
+            source if [".c0", ".c1", ".h0", ".h1"].iter().any(|&ext| source.ends_with(ext)) => {
+                sources.push(directory.join(source));
+            },
+            other => bail!("Unsupported argument '{}'", other)
+        }
+    }
+
+    if !sources.iter().any(|source| source == path) {
+        bail!("The test file (%s) isn't compiled")
+    }
+
+    let behavior = if expect_failure { Behavior::CompileError } else { Behavior::Return(None) };
+    let mut specs = vec![Spec::Behavior(behavior)];
+    if file_check {
+        if checks.is_empty() {
+            bail!("Output is piped to FileCheck, but there are no CHECK lines")
+        }
+        specs.push(Spec::Assertion(Assertion::Checks(checks.to_vec())));
+    }
+
+    let execution = TestExecutionInfo { compiler_options, ..TestExecutionInfo::new(sources, directory.clone()) };
+
+    Ok((execution, specs))
+}
+
+#[cfg(test)]
+mod lit_tests {
+    use super::*;
+
+    fn parse(command: &str, checks: &[&str]) -> Result<(TestExecutionInfo, Specs)> {
+        let checks: Vec<String> = checks.iter().map(|check| check.to_string()).collect();
+        parse_run_line(command, &checks, Path::new("/tests/lit/a.c0"), &Arc::from(Path::new("/tests/lit")))
+    }
+
+    #[test]
+    fn test_directives() {
+        let (runs, checks) = find_directives(
+            "// RUN: %cc0 -d %s \\\n//   RUN: | FileCheck %s\nint main() {\n  // CHECK: hello\n  //CHECK:world \n}\n");
+        assert_eq!(runs, vec!["%cc0 -d %s | FileCheck %s"]);
+        assert_eq!(checks, vec!["hello", "world"]);
+
+        assert_eq!(find_directives("int main() { return 0; }"), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_run_line() -> Result<()> {
+        let (execution, specs) = parse("%cc0 -d lib.c0 %s -o %t", &[])?;
+        assert_eq!(execution.sources, vec![Path::new("/tests/lit/lib.c0"), Path::new("/tests/lit/a.c0")]);
+        assert_eq!(execution.compiler_options, vec!["-d"]);
+        assert!(matches!(specs.as_slice(), [Spec::Behavior(Behavior::Return(None))]));
+
+        let (_, specs) = parse("not %c0 %s | FileCheck %s", &["error:"])?;
+        assert!(matches!(specs.as_slice(), [
+            Spec::Behavior(Behavior::CompileError),
+            Spec::Assertion(Assertion::Checks(checks))
+        ] if checks == &["error:"]));
+
+        assert!(parse("gcc %s", &[]).is_err());
+        assert!(parse("%cc0 lib.c0", &[]).is_err());
+        assert!(parse("%cc0 %s | grep foo", &[]).is_err());
+        assert!(parse("%cc0 %s | FileCheck %s", &[]).is_err());
+        assert!(parse("%cc0 %s && %t", &[]).is_err());
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
-use std::sync::{Mutex, atomic::{self, AtomicUsize}};
-use std::time::Instant;
+use std::sync::{Mutex, mpsc, atomic::{self, AtomicUsize}};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::env;
+use std::thread;
 use rayon::prelude::*;
-use anyhow::{Result, Error, Context};
+use anyhow::{anyhow, bail, Result, Error, Context};
 
 mod spec;
 mod discover_tests;
@@ -12,39 +17,316 @@ mod checker;
 mod executer;
 mod options;
 mod implementations;
+mod suite_config;
+mod baseline;
+mod runtime_comparison;
+mod suppressions;
+mod work_dir;
+mod fingerprint;
+mod corpus_manifest;
+mod lit;
+mod sources_test;
+mod migrate_specs;
+mod stats;
+mod coverage;
+mod mutation;
+mod compile_commands;
+mod hooks;
+mod image_diff;
+mod gc_stress;
+mod stress;
+mod budgets;
+mod run_dir;
+mod circuit_breaker;
+mod result_cache;
+mod throttle;
+mod safety_diff;
+mod predicate_summary;
+mod failure_groups;
+mod matchers;
+mod json_report;
+mod chaos;
+mod host_tools;
+mod last_failures;
+mod suite_libraries;
+mod dependencies;
+mod test_filter;
+mod shard;
+mod list_tests;
+mod executer_pool;
+mod owners;
+mod expectations;
+mod compile_limit;
+mod unified_diff;
+mod schema;
+mod storage;
+mod artifact_cache;
+mod suite_toolchains;
+mod ab_comparison;
+mod leftovers;
+mod synthetic;
+mod slow_tests;
+mod timeline;
+#[cfg(test)]
+mod test_util;
 
 use crate::spec::*;
 use crate::executer::Executer;
-use crate::checker::{Failure, TestResult};
+use crate::checker::{Checker, Failure, SkipReason, TestOutcome, TestResult};
 use crate::options::*;
 use crate::implementations::*;
+use crate::baseline::{Baseline, OutcomeChange};
+use crate::budgets::SuiteBudget;
+use crate::gc_stress::MemoryResult;
+use crate::leftovers::Leftover;
+use crate::work_dir::WorkDir;
+use crate::corpus_manifest::CorpusManifest;
+use crate::coverage::Coverage;
+use crate::hooks::{HookFailure, HookKind, HookRunner};
+use crate::run_dir::RunDir;
+use crate::circuit_breaker::{CircuitBreaker, Trip};
+use crate::result_cache::{CachedPass, ResultCache};
+use crate::executer_pool::ExecuterPool;
+use crate::compile_limit::CompileLimit;
+use crate::throttle::{Degradation, HostLimits, Throttle};
+use crate::json_report::{RecordedRun, Status, TestReport};
+use crate::chaos::Chaos;
+use crate::last_failures::LastFailures;
+use crate::dependencies::DependencyGraph;
+use crate::discover_tests::Exclusions;
+use crate::owners::Owners;
+use crate::storage::Storage;
+use crate::artifact_cache::{ArtifactCache, Primed};
+use crate::suite_toolchains::SuiteToolchains;
+use crate::ab_comparison::Outcome;
+use crate::timeline::{Timeline, TimelineExecuter};
 
 struct TestResults<'a> {
     failures: Vec<(&'a TestInfo, Failure)>,
     timeouts: Vec<&'a TestInfo>,
-    errors: Vec<(&'a TestInfo, Error)>
+    errors: Vec<(&'a TestInfo, Error)>,
+    notes: Vec<(&'a TestInfo, String)>,
+    artifact_sizes: Vec<(&'a TestInfo, u64)>,
+    run_times: Vec<(&'a TestInfo, Duration)>,
+    hook_failures: Vec<HookFailure>,
+    /// Tests which weren't run because their before hook failed
+    skipped: Vec<&'a TestInfo>,
+    /// Tests which weren't run because a 'skip' spec applied or the executer
+    /// can't run them, with why
+    skips: Vec<(&'a TestInfo, SkipReason, String)>,
+    /// Tests which weren't run because a test they depend on didn't pass, with its name
+    blocked: Vec<(&'a TestInfo, String)>,
+    /// Tests which failed as an 'xfail' spec says they do, with how they failed
+    expected_failures: Vec<(&'a TestInfo, Failure)>,
+    /// Tests which passed even though an 'xfail' spec says they fail
+    unexpected_passes: Vec<&'a TestInfo>,
+    /// Why the run was stopped early, if it was
+    trip: Option<Trip>,
+    /// Tests which weren't run because the run was stopped
+    not_run: Vec<&'a TestInfo>,
+    /// How many tests passed last time, and so weren't run again
+    cached: usize,
+    /// The toolchain's fingerprint which passes were cached under, if they were
+    toolchain: Option<String>,
+    /// How much the run was slowed down because the system was too busy, if it was
+    degradation: Option<Degradation>,
+    /// What happened to each test which got to run (or came from the cache), for --report-json
+    runs: Vec<(&'a TestInfo, RecordedRun)>,
+    /// When each test which got to run started and finished, including its hooks
+    /// and retries, so files left behind can be blamed on it (see --leftovers)
+    windows: Vec<(&'a TestInfo, SystemTime, SystemTime)>,
+    /// Artifacts which still couldn't be deleted at the end of the run (see --cleanup-failures)
+    cleanup_failures: Vec<(PathBuf, std::io::Error)>
 }
 
-fn run_tests<'a>(executer: &dyn Executer, tests: &'a [TestInfo]) -> TestResults<'a> {
+/// Tests which expect a compile error are never run, so they are compiled in
+/// a batch of their own, with more threads and less memory than other tests
+struct ErrorBatch {
+    pool: rayon::ThreadPool,
+    memory: u64
+}
+
+impl ErrorBatch {
+    /// 'None' if --error-test-threads turned batching off
+    fn new(options: &Options) -> Result<Option<ErrorBatch>> {
+        let threads = options.error_test_threads.unwrap_or_else(|| 2 * rayon::current_num_threads());
+        if threads == 0 || options.serial {
+            return Ok(None)
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("Couldn't start the threads for compile error tests")?;
+        Ok(Some(ErrorBatch { pool, memory: options.error_test_memory }))
+    }
+}
+
+/// How tests are scheduled, apart from the executer which runs them
+struct Schedule {
+    /// 'None' if tests expecting compile errors aren't batched
+    error_batch: Option<ErrorBatch>,
+    /// The threads which compile tests for the others to run (see --compile-jobs)
+    compile_pool: rayon::ThreadPool,
+    /// How many errors in a row stop the run (see --max-consecutive-errors)
+    max_errors: usize,
+    /// How many failures stop the run (see --max-failures). 0 if they don't
+    max_failures: usize,
+    /// How many times tests are retried when the system is too busy (see --contention-retries)
+    contention_retries: u32,
+    /// How busy the machine can be before tests wait to start (see --max-load)
+    host_limits: HostLimits,
+    /// Which tests passed before, so they needn't run again
+    cache: ResultCache,
+    /// Shuffles tests with --shuffle, and delays them too with --chaos
+    chaos: Option<Chaos>,
+    /// Whether tests which time out are run again (see --timeouts-are)
+    retry_timeouts: bool,
+    /// How many more times tests which fail are run (see --retries)
+    retries: u32,
+    /// Whether tests run one at a time, in order (see --serial)
+    serial: bool,
+    /// When each test ran, if it's wanted (see --timeline)
+    timeline: Option<Arc<Timeline>>
+}
+
+impl Schedule {
+    fn new(options: &Options, c0_homes: &BTreeMap<Arc<Path>, PathBuf>) -> Result<Schedule> {
+        Ok(Schedule {
+            error_batch: ErrorBatch::new(options)?,
+            compile_pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(options.compile_jobs.unwrap_or_else(rayon::current_num_threads))
+                .build()
+                .context("Couldn't start the threads which compile tests")?,
+            max_errors: options.max_consecutive_errors,
+            max_failures: if options.fail_fast { 1 } else { options.max_failures.unwrap_or(0) },
+            contention_retries: options.contention_retries,
+            host_limits: HostLimits { max_load: options.max_load, min_free_memory: options.min_free_memory },
+            cache: ResultCache::new(options, c0_homes)?,
+            chaos: Chaos::new(options),
+            retry_timeouts: options.timeouts_are == TimeoutPolicy::Retry,
+            retries: options.retries,
+            serial: options.serial,
+            timeline: options.timeline.as_ref().map(|_| Arc::new(Timeline::new()))
+        })
+    }
+}
+
+fn run_tests<'a>(
+    checker: &Checker,
+    hooks: &HookRunner,
+    executer: &dyn Executer,
+    tests: &'a [TestInfo],
+    schedule: &Schedule) -> TestResults<'a>
+{
     let failures: Mutex<Vec<(&TestInfo, Failure)>> = Mutex::new(Vec::new());
     let timeouts: Mutex<Vec<&TestInfo>> = Mutex::new(Vec::new());
     let errors: Mutex<Vec<(&TestInfo, Error)>> = Mutex::new(Vec::new());
+    let notes: Mutex<Vec<(&TestInfo, String)>> = Mutex::new(Vec::new());
+    let artifact_sizes: Mutex<Vec<(&TestInfo, u64)>> = Mutex::new(Vec::new());
+    let run_times: Mutex<Vec<(&TestInfo, Duration)>> = Mutex::new(Vec::new());
+    let hook_failures: Mutex<Vec<HookFailure>> = Mutex::new(Vec::new());
+    let skipped: Mutex<Vec<&TestInfo>> = Mutex::new(Vec::new());
+    let skips: Mutex<Vec<(&TestInfo, SkipReason, String)>> = Mutex::new(Vec::new());
+    let blocked: Mutex<Vec<(&TestInfo, String)>> = Mutex::new(Vec::new());
+    let expected_failures: Mutex<Vec<(&TestInfo, Failure)>> = Mutex::new(Vec::new());
+    let unexpected_passes: Mutex<Vec<&TestInfo>> = Mutex::new(Vec::new());
+    let passed: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    let not_run: Mutex<Vec<&TestInfo>> = Mutex::new(Vec::new());
+    let runs: Mutex<Vec<(&TestInfo, RecordedRun)>> = Mutex::new(Vec::new());
+    let windows: Mutex<Vec<(&TestInfo, SystemTime, SystemTime)>> = Mutex::new(Vec::new());
+    let breaker = CircuitBreaker::new(schedule.max_errors, schedule.max_failures);
+    let cached = AtomicUsize::new(0);
+    let throttle = Throttle::new(schedule.contention_retries, schedule.host_limits);
+
+    // Tests only run once the tests they depend on have passed
+    let graph = DependencyGraph::new(tests);
+
+    // Without a fingerprint of the toolchain, nothing can be cached. It only goes by
+    // the binaries' contents, so that runners which share --storage can use each other's passes
+    let toolchain = match fingerprint::toolchain_contents_key(executer) {
+        Ok(key) => Some(key),
+        Err(error) => {
+            eprintln!("⚠: not caching results: {:#}", error);
+            None
+        }
+    };
+    // Tests which depend on each other share state, so they always run
+    let cache_key = |test: &TestInfo| match &toolchain {
+        Some(toolchain) if !graph.is_linked(test) => schedule.cache.test_key(toolchain, test),
+        _ => None
+    };
 
     let count = AtomicUsize::new(1);
     let start = Instant::now();
     let len_width = tests.len().to_string().len();
 
-    tests.par_iter().for_each(|test| {
-        let status = checker::run_test(executer, test);
+    let next_progress = || {
         // Clear 'race condition' but 🤷‍♀️
         let i = count.fetch_add(1, atomic::Ordering::Relaxed);
-        let progress = format!("{:width$}/{:width$}", i, tests.len(), width = len_width);
+        format!("{:width$}/{:width$}", i, tests.len(), width = len_width)
+    };
+
+    let record = |test: &'a TestInfo, status: Result<TestOutcome>, duration: Option<Duration>| {
+        let progress = next_progress();
+
+        let status = status.map(|TestOutcome { result, run, notes: test_notes }| {
+            let mut pass = CachedPass::default();
+            let mut recorded = RecordedRun { behavior: None, output: None, compiler_output: None, duration, cached: false };
+            notes.lock().unwrap().extend(test_notes.into_iter().map(|note| (test, note)));
+            if let Some(run) = run {
+                pass = CachedPass { artifact_size: run.artifact_size, run_time: run.run_time, ..CachedPass::default() };
+                if !run.notes.is_empty() {
+                    notes.lock().unwrap().extend(run.notes.into_iter().map(|note| (test, note)));
+                }
+                if let Some(size) = run.artifact_size {
+                    artifact_sizes.lock().unwrap().push((test, size));
+                }
+                if let Some(time) = run.run_time {
+                    run_times.lock().unwrap().push((test, time));
+                }
+                recorded.behavior = Some(run.behavior);
+                recorded.output = Some(run.output);
+                recorded.compiler_output = run.compiler_output;
+            }
+            runs.lock().unwrap().push((test, recorded));
+            (result, pass)
+        });
+
+        // A test which passed before and doesn't now mustn't be counted as passing next time
+        if !matches!(status, Ok((TestResult::Success, _))) {
+            if let Some(key) = cache_key(test) {
+                schedule.cache.remove(&key);
+            }
+        }
 
         match status {
-            Ok(TestResult::Success) => {
+            Ok((TestResult::Success, pass)) => {
+                breaker.record_success();
                 eprintln!("{} ✅ {}", progress, test);
+                passed.lock().unwrap().insert(test.name());
+                if let Some(key) = cache_key(test) {
+                    schedule.cache.insert(key, pass);
+                }
+            },
+            Ok((TestResult::Skipped(reason, message), _)) => {
+                breaker.record_success();
+                eprintln!("{} ⏭️ {}: {}", progress, test.name(), message);
+                skips.lock().unwrap().push((test, reason, message));
+            },
+            Ok((TestResult::ExpectedFailure(failure), _)) => {
+                breaker.record_success();
+                eprintln!("{} ❎ {}: {}", progress, test, failure);
+                expected_failures.lock().unwrap().push((test, failure));
+            },
+            Ok((TestResult::UnexpectedPass, _)) => {
+                breaker.record_success();
+                eprintln!("{} ❗ {}: passed, but it's expected to fail", progress, test);
+                passed.lock().unwrap().insert(test.name());
+                unexpected_passes.lock().unwrap().push(test);
             },
-            Ok(TestResult::Mismatch(failure)) => {
+            Ok((TestResult::Mismatch(failure), _)) => {
+                let tripped = breaker.record_failure(&format!("{}: {}", test, failure));
                 if failure.is_timeout() {
                     eprintln!("{} ⌛ {}", progress, test);
                     timeouts.lock().unwrap().push(test);
@@ -53,13 +335,228 @@ fn run_tests<'a>(executer: &dyn Executer, tests: &'a [TestInfo]) -> TestResults<
                     eprintln!("{} ❌ {}: {}", progress, test, failure);
                     failures.lock().unwrap().push((test, failure));
                 }
+                if tripped {
+                    eprintln!("🛑 {}\n", breaker.trip().unwrap());
+                }
             },
             Err(error) => {
                 eprintln!("{} ⛔ {}: {:#}\n", progress, test, error);
+                runs.lock().unwrap().push((test, RecordedRun { behavior: None, output: None, compiler_output: None, duration, cached: false }));
+                if breaker.record_error(&format!("{:#}", error)) {
+                    eprintln!("🛑 {}\n", breaker.trip().unwrap());
+                }
                 errors.lock().unwrap().push((test, error));
             }
         }
-    });
+    };
+
+    // Once the breaker trips, the tests which are left aren't run
+    let stopped = |group: &[&'a TestInfo]| {
+        let tripped = breaker.is_tripped();
+        if tripped {
+            not_run.lock().unwrap().extend(group.iter().copied());
+        }
+        tripped
+    };
+
+    // Tests which passed last time are counted as passes without running
+    let from_cache = |test: &'a TestInfo| {
+        let pass = match cache_key(test).and_then(|key| schedule.cache.get(&key)) {
+            Some(pass) => pass,
+            None => return false
+        };
+
+        eprintln!("{} ✅ {} (cached)", next_progress(), test);
+        if let Some(size) = pass.artifact_size {
+            artifact_sizes.lock().unwrap().push((test, size));
+        }
+        if let Some(time) = pass.run_time {
+            run_times.lock().unwrap().push((test, time));
+        }
+        cached.fetch_add(1, atomic::Ordering::Relaxed);
+        passed.lock().unwrap().insert(test.name());
+        runs.lock().unwrap().push((test, RecordedRun { behavior: None, output: None, compiler_output: None, duration: None, cached: true }));
+        true
+    };
+
+    let failed_dependency = |test: &TestInfo| {
+        let passed = passed.lock().unwrap();
+        graph.dependencies(test).iter().map(|dependency| dependency.name()).find(|name| !passed.contains(name))
+    };
+
+    let run_with_hooks = |test: &'a TestInfo, run: &dyn Fn() -> Result<TestOutcome>| {
+        if stopped(&[test]) {
+            return
+        }
+
+        if let Some(dependency) = failed_dependency(test) {
+            eprintln!("{} ⏭️ {}: skipped (dependency '{}' failed)", next_progress(), test.name(), dependency);
+            blocked.lock().unwrap().push((test, dependency));
+            return
+        }
+
+        if let Some(chaos) = &schedule.chaos {
+            chaos.delay(&test.name());
+        }
+        if schedule.serial {
+            eprintln!("▶️ {}", test);
+        }
+
+        // Hooks run again along with the test, so failures of the earlier ones are kept
+        let run_again = |failure: Option<HookFailure>| {
+            if let Some(failure) = failure {
+                eprintln!("🪝 {}\n", failure);
+                hook_failures.lock().unwrap().push(failure);
+            }
+            hooks.around_test(test, || throttle.run(test, run))
+        };
+
+        let start = Instant::now();
+        let started = SystemTime::now();
+        let (mut status, mut failure) = hooks.around_test(test, || throttle.run(test, run));
+        if schedule.retry_timeouts && timed_out(&status) {
+            eprintln!("⌛ {}: timed out, so it's run again", test);
+            let (retried_status, retried_failure) = run_again(failure);
+            if is_pass(&retried_status) {
+                notes.lock().unwrap().push((test, String::from("Passed when it was run again after timing out")));
+            }
+            status = retried_status;
+            failure = retried_failure;
+        }
+
+        let mut retries = 0;
+        while retries < schedule.retries && is_failure(&status) {
+            retries += 1;
+            eprintln!("🔁 {}: failed, so it's run again ({}/{})", test, retries, schedule.retries);
+            let (retried_status, retried_failure) = run_again(failure);
+            if is_pass(&retried_status) {
+                notes.lock().unwrap().push((test, format!("Passed on retry {} of {}", retries, schedule.retries)));
+            }
+            status = retried_status;
+            failure = retried_failure;
+        }
+        windows.lock().unwrap().push((test, started, SystemTime::now()));
+        if let Some(timeline) = &schedule.timeline {
+            timeline.record(test.name(), "test", start);
+        }
+
+        match status {
+            Some(status) => record(test, status, Some(start.elapsed())),
+            None => {
+                eprintln!("{} 🪝 {}: not run since its before hook failed", next_progress(), test);
+                skipped.lock().unwrap().push(test);
+            }
+        }
+
+        if let Some(failure) = failure {
+            eprintln!("🪝 {}\n", failure);
+            hook_failures.lock().unwrap().push(failure);
+        }
+    };
+
+    // Each stage only depends on earlier ones, so it waits for them to finish
+    for stage in graph.stages() {
+        // Each test compiles on its own, so nothing runs out of order
+        if schedule.serial {
+            for test in stage.iter().filter(|test| !from_cache(test)) {
+                run_with_hooks(test, &|| checker.run_test(executer, test));
+            }
+            continue
+        }
+
+        // Groups whose tests are all cached don't even need to be compiled
+        let mut groups: Vec<Vec<&TestInfo>> = group_by_compilation(stage).into_iter()
+            .map(|group| group.into_iter().filter(|test| !from_cache(test)).collect::<Vec<_>>())
+            .filter(|group| !group.is_empty())
+            .collect();
+        if let Some(chaos) = &schedule.chaos {
+            for group in groups.iter_mut() {
+                chaos.shuffle(group, |test| test.name());
+            }
+            chaos.shuffle(&mut groups, |group| group[0].name());
+        }
+        else {
+            // Slow tests start first, so that the run doesn't end waiting for one
+            groups.sort_by_key(|group| !group.iter().any(|test| test.execution.slow));
+        }
+        let (mut error_groups, groups): (Vec<_>, Vec<_>) = match schedule.error_batch {
+            Some(_) => groups.into_iter()
+                .partition(|group| group.iter().all(|test| checker::expects_compile_error(executer, test))),
+            None => (Vec::new(), groups)
+        };
+
+        if let Some(batch) = schedule.error_batch.as_ref().filter(|_| !error_groups.is_empty()) {
+            // Going through a suite at a time keeps its files in the cache, unless --chaos or --shuffle shuffles them
+            match &schedule.chaos {
+                Some(chaos) => chaos.shuffle(&mut error_groups, |group| group[0].name()),
+                None => error_groups.sort_by(|a, b| a[0].execution.directory.cmp(&b[0].execution.directory))
+            }
+
+            batch.pool.install(|| error_groups.par_iter().for_each(|group| {
+                if stopped(group) {
+                    return
+                }
+
+                let mut execution = group[0].execution.clone();
+                execution.compilation_limits.memory = execution.compilation_limits.memory.or(Some(batch.memory));
+
+                match throttle.run(group[0], || checker::catch_panic(|| executer.compile(&execution))) {
+                    Ok(compilation) => for test in group.iter() {
+                        run_with_hooks(test, &|| checker.run_compiled_test(executer, test, &compilation))
+                    },
+                    Err(error) => for test in group.iter() {
+                        record(test, Err(anyhow!("{:#}", error)), None);
+                    }
+                }
+            }));
+        }
+
+        // Groups are compiled by their own threads and handed over to run, so later
+        // groups compile while earlier ones run. The channel only holds a few, so
+        // compilers don't get far ahead of the tests
+        let (compiled, to_run) = mpsc::sync_channel(schedule.compile_pool.current_num_threads());
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let compiled = compiled;
+                schedule.compile_pool.install(|| groups.par_iter().for_each(|group| {
+                    if stopped(group) {
+                        return
+                    }
+
+                    // Tests with their own before hook compile after it, in case it makes their sources
+                    let compiles_early = group.iter().any(|test| checker::needs_run(executer, test))
+                        && !matches!(group.as_slice(), [test] if test.execution.hooks.before.is_some());
+                    let compilation = if compiles_early {
+                        Some(throttle.run(group[0], || checker::catch_panic(|| executer.compile(&group[0].execution))))
+                    }
+                    else {
+                        None
+                    };
+
+                    // The receiver only goes away if running tests panicked
+                    let _ = compiled.send((group, compilation));
+                }));
+            });
+
+            to_run.into_iter().par_bridge().for_each(|(group, compilation)| {
+                if stopped(group) {
+                    return
+                }
+
+                match compilation {
+                    None => for test in group.iter() {
+                        run_with_hooks(test, &|| checker.run_test(executer, test));
+                    },
+                    Some(Ok(compilation)) => group.par_iter().for_each(|test| {
+                        run_with_hooks(test, &|| checker.run_compiled_test(executer, test, &compilation))
+                    }),
+                    Some(Err(error)) => for test in group.iter() {
+                        record(test, Err(anyhow!("{:#}", error)), None);
+                    }
+                }
+            });
+        });
+    }
 
     let elapsed = start.elapsed().as_secs_f64();
     println!("\nFinished testing in {:.3}s", elapsed);
@@ -67,52 +564,1375 @@ fn run_tests<'a>(executer: &dyn Executer, tests: &'a [TestInfo]) -> TestResults<
     TestResults {
         failures: failures.into_inner().unwrap(),
         timeouts: timeouts.into_inner().unwrap(),
-        errors: errors.into_inner().unwrap()
+        errors: errors.into_inner().unwrap(),
+        notes: notes.into_inner().unwrap(),
+        artifact_sizes: artifact_sizes.into_inner().unwrap(),
+        run_times: run_times.into_inner().unwrap(),
+        hook_failures: hook_failures.into_inner().unwrap(),
+        skipped: skipped.into_inner().unwrap(),
+        skips: skips.into_inner().unwrap(),
+        blocked: blocked.into_inner().unwrap(),
+        expected_failures: expected_failures.into_inner().unwrap(),
+        unexpected_passes: unexpected_passes.into_inner().unwrap(),
+        trip: breaker.trip(),
+        not_run: not_run.into_inner().unwrap(),
+        cached: cached.into_inner(),
+        toolchain,
+        degradation: throttle.degradation(),
+        runs: runs.into_inner().unwrap(),
+        windows: windows.into_inner().unwrap(),
+        cleanup_failures: executer::retry_cleanup()
     }
 }
 
-fn main() -> Result<()> {
-    let options = Options::from_args();
-    let Options { ref executer, ref test_dir, .. } = options;
-    
-    let executer: Box<dyn Executer> = match executer {
-        ExecuterKind::CC0 => Box::new(CC0Executer::new(&options)?),
-        ExecuterKind::C0VM => Box::new(C0VMExecuter::new(&options)?),
-        ExecuterKind::Coin => Box::new(CoinExecuter::new(&options)?)
+/// Whether a test ran and passed
+fn is_pass(status: &Option<Result<TestOutcome>>) -> bool {
+    matches!(status, Some(Ok(TestOutcome { result: TestResult::Success, .. })))
+}
+
+/// Whether a test failed or had an error, as opposed to passing or being skipped
+fn is_failure(status: &Option<Result<TestOutcome>>) -> bool {
+    matches!(status, Some(Ok(TestOutcome { result: TestResult::Mismatch(_), .. })) | Some(Err(_)))
+}
+
+/// Whether a test timed out, as opposed to failing some other way
+fn timed_out(status: &Option<Result<TestOutcome>>) -> bool {
+    matches!(status, Some(Ok(TestOutcome { result: TestResult::Mismatch(failure), .. })) if failure.is_timeout())
+}
+
+/// Groups tests which compile to the same thing (see 'compilation_key'),
+/// so that each group only has to be compiled once. This mostly helps
+/// sources.test suites, where many tests can share the same sources
+fn group_by_compilation<'a>(tests: &[&'a TestInfo]) -> Vec<Vec<&'a TestInfo>> {
+    let mut groups: Vec<Vec<&TestInfo>> = Vec::new();
+    let mut group_indices: HashMap<String, usize> = HashMap::new();
+
+    for &test in tests {
+        match fingerprint::compilation_key(&test.execution) {
+            Ok(key) => match group_indices.get(&key) {
+                Some(&i) => groups[i].push(test),
+                None => {
+                    group_indices.insert(key, groups.len());
+                    groups.push(vec![test]);
+                }
+            },
+            // Let the test fail on its own when the compiler can't read it either
+            Err(_) => groups.push(vec![test])
+        }
+    }
+
+    groups
+}
+
+fn create_executer(kind: &ExecuterKind, options: &Options, work_dir: &WorkDir) -> Result<Box<dyn Executer>> {
+    let work_dir = work_dir.path();
+    Ok(match kind {
+        ExecuterKind::CC0 => Box::new(CC0Executer::new(options, work_dir)?),
+        ExecuterKind::C0VM => Box::new(C0VMExecuter::new(options, work_dir)?),
+        ExecuterKind::Coin => Box::new(CoinExecuter::new(options, work_dir)?)
+    })
+}
+
+/// Wraps an executer so that it only compiles --compile-jobs tests at once, if that was given
+fn limit_compiles(executer: Box<dyn Executer>, options: &Options) -> Box<dyn Executer> {
+    match options.compile_jobs {
+        Some(jobs) => Box::new(CompileLimit::new(executer, jobs)),
+        None => executer
+    }
+}
+
+/// Gives suites pinned to another C0 distribution (see --suite-c0-home) executers of their own
+fn use_suite_toolchains(
+    executer: Box<dyn Executer>,
+    kind: &ExecuterKind,
+    options: &Options,
+    work_dir: &WorkDir,
+    c0_homes: &BTreeMap<Arc<Path>, PathBuf>) -> Result<Box<dyn Executer>>
+{
+    if c0_homes.is_empty() {
+        return Ok(executer)
+    }
+
+    for (suite, c0_home) in suite_toolchains::by_name(c0_homes) {
+        eprintln!("🧰 {} uses the C0 distribution in '{}'", suite, c0_home);
+    }
+    Ok(Box::new(SuiteToolchains::new(executer, c0_homes, |c0_home| {
+        let mut suite_options = options.clone();
+        suite_options.c0_home = Some(c0_home.to_path_buf());
+        create_executer(kind, &suite_options, work_dir)
+            .context(format!("Couldn't use the C0 distribution in '{}'", c0_home.display()))
+    })?))
+}
+
+/// Wraps an executer in the timeline, the compile limit, and the artifact cache, as the options ask
+fn wrap_executer(
+    executer: Box<dyn Executer>,
+    options: &Options,
+    work_dir: &WorkDir,
+    timeline: Option<&Arc<Timeline>>,
+    storage: Option<&Arc<dyn Storage>>) -> Result<Box<dyn Executer>>
+{
+    // Inside the compile limit, so that waiting for a turn to compile isn't counted as compiling
+    let executer: Box<dyn Executer> = match timeline {
+        Some(timeline) => Box::new(TimelineExecuter::new(executer, timeline.clone())),
+        None => executer
+    };
+    let executer = limit_compiles(executer, options);
+    Ok(match (options.artifact_cache, storage) {
+        (true, Some(storage)) => Box::new(ArtifactCache::new(executer, options, work_dir.path())?.with_storage(storage.clone())),
+        (true, None) => Box::new(ArtifactCache::new(executer, options, work_dir.path())?),
+        (false, _) => executer
+    })
+}
+
+/// Creates an executer for each member of --pool, sharing the tests between them
+fn create_pool(kind: &ExecuterKind, options: &Options, work_dir: &WorkDir) -> Result<Box<dyn Executer>> {
+    let mut members = Vec::new();
+    for member in options.pool.iter() {
+        let mut member_options = options.clone();
+        if let Some(c0_home) = &member.c0_home {
+            member_options.c0_home = Some(c0_home.clone());
+        }
+
+        let executer = create_executer(kind, &member_options, work_dir)
+            .context(format!("Couldn't create the pool member '{}:{}'", member.weight, member_options.c0_home().display()))?;
+        members.push((executer, member.weight));
+    }
+
+    eprintln!("Sharing the tests between {} executers", members.len());
+    Ok(Box::new(ExecuterPool::new(members)))
+}
+
+/// Makes clang drop any '-g' it is given when CC0 invokes it, by
+/// appending '-g0' to its arguments. The leading '#' stops clang from
+/// printing a message about it. Test programs get their own environment
+/// so this doesn't affect them. Fails if CC0's C compiler isn't clang,
+/// since nothing else reads the override
+fn disable_debug_info() -> Result<()> {
+    const CLANG_OVERRIDE: &str = "CCC_OVERRIDE_OPTIONS";
+
+    // CC0 runs 'gcc', which is clang on e.g. MacOS
+    let version = std::process::Command::new("gcc").arg("--version").output()
+        .context("Couldn't run gcc to check that --no-debug-info works with it")?;
+    if !String::from_utf8_lossy(&version.stdout).contains("clang") {
+        bail!("--no-debug-info only works when CC0's C compiler ('gcc') is clang, and it isn't here")
+    }
+
+    let overrides = match env::var(CLANG_OVERRIDE) {
+        Ok(existing) => format!("# {} +-g0", existing.trim_start_matches('#').trim()),
+        Err(_) => String::from("# +-g0")
+    };
+    env::set_var(CLANG_OVERRIDE, overrides);
+    Ok(())
+}
+
+/// Makes sure the test files haven't changed since the manifest was made,
+/// so that failures aren't blamed on the compiler
+fn verify_corpus(manifest: &Path, test_dir: &Path) -> Result<()> {
+    let manifest = CorpusManifest::load(manifest)?;
+    let problems = manifest.verify(test_dir);
+
+    if !problems.is_empty() {
+        for problem in problems.iter() {
+            eprintln!("❗ {}", problem);
+        }
+        bail!("{} of {} test files don't match the corpus manifest", problems.len(), manifest.file_count())
+    }
+
+    eprintln!("Verified {} test files", manifest.file_count());
+    Ok(())
+}
+
+/// Prints the timeouts, failures, errors, and notes from a run,
+/// leaving out the failures in 'known_failures' (see --baseline)
+fn print_details(results: &TestResults, known_failures: &HashSet<String>) {
+    let is_new = |test: &TestInfo| !known_failures.contains(&test.name());
+
+    println!("\nTimeouts:\n");
+    for test in results.timeouts.iter().filter(|test| is_new(test)) {
+        println!("⌛ {}", test);
+    }
+
+    // Tests which failed the same way are listed together, with the output once
+    println!("\nFailed tests:\n");
+    let failures = failure_groups::group_failures(results.failures.iter()
+        .filter(|(test, _)| is_new(test))
+        .map(|(test, failure)| (*test, failure.to_string())));
+    failure_groups::print_groups(&failures, "❌", "failed");
+
+    println!("\nErrors:\n");
+    let errors = failure_groups::group_failures(results.errors.iter()
+        .filter(|(test, _)| is_new(test))
+        .map(|(test, error)| (*test, format!("{:#}", error))));
+    failure_groups::print_groups(&errors, "⛔", "had errors");
+
+    if !known_failures.is_empty() {
+        println!("\n({} failures which are also in the baseline aren't shown)", known_failures.len());
+    }
+
+    if !results.skips.is_empty() || !results.blocked.is_empty() {
+        println!("\nSkipped tests:\n");
+        for (test, reason, message) in results.skips.iter() {
+            println!("⏭️ {}: {} ({})", test.name(), message, reason);
+        }
+        for (test, dependency) in results.blocked.iter() {
+            println!("⏭️ {}: skipped (dependency '{}' failed)", test.name(), dependency);
+        }
+    }
+
+    if !results.expected_failures.is_empty() {
+        println!("\nExpected failures:\n");
+        for (test, failure) in results.expected_failures.iter() {
+            println!("❎ {}: {}", test.name(), failure.to_string().lines().next().unwrap_or_default());
+        }
+    }
+
+    if !results.unexpected_passes.is_empty() {
+        println!("\nUnexpectedly passed (their 'xfail' specs can go):\n");
+        for test in results.unexpected_passes.iter() {
+            println!("❗ {}", test);
+        }
+    }
+
+    if !results.hook_failures.is_empty() {
+        println!("\nHook failures:\n");
+        for failure in results.hook_failures.iter() {
+            println!("🪝 {}", failure);
+        }
+    }
+
+    if !results.notes.is_empty() {
+        println!("\nNotes:\n");
+        for (test, note) in results.notes.iter() {
+            println!("📝 {}\n{}", test, note);
+        }
+    }
+
+    if !results.cleanup_failures.is_empty() {
+        println!("\nArtifacts which couldn't be deleted, even at the end of the run:\n");
+        for (path, error) in results.cleanup_failures.iter() {
+            println!("🧹 {}: {}", path.display(), error);
+        }
+    }
+}
+
+fn print_summary(results: &TestResults, test_count: usize) {
+    let successes = test_count - results.failures.len() - results.errors.len() - results.skipped.len()
+        - results.skips.len() - results.blocked.len() - results.not_run.len()
+        - results.expected_failures.len() - results.unexpected_passes.len();
+
+    println!("\nTest summary: ");
+    if results.cached > 0 {
+        println!("✅ Passed: {} ({} cached)", successes, results.cached);
+    }
+    else {
+        println!("✅ Passed: {}", successes);
+    }
+    println!("⌛ Timeouts: {}", results.timeouts.len());
+    println!("❌ Failed: {}", results.failures.len());
+    println!("⛔ Error: {}", results.errors.len());
+    if !results.expected_failures.is_empty() || !results.unexpected_passes.is_empty() {
+        println!("❎ XFAIL: {}", results.expected_failures.len());
+        println!("❗ XPASS: {}", results.unexpected_passes.len());
+    }
+    let skip_counts = skip_counts(results);
+    if !skip_counts.is_empty() {
+        let counts: Vec<String> = skip_counts.iter().map(|(reason, count)| format!("{}: {}", reason, count)).collect();
+        println!("⏭️ Skipped: {} ({})", skip_counts.values().sum::<usize>(), counts.join(", "));
+    }
+    if !results.hook_failures.is_empty() {
+        println!("🪝 Hook failures: {} ({} tests not run)", results.hook_failures.len(), results.skipped.len());
+    }
+    if let Some(trip) = &results.trip {
+        println!("🛑 Not run: {} (stopped {})", results.not_run.len(), trip.reason());
+    }
+    if !results.cleanup_failures.is_empty() {
+        println!("🧹 Cleanup failures: {} artifacts couldn't be deleted", results.cleanup_failures.len());
+    }
+    if let Some(degradation) = &results.degradation {
+        if degradation.retries > 0 {
+            println!("🐢 The system was too busy: {} retries, then at most {} tests at once",
+                degradation.retries, degradation.limit);
+        }
+        if degradation.held > 0 {
+            println!("🐢 The machine was overloaded, so {} tests waited to start", degradation.held);
+        }
+    }
+}
+
+/// Runs the tests with each of the other executers,
+/// then compares how long they took to run
+fn compare_executers(
+    checker: &Checker, 
+    hooks: &HookRunner,
+    executer_name: &'static str, 
+    results: &TestResults, 
+    compared_executers: &[Box<dyn Executer>], 
+    tests: &[TestInfo],
+    schedule: &Schedule)
+{
+    let mut executer_names = vec![executer_name];
+    let mut run_times: Vec<HashMap<String, Duration>> = vec![
+        results.run_times.iter().map(|(test, time)| (test.name(), *time)).collect()
+    ];
+
+    for executer in compared_executers.iter() {
+        let name = executer.properties().name;
+        eprintln!("\nTesting with {}", name);
+        println!("\nResults for {}:", name);
+
+        let results = run_tests(checker, hooks, &**executer, tests, schedule);
+        print_details(&results, &HashSet::new());
+        print_summary(&results, tests.len());
+
+        executer_names.push(name);
+        run_times.push(results.run_times.iter().map(|(test, time)| (test.name(), *time)).collect());
+    }
+
+    runtime_comparison::print_comparison(tests, &executer_names, &run_times);
+}
+
+/// How each test went in one side of an 'ab' run
+fn ab_outcomes(results: &TestResults, tests: &[TestInfo]) -> BTreeMap<String, Outcome> {
+    let statuses = test_statuses(results, &[]);
+    let runs: HashMap<String, &RecordedRun> = results.runs.iter().map(|(test, run)| (test.name(), run)).collect();
+    let run_times: HashMap<String, Duration> = results.run_times.iter().map(|(test, time)| (test.name(), *time)).collect();
+    let sizes: HashMap<String, u64> = results.artifact_sizes.iter().map(|(test, size)| (test.name(), *size)).collect();
+
+    tests.iter().map(|test| {
+        let name = test.name();
+        let run = runs.get(&name);
+        let outcome = Outcome {
+            status: statuses.get(&name).map(|(status, _)| *status).unwrap_or(Status::Passed),
+            behavior: run.and_then(|run| run.behavior).map(|behavior| behavior.to_string()),
+            output: run.and_then(|run| run.output.clone()),
+            run_time: run_times.get(&name).copied(),
+            artifact_size: sizes.get(&name).copied()
+        };
+        (name, outcome)
+    }).collect()
+}
+
+/// The tests which didn't pass in a run, including ones which weren't run
+fn failed_tests<'a>(results: &TestResults<'a>) -> Vec<&'a TestInfo> {
+    results.failures.iter().map(|(test, _)| *test)
+        .chain(results.timeouts.iter().copied())
+        .chain(results.errors.iter().map(|(test, _)| *test))
+        .chain(results.skipped.iter().copied())
+        .chain(results.blocked.iter().map(|(test, _)| *test))
+        .chain(results.not_run.iter().copied())
+        .collect()
+}
+
+/// The tests which passed in a run, which doesn't include ones a spec skipped
+fn passed_tests<'a>(results: &TestResults, tests: &'a [TestInfo]) -> Vec<&'a TestInfo> {
+    let failed: HashSet<String> = failed_tests(results).iter().map(|test| test.name())
+        .chain(results.skips.iter().map(|(test, _, _)| test.name()))
+        .chain(results.expected_failures.iter().map(|(test, _)| test.name()))
+        .collect();
+    tests.iter().filter(|test| !failed.contains(&test.name())).collect()
+}
+
+/// How each test which didn't pass ended up, with why if there's a reason. 'skipped_suites'
+/// are tests from suites whose before hook failed, so they weren't in the run
+fn test_statuses(results: &TestResults, skipped_suites: &[TestInfo]) -> HashMap<String, (Status, Option<String>)> {
+    let mut statuses: HashMap<String, (Status, Option<String>)> = HashMap::new();
+    for test in results.timeouts.iter() {
+        statuses.insert(test.name(), (Status::Timeout, None));
+    }
+    for (test, failure) in results.failures.iter() {
+        statuses.insert(test.name(), (Status::Failed, Some(failure.to_string())));
+    }
+    for (test, error) in results.errors.iter() {
+        statuses.insert(test.name(), (Status::Error, Some(format!("{:#}", error))));
+    }
+    for test in results.skipped.iter().copied().chain(skipped_suites.iter()) {
+        statuses.insert(test.name(), (Status::Skipped, Some(String::from("Its before hook failed"))));
+    }
+    for (test, _, message) in results.skips.iter() {
+        statuses.insert(test.name(), (Status::Skipped, Some(message.clone())));
+    }
+    for (test, dependency) in results.blocked.iter() {
+        statuses.insert(test.name(), (Status::Skipped, Some(format!("Its dependency '{}' failed", dependency))));
+    }
+    for test in results.not_run.iter() {
+        statuses.insert(test.name(), (Status::NotRun, None));
+    }
+    for (test, failure) in results.expected_failures.iter() {
+        statuses.insert(test.name(), (Status::ExpectedFailure, Some(failure.to_string())));
+    }
+    for test in results.unexpected_passes.iter() {
+        statuses.insert(test.name(), (Status::UnexpectedPass, None));
+    }
+
+    statuses
+}
+
+/// Why each test which wasn't run was skipped. Tests whose before hook
+/// failed are included, unlike in the summary's count of skipped tests
+fn skip_reasons(results: &TestResults, skipped_suites: &[TestInfo]) -> HashMap<String, SkipReason> {
+    let hook_failed = results.skipped.iter().copied().chain(skipped_suites.iter()).map(|test| (test, SkipReason::HookFailed));
+    let skips = results.skips.iter().map(|(test, reason, _)| (*test, *reason));
+    let blocked = results.blocked.iter().map(|(test, _)| (*test, SkipReason::DependencyFailed));
+    hook_failed.chain(skips).chain(blocked).map(|(test, reason)| (test.name(), reason)).collect()
+}
+
+/// How many tests were skipped for each reason, leaving out ones whose
+/// before hook failed, since the summary counts those with hook failures
+fn skip_counts(results: &TestResults) -> BTreeMap<SkipReason, usize> {
+    let mut counts = BTreeMap::new();
+    let reasons = results.skips.iter().map(|(_, reason, _)| *reason)
+        .chain(results.blocked.iter().map(|_| SkipReason::DependencyFailed));
+    for reason in reasons {
+        *counts.entry(reason).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Describes how each test went, for --report-json
+fn test_reports(executer: &dyn Executer, owners: &Owners, results: &TestResults, tests: &[TestInfo], skipped_suites: &[TestInfo]) -> Vec<TestReport> {
+    let mut statuses = test_statuses(results, skipped_suites);
+    let skip_reasons = skip_reasons(results, skipped_suites);
+    let runs: HashMap<String, &RecordedRun> = results.runs.iter().map(|(test, run)| (test.name(), run)).collect();
+    let run_times: HashMap<String, Duration> = results.run_times.iter().map(|(test, time)| (test.name(), *time)).collect();
+
+    tests.iter().chain(skipped_suites.iter()).map(|test| {
+        let name = test.name();
+        let (status, message) = statuses.remove(&name).unwrap_or((Status::Passed, None));
+        TestReport::new(executer, test, owners.of(test), status, message, runs.get(&name).copied(), run_times.get(&name).copied())
+            .with_skip_reason(skip_reasons.get(&name).copied())
+    }).collect()
+}
+
+/// Runs the tests which passed with each mutated cc0,
+/// and reports how many mutants they catch
+fn test_mutants(
+    checker: &Checker,
+    hooks: &HookRunner,
+    options: &Options,
+    work_dir: &WorkDir,
+    mutants_dir: &Path,
+    results: &TestResults,
+    tests: &[TestInfo]) -> Result<()>
+{
+    let passed = passed_tests(results, tests);
+    let mutants = mutation::find_mutants(mutants_dir)?;
+    let mutant_count = mutants.len();
+    let mut mutant_results = Vec::new();
+
+    for (i, mutant) in mutants.into_iter().enumerate() {
+        eprintln!("Testing mutant {}/{}: {}", i + 1, mutant_count, mutant.display());
+
+        let executer: Result<Box<dyn Executer>> = match options.executer() {
+            ExecuterKind::CC0 => CC0Executer::new(options, work_dir.path())
+                .and_then(|executer| executer.with_cc0(&mutant))
+                .map(|executer| Box::new(executer) as Box<dyn Executer>),
+            ExecuterKind::C0VM => C0VMExecuter::new(options, work_dir.path())
+                .and_then(|executer| executer.with_cc0(&mutant))
+                .map(|executer| Box::new(executer) as Box<dyn Executer>),
+            ExecuterKind::Coin => unreachable!("Checked in main")
+        };
+
+        let fate = match executer {
+            Ok(executer) => mutation::test_mutant(checker, hooks, &*executer, &passed),
+            Err(error) => mutation::MutantFate::Error(error)
+        };
+        mutant_results.push(mutation::MutantResult { mutant, fate });
+    }
+
+    mutation::print_report(&mutant_results, passed.len());
+    Ok(())
+}
+
+/// Where --storage keeps the result cache
+const CACHE_KEY: &str = "cache/results.json";
+
+/// Pushes the result cache, the run's directory and the JSON report to --storage. Failing to
+/// only warns, so that it doesn't hide how the tests did
+fn push_run(storage: &dyn Storage, options: &Options, run_dir: Option<&RunDir>) {
+    if let Err(error) = storage.put(&ResultCache::path(options), CACHE_KEY) {
+        eprintln!("⚠: couldn't push the result cache: {:#}", error);
+    }
+
+    if let Some(run_dir) = run_dir {
+        let key = format!("runs/{}", run_dir.path().file_name().unwrap_or_default().to_string_lossy());
+        match storage::put_dir(storage, run_dir.path(), &key) {
+            Ok(count) => eprintln!("☁️ Pushed {} files from this run to '{}'", count, storage.url(&key)),
+            Err(error) => eprintln!("⚠: couldn't push this run: {:#}", error)
+        }
+    }
+
+    // Named after the run, so that runners don't replace each other's reports
+    if let Some(report) = &options.report_json {
+        let run = match run_dir {
+            Some(run_dir) => Ok(run_dir.path().file_name().unwrap_or_default().to_string_lossy().into_owned()),
+            None => run_dir::timestamp()
+        };
+        let pushed = run.and_then(|run| {
+            let key = format!("reports/{}/{}", run, report.file_name().unwrap_or_default().to_string_lossy());
+            if options.split_report {
+                storage::put_dir(storage, report, &key)?;
+            }
+            else {
+                storage.put(report, &key)?;
+            }
+            Ok(key)
+        });
+        match pushed {
+            Ok(key) => eprintln!("☁️ Pushed the JSON report to '{}'", storage.url(&key)),
+            Err(error) => eprintln!("⚠: couldn't push the JSON report: {:#}", error)
+        }
+    }
+}
+
+/// Runs the tests which passed and should return with and without
+/// cc0's safety checks in both modes, returning how many behaved differently
+fn compare_safety(
+    checker: &Checker,
+    hooks: &HookRunner,
+    options: &Options,
+    work_dir: &WorkDir,
+    results: &TestResults,
+    tests: &[TestInfo]) -> Result<usize>
+{
+    let safe = CC0Executer::new(options, work_dir.path())?;
+    let unsafe_ = CC0Executer::new(options, work_dir.path())?.with_unsafe();
+
+    let comparable: Vec<&TestInfo> = passed_tests(results, tests).into_iter()
+        .filter(|test| safety_diff::is_comparable(&safe, &unsafe_, test))
+        .collect();
+    eprintln!("\nComparing {} tests with and without safety checks", comparable.len());
+
+    let comparisons = safety_diff::compare_tests(checker, hooks, &safe, &unsafe_, &comparable);
+    safety_diff::print_report(&comparisons);
+
+    Ok(comparisons.iter().filter(|result| matches!(result.comparison, safety_diff::Comparison::Diverged(_))).count())
+}
+
+/// What c0check exits with when it finds no tests, unless --allow-empty, so
+/// scripts can tell a wrong test directory from tests which failed
+const NO_TESTS_EXIT_CODE: i32 = 3;
+
+/// Discovery found no tests, which c0check exits with NO_TESTS_EXIT_CODE for
+#[derive(Debug, thiserror::Error)]
+#[error("Found no tests in '{}', which has:\n{}\nGive --allow-empty if this is expected", .directory.display(), .scan.join("\n"))]
+struct NoTests {
+    directory: PathBuf,
+    /// What discovery looked at, a line for each
+    scan: Vec<String>
+}
+
+/// The code to exit with after 'error' stopped c0check
+fn exit_code(error: &Error) -> i32 {
+    if error.is::<NoTests>() { NO_TESTS_EXIT_CODE } else { 1 }
+}
+
+/// Fails with NoTests if discovery found no tests, saying what it looked at
+fn check_not_empty(options: &Options, test_dir: &Path, tests: &[TestInfo]) -> Result<()> {
+    if !tests.is_empty() || options.allow_empty {
+        return Ok(())
+    }
+
+    let scan = discover_tests::describe_scan(test_dir, &Exclusions::new(options))
+        .into_iter()
+        .map(|line| format!("  {}", line))
+        .collect();
+    Err(NoTests { directory: test_dir.to_owned(), scan }.into())
+}
+
+/// Loads the tests without running them, failing if any couldn't be loaded
+fn dry_run(options: &Options) -> Result<()> {
+    let tests = discover_tests::discover(options.test_dir(), &Exclusions::new(options))?;
+    eprintln!("Discovered {} tests", tests.len());
+
+    // Problems with the tests can explain why none were found, so they're reported first
+    let warnings = discover_tests::take_warnings();
+    if !warnings.is_empty() {
+        eprintln!("Found {} problems with the tests:\n{}", warnings.len(), warnings.join("\n"));
+    }
+    check_not_empty(options, options.test_dir(), &tests)?;
+    if !warnings.is_empty() {
+        bail!("Some tests couldn't be loaded")
+    }
+
+    eprintln!("Every test was loaded and its specs parsed");
+    Ok(())
+}
+
+/// Runs a subcommand
+fn run_command(options: &Options, command: &Command) -> Result<()> {
+    match command {
+        Command::ExportSources { dir } => {
+            print!("{}", sources_test::export_sources(dir)?);
+        },
+        Command::SplitSources { dir } => {
+            let changed = sources_test::split_sources(dir)?;
+            eprintln!("Moved {} tests out of sources.test", changed.len());
+        },
+        Command::MigrateSpecs { test_dir, rename, dry_run } => {
+            let renames: HashMap<String, String> = rename.iter().cloned().collect();
+            let changes = migrate_specs::find_changes(test_dir, &renames)?;
+
+            for change in changes.iter() {
+                println!("--- {}:{}\n- {}\n+ {}", change.path.display(), change.line, change.old, change.new);
+            }
+
+            if *dry_run {
+                eprintln!("\n{} specs would be changed", changes.len());
+            }
+            else {
+                migrate_specs::apply_changes(&changes)?;
+                eprintln!("\nChanged {} specs", changes.len());
+            }
+        },
+        Command::Stats { test_dir } => {
+            let tests = discover_tests::discover(test_dir, &Exclusions::new(options))?;
+            stats::CorpusStats::new(&tests).print();
+        },
+        Command::ListTests { test_dir, json } => {
+            let mut tests = discover_tests::discover(test_dir, &Exclusions::new(options))?;
+            if !options.filter.is_empty() {
+                tests = test_filter::filter(&options.filter, tests);
+            }
+            tests = test_filter::filter_tags(&options.tag, &options.skip_tag, tests);
+            if let Some(shard) = options.shard {
+                tests = shard::filter(shard, tests);
+            }
+
+            if *json {
+                list_tests::print_json(&tests)?;
+            }
+            else {
+                list_tests::print(&tests);
+                eprintln!("\n{} tests", tests.len());
+            }
+        },
+        Command::Expectations { test_dir, executer, output } => {
+            let mut tests = discover_tests::discover(test_dir, &Exclusions::new(options))?;
+            if !options.filter.is_empty() {
+                tests = test_filter::filter(&options.filter, tests);
+            }
+            tests = test_filter::filter_tags(&options.tag, &options.skip_tag, tests);
+
+            let kinds = if executer.is_empty() {
+                vec![ExecuterKind::CC0, ExecuterKind::C0VM, ExecuterKind::Coin]
+            }
+            else {
+                executer.clone()
+            };
+            let executers: Vec<_> = kinds.iter().map(properties_of).collect();
+
+            expectations::Snapshot::new(&tests, &executers).write(output.as_deref())?;
+            if let Some(output) = output {
+                eprintln!("Wrote the expectations of {} tests to '{}'", tests.len(), output.display());
+            }
+        },
+        Command::PrimeCache { executer, test_dir } => {
+            if options.c0_home.is_none() {
+                bail!("Set $C0_HOME or give --c0-home before 'prime-cache'")
+            }
+            if options.no_debug_info {
+                disable_debug_info()?;
+            }
+
+            let mut tests = discover_tests::discover(test_dir, &Exclusions::new(options))?;
+            if !options.filter.is_empty() {
+                tests = test_filter::filter(&options.filter, tests);
+            }
+            tests = test_filter::filter_tags(&options.tag, &options.skip_tag, tests);
+            if let Some(shard) = options.shard {
+                tests = shard::filter(shard, tests);
+            }
+            suite_libraries::build(options, &mut tests)?;
+
+            let work_dir = WorkDir::new(options)?;
+            let c0_homes = suite_toolchains::c0_homes(options, &tests)?;
+            let base = create_executer(executer, options, &work_dir)?;
+            let executer = limit_compiles(use_suite_toolchains(base, executer, options, &work_dir, &c0_homes)?, options);
+            let mut cache = ArtifactCache::new(executer, options, work_dir.path())?;
+            if let Some(storage) = options.storage.as_deref().map(storage::open).transpose()? {
+                cache = cache.with_storage(storage);
+            }
+            let tests: Vec<&TestInfo> = tests.iter()
+                .filter(|test| checker::needs_run(&cache, test) && !checker::expects_compile_error(&cache, test))
+                .collect();
+            eprintln!("Compiling {} tests into '{}'", tests.len(), ArtifactCache::path(options).display());
+
+            let threads = rayon::ThreadPoolBuilder::new()
+                .num_threads(options.jobs.unwrap_or(0))
+                .build()
+                .context("Couldn't start the threads which compile tests")?;
+            let counts = threads.install(|| cache.prime(&tests));
+            let count = |primed| counts.get(&primed).copied().unwrap_or(0);
+            eprintln!("🔥 Cached {} new compilations, {} were already cached, and {} failed",
+                count(Primed::Compiled), count(Primed::AlreadyCached), count(Primed::Failed));
+            if count(Primed::NotNeeded) > 0 {
+                eprintln!("{} tests don't need compiling for this executer", count(Primed::NotNeeded));
+            }
+        },
+        Command::Stress { executer, test, instances, duration } => {
+            if options.c0_home.is_none() {
+                bail!("Set $C0_HOME or give --c0-home before 'stress'")
+            }
+            if *instances == 0 {
+                bail!("--instances has to be at least 1")
+            }
+
+            let mut tests = discover_tests::tests_for_file(test)?;
+            suite_libraries::build(options, &mut tests)?;
+            let work_dir = WorkDir::new(options)?;
+            let checker = Checker::new(options)?;
+            let hooks = HookRunner::new(options);
+            let executer = create_executer(executer, options, &work_dir)?;
+
+            let names: Vec<String> = tests.iter().map(TestInfo::name).collect();
+            eprintln!("Stressing {} for {}s with {} instances", names.join(", "), duration.as_secs(), instances);
+
+            let stats = stress::stress(&checker, &hooks, &*executer, &tests, *instances, *duration)?;
+            stress::print_report(&stats, *instances, *duration);
+        },
+        Command::Ab { executer, test_dir, baseline_home, candidate_home, slowdown_threshold } => {
+            if options.no_debug_info {
+                disable_debug_info()?;
+            }
+
+            let mut tests = discover_tests::discover(test_dir, &Exclusions::new(options))?;
+            if !options.filter.is_empty() {
+                tests = test_filter::filter(&options.filter, tests);
+            }
+            tests = test_filter::filter_tags(&options.tag, &options.skip_tag, tests);
+            if let Some(shard) = options.shard {
+                tests = shard::filter(shard, tests);
+            }
+            suite_libraries::build(options, &mut tests)?;
+
+            // Both sides have to run every test, even ones which passed last time
+            let mut options = options.clone();
+            options.force = true;
+            let work_dir = WorkDir::new(&options)?;
+            let checker = Checker::new(&options)?;
+            let hooks = HookRunner::new(&options);
+            let schedule = Schedule::new(&options, &BTreeMap::new())?;
+
+            let suites = hooks::suite_hooks(&tests)?;
+            let suite_hook_failures = hooks.run_suite_hooks(&suites, HookKind::Before);
+            if let Some((_, failure)) = suite_hook_failures.first() {
+                bail!("{}", failure)
+            }
+
+            let mut outcomes = Vec::new();
+            for (side, c0_home) in [("baseline", baseline_home), ("candidate", candidate_home)].iter() {
+                let mut side_options = options.clone();
+                side_options.c0_home = Some(c0_home.to_path_buf());
+                let side_executer = create_executer(executer, &side_options, &work_dir)
+                    .context(format!("Couldn't use the C0 distribution in '{}'", c0_home.display()))?;
+                let side_executer = limit_compiles(side_executer, &side_options);
+
+                eprintln!("\nTesting the {} in '{}'", side, c0_home.display());
+                let results = run_tests(&checker, &hooks, &*side_executer, &tests, &schedule);
+                print_summary(&results, tests.len());
+                outcomes.push(ab_outcomes(&results, &tests));
+            }
+
+            for (_, failure) in hooks.run_suite_hooks(&suites, HookKind::After).iter() {
+                eprintln!("🪝 {}\n", failure);
+            }
+
+            println!("\nComparing '{}' (baseline) with '{}' (candidate):", baseline_home.display(), candidate_home.display());
+            let thresholds = ab_comparison::Thresholds { slowdown: *slowdown_threshold, size: options.size_threshold };
+            let regressions = ab_comparison::print_report(&outcomes[0], &outcomes[1], &thresholds);
+            if regressions > 0 {
+                bail!("{} tests regressed with the candidate", regressions)
+            }
+        },
+        Command::SelfTest => {
+            let dir = env::temp_dir().join(format!("c0check-self-test-{}", std::process::id()));
+            let child = synthetic::Child::build(&dir)?;
+            let cases = synthetic::cases();
+            let mut wrong = 0;
+            for (case, behavior) in cases.iter().zip(child.check(&cases)) {
+                match behavior {
+                    Ok(behavior) if behavior == case.expected => println!("✅ {}: {}", case.name, behavior),
+                    Ok(behavior) => {
+                        wrong += 1;
+                        println!("❌ {}: expected {}, got {}", case.name, case.expected, behavior)
+                    },
+                    Err(error) => {
+                        wrong += 1;
+                        println!("⛔ {}: {:#}", case.name, error)
+                    }
+                }
+            }
+            let _ = fs::remove_dir_all(&dir);
+
+            if wrong > 0 {
+                bail!("{} of {} ways of ending were classified wrongly", wrong, cases.len())
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    // Printed here rather than returned, so that NoTests can exit with its own code
+    if let Err(error) = start() {
+        eprintln!("Error: {:?}", error);
+        std::process::exit(exit_code(&error))
+    }
+}
+
+/// Everything main does, returning why c0check stopped if it failed
+fn start() -> Result<()> {
+    let mut options = Options::from_args();
+    if options.paranoid {
+        launcher::paranoid();
+    }
+    if let Some(command) = &options.command {
+        return run_command(&options, command)
+    }
+    if options.dry_run {
+        return dry_run(&options)
+    }
+
+    match options.jobs {
+        Some(0) => bail!("--jobs has to be at least 1"),
+        Some(jobs) => rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .context("Couldn't start the threads which run tests")?,
+        None => ()
+    }
+    if options.compile_jobs == Some(0) {
+        bail!("--compile-jobs has to be at least 1")
+    }
+    if options.serial {
+        launcher::stream_output();
+    }
+    if options.cleanup_failures == CleanupPolicy::Ignore {
+        executer::ignore_cleanup_failures();
+    }
+
+    // Everything printed from here on is also saved in the run's directory
+    let run_dir = match &options.output_dir {
+        Some(base) => Some(RunDir::create(base, options.keep_runs)?),
+        None => None
+    };
+    if let Some(run_dir) = &run_dir {
+        eprintln!("Saving this run in '{}'", run_dir.path().display());
+        options.coverage_dir.get_or_insert_with(|| run_dir.path().join("coverage"));
+        options.image_diff_dir.get_or_insert_with(|| run_dir.path().join("image-diffs"));
+    }
+
+    let result = run(&options, run_dir.as_ref());
+    // Printed here rather than after returning, so that it's saved in the run's directory too
+    if let (Err(error), Some(run_dir)) = (&result, run_dir) {
+        eprintln!("Error: {:?}", error);
+        drop(run_dir);
+        std::process::exit(exit_code(error))
+    }
+    result
+}
+
+/// Runs the tests, with everything printed saved in 'run_dir' if there is one
+fn run(options: &Options, run_dir: Option<&RunDir>) -> Result<()> {
+    if !options.meta.is_empty() {
+        let tags: Vec<String> = options.meta.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        eprintln!("🏷️ Tagged {}", tags.join(", "));
+    }
+
+    let executer = options.executer();
+    let test_dir = options.test_dir();
+    check_executer_options(options, executer)?;
+
+    if options.no_debug_info {
+        disable_debug_info()?;
+    }
+
+    let coverage = Coverage::new(options)?;
+    if let Some(coverage) = &coverage {
+        coverage.clear()?;
+    }
+
+    let work_dir = WorkDir::new(options)?;
+    let checker = Checker::new(options)?;
+    let hooks = HookRunner::new(options);
+    let executer = if options.pool.is_empty() {
+        create_executer(executer, options, &work_dir)?
+    }
+    else {
+        create_pool(executer, options, &work_dir)?
+    };
+    let compared_executers = options.compare.iter()
+        .map(|kind| create_executer(kind, options, &work_dir).map(|executer| limit_compiles(executer, options)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let executer_name = executer.properties().name;
+    let baseline = match &options.baseline {
+        Some(path) => {
+            let baseline = Baseline::load(path)?;
+            baseline.check_executer(executer_name)?;
+            Some(baseline)
+        },
+        None => None
     };
 
     // Load test cases
     let test_dir = fs::canonicalize(test_dir).context("Couldn't resolve the test directory")?;
-    let tests = discover_tests::discover(&test_dir)?;
+    if let Some(manifest) = &options.verify_corpus {
+        verify_corpus(manifest, &test_dir)?;
+    }
+
+    let tests = discover_tests::discover(&test_dir, &Exclusions::new(options))?;
 
     eprintln!("Discovered {} tests", tests.len());
+    check_not_empty(options, &test_dir, &tests)?;
 
+    let mut tests = match select_tests(options, &test_dir, tests)? {
+        Some(tests) => tests,
+        None => return Ok(())
+    };
+
+    prepare_tests(options, &mut tests)?;
+
+    let c0_homes = suite_toolchains::c0_homes(options, &tests)?;
+    let executer = use_suite_toolchains(executer, options.executer(), options, &work_dir, &c0_homes)?;
     // Run test cases
-    let TestResults { failures, timeouts, errors } = run_tests(&*executer, &tests);
+    let mut schedule = Schedule::new(options, &c0_homes)?;
+    let storage = options.storage.as_deref().map(storage::open).transpose()?;
+    let executer = wrap_executer(executer, options, &work_dir, schedule.timeline.as_ref(), storage.as_ref())?;
+
+    if let Some(path) = &options.compile_commands {
+        let count = compile_commands::write(path, &*executer, &tests)?;
+        eprintln!("Wrote {} compile commands to '{}'", count, path.display());
+    }
+
+    // Taken before any hooks run, since what they leave behind is left over too
+    let snapshot = match options.leftovers {
+        LeftoverPolicy::Ignore => None,
+        _ => Some(leftovers::Snapshot::take(&tests))
+    };
+
+    if let Some(command) = &options.before_hook {
+        if let Err(failure) = hooks.run(String::from("--before-hook"), command, &test_dir) {
+            bail!("{}", failure)
+        }
+    }
+
+    // Tests from suites whose before hook failed aren't run
+    let suites = hooks::suite_hooks(&tests)?;
+    let mut suite_hook_failures = hooks.run_suite_hooks(&suites, HookKind::Before);
+    let failed_suites: HashSet<Arc<Path>> = suite_hook_failures.iter().map(|(dir, _)| dir.clone()).collect();
+    for (_, failure) in suite_hook_failures.iter() {
+        eprintln!("🪝 {}\n", failure);
+    }
+
+    let (tests, skipped_tests): (Vec<TestInfo>, Vec<TestInfo>) = tests.into_iter()
+        .partition(|test| !failed_suites.contains(&test.execution.directory));
+    if !skipped_tests.is_empty() {
+        eprintln!("Skipping {} tests from suites whose before hook failed", skipped_tests.len());
+    }
+
+    // Other runs' cache lets this one skip what they already passed
+    if let Some(storage) = &storage {
+        fetch_shared_results(&**storage, options, &mut schedule.cache);
+    }
+
+    match &schedule.chaos {
+        Some(chaos) if chaos.delays() => eprintln!("🎲 Shuffling and delaying tests with --chaos-seed {}", chaos.seed()),
+        Some(chaos) => eprintln!("🎲 Shuffling tests with --seed {}", chaos.seed()),
+        None => ()
+    }
+    let results = run_tests(&checker, &hooks, &*executer, &tests, &schedule);
+    let stopped = results.trip.is_some();
+    if let (Some(path), Some(timeline)) = (&options.timeline, &schedule.timeline) {
+        match timeline.write(path) {
+            Ok(()) => eprintln!("Wrote a timeline of the run to '{}'", path.display()),
+            Err(error) => eprintln!("⚠: couldn't write the timeline: {:#}", error)
+        }
+    }
+    if let Err(error) = schedule.cache.save() {
+        eprintln!("⚠: couldn't save which tests passed: {:#}", error);
+    }
+
+    // Even a run which was stopped says which tests still need to pass
+    let last_failures = LastFailures::new(&test_dir, failed_tests(&results).into_iter().chain(skipped_tests.iter()));
+    if let Err(error) = last_failures.save(&LastFailures::path(options)) {
+        eprintln!("⚠: couldn't save which tests failed: {:#}", error);
+    }
+
+    let gc_results = if options.gc_stress && !stopped {
+        let gc_tests: Vec<&TestInfo> = passed_tests(&results, &tests).into_iter()
+            .filter(|test| gc_stress::is_gc_test(test) && checker::needs_run(&*executer, test))
+            .collect();
+        eprintln!("\nStressing {} gc tests", gc_tests.len());
+        gc_stress::stress_tests(&checker, &hooks, &*executer, &gc_tests, options.test_memory)
+    }
+    else {
+        Vec::new()
+    };
+
+    let meta: BTreeMap<String, String> = options.meta.iter().cloned().collect();
+    let owners = Owners::load(tests.iter().chain(skipped_tests.iter()))?;
+    let mut current = run_baseline(executer_name, &results, &gc_results, &tests, &skipped_tests);
+    current.meta = meta.clone();
+
+    // Unlike the baseline, this says which tests weren't run
+    if options.report_json.is_some() || run_dir.is_some() {
+        let reports = test_reports(&*executer, &owners, &results, &tests, &skipped_tests);
+        write_reports(options, run_dir, executer_name, &meta, &suite_toolchains::by_name(&c0_homes), &reports)?;
+    }
+
+    // A run which was stopped would make a misleading baseline
+    if !stopped {
+        if let Some(path) = &options.save_baseline {
+            current.save(path)?;
+        }
+        if let Some(run_dir) = &run_dir {
+            current.save(&run_dir.path().join("baseline.json"))?;
+        }
+    }
     
-    // Report results
-    let successes = tests.len() - failures.len() - errors.len();
+    // Report results. Failures the baseline already had aren't repeated
+    let known_failures = match &baseline {
+        Some(baseline) => baseline.known_failures(&current),
+        None => HashSet::new()
+    };
+    print_details(&results, &known_failures);
 
-    println!("\nTimeouts:\n");
-    for test in timeouts.iter() {
-        println!("⌛ {}", test);
+    let outcome_changes = match &baseline {
+        Some(baseline) => baseline.outcome_changes(&current),
+        None => Vec::new()
+    };
+    if !outcome_changes.is_empty() {
+        baseline::print_changes(&outcome_changes);
     }
 
-    println!("\nFailed tests:\n");
-    for (test, failure) in failures.iter() {
-        println!("❌ {}\n{}", test, failure);
+    if let Some(baseline) = &baseline {
+        print_size_regressions(baseline, &current, options.size_threshold);
+    }
+    if options.gc_stress {
+        gc_stress::print_report(&gc_results);
+        if let Some(baseline) = &baseline {
+            print_memory_regressions(baseline, &current, options.size_threshold);
+        }
     }
 
-    println!("\nErrors:\n");
-    for (test, error) in errors.iter() {
-        println!("⛔ {}\n{:#}", test, error);
+    suggest_slow_tests(options, &*executer, &schedule.cache, &results, &tests);
+
+    print_summary(&results, tests.len());
+
+    if options.by_predicate {
+        let counts = predicate_summary::summarize(&executer.properties(), &passed_tests(&results, &tests), &failed_tests(&results));
+        predicate_summary::print_summary(&counts);
     }
 
-    println!("\nTest summary: ");
-    println!("✅ Passed: {}", successes);
-    println!("⌛ Timeouts: {}", timeouts.len());
-    println!("❌ Failed: {}", failures.len());
-    println!("⛔ Error: {}", errors.len());
+    // Tests from suites whose before hook failed count against their budget too,
+    // but timeouts don't if they're only warnings
+    let mut failed = failed_tests(&results);
+    failed.extend(skipped_tests.iter());
+    if options.timeouts_are == TimeoutPolicy::Warn && !results.timeouts.is_empty() {
+        let timeouts: HashSet<String> = results.timeouts.iter().map(|test| test.name()).collect();
+        failed.retain(|test| !timeouts.contains(&test.name()));
+        println!("\n⚠: {} timeouts are only warnings, because of --timeouts-are warn", results.timeouts.len());
+    }
+    let budgets = budgets::check_budgets(tests.iter().chain(skipped_tests.iter()), &failed)?;
+    if !budgets.is_empty() {
+        budgets::print_budgets(&budgets);
+    }
+    if owners.any() {
+        owners::print_routing(&owners, failed.iter().copied());
+    }
+
+    if !compared_executers.is_empty() && !stopped {
+        compare_executers(&checker, &hooks, executer_name, &results, &compared_executers, &tests, &schedule);
+    }
+
+    if let (Some(mutants_dir), false) = (&options.mutants, stopped) {
+        test_mutants(&checker, &hooks, options, &work_dir, mutants_dir, &results, &tests)?;
+    }
+
+    let divergences = if options.safety_diff && !stopped {
+        compare_safety(&checker, &hooks, options, &work_dir, &results, &tests)?
+    }
+    else {
+        0
+    };
+
+    let started_suites: Vec<_> = suites.into_iter().filter(|(dir, _)| !failed_suites.contains(dir)).collect();
+    suite_hook_failures.extend(hooks.run_suite_hooks(&started_suites, HookKind::After));
+    run_after_hooks(options, &hooks, &test_dir, suite_hook_failures);
+
+    let leftovers = match snapshot {
+        Some(snapshot) => handle_leftovers(options, snapshot, &results, &test_dir),
+        None => Vec::new()
+    };
+
+    if let Some(coverage) = &coverage {
+        let merged = coverage.merge()?;
+        println!("\nCoverage data merged into '{}'", merged.display());
+    }
+
+    // Failed runs are the ones worth keeping
+    if let Some(storage) = &storage {
+        push_run(&**storage, options, run_dir);
+    }
+
+    check_run(options, &results, &outcome_changes, &budgets, divergences, &leftovers)
+}
+
+/// Options which only some executers support
+fn check_executer_options(options: &Options, executer: &ExecuterKind) -> Result<()> {
+    if options.save_files.is_some() && !matches!(executer, ExecuterKind::CC0) {
+        bail!("--save-files is only supported by the cc0 executer")
+    }
+
+    if options.safety_diff && !matches!(executer, ExecuterKind::CC0) {
+        bail!("--safety-diff is only supported by the cc0 executer")
+    }
+
+    if options.mutants.is_some() && matches!(executer, ExecuterKind::Coin) {
+        bail!("--mutants needs an executer which uses cc0")
+    }
+
+    Ok(())
+}
+
+/// Narrows the discovered tests down to the ones the options ask for, and gives ones
+/// tagged '@slow' their time limit. 'None' means there's nothing to run, which isn't an error
+fn select_tests(options: &Options, test_dir: &Path, mut tests: Vec<TestInfo>) -> Result<Option<Vec<TestInfo>>> {
+    if !options.filter.is_empty() {
+        let discovered = tests.len();
+        tests = test_filter::filter(&options.filter, tests);
+        if tests.is_empty() {
+            bail!("None of the {} tests match --filter", discovered)
+        }
+        eprintln!("Running the {} tests which match --filter", tests.len());
+    }
+
+    if !options.tag.is_empty() || !options.skip_tag.is_empty() {
+        let before = tests.len();
+        tests = test_filter::filter_tags(&options.tag, &options.skip_tag, tests);
+        if tests.is_empty() {
+            bail!("None of the {} tests match --tag and --skip-tag", before)
+        }
+        eprintln!("Running the {} of {} tests which match --tag and --skip-tag", tests.len(), before);
+    }
+
+    if let Some(shard) = options.shard {
+        let before = tests.len();
+        tests = shard::filter(shard, tests);
+        eprintln!("Running the {} of {} tests in shard {}/{}", tests.len(), before, shard.index, shard.count);
+    }
+
+    if options.rerun_failed {
+        let last = LastFailures::load(&LastFailures::path(options))?;
+        if last.is_empty() {
+            eprintln!("No tests failed last time");
+            return Ok(None)
+        }
+
+        tests = last.filter(test_dir, tests)?;
+        eprintln!("Rerunning {} of the {} tests which failed last time", tests.len(), last.len());
+    }
+
+    if options.fast {
+        let before = tests.len();
+        tests.retain(|test| !test.execution.slow);
+        eprintln!("Leaving out {} tests tagged @slow, because of --fast", before - tests.len());
+    }
+    // An explicit @timeout wins over --slow-factor
+    for test in tests.iter_mut().filter(|test| test.execution.slow) {
+        test.execution.run_limits.time.get_or_insert(options.test_time * options.slow_factor);
+    }
+
+    Ok(Some(tests))
+}
+
+/// Checks which tools the tests require, and builds the libraries their suites declare
+fn prepare_tests(options: &Options, tests: &mut [TestInfo]) -> Result<()> {
+    // Tests which require tools this machine doesn't have are skipped
+    let missing_tools = host_tools::probe_tests(tests);
+    if !missing_tools.is_empty() {
+        eprintln!("🔧 This machine doesn't have {}, so tests which require them are skipped", missing_tools.join(", "));
+    }
+
+    let libraries = suite_libraries::build(options, tests)?;
+    if libraries > 0 {
+        eprintln!("Built {} libraries declared in suite.toml files", libraries);
+    }
+    Ok(())
+}
+
+/// Merges the result cache other runs shared through --storage into this run's
+fn fetch_shared_results(storage: &dyn Storage, options: &Options, cache: &mut ResultCache) {
+    let shared = options.cache_dir().join(format!(".shared-results-{}.json", std::process::id()));
+    let fetched = fs::create_dir_all(options.cache_dir())
+        .context(format!("Couldn't create '{}'", options.cache_dir().display()))
+        .and_then(|_| storage.get(CACHE_KEY, &shared));
+    match fetched {
+        Ok(true) => {
+            let added = cache.merge(&shared);
+            eprintln!("☁️ Fetched {} passes from the result cache in '{}'", added, storage.url(CACHE_KEY));
+        },
+        Ok(false) => (),
+        Err(error) => eprintln!("⚠: couldn't fetch the result cache: {:#}", error)
+    }
+    let _ = fs::remove_file(&shared);
+}
+
+/// The baseline of this run, to compare with --baseline and to save
+fn run_baseline(executer_name: &str, results: &TestResults, gc_results: &[MemoryResult], tests: &[TestInfo], skipped_tests: &[TestInfo])
+    -> Baseline {
+    let mut current = Baseline::new(executer_name);
+    for (test, size) in results.artifact_sizes.iter() {
+        current.entry(test.name()).artifact_size = Some(*size);
+    }
+    for result in gc_results.iter() {
+        if let Ok(memory) = result.min_memory {
+            current.entry(result.test.name()).min_memory = Some(memory);
+        }
+    }
+    let statuses = test_statuses(results, skipped_tests);
+    let behaviors: HashMap<String, Behavior> = results.runs.iter()
+        .filter_map(|(test, run)| Some((test.name(), run.behavior?)))
+        .collect();
+    for test in tests.iter().chain(skipped_tests.iter()) {
+        let name = test.name();
+        let outcome = statuses.get(&name).map_or(Status::Passed, |(status, _)| *status);
+        let behavior = behaviors.get(&name).map(Behavior::to_string);
+        let entry = current.entry(name);
+        entry.outcome = Some(outcome);
+        entry.behavior = behavior;
+    }
+    current
+}
+
+/// Writes the JSON report to --report-json and the run directory
+fn write_reports(options: &Options, run_dir: Option<&RunDir>, executer_name: &str, meta: &BTreeMap<String, String>,
+    toolchains: &BTreeMap<String, String>, reports: &[TestReport]) -> Result<()> {
+    let write = |path: &Path| if options.split_report {
+        json_report::write_split(path, executer_name, meta, toolchains, reports)
+    }
+    else {
+        json_report::write(path, executer_name, meta, toolchains, reports)
+    };
+    if let Some(path) = &options.report_json {
+        write(path)?;
+    }
+    if let Some(run_dir) = run_dir {
+        write(&run_dir.path().join(if options.split_report { "results" } else { "results.json" }))?;
+    }
+    Ok(())
+}
+
+fn print_size_regressions(baseline: &Baseline, current: &Baseline, threshold: f64) {
+    let size_regressions = baseline.size_regressions(current, threshold);
+    if !size_regressions.is_empty() {
+        println!("\nSize regressions:\n");
+        for regression in size_regressions.iter() {
+            let growth = (regression.new_size as f64 / regression.old_size as f64 - 1.) * 100.;
+            println!("📈 {}: {} → {} bytes (+{:.1}%)", regression.test, regression.old_size, regression.new_size, growth);
+        }
+    }
+}
+
+fn print_memory_regressions(baseline: &Baseline, current: &Baseline, threshold: f64) {
+    let memory_regressions = baseline.memory_regressions(current, threshold);
+    if !memory_regressions.is_empty() {
+        println!("\nMemory regressions:\n");
+        for regression in memory_regressions.iter() {
+            let growth = (regression.new_size as f64 / regression.old_size as f64 - 1.) * 100.;
+            println!("📈 {}: {} → {} (+{:.1}%)", regression.test,
+                gc_stress::format_memory(regression.old_size),
+                gc_stress::format_memory(regression.new_size),
+                growth);
+        }
+    }
+}
+
+/// Lists tests which keep getting close to their time limit, going by the run times in the result cache
+fn suggest_slow_tests(options: &Options, executer: &dyn Executer, cache: &ResultCache, results: &TestResults, tests: &[TestInfo]) {
+    // Tests which are expected to loop always use all of their time
+    let timed_tests: Vec<&TestInfo> = tests.iter()
+        .filter(|test| !checker::expected_behaviors(executer, test).contains(&Behavior::InfiniteLoop))
+        .collect();
+    let cached_run_times = |test: &TestInfo| results.toolchain.as_ref()
+        .and_then(|toolchain| cache.test_key(toolchain, test))
+        .map(|key| cache.run_times(&key))
+        .unwrap_or_default();
+    let slow_suggestions = slow_tests::suggestions(&timed_tests, cached_run_times, options.test_time);
+    if !slow_suggestions.is_empty() {
+        slow_tests::print_suggestions(&slow_suggestions);
+    }
+}
+
+/// Runs --after-hook, and reports it and the suites' after hooks if they failed
+fn run_after_hooks(options: &Options, hooks: &HookRunner, test_dir: &Path, suite_hook_failures: Vec<(Arc<Path>, HookFailure)>) {
+    let mut hook_failures: Vec<HookFailure> = suite_hook_failures.into_iter().map(|(_, failure)| failure).collect();
+
+    if let Some(command) = &options.after_hook {
+        if let Err(failure) = hooks.run(String::from("--after-hook"), command, test_dir) {
+            hook_failures.push(failure);
+        }
+    }
+
+    if !hook_failures.is_empty() {
+        println!("\nSuite hook failures:\n");
+        for failure in hook_failures.iter() {
+            println!("🪝 {}", failure);
+        }
+    }
+}
+
+/// Finds the files tests left in their directories, reports them, and removes them with --leftovers clean
+fn handle_leftovers(options: &Options, snapshot: leftovers::Snapshot, results: &TestResults, test_dir: &Path) -> Vec<Leftover> {
+    let leftovers = snapshot.leftovers(&results.windows);
+    if !leftovers.is_empty() {
+        let clean = options.leftovers == LeftoverPolicy::Clean;
+        if clean {
+            for leftover in leftovers.iter() {
+                if let Err(error) = leftovers::remove(leftover) {
+                    eprintln!("⚠: {:#}", error);
+                }
+            }
+        }
+        leftovers::print_report(&leftovers, test_dir, clean);
+    }
+    leftovers
+}
+
+/// Fails the run for what the options say should fail it, besides the tests' own failures
+fn check_run(options: &Options, results: &TestResults, outcome_changes: &[OutcomeChange], budgets: &[SuiteBudget],
+    divergences: usize, leftovers: &[Leftover]) -> Result<()> {
+    if let Some(trip) = &results.trip {
+        bail!("{}", trip)
+    }
+
+    let regressions = outcome_changes.iter()
+        .filter(|change| change.is_regression())
+        .filter(|change| !(change.new == Status::Timeout && options.timeouts_are == TimeoutPolicy::Warn))
+        .count();
+    if regressions > 0 {
+        bail!("{} tests failed differently than in the baseline", regressions)
+    }
+
+    let over_budget = budgets.iter().filter(|budget| budget.exceeded()).count();
+    if over_budget > 0 {
+        bail!("{} suites had more failures than they allow", over_budget)
+    }
+
+    if divergences > 0 {
+        bail!("{} tests behaved differently without safety checks", divergences)
+    }
+
+    if options.leftovers == LeftoverPolicy::Fail && !leftovers.is_empty() {
+        bail!("The tests left {} files in their directories", leftovers.len())
+    }
+
+    if options.cleanup_failures == CleanupPolicy::Fail && !results.cleanup_failures.is_empty() {
+        bail!("{} artifacts couldn't be deleted", results.cleanup_failures.len())
+    }
 
     Ok(())
 }
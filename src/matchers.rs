@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::Path;
+use std::str;
+use regex::Regex;
+
+use crate::image_diff;
+
+/// What an output matcher checks: a test's output, or a file it created
+pub struct Subject<'a> {
+    /// How to refer to it in messages, e.g. "the output" or "'out.txt'"
+    pub name: &'a str,
+    pub contents: &'a [u8],
+    /// Where it is, if the test created it
+    pub path: Option<&'a Path>
+}
+
+/// Everything besides the subject which a matcher might need
+pub struct MatchContext<'a> {
+    /// The test's directory, which golden files are relative to
+    pub dir: &'a Path,
+    /// From the assertion's 'within <n>%', if it has one
+    pub tolerance: Option<u32>,
+    /// How many percent each pixel's color can be off by, if the assertion doesn't say
+    pub image_tolerance: u32,
+    /// Where images highlighting the differences go
+    pub diff_dir: &'a Path
+}
+
+/// A way of comparing output against what a spec expects, e.g.
+/// 'output regex "^[0-9]+$"' or 'creates "out.txt" matching "golden.txt" using unordered'.
+/// New matchers only need to be added to 'MATCHERS'
+pub trait OutputMatcher: Sync {
+    /// Checks the subject against 'expected', which is the string from the spec (text,
+    /// a pattern, or a golden file's name). Describes how they differ if they don't match
+    fn check(&self, subject: &Subject, expected: &str, context: &MatchContext) -> Result<(), String>;
+}
+
+/// The matchers specs can name
+static MATCHERS: &[(&str, &dyn OutputMatcher)] = &[
+    ("exact", &Exact),
+    ("regex", &Pattern(None)),
+    ("golden", &Golden),
+    ("unordered", &UnorderedLines),
+    ("floats", &FuzzyFloats),
+    ("image", &Image)
+];
+
+/// Finds a matcher by the name specs use for it
+pub fn find(name: &str) -> Option<&'static dyn OutputMatcher> {
+    MATCHERS.iter().find(|(matcher, _)| *matcher == name).map(|(_, matcher)| *matcher)
+}
+
+/// The matcher 'creates' assertions use if they don't name one
+pub fn default_for(golden: &str) -> &'static dyn OutputMatcher {
+    if image_diff::is_image(golden) { &Image } else { &Golden }
+}
+
+/// The output is exactly the given text
+struct Exact;
+
+impl OutputMatcher for Exact {
+    fn check(&self, subject: &Subject, expected: &str, _context: &MatchContext) -> Result<(), String> {
+        compare_bytes(subject, subject.contents, expected.as_bytes(), "the expected text")
+    }
+}
+
+/// Part of the output matches the given regex. Use '^' and '$' to match all of it.
+/// The one in 'MATCHERS' compiles the regex from the spec when checking, while
+/// 'prints /<regex>/' holds one which was compiled when the spec was parsed
+#[derive(Debug, Clone)]
+pub struct Pattern(Option<Regex>);
+
+impl Pattern {
+    pub fn compile(pattern: &str) -> Result<Pattern, regex::Error> {
+        Ok(Pattern(Some(Regex::new(pattern)?)))
+    }
+}
+
+impl OutputMatcher for Pattern {
+    fn check(&self, subject: &Subject, expected: &str, _context: &MatchContext) -> Result<(), String> {
+        let compiled;
+        let pattern = match &self.0 {
+            Some(pattern) => pattern,
+            None => {
+                compiled = Regex::new(expected).map_err(|e| format!("Invalid regex {:?}: {}", expected, e))?;
+                &compiled
+            }
+        };
+        let text = String::from_utf8_lossy(subject.contents);
+        if pattern.is_match(&text) {
+            Ok(())
+        }
+        else {
+            Err(format!("{} doesn't match /{}/:\n{}", subject.name, expected, text))
+        }
+    }
+}
+
+/// The output is exactly the same as a golden file
+struct Golden;
+
+impl OutputMatcher for Golden {
+    fn check(&self, subject: &Subject, expected: &str, context: &MatchContext) -> Result<(), String> {
+        let golden = read_golden(context.dir, expected)?;
+        compare_bytes(subject, subject.contents, &golden, &format!("'{}'", expected))
+    }
+}
+
+/// The output has the same lines as a golden file, in any order
+struct UnorderedLines;
+
+impl OutputMatcher for UnorderedLines {
+    fn check(&self, subject: &Subject, expected: &str, context: &MatchContext) -> Result<(), String> {
+        let golden = read_golden(context.dir, expected)?;
+        let golden = String::from_utf8_lossy(&golden);
+        let actual = String::from_utf8_lossy(subject.contents);
+
+        let mut expected_lines: Vec<&str> = golden.lines().collect();
+        let mut actual_lines: Vec<&str> = actual.lines().collect();
+        expected_lines.sort_unstable();
+        actual_lines.sort_unstable();
+
+        if let Some(line) = expected_lines.iter().find(|line| count(&actual_lines, line) < count(&expected_lines, line)) {
+            return Err(format!("{} is missing the line {:?} from '{}'", subject.name, line, expected))
+        }
+        if let Some(line) = actual_lines.iter().find(|line| count(&actual_lines, line) > count(&expected_lines, line)) {
+            return Err(format!("{} has the line {:?}, which '{}' doesn't", subject.name, line, expected))
+        }
+
+        Ok(())
+    }
+}
+
+fn count(lines: &[&str], line: &str) -> usize {
+    lines.iter().filter(|&&other| other == line).count()
+}
+
+/// The output has the same words as a golden file, except that numbers only
+/// have to be within 'within <n>%' of each other (or a millionth if not given)
+struct FuzzyFloats;
+
+impl OutputMatcher for FuzzyFloats {
+    fn check(&self, subject: &Subject, expected: &str, context: &MatchContext) -> Result<(), String> {
+        let golden = read_golden(context.dir, expected)?;
+        let golden = String::from_utf8_lossy(&golden);
+        let actual = String::from_utf8_lossy(subject.contents);
+        let tolerance = context.tolerance.map_or(1e-6, |percent| percent as f64 / 100.);
+
+        let mut expected_words = golden.split_whitespace();
+        let mut actual_words = actual.split_whitespace();
+        for word in 1.. {
+            let close = match (expected_words.next(), actual_words.next()) {
+                (None, None) => return Ok(()),
+                (Some(e), Some(a)) => match (e.parse::<f64>(), a.parse::<f64>()) {
+                    (Ok(x), Ok(y)) => (x - y).abs() <= tolerance * x.abs().max(y.abs()),
+                    _ => e == a
+                },
+                _ => false
+            };
+
+            if !close {
+                return Err(format!("{} differs from '{}' at word {} (with {}% tolerance):\n{}",
+                    subject.name, expected, word, tolerance * 100., actual))
+            }
+        }
+
+        unreachable!()
+    }
+}
+
+/// A PNG the test created looks the same as a golden one, pixel by pixel
+struct Image;
+
+impl OutputMatcher for Image {
+    fn check(&self, subject: &Subject, expected: &str, context: &MatchContext) -> Result<(), String> {
+        let path = subject.path.ok_or_else(|| format!("{} isn't a file, so it can't be compared as an image", subject.name))?;
+        let tolerance = context.tolerance.unwrap_or(context.image_tolerance);
+
+        let suite = context.dir.file_name().unwrap_or_default().to_string_lossy();
+        let file = path.strip_prefix(context.dir).unwrap_or(path).to_string_lossy().replace('/', "-");
+        let diff = context.diff_dir.join(format!("{}-{}", suite, file));
+        fs::create_dir_all(context.diff_dir).map_err(|e| format!("Couldn't create '{}': {}", context.diff_dir.display(), e))?;
+
+        match image_diff::compare_images(path, &context.dir.join(expected), tolerance, &diff) {
+            Ok(0) => Ok(()),
+            Ok(pixels) => Err(format!("{} differs from '{}' in {} pixels (with {}% tolerance). The differences are shown in '{}'",
+                subject.name, expected, pixels, tolerance, diff.display())),
+            Err(error) => Err(format!("Couldn't compare {} with '{}': {:#}", subject.name, expected, error))
+        }
+    }
+}
+
+fn read_golden(dir: &Path, golden: &str) -> Result<Vec<u8>, String> {
+    fs::read(dir.join(golden)).map_err(|e| format!("Couldn't read the golden file '{}': {}", golden, e))
+}
+
+/// Describes the first difference between the subject and what was expected, if there is one
+fn compare_bytes(subject: &Subject, actual: &[u8], expected: &[u8], expected_name: &str) -> Result<(), String> {
+    if actual == expected {
+        return Ok(())
+    }
+
+    match (str::from_utf8(actual), str::from_utf8(expected)) {
+        (Ok(actual), Ok(expected)) => {
+            let mut actual_lines = actual.lines();
+            let mut expected_lines = expected.lines();
+            for line in 1.. {
+                match (expected_lines.next(), actual_lines.next()) {
+                    (None, None) => break,
+                    (expected, actual) if expected == actual => continue,
+                    (expected, actual) => return Err(format!("{} differs from {} on line {}:\nexpected: {}\nactual:   {}",
+                        subject.name, expected_name, line,
+                        expected.unwrap_or("<end of file>"),
+                        actual.unwrap_or("<end of file>")))
+                }
+            }
+
+            Err(format!("{} differs from {} only in line endings or a trailing newline", subject.name, expected_name))
+        },
+        _ => {
+            let offset = actual.iter().zip(expected.iter())
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| actual.len().min(expected.len()));
+            Err(format!("{} ({} bytes) differs from {} ({} bytes) at byte {}",
+                subject.name, actual.len(), expected_name, expected.len(), offset))
+        }
+    }
+}
+
+#[cfg(test)]
+mod matchers_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn check(matcher: &str, output: &str, expected: &str, golden: Option<&str>, tolerance: Option<u32>) -> Result<(), String> {
+        let dir = TempDir::new(&format!("matchers-{}", matcher));
+        if let Some(golden) = golden {
+            fs::write(dir.join(expected), golden).unwrap();
+        }
+
+        let subject = Subject { name: "the output", contents: output.as_bytes(), path: None };
+        let context = MatchContext { dir: &dir, tolerance, image_tolerance: 0, diff_dir: &dir };
+        find(matcher).unwrap().check(&subject, expected, &context)
+    }
+
+    #[test]
+    fn test_matchers() {
+        assert!(check("exact", "a\nb\n", "a\nb\n", None, None).is_ok());
+        assert_eq!(check("exact", "a\nc\n", "a\nb\n", None, None).unwrap_err(),
+            "the output differs from the expected text on line 2:\nexpected: b\nactual:   c");
+
+        assert!(check("regex", "sum: 15\n", r"^sum: \d+$", None, None).is_err());
+        assert!(check("regex", "sum: 15\n", r"(?m)^sum: \d+$", None, None).is_ok());
+        assert!(check("regex", "sum: 15\n", r"(", None, None).unwrap_err().starts_with("Invalid regex"));
+
+        assert!(check("golden", "a\n", "golden.txt", Some("a\n"), None).is_ok());
+        assert!(check("golden", "a\n", "missing.txt", None, None).unwrap_err().starts_with("Couldn't read the golden file"));
+
+        assert!(check("unordered", "b\na\na\n", "golden.txt", Some("a\nb\na\n"), None).is_ok());
+        assert!(check("unordered", "b\na\n", "golden.txt", Some("a\nb\na\n"), None).unwrap_err().contains("missing the line \"a\""));
+        assert!(check("unordered", "b\na\nc\n", "golden.txt", Some("a\nb\n"), None).unwrap_err().contains("has the line \"c\""));
+
+        assert!(check("floats", "pi = 3.1415927\n", "golden.txt", Some("pi = 3.14159265"), None).is_ok());
+        assert!(check("floats", "pi = 3.2\n", "golden.txt", Some("pi = 3.14159265"), None).is_err());
+        assert!(check("floats", "pi = 3.2\n", "golden.txt", Some("pi = 3.14159265"), Some(5)).is_ok());
+        assert!(check("floats", "tau = 3.14159265\n", "golden.txt", Some("pi = 3.14159265"), None).is_err());
+        assert!(check("floats", "pi = 3.14159265 2\n", "golden.txt", Some("pi = 3.14159265"), None).is_err());
+
+        assert!(check("image", "", "golden.png", None, None).unwrap_err().contains("isn't a file"));
+        assert!(find("fuzzy").is_none());
+    }
+}
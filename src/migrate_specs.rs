@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+
+use crate::discover_tests;
+use crate::parse_spec::{self, ParseOptions};
+
+/// A spec line which changes when migrating
+pub struct SpecChange {
+    pub path: PathBuf,
+    /// 1-based line number in 'path'
+    pub line: usize,
+    pub old: String,
+    pub new: String
+}
+
+/// Finds every spec in the corpus which mentions one of the renamed words:
+/// the lines of each sources.test, the '//test' lines of test files, and spec files
+pub fn find_changes(test_dir: &Path, renames: &HashMap<String, String>) -> Result<Vec<SpecChange>> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(test_dir)
+        .context(format!("Couldn't open the root test directory '{}'", test_dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+
+    let mut changes = Vec::new();
+    for dir in dirs.iter() {
+        changes.extend(find_directory_changes(dir, renames)?);
+    }
+
+    for change in changes.iter() {
+        let spec = change.new.split('~').next().unwrap_or_default();
+        let requires_marker = !change.path.ends_with("sources.test") && !is_spec_file(&change.path);
+        if let Err(e) = parse_spec::parse(spec, ParseOptions { require_test_marker: requires_marker }) {
+            eprintln!("⚠: this won't parse after migrating: {}", e.render(&change.path, change.line, spec));
+        }
+    }
+
+    Ok(changes)
+}
+
+fn find_directory_changes(dir: &Path, renames: &HashMap<String, String>) -> Result<Vec<SpecChange>> {
+    let mut changes = Vec::new();
+
+    let sources_test = dir.join("sources.test");
+    if sources_test.exists() {
+        let contents = fs::read_to_string(&sources_test).context(format!("Couldn't read '{}'", sources_test.display()))?;
+        for (line, lineno) in contents.lines().zip(1..) {
+            // Only the spec comes before the '~'
+            let new = match line.split_once('~') {
+                Some((spec, cmdline)) => format!("{}~{}", parse_spec::rename_words(spec, renames), cmdline),
+                None => continue
+            };
+
+            if new != line {
+                changes.push(SpecChange { path: sources_test.clone(), line: lineno, old: line.to_string(), new });
+            }
+        }
+    }
+
+    let mut test_files: Vec<PathBuf> = fs::read_dir(dir)
+        .context(format!("Couldn't open a test directory '{}'", dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| matches!(path.extension().and_then(|ext| ext.to_str()), Some("c0") | Some("c1")))
+        .collect();
+    test_files.sort();
+
+    for path in test_files {
+        // Files with spec files don't have to be UTF-8
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+
+        // Its spec might be in a spec file instead
+        let (path, contents) = if contents.lines().next().unwrap_or_default().trim_start().starts_with("//test") {
+            (path, contents)
+        }
+        else {
+            let spec_file = discover_tests::spec_file_path(&path);
+            if !spec_file.exists() {
+                continue
+            }
+            let contents = fs::read_to_string(&spec_file).context(format!("Couldn't read '{}'", spec_file.display()))?;
+            (spec_file, contents)
+        };
+
+        // Specs can go on over several '//test' lines, but spec files only have one
+        let spec_lines = if is_spec_file(&path) {
+            1
+        }
+        else {
+            contents.lines().take_while(|line| line.trim_start().starts_with("//test")).count()
+        };
+        for (line, lineno) in contents.lines().zip(1..).take(spec_lines) {
+            let new = parse_spec::rename_words(line, renames);
+            if new != line {
+                changes.push(SpecChange { path: path.clone(), line: lineno, old: line.to_string(), new });
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+fn is_spec_file(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("spec"))
+}
+
+/// Writes the changes to their files
+pub fn apply_changes(changes: &[SpecChange]) -> Result<()> {
+    let mut by_file: HashMap<&Path, Vec<&SpecChange>> = HashMap::new();
+    for change in changes.iter() {
+        by_file.entry(&change.path).or_default().push(change);
+    }
+
+    for (path, changes) in by_file {
+        let contents = fs::read_to_string(path).context(format!("Couldn't read '{}'", path.display()))?;
+        let mut lines: Vec<String> = contents.split_inclusive('\n').map(String::from).collect();
+
+        for change in changes {
+            let line = match lines.get_mut(change.line - 1) {
+                Some(line) => line,
+                None => bail!("'{}' changed while migrating", path.display())
+            };
+
+            // Keep the line ending (which might be \r\n) as it was
+            let content_len = line.trim_end_matches(['\n', '\r']).len();
+            if line[..content_len] != change.old {
+                bail!("'{}' changed while migrating", path.display())
+            }
+            line.replace_range(..content_len, &change.new);
+        }
+
+        fs::write(path, lines.concat()).context(format!("Couldn't write '{}'", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod migrate_specs_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test() -> Result<()> {
+        let base = TempDir::new("migrate");
+        let suite = base.join("suite");
+        fs::create_dir_all(&suite)?;
+        fs::write(suite.join("sources.test"), "typecheck => fail ~ fail.c0\r\nreturn 0 ~ a.c0\n")?;
+        fs::write(suite.join("a.c0"), "//test !typecheck => error\nint main() { return 0; } // typecheck\n")?;
+        fs::write(suite.join("b.c1"), "int main() { return 0; } //test typecheck\n")?;
+        fs::write(suite.join("c.c0"), "int main() { return 0; }\n")?;
+        fs::write(suite.join("c.c0.spec"), "typecheck => return 0\n")?;
+        fs::write(suite.join("d.c0"), "//test typecheck => return 0\n//test !typecheck => error\n// typecheck\n")?;
+
+        let renames: HashMap<String, String> = vec![
+            (String::from("typecheck"), String::from("typechecked")),
+            (String::from("fail"), String::from("failure"))
+        ].into_iter().collect();
+
+        let changes = find_changes(&base, &renames)?;
+        assert_eq!(changes.len(), 5);
+        assert_eq!(changes[0].new, "typechecked => failure ~ fail.c0");
+
+        apply_changes(&changes)?;
+        assert_eq!(fs::read_to_string(suite.join("sources.test"))?, "typechecked => failure ~ fail.c0\r\nreturn 0 ~ a.c0\n");
+        assert_eq!(fs::read_to_string(suite.join("a.c0"))?, "//test !typechecked => error\nint main() { return 0; } // typecheck\n");
+        assert_eq!(fs::read_to_string(suite.join("c.c0.spec"))?, "typechecked => return 0\n");
+        assert_eq!(fs::read_to_string(suite.join("d.c0"))?, "//test typechecked => return 0\n//test !typechecked => error\n// typecheck\n");
+        assert!(find_changes(&base, &renames)?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use rayon::prelude::*;
+use anyhow::{Context, Error, Result};
+
+use crate::checker::{Checker, TestOutcome, TestResult};
+use crate::executer::Executer;
+use crate::hooks::HookRunner;
+use crate::spec::TestInfo;
+
+/// What happened to a mutated compiler when running the tests with it
+pub enum MutantFate {
+    /// The named test failed, so the tests catch the mutation
+    Killed(String),
+    /// Every test passed
+    Survived,
+    /// The mutant couldn't be used
+    Error(Error)
+}
+
+pub struct MutantResult {
+    pub mutant: PathBuf,
+    pub fate: MutantFate
+}
+
+/// Finds the mutants (executable files) in a directory
+pub fn find_mutants(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut mutants: Vec<PathBuf> = fs::read_dir(dir)
+        .context(format!("Couldn't open the mutant directory '{}'", dir.display()))?
+        .filter_map(Result::ok)
+        .filter(|entry| match entry.metadata() {
+            Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+            Err(_) => false
+        })
+        .map(|entry| entry.path())
+        .collect();
+
+    mutants.sort();
+    Ok(mutants)
+}
+
+/// Runs tests with a mutant until one of them fails or has an error
+pub fn test_mutant(checker: &Checker, hooks: &HookRunner, executer: &dyn Executer, tests: &[&TestInfo]) -> MutantFate {
+    let fate = tests.par_iter().find_map_any(|test| {
+        // A test whose before hook failed, or which was skipped, says nothing about the mutant
+        match hooks.around_test(test, || checker.run_test(executer, test)) {
+            (Some(Ok(TestOutcome { result: TestResult::Mismatch(_), .. })), _) => Some(MutantFate::Killed(test.name())),
+            (Some(Err(error)), _) => Some(MutantFate::Error(error.context(format!("When running {}", test.name())))),
+            _ => None
+        }
+    });
+
+    fate.unwrap_or(MutantFate::Survived)
+}
+
+/// Prints what happened to each mutant, and the fraction that were killed
+pub fn print_report(results: &[MutantResult], test_count: usize) {
+    println!("\nMutation testing ({} mutants, {} tests):\n", results.len(), test_count);
+
+    for result in results.iter() {
+        let name = result.mutant.file_name().unwrap_or_default().to_string_lossy();
+        match &result.fate {
+            MutantFate::Killed(test) => println!("💀 {}: killed by {}", name, test),
+            MutantFate::Survived => println!("🧟 {}: survived", name),
+            MutantFate::Error(error) => println!("⛔ {}: {:#}", name, error)
+        }
+    }
+
+    let killed = results.iter().filter(|result| matches!(result.fate, MutantFate::Killed(_))).count();
+    let tested = results.iter().filter(|result| !matches!(result.fate, MutantFate::Error(_))).count();
+    let score = if tested == 0 { 0. } else { killed as f64 / tested as f64 * 100. };
+    println!("\nMutation score: {}/{} killed ({:.1}%)", killed, tested, score);
+}
+
+#[cfg(test)]
+mod mutation_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_find_mutants() -> Result<()> {
+        let dir = TempDir::new("mutants");
+        fs::create_dir_all(dir.join("subdir"))?;
+
+        for (name, mode) in [("b", 0o755), ("a", 0o700), ("notes.txt", 0o644)].iter() {
+            let path = dir.join(name);
+            fs::write(&path, "#!/bin/sh\n")?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(*mode))?;
+        }
+
+        assert_eq!(find_mutants(&dir)?, vec![dir.join("a"), dir.join("b")]);
+
+        Ok(())
+    }
+}
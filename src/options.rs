@@ -1,14 +1,24 @@
-use std::path::PathBuf;
-use structopt::clap::{AppSettings, arg_enum};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use structopt::clap::{AppSettings, ArgSettings, arg_enum};
 use anyhow::{bail, Result, Context};
 
+use crate::executer_pool::PoolMember;
+use crate::shard::Shard;
+use crate::test_filter::TestFilter;
+
 pub use structopt::StructOpt;
 
-#[derive(StructOpt)]
+#[derive(StructOpt, Clone)]
 #[structopt(setting(AppSettings::ColoredHelp))]
 #[structopt(setting(AppSettings::DeriveDisplayOrder))]
+#[structopt(setting(AppSettings::SubcommandsNegateReqs))]
 #[structopt(set_term_width(80))]
 pub struct Options {
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+
     /// Which implementation to test
     ///
     /// 'cc0' tests the GCC backend.
@@ -16,16 +26,17 @@ pub struct Options {
     /// 'coin' tests the interpreter
     #[structopt(
         possible_values = &ExecuterKind::variants(),
-        case_insensitive = true
+        case_insensitive = true,
+        set = ArgSettings::Required
     )]
-    pub executer: ExecuterKind,
+    pub executer: Option<ExecuterKind>,
 
     /// Path to the top-level test directory.
     ///
     /// The directory should contain subdirectories which 
     /// should either contain test cases or a sources.test file
-    #[structopt(parse(from_os_str))]
-    pub test_dir: PathBuf,
+    #[structopt(parse(from_os_str), set = ArgSettings::Required)]
+    pub test_dir: Option<PathBuf>,
 
     /// Path to CC0 directory.
     ///
@@ -34,8 +45,9 @@ pub struct Options {
     #[structopt(
         long, 
         parse(from_os_str),
-        env = "C0_HOME")]
-    pub c0_home: PathBuf,
+        env = "C0_HOME",
+        set = ArgSettings::Required)]
+    pub c0_home: Option<PathBuf>,
 
     /// Timeout in seconds for running each test
     ///
@@ -44,6 +56,89 @@ pub struct Options {
     #[structopt(short = "t", long, default_value = "10")]
     pub test_time: u64,
 
+    /// How many times --test-time tests tagged @slow get
+    ///
+    /// Tests which give their own limit with @timeout keep it
+    #[structopt(long, default_value = "4")]
+    pub slow_factor: u64,
+
+    /// Leave out tests tagged @slow
+    #[structopt(long)]
+    pub fast: bool,
+
+    /// Check that tests which pass by timing out used (nearly) all of their CPU time
+    ///
+    /// Otherwise an 'infloop' test which was killed early, e.g. by raising
+    /// SIGXCPU itself, fails as having terminated early
+    #[structopt(long)]
+    pub check_infloop_cpu: bool,
+
+    /// What a test which times out counts as
+    ///
+    /// 'fail' counts it as a failure. 'warn' still reports it, but it doesn't
+    /// count against its suite's allowed failures or fail the run, e.g. on
+    /// overloaded shared machines. 'retry' runs it once more, and it only
+    /// fails if it times out again
+    #[structopt(
+        long,
+        possible_values = &TimeoutPolicy::variants(),
+        case_insensitive = true,
+        default_value = "fail"
+    )]
+    pub timeouts_are: TimeoutPolicy,
+
+    /// What to do when a test in sources.test runs a file whose own '//test' line expects something else
+    ///
+    /// 'sources' goes by sources.test and 'inline' by the '//test' line, and
+    /// both warn about it. 'error' skips the suite until they agree
+    #[structopt(
+        long,
+        possible_values = &SpecConflicts::variants(),
+        case_insensitive = true,
+        default_value = "sources"
+    )]
+    pub spec_conflicts: SpecConflicts,
+
+    /// What to do with files the tests leave in their suite directories
+    ///
+    /// Files and directories which weren't there before the run are listed with
+    /// the tests which were running when they were written. 'clean' deletes them
+    /// too, and 'fail' fails the run. 'ignore' doesn't look for them
+    #[structopt(
+        long,
+        possible_values = &LeftoverPolicy::variants(),
+        case_insensitive = true,
+        default_value = "report"
+    )]
+    pub leftovers: LeftoverPolicy,
+
+    /// What to do when compiled tests (a.out, .bc0) can't be deleted after running
+    ///
+    /// Artifacts which can't be deleted, e.g. on NFS, are tried again at the end of
+    /// the run. 'warn' says so as it happens and counts the ones which still can't
+    /// be in the summary, and 'fail' fails the run too. 'ignore' leaves them alone
+    #[structopt(
+        long,
+        possible_values = &CleanupPolicy::variants(),
+        case_insensitive = true,
+        default_value = "warn"
+    )]
+    pub cleanup_failures: CleanupPolicy,
+
+    /// How many more times to run a test which fails or has an error before reporting it
+    ///
+    /// For flaky tests, e.g. VM or GC tests which sometimes fail when the machine
+    /// is busy. Tests which pass on a retry are noted as such
+    #[structopt(long, default_value = "0")]
+    pub retries: u32,
+
+    /// Add how each test's specs were evaluated to its notes
+    ///
+    /// For each spec, this shows the executer properties every predicate
+    /// looked at, whether it held, and so whether the spec applied
+    #[structopt(long)]
+    pub trace_specs: bool,
+
     /// Max amount of memory a test can use. 
     ///
     /// Should be of the form <n> <unit>
@@ -66,10 +161,657 @@ pub struct Options {
         long, 
         parse(try_from_str = parse_size),
         default_value = "4 GB")]
-    pub compilation_mem: u64
+    pub compilation_mem: u64,
+
+    /// Directory to keep the C code generated by CC0 in
+    ///
+    /// Only supported by the cc0 executer. Passes --save-files to CC0
+    /// and moves the generated code for each test into this directory
+    #[structopt(long, parse(from_os_str))]
+    pub save_files: Option<PathBuf>,
+
+    /// Command to check the C code saved by --save-files with
+    ///
+    /// e.g. 'clang -fsyntax-only -Wall'. The path to the generated code
+    /// is appended to the command, and anything it prints is reported
+    /// for that test. Uses the same limits as compilation
+    #[structopt(long, requires = "save-files")]
+    pub check_c: Option<String>,
+
+    /// Record the results of this run to a file
+    ///
+    /// This can be compared against in later runs with --baseline
+    #[structopt(long, parse(from_os_str))]
+    pub save_baseline: Option<PathBuf>,
+
+    /// Compare the results of this run against a file from --save-baseline
+    ///
+    /// Only tests whose outcome differs from the baseline are reported (new
+    /// failures, new passes, new timeouts, etc.), and the run fails if any test
+    /// fails now and didn't, or fails differently
+    #[structopt(long, parse(from_os_str))]
+    pub baseline: Option<PathBuf>,
+
+    /// How many percent larger than in the baseline a compiled test
+    /// can get before it is reported
+    #[structopt(long, default_value = "10")]
+    pub size_threshold: f64,
+
+    /// Also run the tests with another executer, and compare run times
+    ///
+    /// Can be given multiple times. Each executer gets its own results,
+    /// followed by a table of how long each test took to run in each
+    /// executer and the average slowdown relative to <executer>
+    #[structopt(
+        long,
+        possible_values = &ExecuterKind::variants(),
+        case_insensitive = true,
+        number_of_values = 1
+    )]
+    pub compare: Vec<ExecuterKind>,
+
+    /// Split the tests between several executers of the same kind (can be repeated),
+    /// written as <weight>[:<c0-home>], e.g. '--pool 2 --pool 1:/mnt/other/c0'
+    ///
+    /// Each test goes to the member with the fewest tests running for its weight.
+    /// Members use --c0-home unless they name another C0 distribution
+    #[structopt(long, number_of_values = 1)]
+    pub pool: Vec<PoolMember>,
+
+    /// Run a suite's tests with another C0 distribution, written as <suite>=<c0-home>
+    /// (can be repeated), e.g. '--suite-c0-home legacy=/opt/c0-2019'
+    ///
+    /// Overrides 'c0_home' in the suite's suite.toml. Suites are named by their
+    /// directory, and run outside of --pool
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_suite_c0_home))]
+    pub suite_c0_home: Vec<(String, PathBuf)>,
+
+    /// File of regexes for output lines to ignore, one per line
+    ///
+    /// Matching lines are removed from test and compiler output before
+    /// it is checked or reported, to filter out noise such as GC
+    /// diagnostics or linker warnings. Lines starting with # are comments
+    #[structopt(long, parse(from_os_str))]
+    pub suppressions: Option<PathBuf>,
+
+    /// Compile tests without debug information
+    ///
+    /// Speeds up compilation, and means no .dSYM directories need to be
+    /// cleaned up. Only works when CC0 uses clang (which is what 'gcc'
+    /// is on MacOS), and c0check stops if it doesn't
+    #[structopt(long)]
+    pub no_debug_info: bool,
+
+    /// Put compiled tests and other temporary files in a RAM-backed
+    /// directory of this size (Linux only)
+    ///
+    /// Mounts a tmpfs if running as root, otherwise uses /dev/shm.
+    /// Saves a lot of I/O on machines with slow disks
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub tmpfs: Option<u64>,
+
+    /// Check the test files against a manifest of SHA-256 hashes before testing
+    ///
+    /// The manifest is in the format printed by 'sha256sum', with paths
+    /// relative to <test-dir>. Testing stops if any file is missing or
+    /// has been modified
+    #[structopt(long, parse(from_os_str))]
+    pub verify_corpus: Option<PathBuf>,
+
+    /// Collect coverage data from a toolchain built with coverage instrumentation
+    ///
+    /// 'gcov' sets GCOV_PREFIX and 'llvm' sets LLVM_PROFILE_FILE, so that
+    /// the profile data from each test goes to its own directory under
+    /// --coverage-dir. Afterwards, it is merged using gcov-tool or
+    /// llvm-profdata (which have to be in $PATH)
+    #[structopt(
+        long,
+        possible_values = &CoverageKind::variants(),
+        case_insensitive = true
+    )]
+    pub coverage: Option<CoverageKind>,
+
+    /// Where to put coverage data
+    ///
+    /// Defaults to 'coverage', or to a directory in the run's --output-dir
+    #[structopt(long, parse(from_os_str))]
+    pub coverage_dir: Option<PathBuf>,
+
+    /// Directory of mutated cc0 binaries, to measure how many bugs the tests catch
+    ///
+    /// After testing normally, the tests which passed are run again with
+    /// each mutant in place of cc0, until one fails and 'kills' it. Reports
+    /// the mutants which no test kills. Not supported by coin
+    #[structopt(long, parse(from_os_str))]
+    pub mutants: Option<PathBuf>,
+
+    /// Check that tests run the same with and without cc0's safety checks
+    ///
+    /// After testing normally, the passing tests which should return both
+    /// with and without safety checks are run with and without --unsafe.
+    /// Different return values or output point to a bug in cc0's code
+    /// generation or the runtime, even though both runs pass. Only supported by cc0
+    #[structopt(long)]
+    pub safety_diff: bool,
+
+    /// Write the results of each test to a file as JSON
+    ///
+    /// Each test has its name, sources, compiler options, expected and actual
+    /// behaviors, output, and how long it took. The top-level 'version' field
+    /// changes whenever existing fields do
+    #[structopt(long, parse(from_os_str))]
+    pub report_json: Option<PathBuf>,
+
+    /// Split JSON reports by suite, for runs too large to read as one file
+    ///
+    /// --report-json names a directory instead, which gets an index.json with how
+    /// each suite went and a file per suite under suites/. The run's directory
+    /// (see --output-dir) gets results/ instead of results.json
+    #[structopt(long)]
+    pub split_report: bool,
+
+    /// Tag the run with a key and value (can be repeated), e.g. 'branch=feature-x'
+    ///
+    /// Tags are kept in --report-json and baselines, so results can
+    /// be told apart by branch, builder, and so on later
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_tag))]
+    pub meta: Vec<(String, String)>,
+
+    /// Write the cc0 command which compiles each test to a file,
+    /// in the format of a compile_commands.json
+    ///
+    /// Each entry also has a 'test' field with the name of the test
+    #[structopt(long, parse(from_os_str))]
+    pub compile_commands: Option<PathBuf>,
+
+    /// Write a timeline of when each test compiled and ran, and on which thread, to a file
+    ///
+    /// It's in the Chrome tracing format, which Perfetto (ui.perfetto.dev) and
+    /// chrome://tracing open, for finding gaps in the schedule, compilations
+    /// waiting on each other, and tests which hold up the end of the run
+    #[structopt(long, parse(from_os_str))]
+    pub timeline: Option<PathBuf>,
+
+    /// How many percent each pixel's color can be off by when comparing
+    /// PNG images from 'creates' assertions
+    ///
+    /// PNGs are compared pixel by pixel using ImageMagick's 'compare'
+    /// (which has to be in $PATH), since the same image can be encoded
+    /// differently. Assertions can give their own tolerance with 'within <n>%'
+    #[structopt(long, default_value = "0")]
+    pub image_tolerance: u32,
+
+    /// Where to put images showing how PNGs from 'creates' assertions
+    /// differ from their golden copies
+    ///
+    /// Defaults to 'image-diffs', or to a directory in the run's --output-dir
+    #[structopt(long, parse(from_os_str))]
+    pub image_diff_dir: Option<PathBuf>,
+
+    /// Find the smallest memory limit each passing gc test still passes with
+    ///
+    /// Tests whose specs mention 'gc' are run repeatedly with smaller
+    /// and smaller --test-memory. The results are saved by --save-baseline,
+    /// and growth past --size-threshold from --baseline is reported, since
+    /// some collector bugs only show up under memory pressure
+    #[structopt(long)]
+    pub gc_stress: bool,
+
+    /// Also summarize results by the spec predicates which applied
+    ///
+    /// e.g. all tests whose specs applied through 'gc =>', so that it's
+    /// clear which capability of the executer is failing
+    #[structopt(long)]
+    pub by_predicate: bool,
+
+    /// Put everything from this run in a new directory under this one
+    ///
+    /// The directory is named after the time the run started, and gets
+    /// a copy of the output (report.txt and log.txt), the results
+    /// (baseline.json and results.json), and coverage data and image diffs unless they were
+    /// put elsewhere. <output-dir>/latest links to the newest run
+    #[structopt(long, parse(from_os_str))]
+    pub output_dir: Option<PathBuf>,
+
+    /// Delete all but this many of the newest runs in --output-dir
+    #[structopt(long, requires = "output-dir")]
+    pub keep_runs: Option<usize>,
+
+    /// Share the caches, runs and reports through this storage, so CI runners can use each other's caches
+    ///
+    /// Either a directory, s3://<bucket>/<prefix> or gs://<bucket>/<prefix>, which need the aws or
+    /// gsutil command line tools. The result cache is fetched from <storage>/cache/results.json
+    /// and merged into the local one before the tests run, and pushed back afterwards. With
+    /// --artifact-cache, artifacts are shared under <storage>/artifacts. The run's directory from
+    /// --output-dir is pushed to <storage>/runs/<run> and --report-json to <storage>/reports/<run>,
+    /// even if tests failed
+    #[structopt(long)]
+    pub storage: Option<String>,
+
+    /// Shell command to run from <test-dir> before any tests are run
+    ///
+    /// Testing stops if it fails. Suites can have their own hooks in
+    /// suite.toml, and tests can have them with @before(<command>).
+    /// Hooks get the same limits as compilation
+    #[structopt(long)]
+    pub before_hook: Option<String>,
+
+    /// Shell command to run from <test-dir> after all tests have finished
+    #[structopt(long)]
+    pub after_hook: Option<String>,
+
+    /// How many tests to run at once. Defaults to the number of CPUs
+    ///
+    /// Fewer jobs keep suites with big tests (e.g. images or large GC tests)
+    /// from thrashing the machine, since each test may use --test-memory
+    #[structopt(short = "j", long)]
+    pub jobs: Option<usize>,
+
+    /// How many tests to compile at once. Defaults to --jobs
+    ///
+    /// Tests are compiled by threads of their own and then handed to the --jobs
+    /// threads which run them, so later tests compile while earlier ones run.
+    /// CC0 and GCC take far more memory than running most tests, so fewer
+    /// compile jobs keep the compilers from thrashing the machine
+    #[structopt(long)]
+    pub compile_jobs: Option<usize>,
+
+    /// Run tests one at a time in the order they were found, showing their output as they run
+    ///
+    /// For debugging tests which interfere with each other, where the logs of
+    /// tests running at once would be interleaved. Tests still run after the
+    /// ones they depend on
+    #[structopt(long, conflicts_with_all = &["jobs", "shuffle", "chaos", "pool"])]
+    pub serial: bool,
+
+    /// Start programs more carefully, trading a little speed for reliability
+    ///
+    /// Programs are started with the standard library's process spawning, which
+    /// only runs async-signal-safe code between fork() and exec(), rather than
+    /// c0check's own, and their output is recorded through close-on-exec pipes.
+    /// For platforms where tests hang or crash in ways which don't reproduce
+    #[structopt(long)]
+    pub paranoid: bool,
+
+    /// How many threads compile the tests which expect a compile error
+    ///
+    /// These tests never run, so they are compiled in a batch of their own
+    /// before the other tests, with more threads and --error-test-memory.
+    /// Defaults to twice --jobs. 0 runs them with the other tests
+    #[structopt(long)]
+    pub error_test_threads: Option<usize>,
+
+    /// Maximum amount of memory CC0 can use for tests which expect a compile error
+    ///
+    /// Tests which get a limit from @compile_memory or suite.toml keep it
+    #[structopt(
+        long,
+        parse(try_from_str = parse_size),
+        default_value = "1 GB")]
+    pub error_test_memory: u64,
+
+    /// Stop testing after this many tests in a row have errors
+    ///
+    /// This usually means the executer itself is broken (e.g. cc0 was
+    /// replaced partway through), and every remaining test would have the
+    /// same error. 0 never stops
+    #[structopt(long, default_value = "25")]
+    pub max_consecutive_errors: usize,
+
+    /// Stop testing once this many tests have failed or timed out
+    ///
+    /// Tests which are already running finish, but no more are started.
+    /// For not waiting on a whole run when the compiler is obviously broken
+    #[structopt(long)]
+    pub max_failures: Option<usize>,
+
+    /// Stop testing after the first failure, like '--max-failures 1'
+    #[structopt(long, conflicts_with = "max-failures")]
+    pub fail_fast: bool,
+
+    /// How many times to retry a test which couldn't start because the
+    /// system ran out of processes, file descriptors, or memory
+    ///
+    /// Each retry waits twice as long as the last, and fewer tests are run
+    /// at once from then on
+    #[structopt(long, default_value = "5")]
+    pub contention_retries: u32,
+
+    /// Wait to start tests while the machine's 1-minute load average is above this
+    ///
+    /// For shared machines, where other people's jobs would otherwise make tests
+    /// time out. One test keeps running meanwhile
+    #[structopt(long)]
+    pub max_load: Option<f64>,
+
+    /// Wait to start tests while the machine has less memory available than this,
+    /// e.g. '2 GB'. One test keeps running meanwhile
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub min_free_memory: Option<u64>,
+
+    /// Run tests in a shuffled order, with a small random delay before each one
+    ///
+    /// This shakes out tests which depend on each other or on timing. The
+    /// seed is printed, and the same seed gives the same order and delays.
+    /// Every test is run, even ones which passed last time
+    #[structopt(long)]
+    pub chaos: bool,
+
+    /// Seed for --chaos, to reproduce an earlier run. Random if not given
+    #[structopt(long, requires = "chaos")]
+    pub chaos_seed: Option<u64>,
+
+    /// Run tests in a shuffled order, without --chaos's delays
+    ///
+    /// For finding tests which interfere with each other, e.g. through files
+    /// they leave behind. The seed is printed, and the same seed gives the same
+    /// order. Every test is run, even ones which passed last time
+    #[structopt(long)]
+    pub shuffle: bool,
+
+    /// Seed for --shuffle, to reproduce an earlier run. Random if not given
+    #[structopt(long, requires = "shuffle")]
+    pub seed: Option<u64>,
+
+    /// Run every test, even ones which passed last time
+    ///
+    /// Tests which passed are skipped as long as their sources, specs, the
+    /// toolchain's binaries, and the limits are the same as when they passed.
+    /// Tests with hooks or 'creates' assertions always run
+    #[structopt(long)]
+    pub force: bool,
+
+    /// Where to remember which tests passed, and which failed for --rerun-failed
+    ///
+    /// Defaults to $XDG_CACHE_HOME/c0check or ~/.cache/c0check
+    #[structopt(long, parse(from_os_str))]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Keep compiled tests in --cache-dir, and run tests from there instead of
+    /// compiling them when their sources, options, and the toolchain haven't changed
+    ///
+    /// 'c0check prime-cache' fills the cache ahead of time. Delete
+    /// <cache-dir>/artifacts to free the space it takes up
+    #[structopt(long, conflicts_with_all = &["pool", "coverage", "save-files"])]
+    pub artifact_cache: bool,
+
+    /// Only run the tests which failed, timed out, had errors, or weren't
+    /// run in the last run of the same test directory
+    ///
+    /// Every run remembers which tests didn't pass in --cache-dir
+    #[structopt(long)]
+    pub rerun_failed: bool,
+
+    /// Don't fail when no tests are found, e.g. for a suite which is still empty
+    ///
+    /// Otherwise c0check exits with code 3, listing what it looked at, since
+    /// finding no tests usually means the test directory is wrong
+    #[structopt(long)]
+    pub allow_empty: bool,
+
+    /// Only discover the tests and parse their specs, without running anything
+    ///
+    /// Exits with an error if any test couldn't be loaded, e.g. because of a
+    /// malformed '//test' line, which a normal run only warns about and skips
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Only run tests whose name or source files match a pattern (can be repeated)
+    ///
+    /// Patterns are globs, e.g. 'basic/*' or '**/gc-*.c0', where '*' doesn't match
+    /// '/' but '**' does. Patterns starting with 're:' are regexes instead, which
+    /// only have to match part of the name, e.g. 're:^(basic|arrays)/'. Names are
+    /// as shown in the results, and sources can be matched by their full paths
+    #[structopt(long, number_of_values = 1)]
+    pub filter: Vec<TestFilter>,
+
+    /// Only run tests tagged with a name, e.g. 'gc' for '@tags(gc, slow)' (can be repeated)
+    ///
+    /// Tests tagged with any of the names are run. Tests tagged @slow also count as tagged 'slow'
+    #[structopt(long, number_of_values = 1)]
+    pub tag: Vec<String>,
+
+    /// Leave out tests tagged with a name (can be repeated)
+    ///
+    /// --skip-tag wins over --tag, and '--skip-tag slow' is the same as --fast
+    #[structopt(long, number_of_values = 1)]
+    pub skip_tag: Vec<String>,
+
+    /// Leave out tests whose name or source files match a pattern (can be repeated)
+    ///
+    /// Patterns are written as for --filter, and --exclude wins over it
+    #[structopt(long, number_of_values = 1)]
+    pub exclude: Vec<TestFilter>,
+
+    /// Don't load a test directory with this name at all (can be repeated),
+    /// e.g. suites which need a display on a headless machine
+    #[structopt(long, number_of_values = 1)]
+    pub exclude_dir: Vec<String>,
+
+    /// Only run the Kth of N shards of the tests, written as K/N, e.g. 2/4
+    ///
+    /// Tests are split by hashing their names, so each one stays in the same shard
+    /// from run to run, and running shards 1/N to N/N runs every test once. Tests
+    /// linked by '@depends' are kept in the same shard
+    #[structopt(long)]
+    pub shard: Option<Shard>
+}
+
+// Tools for working with test suites, which don't run any tests
+#[derive(StructOpt, Clone)]
+pub enum Command {
+    /// Print a sources.test equivalent to the '//test' lines in a directory
+    ///
+    /// For moving a suite of individual test files to a sources.test
+    ExportSources {
+        /// The test directory, which shouldn't have a sources.test yet
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf
+    },
+
+    /// Move the specs in a directory's sources.test into '//test' lines
+    ///
+    /// Each test in sources.test has to consist of a single source file
+    /// with no compiler options, since that's all a '//test' line can
+    /// describe. Nothing is changed unless every test can be moved.
+    /// sources.test is removed afterwards
+    SplitSources {
+        /// The test directory containing sources.test
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf
+    },
+
+    /// Rename predicates or behaviors in every spec in a test directory
+    ///
+    /// Prints each changed spec. Only whole words are renamed, so strings
+    /// and annotations are left alone
+    MigrateSpecs {
+        /// Path to the top-level test directory
+        #[structopt(parse(from_os_str))]
+        test_dir: PathBuf,
+
+        /// A rename of the form <old>=<new>. Can be given multiple times
+        #[structopt(
+            long,
+            required = true,
+            number_of_values = 1,
+            parse(try_from_str = parse_rename)
+        )]
+        rename: Vec<(String, String)>,
+
+        /// Only print what would change
+        #[structopt(long)]
+        dry_run: bool
+    },
+
+    /// Print statistics about the tests in a test directory
+    ///
+    /// Counts tests by behavior, assertion, predicate, language,
+    /// and directory, without running anything
+    Stats {
+        /// Path to the top-level test directory
+        #[structopt(parse(from_os_str))]
+        test_dir: PathBuf
+    },
+
+    /// Print every test in a test directory, with its sources, compiler options,
+    /// and specs, without running anything
+    ///
+    /// For checking what c0check thinks a suite contains. --filter, --exclude,
+    /// --exclude-dir, and --shard apply, so other tools can see which tests a
+    /// run would have
+    ListTests {
+        /// Path to the top-level test directory
+        #[structopt(parse(from_os_str))]
+        test_dir: PathBuf,
+
+        /// Print a JSON array with an object for each test instead
+        #[structopt(long)]
+        json: bool
+    },
+
+    /// Write what every test's specs expect from each executer, as JSON
+    ///
+    /// The behaviors, assertions, and skips which apply to each executer, after
+    /// evaluating predicates. Checking the snapshot in makes changes to what specs
+    /// mean show up in review. --filter, --exclude, and --exclude-dir apply
+    Expectations {
+        /// Path to the top-level test directory
+        #[structopt(parse(from_os_str))]
+        test_dir: PathBuf,
+
+        /// An executer to include (can be repeated). Defaults to all of them
+        #[structopt(
+            long,
+            possible_values = &ExecuterKind::variants(),
+            case_insensitive = true,
+            number_of_values = 1
+        )]
+        executer: Vec<ExecuterKind>,
+
+        /// Write the snapshot to this file instead of printing it
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>
+    },
+
+    /// Compile tests into the artifact cache without running them
+    ///
+    /// For filling the cache off-hours, so that later runs with --artifact-cache
+    /// only have to run tests. Tests which don't need to run, or only expect a
+    /// compile error, are left out. --filter, --exclude, --exclude-dir, --shard,
+    /// --jobs, and --compile-jobs apply. Options like --c0-home and --cache-dir
+    /// have to come before 'prime-cache'
+    PrimeCache {
+        /// Which implementation to compile tests for
+        #[structopt(possible_values = &ExecuterKind::variants(), case_insensitive = true)]
+        executer: ExecuterKind,
+
+        /// Path to the top-level test directory
+        #[structopt(parse(from_os_str))]
+        test_dir: PathBuf
+    },
+
+    /// Run a single test over and over from many threads at once
+    ///
+    /// For reproducing rare failures, e.g. races in the runtime or in how
+    /// compiled tests are handled. Prints how often each outcome happened.
+    /// Options like --c0-home and -t have to come before 'stress'
+    Stress {
+        /// Which implementation to test
+        #[structopt(possible_values = &ExecuterKind::variants(), case_insensitive = true)]
+        executer: ExecuterKind,
+
+        /// A test's source file. Every test with that file is run
+        #[structopt(parse(from_os_str))]
+        test: PathBuf,
+
+        /// How many copies of the test to run at once
+        #[structopt(long, default_value = "8")]
+        instances: usize,
+
+        /// How long to keep running the test, e.g. '30s', '10m', or '1h'
+        #[structopt(long, parse(try_from_str = parse_duration), default_value = "1m")]
+        duration: Duration
+    },
+
+    /// Run the tests with two C0 distributions and compare them
+    ///
+    /// For qualifying a release candidate of cc0 against the current release.
+    /// Reports tests which regressed, were fixed, or behaved differently, and
+    /// ones which ran slower or faster, or compiled larger or smaller, past the
+    /// thresholds. Fails if any test regressed. Options like -t, --filter, and
+    /// --size-threshold have to come before 'ab'
+    Ab {
+        /// Which implementation to test
+        #[structopt(possible_values = &ExecuterKind::variants(), case_insensitive = true)]
+        executer: ExecuterKind,
+
+        /// Path to the top-level test directory
+        #[structopt(parse(from_os_str))]
+        test_dir: PathBuf,
+
+        /// The C0 distribution to compare against, e.g. the current release
+        #[structopt(long, parse(from_os_str))]
+        baseline_home: PathBuf,
+
+        /// The C0 distribution being qualified, e.g. a release candidate
+        #[structopt(long, parse(from_os_str))]
+        candidate_home: PathBuf,
+
+        /// How many percent slower than with the baseline a test
+        /// can run with the candidate before it is reported
+        #[structopt(long, default_value = "25")]
+        slowdown_threshold: f64
+    },
+
+    /// Check how test programs which end in every possible way are classified
+    ///
+    /// Compiles a small C program with $CC (or cc) which exits with chosen
+    /// codes, raises signals, or runs out of CPU time or memory, and runs it
+    /// the way tests are run. For checking a platform without a C0 toolchain
+    #[structopt(setting(AppSettings::Hidden))]
+    SelfTest
+}
+
+impl Options {
+    /// Which implementation to test. Only missing when running a
+    /// subcommand, otherwise clap requires it
+    pub fn executer(&self) -> &ExecuterKind {
+        self.executer.as_ref().expect("Missing <executer>")
+    }
+
+    pub fn test_dir(&self) -> &Path {
+        self.test_dir.as_deref().expect("Missing <test-dir>")
+    }
+
+    pub fn c0_home(&self) -> &Path {
+        self.c0_home.as_deref().expect("Missing --c0-home")
+    }
+
+    pub fn coverage_dir(&self) -> &Path {
+        self.coverage_dir.as_deref().unwrap_or_else(|| Path::new("coverage"))
+    }
+
+    pub fn image_diff_dir(&self) -> &Path {
+        self.image_diff_dir.as_deref().unwrap_or_else(|| Path::new("image-diffs"))
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        if let Some(dir) = &self.cache_dir {
+            return dir.clone()
+        }
+
+        let base = match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+            (Some(cache), _) if !cache.is_empty() => PathBuf::from(cache),
+            (_, Some(home)) => Path::new(&home).join(".cache"),
+            _ => env::temp_dir()
+        };
+        base.join("c0check")
+    }
 }
 
 arg_enum! {
+    #[derive(Clone, Copy)]
     pub enum ExecuterKind {
         CC0,
         C0VM,
@@ -77,7 +819,78 @@ arg_enum! {
     }
 }
 
-fn parse_size(size: &str) -> Result<u64> {
+fn parse_rename(rename: &str) -> Result<(String, String)> {
+    match rename.split_once('=') {
+        Some((old, new)) if !old.trim().is_empty() && !new.trim().is_empty() =>
+            Ok((String::from(old.trim()), String::from(new.trim()))),
+        _ => bail!("Expected <old>=<new>, got '{}'", rename)
+    }
+}
+
+fn parse_suite_c0_home(mapping: &str) -> Result<(String, PathBuf)> {
+    match mapping.split_once('=') {
+        Some((suite, c0_home)) if !suite.trim().is_empty() && !c0_home.trim().is_empty() =>
+            Ok((String::from(suite.trim()), PathBuf::from(c0_home.trim()))),
+        _ => bail!("Expected <suite>=<c0-home>, got '{}'", mapping)
+    }
+}
+
+fn parse_tag(tag: &str) -> Result<(String, String)> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((String::from(key.trim()), String::from(value.trim()))),
+        _ => bail!("Expected <key>=<value>, got '{}'", tag)
+    }
+}
+
+arg_enum! {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub enum TimeoutPolicy {
+        Fail,
+        Warn,
+        Retry
+    }
+}
+
+arg_enum! {
+    /// What to do when sources.test and a file's '//test' line disagree (see --spec-conflicts)
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub enum SpecConflicts {
+        Sources,
+        Inline,
+        Error
+    }
+}
+
+arg_enum! {
+    /// What to do with files the tests leave behind (see --leftovers)
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub enum LeftoverPolicy {
+        Ignore,
+        Report,
+        Clean,
+        Fail
+    }
+}
+
+arg_enum! {
+    /// What to do with artifacts which can't be deleted (see --cleanup-failures)
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub enum CleanupPolicy {
+        Ignore,
+        Warn,
+        Fail
+    }
+}
+
+arg_enum! {
+    #[derive(Clone, Copy)]
+    pub enum CoverageKind {
+        Gcov,
+        Llvm
+    }
+}
+
+pub fn parse_size(size: &str) -> Result<u64> {
     let size = size.trim();
 
     let suffix_pos = match size.rfind(|c: char| c.is_ascii_digit()) {
@@ -111,6 +924,23 @@ fn parse_size(size: &str) -> Result<u64> {
     Ok(bytes as u64)
 }
 
+/// Parses a duration of the form <n><unit>,
+/// where unit is s, m, or h (seconds if blank)
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    let (n, unit) = duration.split_at(duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len()));
+    let n: u64 = n.parse().context(format!("Invalid duration '{}'", duration))?;
+
+    let seconds = match unit.trim().to_ascii_lowercase().as_str() {
+        "h" => n * 60 * 60,
+        "m" => n * 60,
+        "s" | "" => n,
+        _ => bail!("Invalid duration unit '{}'", unit)
+    };
+
+    Ok(Duration::from_secs(seconds))
+}
+
 #[cfg(test)]
 mod options_tests {
     use super::*;
@@ -155,4 +985,16 @@ mod options_tests {
             "9999999999999999999999999999999999999999999999999999999999"
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("30")?, Duration::from_secs(30));
+        assert_eq!(parse_duration("30s")?, Duration::from_secs(30));
+        assert_eq!(parse_duration("10m")?, Duration::from_secs(600));
+        assert_eq!(parse_duration(" 2 h")?, Duration::from_secs(7200));
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5 days").is_err());
+        assert!(parse_duration("-5s").is_err());
+        Ok(())
+    }
+}
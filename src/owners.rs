@@ -0,0 +1,110 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::Result;
+
+use crate::spec::TestInfo;
+use crate::suite_config::SuiteConfig;
+
+/// Who maintains each suite (see 'owners' in suite.toml), so that
+/// failures go to the people who know the tests instead of everyone
+#[derive(Debug, Default)]
+pub struct Owners {
+    by_suite: HashMap<Arc<Path>, Vec<String>>
+}
+
+impl Owners {
+    /// Reads the owners of every suite the tests come from
+    pub fn load<'a>(tests: impl Iterator<Item = &'a TestInfo>) -> Result<Owners> {
+        let mut by_suite = HashMap::new();
+        for test in tests {
+            let dir = &test.execution.directory;
+            if !by_suite.contains_key(dir) {
+                by_suite.insert(dir.clone(), SuiteConfig::load(dir)?.owners().to_vec());
+            }
+        }
+
+        Ok(Owners { by_suite })
+    }
+
+    pub fn of(&self, test: &TestInfo) -> &[String] {
+        match self.by_suite.get(&test.execution.directory) {
+            Some(owners) => owners,
+            None => &[]
+        }
+    }
+
+    /// Whether any suite has owners
+    pub fn any(&self) -> bool {
+        self.by_suite.values().any(|owners| !owners.is_empty())
+    }
+
+    /// The names of the failed tests for each owner, and those from suites without owners
+    pub fn route<'a>(&self, failed: impl Iterator<Item = &'a TestInfo>) -> (BTreeMap<String, BTreeSet<String>>, BTreeSet<String>) {
+        let mut routes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut unowned = BTreeSet::new();
+
+        for test in failed {
+            let owners = self.of(test);
+            if owners.is_empty() {
+                unowned.insert(test.name());
+            }
+            for owner in owners.iter() {
+                routes.entry(owner.clone()).or_default().insert(test.name());
+            }
+        }
+
+        (routes, unowned)
+    }
+}
+
+/// Lists the failed tests under the people who own them
+pub fn print_routing<'a>(owners: &Owners, failed: impl Iterator<Item = &'a TestInfo>) {
+    let (routes, unowned) = owners.route(failed);
+    if routes.is_empty() {
+        return
+    }
+
+    println!("\nFailures by owner:\n");
+    for (owner, tests) in routes.iter() {
+        let tests: Vec<&str> = tests.iter().map(String::as_str).collect();
+        println!("👤 {} ({}): {}", owner, tests.len(), tests.join(", "));
+    }
+    if !unowned.is_empty() {
+        let tests: Vec<&str> = unowned.iter().map(String::as_str).collect();
+        println!("👤 No owner ({}): {}", tests.len(), tests.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod owners_tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::spec::*;
+
+    fn test_in(suite: &str, name: &str) -> TestInfo {
+        let directory = PathBuf::from("/tests").join(suite);
+        TestInfo::new(TestExecutionInfo::for_source(directory.join(name)), Vec::new())
+    }
+
+    #[test]
+    fn test() {
+        let tests = [test_in("parser", "a.c0"), test_in("parser", "b.c0"), test_in("runtime", "c.c0"), test_in("misc", "d.c0")];
+        let mut by_suite = HashMap::new();
+        by_suite.insert(Arc::from(Path::new("/tests/parser")), vec![String::from("@alice")]);
+        by_suite.insert(Arc::from(Path::new("/tests/runtime")), vec![String::from("@alice"), String::from("@bob")]);
+        let owners = Owners { by_suite };
+
+        assert!(owners.any());
+        assert_eq!(owners.of(&tests[0]), &[String::from("@alice")]);
+        assert!(owners.of(&tests[3]).is_empty());
+
+        let (routes, unowned) = owners.route([&tests[1], &tests[2], &tests[3], &tests[1]].iter().copied());
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<BTreeSet<String>>();
+        assert_eq!(routes["@alice"], names(&["parser/b.c0", "runtime/c.c0"]));
+        assert_eq!(routes["@bob"], names(&["runtime/c.c0"]));
+        assert_eq!(unowned, names(&["misc/d.c0"]));
+
+        assert!(!Owners::default().any());
+    }
+}
@@ -1,28 +1,104 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+use nix::sys::signal::Signal;
 use thiserror::Error;
 use logos::{Lexer, Logos, Span};
+use regex::Regex;
 
 use crate::spec::*;
+use crate::options::parse_size;
+use crate::matchers::{self, Pattern};
+use crate::launcher::{EXEC_CONTENTION_CODE, EXEC_FAILURE_CODE, RUST_PANIC_CODE};
 
 /// Parses a 'spec' string with the given options
 ///
 ///```text
+/// line ::= <annotation>* <spec>
+///
+/// annotation ::= @compile_timeout(<seconds>) | @compile_memory(<size>)
+///              | @timeout(<seconds>) | @memory(<size>)
+///              | @before(<command>) | @after(<command>) | @depends(<test>)
+///              | @slow | @no_capture | @tags(<tag>, ...)
+///
 /// spec ::= <predicate> => <spec>
 ///        | <spec> ; <spec>
 ///        | <behavior>
+///        | <assertion>
+///        | skip "<reason>" | skip("<reason>")
+///        | xfail <spec>
 ///
-/// predicate ::= lib | typechecked | gc | safe | false | <ident>
+/// predicate ::= lib | typechecked | gc | safe | false | <ident> | requires(<tool>) | opt(<flag>)
 ///             | ! <predicate>
 ///             | <predicate>, <predicate>
 ///             | <predicate> or <predicate>
 ///
 /// behavior ::= error | infloop | abort | failure | segfault | div-by-zero
-///            | runs | return * | return <int>
+///            | runs | return * | return <int> | exit <code>
+///            | assert | contract | error()
+///
+/// assertion ::= warns "<text>" | no-warnings
+///             | output <matcher> "<expected>" [within <n>%]
+///             | output < <size>
+///             | creates "<file>" matching "<golden file>" [using <matcher>] [within <n>%]
+///             | raw-exit <code> | raw-signal <signal>
 ///```
-pub fn parse(input: &str, options: ParseOptions) -> Result<Specs, SpecParseError> {
+pub fn parse(input: &str, options: ParseOptions) -> Result<(Annotations, Specs), SpecParseError> {
+    let (annotations, specs, _) = parse_with_spans(input, options)?;
+    Ok((annotations, specs))
+}
+
+/// Like 'parse', but also gives the bytes of the input each spec takes up (see 'SpecOrigin')
+pub fn parse_with_spans(input: &str, options: ParseOptions) -> Result<(Annotations, Specs, Vec<Span>), SpecParseError> {
     let mut parser = SpecParser::new(input, options);
     parser.parse()
 }
 
+/// Parses a single behavior, e.g. from the exit codes in suite.toml
+pub fn parse_behavior(input: &str) -> Result<Behavior, SpecParseError> {
+    let mut parser = SpecParser::new(input, ParseOptions { require_test_marker: false });
+    let behavior = parser.parse_behavior()?;
+
+    match parser.lexer.next() {
+        None => Ok(behavior),
+        Some((_, range)) => Err(parser.unexpected_token(range, "end of behavior"))
+    }
+}
+
+/// Renames words (predicates, implementation names, behaviors, etc.) in
+/// a spec string, leaving everything else exactly as it was written
+pub fn rename_words(input: &str, renames: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+
+    for (tok, range) in SpecToken::lexer(input).spanned() {
+        let (start, word) = match tok {
+            SpecToken::TestStartMarker | SpecToken::Annotation(_) | SpecToken::StringLiteral(_) | SpecToken::Requires(_) | SpecToken::Opt(_) => continue,
+            SpecToken::Return(_) => (token_start(input, &tok, &range), "return"),
+            _ => (range.start, &input[range.clone()])
+        };
+
+        if let Some(new_word) = renames.get(word) {
+            result.push_str(&input[last..start]);
+            result.push_str(new_word);
+            last = start + word.len();
+        }
+    }
+
+    result.push_str(&input[last..]);
+    result
+}
+
+/// Where a token starts. 'return' is lexed together with its
+/// value, and the span only covers the value
+fn token_start(input: &str, tok: &SpecToken, range: &Span) -> usize {
+    match tok {
+        SpecToken::Return(_) => input[..range.start].rfind("return").unwrap_or(range.start),
+        _ => range.start
+    }
+}
+
 pub struct ParseOptions {
     /// Whether '//test' should appear at the start of the spec string
     pub require_test_marker: bool
@@ -40,24 +116,29 @@ impl<'a> SpecParser<'a> {
         SpecParser { input, lexer: SpecLexer::new(input), options }
     }
 
-    fn parse(&mut self) -> Result<Specs, SpecParseError> {
+    fn parse(&mut self) -> Result<(Annotations, Specs, Vec<Span>), SpecParseError> {
         use SpecParseError::*;
         use SpecToken::*;
 
         self.lexer = SpecLexer::new(self.input);
 
         // Make sure it starts with //test if required
-        if self.options.require_test_marker {
-            if !matches!(self.lexer.next(), Some((TestStartMarker, _))) {
-                return Err(NotSpec)
-            }
+        if self.options.require_test_marker && !matches!(self.lexer.next(), Some((TestStartMarker, _))) {
+            return Err(NotSpec)
         }
 
+        let annotations = self.parse_annotations()?;
         let mut tests: Specs = Vec::new();
+        let mut spans = Vec::new();
 
         loop {
+            let start = match self.lexer.peek() {
+                Some((tok, range)) => token_start(self.input, &tok, &range),
+                None => self.input.len()
+            };
             let spec = self.parse_spec()?;
             tests.push(spec);
+            spans.push(start..self.lexer.end);
 
             match self.lexer.next() {
                 Some((Semicolon, _)) => (),
@@ -66,7 +147,71 @@ impl<'a> SpecParser<'a> {
             }
         };
 
-        Ok(tests)
+        Ok((annotations, tests, spans))
+    }
+
+    /// Parses the annotations at the start of a spec line
+    ///
+    ///```text
+    /// annotation ::= @compile_timeout(<seconds>) | @compile_memory(<size>)
+    ///              | @timeout(<seconds>) | @memory(<size>)
+    ///              | @before(<command>) | @after(<command>) | @depends(<test>)
+    ///              | @slow | @no_capture | @tags(<tag>, ...)
+    ///```
+    fn parse_annotations(&mut self) -> Result<Annotations, SpecParseError> {
+        let mut annotations = Annotations::default();
+
+        while let Some((SpecToken::Annotation((name, argument)), range)) = self.lexer.peek() {
+            self.lexer.next();
+
+            let invalid = |msg: String| SpecParseError::InvalidAnnotation {
+                name: name.clone(),
+                argument: argument.clone(),
+                msg
+            };
+
+            match name.as_str() {
+                "compile_timeout" => {
+                    let time = argument.trim().parse()
+                        .map_err(|e| invalid(format!("{}", e)))?;
+                    annotations.compilation_limits.time = Some(time);
+                },
+                "compile_memory" => {
+                    let memory = parse_size(&argument)
+                        .map_err(|e| invalid(format!("{:#}", e)))?;
+                    annotations.compilation_limits.memory = Some(memory);
+                },
+                "timeout" => {
+                    let time = argument.trim().parse()
+                        .map_err(|e| invalid(format!("{}", e)))?;
+                    annotations.run_limits.time = Some(time);
+                },
+                "memory" => {
+                    let memory = parse_size(&argument)
+                        .map_err(|e| invalid(format!("{:#}", e)))?;
+                    annotations.run_limits.memory = Some(memory);
+                },
+                "before" => annotations.hooks.before = Some(argument.trim().to_string()),
+                "after" => annotations.hooks.after = Some(argument.trim().to_string()),
+                "depends" => annotations.depends.push(argument.trim().to_string()),
+                "slow" if argument.is_empty() => annotations.slow = true,
+                "no_capture" if argument.is_empty() => annotations.no_capture = true,
+                "tags" => {
+                    for tag in argument.split(',').map(str::trim) {
+                        if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                            return Err(invalid(format!("'{}' isn't a valid tag", tag)))
+                        }
+                        if !annotations.tags.iter().any(|t| t == tag) {
+                            annotations.tags.push(tag.to_string());
+                        }
+                    }
+                },
+                "slow" | "no_capture" => return Err(invalid(String::from("it doesn't take an argument"))),
+                _ => return Err(self.unexpected_token(range, "a known annotation"))
+            }
+        }
+
+        Ok(annotations)
     }
 
     // Pratt parser based on matklad's blog post
@@ -77,6 +222,9 @@ impl<'a> SpecParser<'a> {
     ///```text
     /// spec ::= <predicate> => <spec>
     ///        | <behavior>
+    ///        | <assertion>
+    ///        | skip "<reason>" | skip("<reason>")
+    ///        | xfail <spec>
     ///```
     fn parse_spec(&mut self) -> Result<Spec, SpecParseError> {
         use SpecParseError::*;
@@ -91,6 +239,33 @@ impl<'a> SpecParser<'a> {
             let behavior = self.parse_behavior()?;
             Ok(Spec::Behavior(behavior))
         }
+        else if tok.is_assertion() {
+            let assertion = self.parse_assertion()?;
+            Ok(Spec::Assertion(assertion))
+        }
+        else if matches!(tok, Skip) {
+            self.lexer.next();
+            if !matches!(self.lexer.peek(), Some((LeftParen, _))) {
+                return Ok(Spec::Skip(self.parse_string("reason after 'skip'")?))
+            }
+
+            self.lexer.next();
+            let reason = self.parse_string("reason after 'skip('")?;
+            match self.lexer.next() {
+                Some((RightParen, _)) => Ok(Spec::Skip(reason)),
+                Some((_, range)) => Err(self.unexpected_token(range, "')' after the reason")),
+                None => Err(UnexpectedEOF { msg: "')' after the reason" })
+            }
+        }
+        else if matches!(tok, Xfail) {
+            self.lexer.next();
+            match self.lexer.peek() {
+                Some((Skip, range)) | Some((Xfail, range)) =>
+                    return Err(self.unexpected_token(range, "behavior, assertion or predicate after 'xfail'")),
+                _ => ()
+            }
+            Ok(expect_failure(self.parse_spec()?))
+        }
         else {
             let implementation = self.parse_implementation(0)?;
 
@@ -112,7 +287,7 @@ impl<'a> SpecParser<'a> {
     /// Parses an implementation predicate
     ///
     ///```text
-    /// predicate ::= lib | typechecked | gc | safe | false | <ident>
+    /// predicate ::= lib | typechecked | gc | safe | false | <ident> | requires(<tool>) | opt(<flag>)
     ///             | ! <predicate>
     ///             | <predicate>, <predicate>
     ///             | <predicate> or <predicate>
@@ -148,6 +323,8 @@ impl<'a> SpecParser<'a> {
                     SpecToken::Safe => Safe,
                     SpecToken::False => False,
                     SpecToken::Implementation(name) => ImplementationName(name),
+                    SpecToken::Requires(tool) => Requires(tool),
+                    SpecToken::Opt(flag) => Opt(flag),
 
                     // Not an atom, try to parse it as a prefix operator
                     tok => {
@@ -197,6 +374,10 @@ impl<'a> SpecParser<'a> {
     ///```text
     /// behavior ::= error | infloop | abort | failure | segfault | div-by-zero
     ///            | runs | return * | return <int>
+    ///            | return in [<int>, <int>] | return != <int>
+    ///            | exit <code> | assert | contract | error()
+    ///
+    /// int ::= <decimal> | <hex, e.g. 0x7fffffff> | <char, e.g. '\n'>
     ///```
     fn parse_behavior(&mut self) -> Result<Behavior, SpecParseError> {
         use SpecParseError::*;
@@ -213,13 +394,163 @@ impl<'a> SpecParser<'a> {
                     SpecToken::Failure => Ok(Failure),
                     SpecToken::Segfault => Ok(Segfault),
                     SpecToken::DivZero => Ok(DivZero),
-                    SpecToken::Return(x) => Ok(Return(x)),
+                    SpecToken::Assert => Ok(Failed(RuntimeFailure::Assert, None)),
+                    SpecToken::Contract => Ok(Failed(RuntimeFailure::Contract, None)),
+                    SpecToken::ErrorCall => Ok(Failed(RuntimeFailure::ErrorCall, None)),
+                    SpecToken::Return(behavior) => Ok(behavior),
+                    SpecToken::Exit => match self.lexer.next() {
+                        Some((SpecToken::Number(code), range)) => match code {
+                            0 | 1 => Err(self.unexpected_token(range, "an exit code other than 0 or 1, which mean main returned or the program failed")),
+                            EXEC_FAILURE_CODE | RUST_PANIC_CODE | EXEC_CONTENTION_CODE =>
+                                Err(self.unexpected_token(range, "an exit code other than 100, 101, or 102, which c0check uses for its own failures")),
+                            2..=255 => Ok(Exit(code)),
+                            _ => Err(self.unexpected_token(range, "an exit code from 2 to 255"))
+                        },
+                        Some((_, range)) => Err(self.unexpected_token(range, "exit code")),
+                        None => Err(UnexpectedEOF { msg: "exit code" })
+                    },
 
                     _ => Err(self.unexpected_token(range, "behavior"))
                 }
         }
     }
 
+    /// Parses an assertion about the test output
+    ///
+    ///```text
+    /// assertion ::= warns "<text>" | no-warnings
+    ///             | output <matcher> "<expected>" [within <n>%]
+    ///             | output < <size>
+    ///             | creates "<file>" matching "<golden file>" [using <matcher>] [within <n>%]
+    ///             | expects "<file>" | prints "<text>" | prints /<regex>/
+    ///             | raw-exit <code> | raw-signal <signal>
+    ///```
+    fn parse_assertion(&mut self) -> Result<Assertion, SpecParseError> {
+        use SpecParseError::*;
+        use Assertion::*;
+
+        match self.lexer.next() {
+            None => Err(UnexpectedEOF { msg: "assertion" }),
+            Some((SpecToken::Warns, _)) => Ok(Warns(self.parse_string("string after 'warns'")?)),
+            Some((SpecToken::NoWarnings, _)) => Ok(NoWarnings),
+            Some((SpecToken::Output, _)) if matches!(self.lexer.peek(), Some((SpecToken::Less, _))) => {
+                self.lexer.next();
+                Ok(OutputSize(self.parse_size_limit()?))
+            },
+            Some((SpecToken::Output, _)) => {
+                let matcher = self.parse_matcher("output matcher after 'output'")?;
+                let expected = self.parse_string("expected output after the matcher")?;
+                let tolerance = self.parse_tolerance()?;
+                Ok(Output { matcher, expected, tolerance })
+            },
+            Some((SpecToken::Creates, _)) => {
+                let file = self.parse_string("file name after 'creates'")?;
+                match self.lexer.next() {
+                    Some((SpecToken::Matching, _)) => (),
+                    Some((_, range)) => return Err(self.unexpected_token(range, "'matching' after the file name")),
+                    None => return Err(UnexpectedEOF { msg: "'matching' after the file name" })
+                }
+                let golden = self.parse_string("golden file name after 'matching'")?;
+
+                let matcher = match self.lexer.peek() {
+                    Some((SpecToken::Using, _)) => {
+                        self.lexer.next();
+                        Some(self.parse_matcher("output matcher after 'using'")?)
+                    },
+                    _ => None
+                };
+                let tolerance = self.parse_tolerance()?;
+
+                Ok(Creates { file, golden, matcher, tolerance })
+            },
+            Some((SpecToken::Expects, _)) => Ok(Expects(self.parse_string("file name after 'expects'")?)),
+            Some((SpecToken::Prints, _)) => match self.lexer.next() {
+                Some((SpecToken::StringLiteral(pattern), _)) => Ok(Prints { pattern, regex: None }),
+                Some((SpecToken::RegexLiteral(pattern), _)) => {
+                    let regex = Pattern::compile(&pattern).expect("the lexer only accepts valid regexes");
+                    Ok(Prints { pattern, regex: Some(regex) })
+                },
+                Some((_, range)) => Err(self.unexpected_token(range, "string or /regex/ after 'prints'")),
+                None => Err(UnexpectedEOF { msg: "string or /regex/ after 'prints'" })
+            },
+            Some((SpecToken::RawExit, _)) => match self.lexer.next() {
+                Some((SpecToken::Number(code), _)) if (0..=255).contains(&code) => Ok(RawExit(code)),
+                Some((SpecToken::Number(_), range)) => Err(self.unexpected_token(range, "an exit code from 0 to 255 after 'raw-exit'")),
+                Some((_, range)) => Err(self.unexpected_token(range, "exit code after 'raw-exit'")),
+                None => Err(UnexpectedEOF { msg: "exit code after 'raw-exit'" })
+            },
+            Some((SpecToken::RawSignal, _)) => Ok(RawSignal(self.parse_signal()?)),
+            Some((_, range)) => Err(self.unexpected_token(range, "assertion"))
+        }
+    }
+
+    /// Parses a size after 'output <', e.g. '1kb', '1 kb', or '100'
+    fn parse_size_limit(&mut self) -> Result<u64, SpecParseError> {
+        let msg = "size after 'output <'";
+        let n = match self.lexer.next() {
+            Some((SpecToken::Number(n), _)) if n >= 0 => n,
+            Some((_, range)) => return Err(self.unexpected_token(range, msg)),
+            None => return Err(SpecParseError::UnexpectedEOF { msg })
+        };
+
+        // A unit is optional, so only take the next word if it is one
+        if let Some((SpecToken::Implementation(unit), _)) = self.lexer.peek() {
+            if let Ok(size) = parse_size(&format!("{}{}", n, unit)) {
+                self.lexer.next();
+                return Ok(size)
+            }
+        }
+
+        Ok(n as u64)
+    }
+
+    /// Parses a signal's name with or without 'SIG' (e.g. 'SIGSEGV' or 'SEGV'), or its number
+    fn parse_signal(&mut self) -> Result<Signal, SpecParseError> {
+        let msg = "signal after 'raw-signal'";
+        let signal = match self.lexer.next() {
+            Some((SpecToken::Implementation(name), range)) => {
+                let full_name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+                Signal::from_str(&full_name).map_err(|_| range)
+            },
+            Some((SpecToken::Number(n), range)) => Signal::try_from(n).map_err(|_| range),
+            Some((_, range)) => Err(range),
+            None => return Err(SpecParseError::UnexpectedEOF { msg })
+        };
+
+        signal.map_err(|range| self.unexpected_token(range, msg))
+    }
+
+    /// Parses the name of an output matcher (see 'matchers')
+    fn parse_matcher(&mut self, msg: &'static str) -> Result<String, SpecParseError> {
+        match self.lexer.next() {
+            Some((SpecToken::Implementation(name), _)) if matchers::find(&name).is_some() => Ok(name),
+            Some((_, range)) => Err(self.unexpected_token(range, msg)),
+            None => Err(SpecParseError::UnexpectedEOF { msg })
+        }
+    }
+
+    /// Parses an optional 'within <n>%'
+    fn parse_tolerance(&mut self) -> Result<Option<u32>, SpecParseError> {
+        if !matches!(self.lexer.peek(), Some((SpecToken::Within, _))) {
+            return Ok(None)
+        }
+
+        self.lexer.next();
+        match (self.lexer.next(), self.lexer.next()) {
+            (Some((SpecToken::Number(n), _)), Some((SpecToken::Percent, _))) if n >= 0 => Ok(Some(n as u32)),
+            (Some((_, range)), _) => Err(self.unexpected_token(range, "percentage after 'within'")),
+            (None, _) => Err(SpecParseError::UnexpectedEOF { msg: "percentage after 'within'" })
+        }
+    }
+
+    fn parse_string(&mut self, msg: &'static str) -> Result<String, SpecParseError> {
+        match self.lexer.next() {
+            Some((SpecToken::StringLiteral(text), _)) => Ok(text),
+            Some((_, range)) => Err(self.unexpected_token(range, msg)),
+            None => Err(SpecParseError::UnexpectedEOF { msg })
+        }
+    }
+
     /// Creates an unexpected token error
     fn unexpected_token(&mut self, range: Span, msg: &'static str) -> SpecParseError {
         SpecParseError::UnexpectedToken {
@@ -236,11 +567,46 @@ pub enum SpecParseError {
     #[error("Did not start with //test")]
     NotSpec,
 
-    #[error("unexpected '{actual:?}' at {range:?}, expected {msg}")]
+    #[error("unexpected '{actual}', expected {msg}")]
     UnexpectedToken { actual: String, range: Span, msg: &'static str },
 
     #[error("unexpected end of input, expected {msg}")]
-    UnexpectedEOF { msg: &'static str }
+    UnexpectedEOF { msg: &'static str },
+
+    #[error("invalid argument '{argument}' for '@{name}': {msg}")]
+    InvalidAnnotation { name: String, argument: String, msg: String }
+}
+
+impl SpecParseError {
+    /// The bytes of the input the error is about. Running out of input is
+    /// at the end of it, and bad annotations aren't anywhere in particular
+    pub fn span(&self, input: &str) -> Option<Span> {
+        match self {
+            SpecParseError::UnexpectedToken { range, .. } => Some(range.clone()),
+            SpecParseError::UnexpectedEOF { .. } => {
+                let end = input.trim_end().len();
+                Some(end..end)
+            },
+            SpecParseError::NotSpec | SpecParseError::InvalidAnnotation { .. } => None
+        }
+    }
+
+    /// Describes the error like a compiler would, so editors can jump to it:
+    /// 'file:line:column: message', then the spec with the error underlined.
+    /// 'input' is what was parsed, which starts the given line of the file
+    pub fn render(&self, file: &Path, line: usize, input: &str) -> String {
+        let span = match self.span(input) {
+            Some(span) => span,
+            None => return format!("{}:{}: {}\n    {}", file.display(), line, self, input)
+        };
+
+        // Tabs are kept so that the carets line up however wide they are
+        let before = &input[..span.start];
+        let indent: String = before.chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let width = input[span].chars().count().max(1);
+        format!("{}:{}:{}: {}\n    {}\n    {}{}",
+            file.display(), line, before.chars().count() + 1, self, input, indent, "^".repeat(width))
+    }
 }
 
 #[cfg(test)]
@@ -264,7 +630,199 @@ mod parser_tests {
         parse_test("//test cc0 or coin => return 5", true);
 
         parse_test("//test safe => segfault; !safe => runs", true);
-        parse_test("//test safe => !cc0_c0vm => div-by-zero", true)
+        parse_test("//test safe => !cc0_c0vm => div-by-zero", true);
+
+        parse_test("//test return in [0, 10]", true);
+        parse_test("//test safe => return != 0; !safe => runs", true);
+        parse_test("//test return in [0 10]", false);
+        parse_test("//test return != *", false);
+        parse_test("//test exit 3", true);
+        parse_test("//test coin => exit 42; cc0 => failure", true);
+        parse_test("//test exit 1", false);
+        parse_test("//test exit 255", true);
+        parse_test("//test exit 256", false);
+        parse_test("//test exit -3", false);
+        parse_test("//test exit 101", false);
+        parse_test("//test exit", false);
+        parse_test("//test safe => contract; !safe => return 0", true);
+        parse_test("//test assert; prints \"x > 0\"", true);
+        parse_test("//test error(); prints \"not implemented\"", true);
+        parse_test("//test return 0; warns \"unused variable\"", true);
+        parse_test("//test cc0 => no-warnings; return 0", true);
+        parse_test("//test warns", false);
+        parse_test("//test warns unused", false);
+
+        parse_test("//test return 0; creates \"out.txt\" matching \"golden/out.txt\"", true);
+        parse_test("//test creates \"out.txt\"", false);
+        parse_test("//test return 0; expects \"a.c0.expect\"", true);
+        parse_test("//test expects", false);
+        parse_test("//test failure; prints \"assertion failed\"", true);
+        parse_test("//test return 0; prints /^[0-9]+ primes$/", true);
+        parse_test("//test prints", false);
+        parse_test("//test prints /(/", false);
+        parse_test("//test creates \"out.txt\" \"golden/out.txt\"", false);
+        parse_test("//test creates \"out.png\" matching \"golden/out.png\" within 5%", true);
+        parse_test("//test creates \"out.png\" matching \"golden/out.png\" within 5", false);
+        parse_test("//test creates \"out.png\" matching \"golden/out.png\" within -5%", false);
+        parse_test("//test creates \"out.txt\" matching \"golden/out.txt\" using unordered", true);
+        parse_test("//test creates \"out.txt\" matching \"golden/out.txt\" using floats within 1%", true);
+        parse_test("//test creates \"out.txt\" matching \"golden/out.txt\" using fuzzy", false);
+
+        parse_test("//test return 0; output regex \"^sum: [0-9]+\"", true);
+        parse_test("//test output golden \"expected.txt\"; return 0", true);
+        parse_test("//test output floats \"expected.txt\" within 2%", true);
+        parse_test("//test output \"expected.txt\"", false);
+        parse_test("//test output fuzzy \"expected.txt\"", false);
+
+        parse_test("//test return 0; output < 1kb", true);
+        parse_test("//test output < 1 kb; return 0", true);
+        parse_test("//test lib => output < 0; return 0", true);
+        parse_test("//test output < -1", false);
+        parse_test("//test output < lots", false);
+        parse_test("//test output <", false);
+
+        parse_test("//test raw-exit 3", true);
+        parse_test("//test raw-exit 0x40; raw-signal SIGSEGV", true);
+        parse_test("//test raw-signal SEGV", true);
+        parse_test("//test raw-signal 6", true);
+        parse_test("//test raw-exit", false);
+        parse_test("//test raw-exit 255", true);
+        parse_test("//test raw-exit 256", false);
+        parse_test("//test raw-exit -1", false);
+        parse_test("//test raw-signal SIGNOPE", false);
+        parse_test("//test raw-signal 1000", false);
+
+        parse_test("//test skip \"needs the new runtime\"", true);
+        parse_test("//test coin => skip \"coin can't load the library\"; return 0", true);
+        parse_test("//test skip", false);
+        parse_test("//test skip needs the new runtime", false);
+        parse_test("//test skip(\"needs the new runtime\")", true);
+        parse_test("//test coin => skip( \"no images\" ); return 0", true);
+        parse_test("//test skip(\"needs the new runtime\"", false);
+        parse_test("//test skip()", false);
+        parse_test("//test skip(needs)", false);
+
+        parse_test("//test xfail safe => return 0", true);
+        parse_test("//test !safe => xfail return 0; prints \"done\"", true);
+        parse_test("//test xfail", false);
+        parse_test("//test xfail skip \"broken\"", false);
+        parse_test("//test xfail xfail return 0", false);
+
+        parse_test("//test requires(gcc) => return 0", true);
+        parse_test("//test !requires(libpng), cc0 => skip \"needs libpng\"; requires(libpng) => return 0", true);
+        parse_test("//test requires() => return 0", false);
+
+        parse_test("//test opt(-d) => abort; !opt(-d) => return 0", true);
+        parse_test("//test opt(--standard=c1), cc0 => return 1", true);
+        parse_test("//test opt() => return 0", false)
+    }
+
+    #[test]
+    fn test_skip() -> Result<(), SpecParseError> {
+        let (_, specs) = parse("//test skip(\"flaky on \\\"arm\\\"\"); cc0 => skip \"slow\"", ParseOptions { require_test_marker: true })?;
+        let written: Vec<String> = specs.iter().map(Spec::to_string).collect();
+        assert_eq!(written, vec!["skip \"flaky on \\\"arm\\\"\"", "cc0 => skip \"slow\""]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xfail() -> Result<(), SpecParseError> {
+        let (_, specs) = parse("//test xfail safe, lib => return 0; xfail abort", ParseOptions { require_test_marker: true })?;
+        let written: Vec<String> = specs.iter().map(Spec::to_string).collect();
+        assert_eq!(written, vec!["safe, lib => xfail return 0", "xfail abort"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spans() -> Result<(), SpecParseError> {
+        let input = "//test @compile_timeout(5) safe => return 5; output exact \"hi\";cc0 => return in [1, 2]";
+        let (_, specs, spans) = parse_with_spans(input, ParseOptions { require_test_marker: true })?;
+        assert_eq!(specs.len(), spans.len());
+        let written: Vec<&str> = spans.into_iter().map(|span| &input[span]).collect();
+        assert_eq!(written, vec!["safe => return 5", "output exact \"hi\"", "cc0 => return in [1, 2]"]);
+
+        let (_, _, spans) = parse_with_spans("return 0", ParseOptions { require_test_marker: false })?;
+        assert_eq!(spans, vec![0..8]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() {
+        let render = |input: &str| {
+            let error = parse(input, ParseOptions { require_test_marker: true }).unwrap_err();
+            error.render(Path::new("tests/basic/a.c0"), 1, input)
+        };
+
+        assert_eq!(render("//test safe => retrn 5"),
+            "tests/basic/a.c0:1:22: unexpected '5', expected '=>' between implementation and behavior\n    //test safe => retrn 5\n                         ^");
+
+        assert_eq!(render("//test\tsafe =>  "),
+            "tests/basic/a.c0:1:15: unexpected end of input, expected implementation or behavior\n    //test\tsafe =>  \n          \t       ^");
+        assert!(render("//test @compile_timeout(soon) return 0").starts_with("tests/basic/a.c0:1: invalid argument 'soon'"));
+        assert!(render("//test exit 101").contains("expected an exit code other than 100, 101, or 102, which c0check uses for its own failures"));
+    }
+
+    #[test]
+    fn test_annotations() -> Result<(), SpecParseError> {
+        let (annotations, specs) = parse("//test @compile_timeout(60) @compile_memory(8gb) return 0", ParseOptions { require_test_marker: true })?;
+        assert_eq!(annotations.compilation_limits, ResourceLimits {
+            time: Some(60),
+            memory: Some(8 * 1024 * 1024 * 1024)
+        });
+        assert_eq!(specs.len(), 1);
+
+        let (annotations, _) = parse("//test @timeout(60) @memory(8gb) safe => return 0", ParseOptions { require_test_marker: true })?;
+        assert_eq!(annotations.run_limits, ResourceLimits {
+            time: Some(60),
+            memory: Some(8 * 1024 * 1024 * 1024)
+        });
+
+        parse_test("//test @compile_timeout(60) safe => return 0", true);
+        parse_test("//test @compile_timeout(soon) return 0", false);
+        parse_test("//test @compile_memory(lots) return 0", false);
+        parse_test("//test @timeout(1m) return 0", false);
+        parse_test("//test @memory(lots) return 0", false);
+        parse_test("//test @unknown(1) return 0", false);
+        parse_test("//test @timeout return 0", false);
+        parse_test("//test @slow(yes) return 0", false);
+        parse_test("//test @tags() return 0", false);
+        parse_test("//test @tags(gc, two words) return 0", false);
+
+        let (annotations, _) = parse("//test @slow @timeout(60) @no_capture return 0", ParseOptions { require_test_marker: true })?;
+        assert!(annotations.slow && annotations.no_capture);
+        assert_eq!(annotations.run_limits.time, Some(60));
+
+        let (annotations, _) = parse("//test @before(./make-input.sh 100) @after(rm input.txt) return 0", ParseOptions { require_test_marker: true })?;
+        assert_eq!(annotations.hooks.before.as_deref(), Some("./make-input.sh 100"));
+        assert_eq!(annotations.hooks.after.as_deref(), Some("rm input.txt"));
+
+        let (annotations, _) = parse("//test @before(make $(X)) @after((cd a && make clean)) return 0", ParseOptions { require_test_marker: true })?;
+        assert_eq!(annotations.hooks.before.as_deref(), Some("make $(X)"));
+        assert_eq!(annotations.hooks.after.as_deref(), Some("(cd a && make clean)"));
+        let (annotations, _) = parse("//test @before(echo \")\" ')') return 0", ParseOptions { require_test_marker: true })?;
+        assert_eq!(annotations.hooks.before.as_deref(), Some("echo \")\" ')'"));
+        parse_test("//test @before(echo (a) return 0", false);
+
+        let (annotations, _) = parse("//test @depends(writer.c0) @depends(lib.c0 setup.c0) return 0", ParseOptions { require_test_marker: true })?;
+        assert_eq!(annotations.depends, vec!["writer.c0", "lib.c0 setup.c0"]);
+
+        let (annotations, _) = parse("//test @tags(gc, quick) @tags(gc) return 0", ParseOptions { require_test_marker: true })?;
+        assert_eq!(annotations.tags, vec!["gc", "quick"]);
+
+        parse_test("//test return 0 @compile_timeout(60)", false);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_words() {
+        let renames: HashMap<String, String> = [("typecheck", "typechecked"), ("failure", "fail"), ("return", "returns")]
+            .iter().map(|(old, new)| (old.to_string(), new.to_string())).collect();
+
+        assert_eq!(rename_words("//test typecheck, !typecheck-x =>  failure", &renames), "//test typechecked, !typecheck-x =>  fail");
+        assert_eq!(rename_words("@compile_timeout(5) warns \"typecheck\"; return 5", &renames), 
+            "@compile_timeout(5) warns \"typecheck\"; returns 5");
+        assert_eq!(rename_words("safe => error", &renames), "safe => error");
     }
 }
 
@@ -273,10 +831,17 @@ enum SpecToken {
     #[token("//test")]
     TestStartMarker,
 
+    #[regex(r"@[a-zA-Z_][a-zA-Z0-9_]*", lex_annotation)]
+    Annotation((String, String)),
+
     #[token("error")]
     CompileError,
     #[token("runs")]
     Runs,
+    #[token("skip")]
+    Skip,
+    #[token("xfail")]
+    Xfail,
     #[token("infloop")]
     InfiniteLoop,
     #[token("abort")]
@@ -288,13 +853,69 @@ enum SpecToken {
     #[token("div-by-zero")]
     DivZero,
     #[token("return", lex_return)]
-    Return(Option<i32>),
+    Return(Behavior),
+    #[token("exit")]
+    Exit,
+    #[token("assert")]
+    Assert,
+    #[token("contract")]
+    Contract,
+    #[token("error()")]
+    ErrorCall,
+
+    #[token("warns")]
+    Warns,
+    #[token("no-warnings")]
+    NoWarnings,
+    #[token("output")]
+    Output,
+    #[token("creates")]
+    Creates,
+    #[token("expects")]
+    Expects,
+    #[token("prints")]
+    Prints,
+    #[token("raw-exit")]
+    RawExit,
+    #[token("raw-signal")]
+    RawSignal,
+    #[token("matching")]
+    Matching,
+    #[token("using")]
+    Using,
+    #[token("within")]
+    Within,
+    #[token("%")]
+    Percent,
+    #[token("<")]
+    Less,
+    #[regex(r#""([^"\\]|\\.)*""#, lex_string)]
+    StringLiteral(String),
+    #[regex(r"/([^/\\]|\\.)+/", lex_regex)]
+    RegexLiteral(String),
 
     // Only used to help lex return
     #[token("*")]
     Star,
+    #[token("[")]
+    LeftBracket,
+    #[token("]")]
+    RightBracket,
+    #[token("in")]
+    In,
+    #[token("!=")]
+    NotEqual,
+
+    // Only used for 'skip("<reason>")'
+    #[token("(")]
+    LeftParen,
+    #[token(")")]
+    RightParen,
+    #[regex(r"'([^'\\]|\\.)'", lex_char)]
+    Char(i32),
     #[regex("[+-]?(0|[1-9][0-9]*)", |lex| lex.slice().parse())]
-    #[regex("0[xX][0-9a-fA-F]+", |lex| i32::from_str_radix(&lex.slice()[2..], 16).ok())]
+    // Like C0, hex literals are 32 bits wide, so 0xffffffff is -1
+    #[regex("0[xX][0-9a-fA-F]+", |lex| u32::from_str_radix(&lex.slice()[2..], 16).ok().map(|x| x as i32))]
     Number(i32),
 
     #[token("lib")]
@@ -307,6 +928,10 @@ enum SpecToken {
     Safe,
     #[token("false")]
     False,
+    #[regex(r"requires\([^)]*\)", lex_argument)]
+    Requires(String),
+    #[regex(r"opt\([^)]*\)", lex_argument)]
+    Opt(String),
 
     #[regex(r"[a-zA-Z_][-a-zA-Z0-9_]*", |lex| String::from(lex.slice()))]
     Implementation(String),
@@ -340,21 +965,154 @@ impl SpecToken {
             | Failure
             | DivZero
             | Return(_)
+            | Exit
+            | Assert
+            | Contract
+            | ErrorCall
         )
     }
+
+    fn is_assertion(&self) -> bool {
+        use SpecToken::*;
+
+        matches!(self, Warns | NoWarnings | Output | Creates | Expects | Prints | RawExit | RawSignal)
+    }
 }
 
-/// Does the dirty work of lexing 'return *' and 'return <n>' as one token
-fn lex_return(lexer: &mut Lexer<SpecToken>) -> Option<Option<i32>> {
-    match lexer.next() {
-        Some(SpecToken::Number(x)) => Some(Some(x)),
-        Some(SpecToken::Star) => Some(None),
+/// Marks what a spec expects as expected to fail, inside any predicates, so
+/// that 'xfail safe => return 0' is the same as 'safe => xfail return 0'
+fn expect_failure(spec: Spec) -> Spec {
+    match spec {
+        Spec::Implication(predicate, consequent) => Spec::Implication(predicate, Box::new(expect_failure(*consequent))),
+        Spec::Skip(_) | Spec::ExpectedFailure(_) => spec,
+        spec => Spec::ExpectedFailure(Box::new(spec))
+    }
+}
+
+/// Does the dirty work of lexing 'return *', 'return <n>', 'return in [<min>, <max>]'
+/// and 'return != <n>' as one token. Values can be written as numbers or chars
+fn lex_return(lexer: &mut Lexer<SpecToken>) -> Option<Behavior> {
+    let value = |tok: SpecToken| match tok {
+        SpecToken::Number(x) | SpecToken::Char(x) => Some(x),
         _ => None
+    };
+
+    match lexer.next()? {
+        SpecToken::Star => Some(Behavior::Return(None)),
+        SpecToken::NotEqual => Some(Behavior::ReturnIn(ReturnRange::Except(value(lexer.next()?)?))),
+        SpecToken::In => {
+            let mut next = || lexer.next();
+            match (next()?, next()?, next()?, next()?, next()?) {
+                (SpecToken::LeftBracket, min, SpecToken::Comma, max, SpecToken::RightBracket) => {
+                    let (min, max) = (value(min)?, value(max)?);
+                    if min <= max { Some(Behavior::ReturnIn(ReturnRange::Between(min, max))) } else { None }
+                },
+                _ => None
+            }
+        },
+        tok => Some(Behavior::Return(Some(value(tok)?)))
+    }
+}
+
+/// The value of a C0 char literal, e.g. 'a' or '\n'
+fn lex_char(lexer: &mut Lexer<SpecToken>) -> Option<i32> {
+    let slice = lexer.slice();
+    let mut chars = slice[1..slice.len() - 1].chars();
+
+    let c = match chars.next()? {
+        '\\' => match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'v' => '\x0b',
+            'b' => '\x08',
+            'r' => '\r',
+            'f' => '\x0c',
+            'a' => '\x07',
+            '0' => '\0',
+            c @ ('\\' | '\'' | '"') => c,
+            _ => return None
+        },
+        c => c
+    };
+
+    if c.is_ascii() { Some(c as i32) } else { None }
+}
+
+/// Removes the slashes around a regex literal, and the escapes from slashes inside it.
+/// Invalid regexes don't lex, so they're reported when the spec is parsed
+fn lex_regex(lexer: &mut Lexer<SpecToken>) -> Option<String> {
+    let slice = lexer.slice();
+    let pattern = slice[1..slice.len() - 1].replace("\\/", "/");
+    Regex::new(&pattern).ok().map(|_| pattern)
+}
+
+/// Removes the quotes and escapes from a string literal
+fn lex_string(lexer: &mut Lexer<SpecToken>) -> String {
+    let slice = lexer.slice();
+    let mut result = String::new();
+    let mut chars = slice[1..slice.len() - 1].chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(c) => result.push(c),
+            None => ()
+        }
     }
+
+    result
+}
+
+/// Gets the tool out of 'requires(<tool>)' or the flag out of 'opt(<flag>)', which can't be empty
+fn lex_argument(lexer: &mut Lexer<SpecToken>) -> Option<String> {
+    let slice = lexer.slice();
+    let argument = slice[slice.find('(').unwrap() + 1..slice.len() - 1].trim();
+    if argument.is_empty() { None } else { Some(String::from(argument)) }
+}
+
+/// Lexes an annotation's name and its argument in parentheses, if it has one. The
+/// argument can have parentheses of its own if they're balanced or quoted, e.g.
+/// '@before(make $(X))' or '@before(echo ")")'. An unclosed one is an error
+fn lex_annotation(lexer: &mut Lexer<SpecToken>) -> Option<(String, String)> {
+    let name = String::from(&lexer.slice()[1..]);
+    let rest = lexer.remainder();
+    // e.g. '@slow'
+    if !rest.starts_with('(') {
+        return Some((name, String::new()))
+    }
+
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    lexer.bump(i + 1);
+                    return Some((name, String::from(&rest[1..i])))
+                }
+            },
+            _ => ()
+        }
+    }
+
+    None
 }
 
 struct SpecLexer {
-    tokens: Vec<(SpecToken, Span)>
+    tokens: Vec<(SpecToken, Span)>,
+    /// Where the last token taken ends
+    end: usize
 }
 
 impl SpecLexer {
@@ -363,11 +1121,15 @@ impl SpecLexer {
         let mut tokens: Vec<_> = lexer.collect();
         tokens.reverse();
 
-        SpecLexer { tokens }
+        SpecLexer { tokens, end: 0 }
     }
 
     fn next(&mut self) -> Option<(SpecToken, Span)> {
-        self.tokens.pop()
+        let token = self.tokens.pop();
+        if let Some((_, range)) = &token {
+            self.end = range.end;
+        }
+        token
     }
 
     fn peek(&self) -> Option<(SpecToken, Span)> {
@@ -388,8 +1150,24 @@ mod lexer_tests {
 
     #[test]
     fn test() {
-        lex_test("safe => return 5", &[Safe, FatArrow, Return(Some(5))]);
-        lex_test("safe => return *", &[Safe, FatArrow, Return(None)]);
-        lex_test("safe => return *", &[Safe, FatArrow, Return(None)]);
+        lex_test("safe => return 5", &[Safe, FatArrow, Return(Behavior::Return(Some(5)))]);
+        lex_test("error(); error", &[ErrorCall, Semicolon, CompileError]);
+        lex_test("safe => return *", &[Safe, FatArrow, Return(Behavior::Return(None))]);
+        lex_test("safe => return *", &[Safe, FatArrow, Return(Behavior::Return(None))]);
+        lex_test("@compile_memory(8 gb) return 0", &[
+            Annotation((String::from("compile_memory"), String::from("8 gb"))),
+            Return(Behavior::Return(Some(0)))
+        ]);
+        lex_test("return in [-1, 10]", &[Return(Behavior::ReturnIn(ReturnRange::Between(-1, 10)))]);
+        lex_test("return != 0", &[Return(Behavior::ReturnIn(ReturnRange::Except(0)))]);
+        lex_test("return in [10, 0]", &[Error]);
+        lex_test("return '\\n'", &[Return(Behavior::Return(Some(10)))]);
+        lex_test("return != 'a'", &[Return(Behavior::ReturnIn(ReturnRange::Except(97)))]);
+        lex_test("return in ['0', '9']", &[Return(Behavior::ReturnIn(ReturnRange::Between(48, 57)))]);
+        lex_test("return 0x7fffffff", &[Return(Behavior::Return(Some(i32::MAX)))]);
+        lex_test("return 0xffffffff", &[Return(Behavior::Return(Some(-1)))]);
+        lex_test("return '\\q'", &[Error]);
+        lex_test(r"prints /a\/b+/", &[Prints, RegexLiteral(String::from("a/b+"))]);
+        lex_test(r#"warns "say \"hi\"""#, &[Warns, StringLiteral(String::from("say \"hi\""))]);
     }
 }
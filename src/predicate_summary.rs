@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::executer::ExecuterProperties;
+use crate::spec::*;
+
+/// Label for specs without any predicates
+const UNCONDITIONAL: &str = "(no predicate)";
+
+/// How many tests passed and failed
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BranchCounts {
+    pub passed: usize,
+    pub failed: usize
+}
+
+/// Counts results by the predicates that led to the specs which applied to the
+/// executer, e.g. 'safe => !gc' for 'safe => !gc => return 0'. A test counts
+/// once for each different branch it took, so that failures in one capability
+/// (e.g. everything under 'gc') stand out
+pub fn summarize(properties: &ExecuterProperties, passed: &[&TestInfo], failed: &[&TestInfo]) -> BTreeMap<String, BranchCounts> {
+    let mut counts: BTreeMap<String, BranchCounts> = BTreeMap::new();
+
+    for (tests, passed) in [(passed, true), (failed, false)].iter() {
+        for test in tests.iter() {
+            let branches: HashSet<String> = test.specs.iter()
+                .filter_map(|spec| matched_branch(spec, properties, &test.execution.compiler_options))
+                .collect();
+
+            for branch in branches {
+                let counts = counts.entry(branch).or_default();
+                if *passed { counts.passed += 1 } else { counts.failed += 1 }
+            }
+        }
+    }
+
+    counts
+}
+
+/// The predicates leading to a spec's behavior or assertion, if they all match
+fn matched_branch(spec: &Spec, properties: &ExecuterProperties, compiler_options: &[String]) -> Option<String> {
+    let mut predicates = Vec::new();
+    let mut spec = spec;
+    while let Spec::Implication(predicate, consequent) = spec {
+        if !properties.matches_predicate(predicate, compiler_options) {
+            return None
+        }
+        predicates.push(predicate.to_string());
+        spec = consequent;
+    }
+
+    if predicates.is_empty() {
+        Some(String::from(UNCONDITIONAL))
+    }
+    else {
+        Some(predicates.join(" => "))
+    }
+}
+
+/// Prints the branches with the most failures first
+pub fn print_summary(counts: &BTreeMap<String, BranchCounts>) {
+    if counts.is_empty() {
+        return
+    }
+
+    let mut counts: Vec<(&String, &BranchCounts)> = counts.iter().collect();
+    counts.sort_by(|(name1, counts1), (name2, counts2)| counts2.failed.cmp(&counts1.failed).then(name1.cmp(name2)));
+
+    let name_width = counts.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+    println!("\nBy predicate:\n");
+    for (name, counts) in counts {
+        let total = counts.passed + counts.failed;
+        let percent = counts.passed as f64 / total.max(1) as f64 * 100.;
+        let marker = if counts.failed == 0 { "✅" } else { "❌" };
+        println!("{} {:name_width$}  {:>5} passed  {:>5} failed  {:>5.1}%",
+            marker, name, counts.passed, counts.failed, percent, name_width = name_width);
+    }
+}
+
+#[cfg(test)]
+mod predicate_summary_tests {
+    use super::*;
+    use crate::parse_spec::{self, ParseOptions};
+
+    fn test_with_spec(spec: &str) -> TestInfo {
+        let (_, specs) = parse_spec::parse(spec, ParseOptions { require_test_marker: false }).unwrap();
+        TestInfo::new(TestExecutionInfo::for_source("/tests/a.c0"), specs)
+    }
+
+    #[test]
+    fn test_summarize() {
+        let properties = ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: false, name: "cc0" };
+        let gc = test_with_spec("gc => return 0");
+        let unsafe_gc = test_with_spec("!safe => gc => segfault; safe => abort");
+        let plain = test_with_spec("return 1; return 1");
+        let other = test_with_spec("coin => return 2");
+
+        let counts = summarize(&properties, &[&gc, &plain, &other], &[&unsafe_gc]);
+        assert_eq!(counts["gc"], BranchCounts { passed: 1, failed: 0 });
+        assert_eq!(counts["!safe => gc"], BranchCounts { passed: 0, failed: 1 });
+        assert_eq!(counts[UNCONDITIONAL], BranchCounts { passed: 1, failed: 0 });
+        assert_eq!(counts.len(), 3);
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use anyhow::{Context, Result};
+
+use crate::fingerprint;
+use crate::hooks::Hooks;
+use crate::options::Options;
+use crate::spec::*;
+
+/// How many of a test's latest run times are kept
+const RUN_TIMES: usize = 5;
+
+/// Remembers which tests passed, so that running them again with the same
+/// toolchain, settings, and sources can be skipped. Only passes are cached,
+/// so failing tests always run again
+pub struct ResultCache {
+    path: PathBuf,
+    /// Tests are keyed by their paths relative to it, so that the cache
+    /// can be shared between checkouts in different places (see --storage)
+    test_dir: PathBuf,
+    /// Everything about the run's options which can change a test's result
+    settings: String,
+    /// Whether cached results are used (--force still updates the cache)
+    reuse: bool,
+    previous: HashMap<String, CachedPass>,
+    /// Passes from this run, which are added to the previous ones when saved
+    current: Mutex<HashMap<String, CachedPass>>,
+    /// Previous passes of tests which didn't pass this time, which aren't saved again
+    stale: Mutex<HashSet<String>>
+}
+
+/// What is kept from a passing run, for the baseline and run time comparisons
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CachedPass {
+    pub artifact_size: Option<u64>,
+    pub run_time: Option<Duration>,
+    /// Run times of the test's earlier passes, oldest first, so that tests
+    /// which keep getting close to their time limit can be suggested for '@slow'
+    #[serde(default)]
+    pub earlier_run_times: Vec<Duration>
+}
+
+impl CachedPass {
+    /// The run times of this pass and the earlier ones, oldest first
+    pub fn run_times(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.earlier_run_times.iter().copied().chain(self.run_time)
+    }
+}
+
+impl ResultCache {
+    pub fn path(options: &Options) -> PathBuf {
+        options.cache_dir().join("results.json")
+    }
+
+    /// 'c0_homes' are the suites' own C0 distributions (see suite_toolchains::c0_homes)
+    pub fn new(options: &Options, c0_homes: &BTreeMap<Arc<Path>, PathBuf>) -> Result<ResultCache> {
+        let path = ResultCache::path(options);
+
+        let previous = load(&path);
+        let test_dir = fs::canonicalize(options.test_dir()).context("Couldn't resolve the test directory")?;
+
+        let suppressions = match &options.suppressions {
+            Some(path) => fingerprint::file_hash(path)?,
+            None => String::new()
+        };
+        let settings = format!("{} {} {} {} {} {} {} {} {} {} {} {} {:?}",
+            options.test_time, options.test_memory, options.compilation_time, options.compilation_mem,
+            options.no_debug_info, options.image_tolerance, options.check_infloop_cpu, suppressions,
+            options.timeouts_are, options.retries, options.slow_factor, options.paranoid, c0_homes);
+
+        // Saving generated code, collecting coverage, or shuffling tests needs every test to actually run
+        let reuse = !options.force && options.save_files.is_none() && options.coverage.is_none() && !options.chaos && !options.shuffle;
+
+        Ok(ResultCache { path, test_dir, settings, reuse, previous, current: Mutex::default(), stale: Mutex::default() })
+    }
+
+    /// The key a test's result is cached under, or 'None' if it can't be cached:
+    /// tests with hooks or which compare files depend on more than their sources
+    pub fn test_key(&self, toolchain: &str, test: &TestInfo) -> Option<String> {
+        if test.execution.hooks != Hooks::default() || test.specs.iter().any(uses_files) {
+            return None
+        }
+
+        let compilation = fingerprint::relative_compilation_key(&test.execution, &self.test_dir).ok()?;
+        let directory = test.execution.directory.strip_prefix(&self.test_dir).unwrap_or(&test.execution.directory);
+        let limits = test.execution.run_limits;
+        let key = format!("{} {} {} {} {:?} {:?} {} {:?}",
+            toolchain, self.settings, compilation, test.spec_line(), limits.time, limits.memory,
+            directory.display(), test.execution.exit_codes);
+
+        Some(format!("{:x}", Sha256::digest(key.as_bytes())))
+    }
+
+    /// Finds a previous pass of a test. It stays in the cache
+    pub fn get(&self, key: &str) -> Option<CachedPass> {
+        if !self.reuse {
+            return None
+        }
+
+        let pass = self.previous.get(key)?.clone();
+        self.current.lock().unwrap().insert(key.to_string(), pass.clone());
+        Some(pass)
+    }
+
+    /// Adds a pass of a test, which keeps the latest run times of its previous passes
+    pub fn insert(&self, key: String, mut pass: CachedPass) {
+        if let Some(previous) = self.previous.get(&key) {
+            let mut run_times: Vec<Duration> = previous.run_times().collect();
+            run_times.drain(..run_times.len().saturating_sub(RUN_TIMES - 1));
+            pass.earlier_run_times = run_times;
+        }
+        self.current.lock().unwrap().insert(key, pass);
+    }
+
+    /// The run times of a test's latest passes, oldest first, including this run's
+    pub fn run_times(&self, key: &str) -> Vec<Duration> {
+        let current = self.current.lock().unwrap();
+        let pass = match current.get(key) {
+            Some(pass) => Some(pass),
+            None if self.stale.lock().unwrap().contains(key) => None,
+            None => self.previous.get(key)
+        };
+        pass.map(|pass| pass.run_times().collect()).unwrap_or_default()
+    }
+
+    /// Adds the passes in another cache file, e.g. one fetched from --storage,
+    /// to the previous ones. Returns how many this machine didn't have yet
+    pub fn merge(&mut self, path: &Path) -> usize {
+        let mut added = 0;
+        for (key, pass) in load(path) {
+            if let Entry::Vacant(entry) = self.previous.entry(key) {
+                entry.insert(pass);
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Forgets a previous pass of a test which ran and didn't pass this time
+    pub fn remove(&self, key: &str) {
+        self.current.lock().unwrap().remove(key);
+        self.stale.lock().unwrap().insert(key.to_string());
+    }
+
+    /// Adds the passes from this run to the cache file. Tests which weren't run
+    /// this time (e.g. filtered out) keep their previous passes
+    pub fn save(&self) -> Result<()> {
+        let stale = self.stale.lock().unwrap();
+        let mut passes: HashMap<&String, &CachedPass> = self.previous.iter()
+            .filter(|(key, _)| !stale.contains(*key))
+            .collect();
+        let current = self.current.lock().unwrap();
+        passes.extend(current.iter());
+
+        let parent = self.path.parent().unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(parent).context(format!("Couldn't create '{}'", parent.display()))?;
+
+        // Written under a temporary name and then renamed, so other runs never see half of it
+        let temp = parent.join(format!(".results-{}.json", std::process::id()));
+        let file = File::create(&temp).context(format!("Couldn't create '{}'", temp.display()))?;
+        serde_json::to_writer(BufWriter::new(file), &passes)?;
+        fs::rename(&temp, &self.path).context(format!("Couldn't move it to '{}'", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// A corrupted or outdated cache is just ignored
+fn load(path: &Path) -> HashMap<String, CachedPass> {
+    File::open(path).ok()
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+        .unwrap_or_default()
+}
+
+/// Whether a spec depends on files besides the test's sources, e.g. golden files
+fn uses_files(spec: &Spec) -> bool {
+    match spec {
+        Spec::Implication(_, consequent) | Spec::ExpectedFailure(consequent) => uses_files(consequent),
+        Spec::Assertion(Assertion::Creates { .. }) | Spec::Assertion(Assertion::Output { .. })
+            | Spec::Assertion(Assertion::Expects(_)) => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod result_cache_tests {
+    use super::*;
+    use crate::parse_spec::{self, ParseOptions};
+    use crate::test_util::TempDir;
+
+    fn test_with_spec(directory: &Path, spec: &str) -> TestInfo {
+        let source = directory.join("a.c0");
+        fs::write(&source, "int main() { return 0; }").unwrap();
+
+        let (_, specs) = parse_spec::parse(spec, ParseOptions { require_test_marker: false }).unwrap();
+        TestInfo::new(TestExecutionInfo::new(vec![source], Arc::from(directory)), specs)
+    }
+
+    fn cache(path: &Path, reuse: bool) -> ResultCache {
+        let test_dir = path.parent().unwrap().to_path_buf();
+        ResultCache { path: path.to_path_buf(), test_dir, settings: String::new(), reuse, previous: load(path), current: Mutex::default(), stale: Mutex::default() }
+    }
+
+    #[test]
+    fn test() -> Result<()> {
+        let directory = TempDir::new("cache");
+        let path = directory.join("results.json");
+
+        let test = test_with_spec(&directory, "return 0");
+        let creates = test_with_spec(&directory, "return 0; creates \"out.png\" matching \"golden.png\"");
+
+        let first = cache(&path, true);
+        assert!(first.test_key("toolchain", &creates).is_none());
+        let key = first.test_key("toolchain", &test).unwrap();
+        assert_ne!(Some(&key), first.test_key("other toolchain", &test).as_ref());
+
+        assert!(first.get(&key).is_none());
+        first.insert(key.clone(), CachedPass { artifact_size: Some(10), ..CachedPass::default() });
+        first.save()?;
+
+        let second = cache(&path, true);
+        assert_eq!(second.get(&key).unwrap().artifact_size, Some(10));
+        assert!(cache(&path, false).get(&key).is_none());
+
+        // Passes of tests which didn't run are kept, and ones which stopped passing are dropped
+        let other = second.test_key("other toolchain", &test).unwrap();
+        second.insert(other.clone(), CachedPass::default());
+        second.save()?;
+        let mut third = cache(&path, true);
+        assert_eq!(third.merge(&directory.join("missing.json")), 0);
+        assert!(third.get(&key).is_some() && third.get(&other).is_some());
+        third.remove(&key);
+        third.save()?;
+        assert!(cache(&path, true).get(&key).is_none());
+
+        // Only the latest run times are kept
+        for millis in 1..=RUN_TIMES as u64 + 2 {
+            let cache = cache(&path, true);
+            assert_eq!(cache.run_times(&other).len(), (millis as usize - 1).min(RUN_TIMES));
+            cache.insert(other.clone(), CachedPass { run_time: Some(Duration::from_millis(millis)), ..CachedPass::default() });
+            cache.save()?;
+        }
+        let times = cache(&path, true).run_times(&other);
+        assert_eq!(times.len(), RUN_TIMES);
+        assert_eq!(times.last(), Some(&Duration::from_millis(RUN_TIMES as u64 + 2)));
+        assert!(third.run_times(&key).is_empty());
+
+        // Changing the source changes the key
+        fs::write(&test.execution.sources[0], "int main() { return 1; }")?;
+        assert_ne!(Some(key), second.test_key("toolchain", &test));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_dirs() -> Result<()> {
+        // The same tests checked out in two places, e.g. on two CI runners
+        let first = TempDir::new("first");
+        let second = TempDir::new("second");
+        let keys: Vec<String> = [&first, &second].iter().map(|test_dir| {
+            let suite = test_dir.join("suite");
+            fs::create_dir_all(&suite).unwrap();
+            let test = test_with_spec(&suite, "return 0");
+            cache(&test_dir.join("results.json"), true).test_key("toolchain", &test).unwrap()
+        }).collect();
+
+        assert_eq!(keys[0], keys[1]);
+        Ok(())
+    }
+}
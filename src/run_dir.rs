@@ -0,0 +1,205 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::unistd;
+use nix::libc::{self, STDOUT_FILENO, STDERR_FILENO};
+use anyhow::{Context, Result};
+
+/// Format of run directory names. They sort in the order the runs started
+const TIMESTAMP_FORMAT: &[u8] = b"%Y-%m-%d_%H-%M-%S\0";
+const TIMESTAMP_LENGTH: usize = "YYYY-mm-dd_HH-MM-SS".len();
+
+/// A directory under --output-dir for everything from one run.
+/// Whatever is printed is also written to it until this is dropped
+pub struct RunDir {
+    path: PathBuf,
+    _stdout: Tee,
+    _stderr: Tee
+}
+
+impl RunDir {
+    /// Makes a directory for this run, and deletes the oldest
+    /// runs so that only 'keep' are left, if given
+    pub fn create(base: &Path, keep: Option<usize>) -> Result<RunDir> {
+        fs::create_dir_all(base).context(format!("Couldn't create '{}'", base.display()))?;
+
+        // Runs which start in the same second get a suffix
+        let timestamp = timestamp()?;
+        let mut name = timestamp.clone();
+        let mut attempt = 1;
+        let path = loop {
+            let path = base.join(&name);
+            match fs::create_dir(&path) {
+                Ok(()) => break path,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    attempt += 1;
+                    name = format!("{}-{}", timestamp, attempt);
+                },
+                Err(e) => return Err(e).context(format!("Couldn't create '{}'", path.display()))
+            }
+        };
+
+        let latest = base.join("latest");
+        let _ = fs::remove_file(&latest);
+        std::os::unix::fs::symlink(&name, &latest).context(format!("Couldn't create '{}'", latest.display()))?;
+
+        if let Some(keep) = keep {
+            prune_runs(base, keep)?;
+        }
+
+        let _stdout = Tee::new(STDOUT_FILENO, File::create(path.join("report.txt"))?)?;
+        let _stderr = Tee::new(STDERR_FILENO, File::create(path.join("log.txt"))?)?;
+
+        Ok(RunDir { path, _stdout, _stderr })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Deletes all but the newest 'keep' run directories in 'base'
+fn prune_runs(base: &Path, keep: usize) -> Result<()> {
+    let mut runs: Vec<PathBuf> = fs::read_dir(base)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false))
+        .filter(|entry| is_run_name(&entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .collect();
+
+    // Timestamps sort by time, but 'x-10' sorts before 'x-2'
+    runs.sort_by_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let attempt: u32 = name.get(TIMESTAMP_LENGTH + 1..).and_then(|n| n.parse().ok()).unwrap_or(1);
+        (name[..TIMESTAMP_LENGTH].to_string(), attempt)
+    });
+
+    let excess = runs.len().saturating_sub(keep);
+    for run in runs.iter().take(excess) {
+        fs::remove_dir_all(run).context(format!("Couldn't delete the old run '{}'", run.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Whether a directory name looks like it came from 'timestamp', so
+/// that pruning never deletes anything c0check didn't create
+fn is_run_name(name: &str) -> bool {
+    // Names which are too short, or where the timestamp would end inside a character, aren't runs
+    let timestamp = match name.get(..TIMESTAMP_LENGTH) {
+        Some(timestamp) => timestamp,
+        None => return false
+    };
+    let suffix = &name[TIMESTAMP_LENGTH..];
+    let format_matches = timestamp.char_indices().all(|(i, c)| match i {
+        4 | 7 | 13 | 16 => c == '-',
+        10 => c == '_',
+        _ => c.is_ascii_digit()
+    });
+
+    format_matches && (suffix.is_empty() || matches!(suffix.strip_prefix('-').map(str::parse::<u32>), Some(Ok(_))))
+}
+
+/// The current local time, formatted with TIMESTAMP_FORMAT
+pub fn timestamp() -> Result<String> {
+    let mut buffer = [0u8; 64];
+    let length = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut time: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut time).is_null() {
+            anyhow::bail!("Couldn't get the local time")
+        }
+        libc::strftime(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len(), TIMESTAMP_FORMAT.as_ptr() as *const libc::c_char, &time)
+    };
+
+    Ok(String::from_utf8_lossy(&buffer[..length]).into_owned())
+}
+
+/// Copies everything written to stdout or stderr into a file as well,
+/// by pointing it at a pipe which a thread copies from
+struct Tee {
+    fd: RawFd,
+    /// What 'fd' was before, to restore it afterwards
+    original: RawFd,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Tee {
+    fn new(fd: RawFd, mut log: File) -> Result<Tee> {
+        // None of these are inherited by the programs c0check runs, since one which
+        // kept the pipe open (e.g. in the background) would stop the thread from ending
+        let original = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+        let output = fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+        let (read_pipe, write_pipe) = unistd::pipe2(OFlag::O_CLOEXEC).context("When creating a pipe to record output")?;
+        unistd::dup2(write_pipe, fd)?;
+        unistd::close(write_pipe)?;
+
+        let thread = thread::spawn(move || {
+            let mut input = unsafe { File::from_raw_fd(read_pipe) };
+            let mut output = unsafe { File::from_raw_fd(output) };
+            let mut buffer = [0u8; 4096];
+
+            while let Ok(n) = input.read(&mut buffer) {
+                if n == 0 {
+                    break
+                }
+                let _ = output.write_all(&buffer[..n]);
+                let _ = log.write_all(&buffer[..n]);
+            }
+        });
+
+        Ok(Tee { fd, original, thread: Some(thread) })
+    }
+}
+
+impl Drop for Tee {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+
+        // This closes the last write end of the pipe, so the thread stops
+        let _ = unistd::dup2(self.original, self.fd);
+        let _ = unistd::close(self.original);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod run_dir_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_is_run_name() {
+        assert!(is_run_name("2021-01-05_13-04-59"));
+        assert!(is_run_name("2021-01-05_13-04-59-2"));
+        assert!(is_run_name(&timestamp().unwrap()));
+        assert!(!is_run_name("latest"));
+        assert!(!is_run_name("2021-01-05"));
+        assert!(!is_run_name("2021-01-05_13-04-59-old"));
+        assert!(!is_run_name("2021-01-05 13:04:59"));
+        assert!(!is_run_name("2021-01-05_13-04-5é"));
+    }
+
+    #[test]
+    fn test_prune_runs() -> Result<()> {
+        let base = TempDir::new("runs");
+        for name in ["2021-01-05_13-04-59", "2021-01-05_13-04-59-2", "2021-01-05_13-04-59-10", "2021-02-01_00-00-00", "notes"].iter() {
+            fs::create_dir_all(base.join(name))?;
+        }
+
+        prune_runs(&base, 2)?;
+
+        let mut left: Vec<String> = fs::read_dir(&base)?
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(left, vec!["2021-01-05_13-04-59-10", "2021-02-01_00-00-00", "notes"]);
+
+        Ok(())
+    }
+}
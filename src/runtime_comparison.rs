@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::spec::TestInfo;
+
+/// Prints a table of how long each test took to run with each executer,
+/// followed by how much slower each executer is than the first one.
+/// Only tests which were run by every executer are compared
+pub fn print_comparison(tests: &[TestInfo], executers: &[&str], run_times: &[HashMap<String, Duration>]) {
+    let rows = compared_times(tests, run_times);
+
+    let name_width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("Total".len());
+    let column_width = executers.iter().map(|name| name.len()).max().unwrap_or(0).max(10);
+
+    println!("\nRun times (ms of CPU time):\n");
+
+    print!("{:width$}", "Test", width = name_width);
+    for executer in executers {
+        print!("  {:>width$}", executer, width = column_width);
+    }
+    println!();
+
+    for (name, times) in rows.iter() {
+        print!("{:width$}", name, width = name_width);
+        for time in times {
+            print!("  {:>width$.1}", time.as_secs_f64() * 1000., width = column_width);
+        }
+        println!();
+    }
+
+    let totals: Vec<f64> = (0..executers.len())
+        .map(|i| rows.iter().map(|(_, times)| times[i].as_secs_f64()).sum())
+        .collect();
+
+    print!("{:width$}", "Total", width = name_width);
+    for total in totals.iter() {
+        print!("  {:>width$.1}", total * 1000., width = column_width);
+    }
+    println!();
+
+    println!("\nSlowdown relative to {} ({} tests):\n", executers[0], rows.len());
+    for (i, executer) in executers.iter().enumerate().skip(1) {
+        let total_ratio = totals[i] / totals[0];
+        match geometric_mean(&ratios(&rows, i)) {
+            Some(mean) => println!("{}: {:.2}x total, {:.2}x geometric mean per test", executer, total_ratio, mean),
+            None => println!("{}: {:.2}x total", executer, total_ratio)
+        }
+    }
+}
+
+/// Each test's run time with each executer, for the tests which were run by every executer
+fn compared_times(tests: &[TestInfo], run_times: &[HashMap<String, Duration>]) -> Vec<(String, Vec<Duration>)> {
+    tests.iter().filter_map(|test| {
+        let name = test.name();
+        let times = run_times.iter()
+            .map(|times| times.get(&name).copied())
+            .collect::<Option<Vec<_>>>()?;
+
+        Some((name, times))
+    }).collect()
+}
+
+/// How many times slower each test ran with the i-th executer than the first one
+fn ratios(rows: &[(String, Vec<Duration>)], i: usize) -> Vec<f64> {
+    // Very short tests can take no measurable time at all
+    rows.iter()
+        .filter(|(_, times)| !times[0].is_zero() && !times[i].is_zero())
+        .map(|(_, times)| times[i].as_secs_f64() / times[0].as_secs_f64())
+        .collect()
+}
+
+/// The geometric mean of how many times slower tests ran, or 'None' if there are none
+pub fn geometric_mean(ratios: &[f64]) -> Option<f64> {
+    if ratios.is_empty() {
+        return None
+    }
+    Some((ratios.iter().map(|ratio| ratio.ln()).sum::<f64>() / ratios.len() as f64).exp())
+}
+
+#[cfg(test)]
+mod runtime_comparison_tests {
+    use super::*;
+    use crate::spec::TestExecutionInfo;
+
+    fn millis(times: &[(&str, u64)]) -> HashMap<String, Duration> {
+        times.iter().map(|(name, time)| (format!("basic/{}", name), Duration::from_millis(*time))).collect()
+    }
+
+    #[test]
+    fn test_compared_times() {
+        let tests: Vec<TestInfo> = ["a.c0", "b.c0", "c.c0"].iter()
+            .map(|name| TestInfo::new(TestExecutionInfo::for_source(format!("/tests/basic/{}", name)), Vec::new()))
+            .collect();
+        // c.c0 wasn't run by the second executer, so it isn't compared
+        let run_times = [millis(&[("a.c0", 10), ("b.c0", 0), ("c.c0", 5)]), millis(&[("a.c0", 40), ("b.c0", 3)])];
+
+        let rows = compared_times(&tests, &run_times);
+        let names: Vec<&str> = rows.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["basic/a.c0", "basic/b.c0"]);
+        assert_eq!(rows[0].1, vec![Duration::from_millis(10), Duration::from_millis(40)]);
+
+        // Tests which took no measurable time have no ratio
+        assert_eq!(ratios(&rows, 1), vec![4.]);
+    }
+
+    #[test]
+    fn test_geometric_mean() {
+        assert_eq!(geometric_mean(&[]), None);
+        assert!((geometric_mean(&[2., 8.]).unwrap() - 4.).abs() < 1e-9);
+        assert!((geometric_mean(&[0.5, 2.]).unwrap() - 1.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,138 @@
+use rayon::prelude::*;
+use anyhow::{anyhow, bail, Error, Result};
+
+use crate::checker::{self, Checker, TestOutcome, TestResult};
+use crate::executer::{Executer, TestRun};
+use crate::hooks::HookRunner;
+use crate::spec::*;
+
+/// What happened when running a test with and without cc0's safety checks
+pub enum Comparison {
+    Same,
+    /// Both runs were expected to return, but they didn't do the same thing.
+    /// Holds how they differed
+    Diverged(String),
+    Error(Error)
+}
+
+pub struct ComparisonResult<'a> {
+    pub test: &'a TestInfo,
+    pub comparison: Comparison
+}
+
+/// Whether a test is expected to return with and without safety checks,
+/// so the two runs should return the same value and print the same output
+pub fn is_comparable(safe: &dyn Executer, unsafe_: &dyn Executer, test: &TestInfo) -> bool {
+    checker::runs_cleanly(safe, test) && checker::runs_cleanly(unsafe_, test)
+}
+
+/// Runs each test with and without safety checks, and compares the runs.
+/// A difference means cc0 generated different code for the two modes,
+/// even though the program shouldn't have hit any safety checks
+pub fn compare_tests<'a>(
+    checker: &Checker,
+    hooks: &HookRunner,
+    safe: &dyn Executer,
+    unsafe_: &dyn Executer,
+    tests: &[&'a TestInfo]) -> Vec<ComparisonResult<'a>>
+{
+    tests.par_iter().map(|&test| {
+        let (comparison, _) = hooks.around_test(test, || compare(checker, safe, unsafe_, test));
+        let comparison = comparison.unwrap_or_else(|| Comparison::Error(anyhow!("Its before hook failed")));
+
+        match &comparison {
+            Comparison::Same => (),
+            Comparison::Diverged(difference) => eprintln!("🔀 {}: {}", test.name(), difference),
+            Comparison::Error(error) => eprintln!("⛔ {}: {:#}", test.name(), error)
+        }
+
+        ComparisonResult { test, comparison }
+    }).collect()
+}
+
+fn compare(checker: &Checker, safe: &dyn Executer, unsafe_: &dyn Executer, test: &TestInfo) -> Comparison {
+    let safe_run = match run(checker, safe, test) {
+        Ok(Ok(run)) => run,
+        // It passed before, so it's flaky rather than a difference between the modes
+        Ok(Err(failure)) => return Comparison::Error(anyhow!("It failed with safety checks this time: {}", failure)),
+        Err(error) => return Comparison::Error(error)
+    };
+
+    let unsafe_run = match run(checker, unsafe_, test) {
+        Ok(Ok(run)) => run,
+        Ok(Err(failure)) => return Comparison::Diverged(format!("it failed without safety checks: {}", failure)),
+        Err(error) => return Comparison::Error(error)
+    };
+
+    match difference(&safe_run, &unsafe_run) {
+        Some(difference) => Comparison::Diverged(difference),
+        None => Comparison::Same
+    }
+}
+
+/// Runs a test, returning the run if it passed, or else why it failed
+fn run(checker: &Checker, executer: &dyn Executer, test: &TestInfo) -> Result<Result<TestRun, String>> {
+    match checker.run_test(executer, test)? {
+        TestOutcome { result: TestResult::Success, run: Some(run), .. }
+            | TestOutcome { result: TestResult::UnexpectedPass, run: Some(run), .. } => Ok(Ok(run)),
+        TestOutcome { result: TestResult::Mismatch(failure), .. }
+            | TestOutcome { result: TestResult::ExpectedFailure(failure), .. } => Ok(Err(failure.to_string())),
+        TestOutcome { result: TestResult::Skipped(_, reason), .. } => bail!("It was skipped: {}", reason),
+        TestOutcome { run: None, .. } => bail!("It wasn't run")
+    }
+}
+
+/// How two passing runs differ, if they do
+fn difference(safe: &TestRun, unsafe_: &TestRun) -> Option<String> {
+    let same_behavior = match (safe.behavior, unsafe_.behavior) {
+        (Behavior::Return(x), Behavior::Return(y)) => x == y,
+        _ => false
+    };
+    if !same_behavior {
+        return Some(format!("{} with safety checks, but {} without", safe.behavior, unsafe_.behavior))
+    }
+
+    if safe.output != unsafe_.output {
+        let (line, (expected, actual)) = safe.output.lines().chain(std::iter::repeat(""))
+            .zip(unsafe_.output.lines().chain(std::iter::repeat("")))
+            .enumerate()
+            .find(|(_, (expected, actual))| expected != actual)
+            .unwrap_or((0, ("", "")));
+        return Some(format!("the output differs on line {}:\nwith safety checks:    {}\nwithout safety checks: {}",
+            line + 1, expected, actual))
+    }
+
+    None
+}
+
+pub fn print_report(results: &[ComparisonResult]) {
+    println!("\nSafe/unsafe comparison ({} tests which should return with both):\n", results.len());
+
+    for result in results.iter() {
+        match &result.comparison {
+            Comparison::Same => (),
+            Comparison::Diverged(difference) => println!("🔀 {}: {}", result.test.name(), difference),
+            Comparison::Error(error) => println!("⛔ {}: {:#}", result.test.name(), error)
+        }
+    }
+
+    println!("{} of {} behaved the same", results.iter().filter(|result| matches!(result.comparison, Comparison::Same)).count(), results.len());
+}
+
+#[cfg(test)]
+mod safety_diff_tests {
+    use super::*;
+
+    fn returning(value: i32, output: &str) -> TestRun {
+        TestRun::new(String::from(output), None, Behavior::Return(Some(value)))
+    }
+
+    #[test]
+    fn test_difference() {
+        assert!(difference(&returning(0, "a\nb\n"), &returning(0, "a\nb\n")).is_none());
+        assert_eq!(difference(&returning(0, ""), &returning(1, "")).unwrap(),
+            "return 0 with safety checks, but return 1 without");
+        assert!(difference(&returning(0, "a\nb\n"), &returning(0, "a\nc\n")).unwrap().starts_with("the output differs on line 2"));
+        assert!(difference(&returning(0, "a\n"), &returning(0, "a\nb\n")).unwrap().starts_with("the output differs on line 2"));
+    }
+}
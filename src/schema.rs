@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use anyhow::{bail, Context, Result};
+
+/// The version of files written before they had one
+const UNVERSIONED: u64 = 1;
+
+/// Reads a JSON file which c0check wrote (e.g. a baseline), described by 'what'
+/// in errors. Files written by older versions of c0check are brought up to
+/// 'current' by calling 'migrate' with each version in turn, which changes
+/// the file from that version to the next, so old files keep working
+pub fn read<T: DeserializeOwned>(
+    path: &Path,
+    what: &str,
+    current: u32,
+    migrate: impl Fn(u32, &mut Map<String, Value>) -> Result<()>) -> Result<T>
+{
+    let file = File::open(path).context(format!("Couldn't open {} '{}'", what, path.display()))?;
+    let json: Value = serde_json::from_reader(BufReader::new(file))
+        .context(format!("Invalid {} '{}'", what, path.display()))?;
+    upgrade(json, current, migrate).context(format!("Invalid {} '{}'", what, path.display()))
+}
+
+fn upgrade<T: DeserializeOwned>(json: Value, current: u32, migrate: impl Fn(u32, &mut Map<String, Value>) -> Result<()>) -> Result<T> {
+    let mut fields = match json {
+        Value::Object(fields) => fields,
+        _ => bail!("Expected a JSON object")
+    };
+
+    let version = match fields.get("version") {
+        None => UNVERSIONED,
+        Some(version) => version.as_u64().context("'version' isn't a number")?
+    };
+    if version > current as u64 {
+        bail!("It was written by a newer c0check, in version {} of the format, but this one only reads up to version {}",
+            version, current)
+    }
+
+    for version in version as u32..current {
+        migrate(version, &mut fields).context(format!("Couldn't upgrade it from version {}", version))?;
+    }
+    fields.insert(String::from("version"), Value::from(current));
+
+    Ok(serde_json::from_value(Value::Object(fields))?)
+}
+
+#[cfg(test)]
+mod schema_tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Point {
+        version: u32,
+        x: i32,
+        y: i32
+    }
+
+    /// Version 1 had 'x' and 'y' swapped, and version 2 called 'y' 'height'
+    fn migrate(version: u32, fields: &mut Map<String, Value>) -> Result<()> {
+        match version {
+            1 => {
+                let (x, y) = (fields.remove("x"), fields.remove("y"));
+                fields.insert(String::from("x"), y.context("no 'y'")?);
+                fields.insert(String::from("height"), x.context("no 'x'")?);
+            },
+            2 => {
+                let height = fields.remove("height").context("no 'height'")?;
+                fields.insert(String::from("y"), height);
+            },
+            _ => unreachable!()
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test() {
+        let point = |json: Value| upgrade::<Point>(json, 3, migrate);
+
+        assert_eq!(point(json!({ "x": 1, "y": 2 })).unwrap(), Point { version: 3, x: 2, y: 1 });
+        assert_eq!(point(json!({ "version": 2, "x": 1, "height": 2 })).unwrap(), Point { version: 3, x: 1, y: 2 });
+        assert_eq!(point(json!({ "version": 3, "x": 1, "y": 2 })).unwrap(), Point { version: 3, x: 1, y: 2 });
+
+        let error = point(json!({ "version": 4, "x": 1, "y": 2 })).unwrap_err();
+        assert!(error.to_string().contains("newer c0check"));
+        assert!(point(json!({ "version": 2, "x": 1 })).is_err());
+        assert!(point(json!([1, 2])).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::str::FromStr;
+use sha2::{Digest, Sha256};
+use anyhow::{anyhow, bail, Error, Result};
+
+use crate::dependencies::DependencyGraph;
+use crate::spec::TestInfo;
+
+/// A --shard, the Kth of N buckets which split the tests between CI machines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// Counts from 1
+    pub index: u64,
+    pub count: u64
+}
+
+impl FromStr for Shard {
+    type Err = Error;
+
+    fn from_str(shard: &str) -> Result<Shard> {
+        let (index, count) = match shard.split_once('/') {
+            Some((index, count)) => (index.trim().parse::<u64>(), count.trim().parse::<u64>()),
+            None => bail!("Shards are written as K/N, e.g. 1/4")
+        };
+
+        match (index, count) {
+            (Ok(index), Ok(count)) if count > 0 && (1..=count).contains(&index) => Ok(Shard { index, count }),
+            (Ok(_), Ok(_)) => Err(anyhow!("Shard '{}' isn't one of 1/N to N/N", shard)),
+            _ => Err(anyhow!("Invalid shard '{}', which should be written as K/N", shard))
+        }
+    }
+}
+
+impl Shard {
+    /// Whether a test goes in this shard. Tests are put in buckets by hashing their
+    /// names, so each test stays in the same shard however tests are discovered.
+    /// Tests which depend on others, or which others depend on, are hashed by
+    /// their directory instead, so that they run on the same machine
+    pub fn contains(&self, test: &TestInfo, linked: bool) -> bool {
+        let identity = if linked {
+            test.execution.directory.file_name().unwrap_or_default().to_string_lossy().into_owned()
+        }
+        else {
+            test.name()
+        };
+
+        let hash = Sha256::digest(identity.as_bytes());
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        u64::from_be_bytes(bytes) % self.count == self.index - 1
+    }
+}
+
+/// Keeps the tests in the shard
+pub fn filter(shard: Shard, tests: Vec<TestInfo>) -> Vec<TestInfo> {
+    let linked: Vec<bool> = {
+        let graph = DependencyGraph::new(&tests);
+        tests.iter().map(|test| graph.is_linked(test)).collect()
+    };
+
+    tests.into_iter().zip(linked).filter(|(test, linked)| shard.contains(test, *linked)).map(|(test, _)| test).collect()
+}
+
+#[cfg(test)]
+mod shard_tests {
+    use super::*;
+    use std::path::Path;
+    use crate::spec::*;
+
+    fn test_named(name: &str, depends: &[&str]) -> TestInfo {
+        let depends = depends.iter().map(|name| name.to_string()).collect();
+        TestInfo::new(TestExecutionInfo { depends, ..TestExecutionInfo::for_source(Path::new("/tests/basic").join(name)) }, Vec::new())
+    }
+
+    #[test]
+    fn test() {
+        assert_eq!("2/4".parse::<Shard>().unwrap(), Shard { index: 2, count: 4 });
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("1/0".parse::<Shard>().is_err());
+        assert!("1".parse::<Shard>().is_err());
+
+        // Every test is in exactly one shard, whatever order they're in
+        let names: Vec<String> = (0..20).map(|i| format!("test{}.c0", i)).collect();
+        let tests = || names.iter().map(|name| test_named(name, &[])).collect::<Vec<_>>();
+        let shards: Vec<Vec<String>> = (1..=3)
+            .map(|index| filter(Shard { index, count: 3 }, tests()).iter().map(TestInfo::name).collect())
+            .collect();
+        assert_eq!(shards.iter().map(Vec::len).sum::<usize>(), names.len());
+
+        let mut reversed = tests();
+        reversed.reverse();
+        let mut first: Vec<String> = filter(Shard { index: 1, count: 3 }, reversed).iter().map(TestInfo::name).collect();
+        first.reverse();
+        assert_eq!(first, shards[0]);
+
+        // Tests linked by '@depends' stay together
+        let mut tests = tests();
+        tests.push(test_named("reader.c0", &["test0.c0"]));
+        let together = (1..=3).any(|index| {
+            let shard: Vec<String> = filter(Shard { index, count: 3 }, tests.clone()).iter().map(TestInfo::name).collect();
+            shard.contains(&String::from("basic/reader.c0")) && shard.contains(&String::from("basic/test0.c0"))
+        });
+        assert!(together);
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use crate::spec::TestInfo;
+
+/// How many passes a test needs before it can be suggested for '@slow'
+const MIN_RUNS: usize = 3;
+
+/// How much of its time limit a test has to use in every one of
+/// its latest passes to be suggested for '@slow'
+const NEAR_LIMIT: f64 = 0.75;
+
+/// Tests which aren't tagged '@slow' but used most of their time limit in each of their
+/// latest passes, with the least they used, most first. 'run_times' are a test's latest
+/// run times, from the result cache, and 'default_limit' is --test-time in seconds
+pub fn suggestions<'a>(tests: &[&'a TestInfo], run_times: impl Fn(&TestInfo) -> Vec<Duration>, default_limit: u64)
+    -> Vec<(&'a TestInfo, f64)> {
+    let mut suggestions: Vec<(&TestInfo, f64)> = tests.iter()
+        .filter(|test| !test.execution.slow)
+        .filter_map(|&test| {
+            let runs = run_times(test);
+            let limit = test.execution.run_limits.time.unwrap_or(default_limit).max(1) as f64;
+            let least = runs.iter().map(|time| time.as_secs_f64() / limit).fold(1., f64::min);
+            if runs.len() >= MIN_RUNS && least >= NEAR_LIMIT { Some((test, least)) } else { None }
+        })
+        .collect();
+    suggestions.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+    suggestions
+}
+
+pub fn print_suggestions(suggestions: &[(&TestInfo, f64)]) {
+    println!("\n🐢 Tests which used most of their time limit in each of their last {}+ runs, \
+        and could be tagged @slow:\n", MIN_RUNS);
+    for (test, least) in suggestions.iter() {
+        println!("  {}: at least {:.0}% of its time limit", test.name(), least * 100.);
+    }
+}
+
+#[cfg(test)]
+mod slow_tests_tests {
+    use super::*;
+    use crate::spec::TestExecutionInfo;
+
+    fn test_named(name: &str, slow: bool) -> TestInfo {
+        let execution = TestExecutionInfo { slow, ..TestExecutionInfo::for_source(format!("/tests/basic/{}", name)) };
+        TestInfo::new(execution, Vec::new())
+    }
+
+    #[test]
+    fn test() {
+        let (near, once, tagged, new) = (test_named("near.c0", false), test_named("once.c0", false),
+            test_named("tagged.c0", true), test_named("new.c0", false));
+        let mut longer = test_named("longer.c0", false);
+        longer.execution.run_limits.time = Some(20);
+
+        let seconds = |times: &[u64]| times.iter().map(|&time| Duration::from_secs(time)).collect();
+        let run_times = |test: &TestInfo| match test.name().as_str() {
+            "basic/near.c0" => seconds(&[9, 8, 10, 12]),
+            "basic/once.c0" => seconds(&[2, 10, 2]),
+            "basic/new.c0" => seconds(&[10, 10]),
+            _ => seconds(&[10, 10, 10])
+        };
+
+        let suggestions = suggestions(&[&near, &once, &tagged, &new, &longer], run_times, 10);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!((suggestions[0].0.name(), suggestions[0].1), (near.name(), 0.8));
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+
+use crate::discover_tests;
+use crate::parse_spec::{self, ParseOptions, SpecParseError};
+use crate::spec::*;
+
+/// Generates a sources.test which is equivalent to the
+/// '//test' lines of the test files in 'dir'
+pub fn export_sources(dir: &Path) -> Result<String> {
+    if dir.join("sources.test").exists() {
+        bail!("'{}' already has a sources.test", dir.display())
+    }
+
+    let mut tests = discover_tests::read_test_files(dir)?;
+    tests.sort_by_key(TestInfo::name);
+
+    let mut output = String::new();
+    for test in tests.iter() {
+        let line = sources_test_line(test, dir).context(format!("Couldn't export '{}'", test.name()))?;
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+/// Formats a test as a line of sources.test
+fn sources_test_line(test: &TestInfo, dir: &Path) -> Result<String> {
+    let spec = test.spec_line();
+
+    // Some specs (e.g. from lit-style tests) have no syntax
+    if let Err(e) = parse_spec::parse(&spec, ParseOptions { require_test_marker: false }) {
+        bail!("Its spec '{}' can't be written in sources.test: {}", spec, e)
+    }
+
+    let mut args: Vec<&str> = test.execution.compiler_options.iter().map(String::as_str).collect();
+    for source in test.execution.sources.iter() {
+        let relative = source.strip_prefix(dir)
+            .context(format!("'{}' is outside of the test directory", source.display()))?;
+        args.push(relative.to_str().context(format!("'{}' can't be written in sources.test, since it isn't UTF-8", relative.display()))?);
+    }
+
+    Ok(format!("{} ~ {}", spec, args.join(" ")))
+}
+
+/// Moves each test in the sources.test of 'dir' into a '//test' line at the
+/// top of its source file, then removes sources.test. Fails without changing
+/// anything if some test can't be written that way. Returns the changed files
+pub fn split_sources(dir: &Path) -> Result<Vec<PathBuf>> {
+    let sources_test_path = dir.join("sources.test");
+    let sources_test = File::open(&sources_test_path)
+        .context(format!("Couldn't open '{}'", sources_test_path.display()))?;
+    let tests = discover_tests::read_sources_file(dir, sources_test)?;
+
+    let mut edits: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+    let mut seen: HashSet<&Path> = HashSet::new();
+
+    for test in tests.iter() {
+        let source = match test.execution.sources.as_slice() {
+            [source] if test.execution.compiler_options.is_empty() => source,
+            _ => bail!("'{}' has compiler options or several sources, so it needs sources.test", test.name())
+        };
+
+        if !seen.insert(source) {
+            bail!("'{}' is used by more than one test", source.display())
+        }
+
+        // The file is kept byte for byte, since it might not be UTF-8
+        let contents = fs::read(source).context(format!("Couldn't read '{}'", source.display()))?;
+        let first_line = String::from_utf8_lossy(contents.split(|&byte| byte == b'\n').next().unwrap_or_default());
+        if !matches!(parse_spec::parse(&first_line, ParseOptions { require_test_marker: true }), Err(SpecParseError::NotSpec)) {
+            bail!("'{}' already has a //test line", source.display())
+        }
+
+        let mut new_contents = format!("//test {}\n", test.spec_line()).into_bytes();
+        new_contents.extend_from_slice(&contents);
+        edits.push((source.clone(), new_contents));
+    }
+
+    for (path, contents) in edits.iter() {
+        fs::write(path, contents).context(format!("Couldn't write '{}'", path.display()))?;
+    }
+    fs::remove_file(&sources_test_path).context(format!("Couldn't remove '{}'", sources_test_path.display()))?;
+
+    Ok(edits.into_iter().map(|(path, _)| path).collect())
+}
+
+#[cfg(test)]
+mod sources_test_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let dir = TempDir::new("sources-test");
+        fs::write(dir.join("a.c0"), "int main() { return 1; }\n")?;
+        fs::write(dir.join("b.c1"), "int main() { return 2; }\n")?;
+        fs::write(dir.join("sources.test"), 
+            "@compile_timeout(60) return 1 ~ a.c0\n!safe => failure; c0vm or coin => warns \"a \\\"b\\\"\" ~ b.c1\n")?;
+
+        let changed = split_sources(&dir)?;
+        assert_eq!(changed.len(), 2);
+        assert!(!dir.join("sources.test").exists());
+        assert_eq!(fs::read_to_string(dir.join("a.c0"))?, "//test @compile_timeout(60) return 1\nint main() { return 1; }\n");
+
+        let exported = export_sources(&dir)?;
+        assert_eq!(exported, 
+            "@compile_timeout(60) return 1 ~ a.c0\n!safe => failure; c0vm or coin => warns \"a \\\"b\\\"\" ~ b.c1\n");
+
+        // Splitting again fails since the files already have specs
+        fs::write(dir.join("sources.test"), exported)?;
+        assert!(split_sources(&dir).is_err());
+        assert!(export_sources(&dir).is_err());
+
+        Ok(())
+    }
+}
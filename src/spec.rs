@@ -1,31 +1,161 @@
+use std::collections::BTreeMap;
 use std::fmt::{self, Formatter, Display};
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use nix::sys::signal::Signal;
+
+use crate::hooks::Hooks;
+use crate::matchers::Pattern;
 
 /// Holds metadata about a test, as well as the parsed spec
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TestInfo {
     pub execution: TestExecutionInfo,
-    pub specs: Specs
+    pub specs: Specs,
+    /// Names from '@tags(...)', which --tag and --skip-tag select tests by
+    pub tags: Vec<String>,
+    /// Where the specs were written, if they came from a spec line
+    pub origin: Option<SpecOrigin>
+}
+
+/// The lines a test's specs were parsed from, e.g. its '//test' lines or
+/// its line in sources.test, so that failures can point at the spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecOrigin {
+    pub file: PathBuf,
+    /// The line each of the specs is on, starting from 1, and
+    /// the bytes of that line it takes up, in order
+    pub spans: Vec<(usize, Range<usize>)>
+}
+
+impl SpecOrigin {
+    /// Specs which were all parsed from one line
+    pub fn new(file: PathBuf, line: usize, spans: Vec<Range<usize>>) -> SpecOrigin {
+        SpecOrigin { file, spans: spans.into_iter().map(|span| (line, span)).collect() }
+    }
+
+    /// Where the spec with the given index is, if it came from the file
+    pub fn location(&self, spec: usize) -> Option<SpecLocation> {
+        let (line, span) = self.spans.get(spec)?;
+        Some(SpecLocation {
+            file: self.file.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            line: *line,
+            columns: span.start + 1..span.end + 1
+        })
+    }
+}
+
+/// Where a single spec was written. Files are named relative to the test's
+/// directory, and columns start from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecLocation {
+    pub file: String,
+    pub line: usize,
+    pub columns: Range<usize>
 }
 
 /// Test metadata
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TestExecutionInfo {
     /// Absolute paths to C0/C1 source files
-    pub sources: Vec<String>,
+    pub sources: Vec<PathBuf>,
     /// Any prescribed compiler options
     pub compiler_options: Vec<String>,
     /// The directory the test came from. Necessary since some
     /// test cases (e.g. <img> library tests) load resources
-    pub directory: Arc<str>
+    pub directory: Arc<Path>,
+    /// Overrides for the CC0 resource limits, for tests which are
+    /// known to stress the compiler
+    pub compilation_limits: ResourceLimits,
+    /// Overrides for the limits of the test program itself
+    pub run_limits: ResourceLimits,
+    /// Commands to run before and after this test
+    pub hooks: Hooks,
+    /// Behaviors for runtime exit codes from suite.toml
+    pub exit_codes: Arc<ExitCodes>,
+    /// Links the libraries declared in suite.toml (see 'suite_libraries').
+    /// Unlike the compiler options, they aren't part of the test's name
+    pub link_options: Vec<String>,
+    /// Tests from the same suite which have to pass before this one runs
+    /// (see 'dependencies'), named by their sources and compiler options
+    pub depends: Vec<String>,
+    /// Tagged '@slow': it gets --slow-factor times the usual time limit,
+    /// is started before other tests, and --fast leaves it out
+    pub slow: bool,
+    /// Tagged '@no_capture': the program's stdout goes to /dev/null and only the end
+    /// of stderr is kept, for tests which print a lot which doesn't matter
+    pub no_capture: bool
 }
 
-/// Specs are of the form 'predicate => spec' or just a '<behavior>'
-#[derive(Debug)]
+/// Behaviors for runtime exit codes by executer name, which take priority
+/// over the executer's own (see 'Executer::exit_code')
+pub type ExitCodes = BTreeMap<String, BTreeMap<i32, Behavior>>;
+
+/// Per-test overrides of the global time/memory limits.
+/// 'None' means the global limit applies
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// CPU time in seconds
+    pub time: Option<u64>,
+    /// Memory in bytes
+    pub memory: Option<u64>
+}
+
+impl ResourceLimits {
+    /// Fills in any missing limits from 'fallback'
+    pub fn or(self, fallback: ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            time: self.time.or(fallback.time),
+            memory: self.memory.or(fallback.memory)
+        }
+    }
+}
+
+/// Test-wide settings given as '@name(argument)' before the specs
+#[derive(Debug, Default)]
+pub struct Annotations {
+    pub compilation_limits: ResourceLimits,
+    pub run_limits: ResourceLimits,
+    pub hooks: Hooks,
+    pub depends: Vec<String>,
+    pub slow: bool,
+    pub no_capture: bool,
+    pub tags: Vec<String>
+}
+
+impl Annotations {
+    /// Adds the annotations of a later '//test' line, which win over these
+    pub fn merge(&mut self, later: Annotations) {
+        self.compilation_limits = later.compilation_limits.or(self.compilation_limits);
+        self.run_limits = later.run_limits.or(self.run_limits);
+        self.hooks.before = later.hooks.before.or(self.hooks.before.take());
+        self.hooks.after = later.hooks.after.or(self.hooks.after.take());
+        self.depends.extend(later.depends);
+        self.slow |= later.slow;
+        self.no_capture |= later.no_capture;
+        for tag in later.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+    }
+}
+
+/// Specs are of the form 'predicate => spec', a '<behavior>',
+/// an '<assertion>' about the test's output, 'skip "<reason>"',
+/// or 'xfail <spec>'
+#[derive(Debug, Clone)]
 pub enum Spec {
     Implication(ImplementationPredicate, Box<Spec>),
-    Behavior(Behavior)
+    Behavior(Behavior),
+    Assertion(Assertion),
+    /// The test isn't run, e.g. because it is broken or unfinished,
+    /// and is reported as skipped for the given reason
+    Skip(String),
+    /// The test is known to be broken, so it's expected not to meet the spec
+    /// (see 'checker::expects_failure'). The parser puts it after any predicates
+    ExpectedFailure(Box<Spec>)
 }
 
 /// Test cases can have multiple specs i.e. if tests have one outcome in cc0
@@ -33,7 +163,7 @@ pub enum Spec {
 pub type Specs = Vec<Spec>;
 
 /// Describes an implementation
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ImplementationPredicate {
     Library,
     Typechecked,
@@ -41,6 +171,10 @@ pub enum ImplementationPredicate {
     Safe,
     False,
     ImplementationName(String),
+    /// 'requires(<tool>)': this machine has a program or library (see 'host_tools')
+    Requires(String),
+    /// 'opt(<flag>)': the test's own compiler options include a flag, e.g. 'opt(-d)'
+    Opt(String),
 
     Not(Box<ImplementationPredicate>),
     And(Box<ImplementationPredicate>, Box<ImplementationPredicate>),
@@ -61,10 +195,104 @@ pub enum Behavior {
     Segfault,
     DivZero,
     Return(Option<i32>),
+    /// 'return in [<min>, <max>]' or 'return != <value>'. Only specs expect this
+    ReturnIn(ReturnRange),
+    /// 'exit <code>': the process exited with a code which isn't a behavior of its own,
+    /// e.g. from a library which calls exit(). Matches 'UnknownExit' with the same code
+    Exit(i32),
+    /// The runtime exited with a code it doesn't have a behavior for (see 'Executer::exit_code').
+    /// Only tests produce this, it can't be expected by a spec
+    UnknownExit(i32),
+    /// The program was killed by a signal which isn't a behavior. Like 'UnknownExit', only tests produce this
+    UnknownSignal(Signal),
+    /// 'assert', 'contract' or 'error()': an 'Abort' or 'Failure' told apart by what the runtime
+    /// printed. Tests say which of the two it was, and specs don't, so 'abort' and 'failure'
+    /// still match the ones which ended that way
+    Failed(RuntimeFailure, Option<Ending>),
 
     Skipped
 }
 
+/// What stopped a C0 program at runtime, going by what the runtime printed (see 'checker::diagnose')
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeFailure {
+    Assert,
+    /// @requires, @ensures, @loop_invariant or @assert
+    Contract,
+    /// error() was called
+    ErrorCall
+}
+
+/// Whether a failed program aborted or exited with a failure code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ending {
+    Aborted,
+    Exited
+}
+
+/// The values a test may return when its result legitimately varies, e.g. with
+/// the platform, but is still constrained, unlike 'return *'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnRange {
+    /// Includes both ends
+    Between(i32, i32),
+    Except(i32)
+}
+
+impl ReturnRange {
+    pub fn contains(&self, value: i32) -> bool {
+        match *self {
+            ReturnRange::Between(min, max) => (min..=max).contains(&value),
+            ReturnRange::Except(excluded) => value != excluded
+        }
+    }
+}
+
+/// How the test program's process ended, before it is classified as a behavior
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawStatus {
+    Exited(i32),
+    Signaled(Signal)
+}
+
+/// A check on the output of a test, which is made in
+/// addition to checking its behavior
+#[derive(Debug, Clone)]
+pub enum Assertion {
+    /// The compiler printed a warning containing the given text
+    Warns(String),
+    /// The compiler didn't print any warnings
+    NoWarnings,
+    /// The output contains each of the given strings, in order.
+    /// Comes from the CHECK lines of lit-style tests
+    Checks(Vec<String>),
+    /// The output satisfies the named output matcher (see 'matchers'), e.g. it
+    /// matches a regex. 'expected' is text, a pattern, or a golden file, depending
+    /// on the matcher. 'tolerance' is a percentage, for matchers which allow one
+    Output { matcher: String, expected: String, tolerance: Option<u32> },
+    /// The output contains the given text, or a match for it if it's a regex,
+    /// in which case 'regex' has it compiled. Written as 'prints "<text>"' or 'prints /<regex>/'
+    Prints { pattern: String, regex: Option<Pattern> },
+    /// The output is shorter than this many bytes, e.g. for libraries which
+    /// shouldn't print anything, or to catch leftover debug printing
+    OutputSize(u64),
+    /// The process exited with exactly this code, whatever behavior it is classified as.
+    /// For developing executers whose exit codes don't have behaviors yet
+    RawExit(i32),
+    /// The process was killed by this signal, whatever behavior it is classified as
+    RawSignal(Signal),
+    /// The test wrote a file with the same contents as a golden copy.
+    /// Both paths are relative to the test's directory. PNG images are
+    /// compared pixel by pixel, with each pixel's color allowed to be
+    /// off by 'tolerance' percent (or --image-tolerance if not given).
+    /// A different output matcher can be named instead
+    Creates { file: String, golden: String, matcher: Option<String>, tolerance: Option<u32> },
+    /// The output is exactly the contents of the given file, which is relative to the
+    /// test's directory. Comes from 'expects "<file>"', or a '<test>.expect' file next
+    /// to a test. Fails with a diff of the output instead of the whole output
+    Expects(String)
+}
+
 impl PartialEq for Behavior {
     fn eq(&self, other: &Behavior) -> bool {
         use Behavior::*;
@@ -82,6 +310,17 @@ impl PartialEq for Behavior {
                     (_, None) => true,
                     (Some(a), Some(b)) => a == b
                 },
+            (ReturnIn(range), Return(value)) | (Return(value), ReturnIn(range)) => match value {
+                Some(value) => range.contains(*value),
+                None => true
+            },
+            (ReturnIn(x), ReturnIn(y)) => x == y,
+            (Exit(x), Exit(y)) | (Exit(x), UnknownExit(y)) | (UnknownExit(x), Exit(y)) => x == y,
+            (UnknownExit(x), UnknownExit(y)) => x == y,
+            (Failed(x, a), Failed(y, b)) => x == y && (a.is_none() || b.is_none() || a == b),
+            (Abort, Failed(_, Some(Ending::Aborted))) | (Failed(_, Some(Ending::Aborted)), Abort) => true,
+            (Failure, Failed(_, Some(Ending::Exited))) | (Failed(_, Some(Ending::Exited)), Failure) => true,
+            (UnknownSignal(x), UnknownSignal(y)) => x == y,
             (Skipped, _) => true,
             (_, Skipped) => true,
             _ => false
@@ -91,24 +330,115 @@ impl PartialEq for Behavior {
 
 impl Eq for Behavior { }
 
-// Display instances
+impl TestExecutionInfo {
+    /// A test of 'sources' from 'directory', with no compiler options,
+    /// overrides, dependencies, or tags
+    pub fn new(sources: Vec<PathBuf>, directory: Arc<Path>) -> TestExecutionInfo {
+        TestExecutionInfo {
+            sources,
+            compiler_options: Vec::new(),
+            directory,
+            compilation_limits: ResourceLimits::default(),
+            run_limits: ResourceLimits::default(),
+            hooks: Hooks::default(),
+            exit_codes: Arc::default(),
+            link_options: Vec::new(),
+            depends: Vec::new(),
+            slow: false,
+            no_capture: false
+        }
+    }
 
-impl Display for TestInfo {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let sources: Vec<_> = self.execution.sources.iter().map(|source| {
-            let mut path = Path::new(source);
+    /// A test of one source file, from the directory it's in
+    #[cfg(test)]
+    pub fn for_source(source: impl Into<PathBuf>) -> TestExecutionInfo {
+        let source = source.into();
+        let directory = Arc::from(source.parent().unwrap_or_else(|| Path::new("")));
+        TestExecutionInfo::new(vec![source], directory)
+    }
+
+    /// The name of the test (see 'TestInfo::name')
+    pub fn name(&self) -> String {
+        let mut name: Vec<String> = self.sources.iter().map(|source| {
+            let mut path = source.as_path();
             if let Some(prefix) = path.ancestors().nth(2) {
                 path = path.strip_prefix(prefix).unwrap_or(path);
             }
 
-            path.to_str().unwrap()
+            path.to_string_lossy().into_owned()
         }).collect();
 
-        write!(f, "{}", sources.join(" "))?;
-        for option in self.execution.compiler_options.iter() {
-            write!(f, " {}", option)?;
+        name.extend(self.compiler_options.iter().cloned());
+        name.join(" ")
+    }
+}
+
+impl TestInfo {
+    /// A test with no tags, whose specs didn't come from a spec line
+    pub fn new(execution: TestExecutionInfo, specs: Specs) -> TestInfo {
+        TestInfo { execution, specs, tags: Vec::new(), origin: None }
+    }
+
+    /// Whether the test is tagged with 'tag'. Tests tagged '@slow' count as tagged 'slow'
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag) || (tag == "slow" && self.execution.slow)
+    }
+
+    /// A name for the test which stays the same between runs:
+    /// its source files relative to the test directory and its compiler options
+    pub fn name(&self) -> String {
+        self.execution.name()
+    }
+
+    /// The test's annotations and specs, written so they can be parsed again
+    pub fn spec_line(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+
+        let limits = self.execution.compilation_limits;
+        if let Some(time) = limits.time {
+            parts.push(format!("@compile_timeout({})", time));
+        }
+        if let Some(memory) = limits.memory {
+            parts.push(format!("@compile_memory({})", memory));
+        }
+        if let Some(time) = self.execution.run_limits.time {
+            parts.push(format!("@timeout({})", time));
         }
-        write!(f, ": ")?;
+        if let Some(memory) = self.execution.run_limits.memory {
+            parts.push(format!("@memory({})", memory));
+        }
+
+        let hooks = &self.execution.hooks;
+        if let Some(command) = &hooks.before {
+            parts.push(format!("@before({})", command));
+        }
+        if let Some(command) = &hooks.after {
+            parts.push(format!("@after({})", command));
+        }
+        for dependency in self.execution.depends.iter() {
+            parts.push(format!("@depends({})", dependency));
+        }
+        if self.execution.slow {
+            parts.push(String::from("@slow"));
+        }
+        if self.execution.no_capture {
+            parts.push(String::from("@no_capture"));
+        }
+        if !self.tags.is_empty() {
+            parts.push(format!("@tags({})", self.tags.join(", ")));
+        }
+
+        let specs: Vec<String> = self.specs.iter().map(Spec::to_string).collect();
+        parts.push(specs.join("; "));
+        parts.join(" ")
+    }
+}
+
+// Display instances
+
+impl Display for TestInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.name())?;
 
         let mut first = true;
         for spec in self.specs.iter() {
@@ -124,11 +454,21 @@ impl Display for TestInfo {
     }    
 }
 
+impl Display for SpecLocation {
+    /// e.g. 'sources.test:3:12-19', with the last column included
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}-{}", self.file, self.line, self.columns.start, self.columns.end - 1)
+    }
+}
+
 impl Display for Spec {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         use Spec::*;
         match self {
             Behavior(b) => write!(f, "{}", b),
+            Assertion(a) => write!(f, "{}", a),
+            Skip(reason) => write!(f, "skip {:?}", reason),
+            ExpectedFailure(spec) => write!(f, "xfail {}", spec),
             Implication(p, spec) => write!(f, "{} => {}", p, spec)
         }
     }
@@ -144,6 +484,8 @@ impl Display for ImplementationPredicate {
             Safe => write!(f, "safe"),
             False => write!(f, "false"),
             ImplementationName(name) => write!(f, "{}", name),
+            Requires(tool) => write!(f, "requires({})", tool),
+            Opt(flag) => write!(f, "opt({})", flag),
 
             Not(p) => write!(f, "!{}", p),
             And(p1, p2) => write!(f, "{}, {}", p1, p2),
@@ -160,13 +502,69 @@ impl Display for Behavior {
             Runs => write!(f, "runs"),
             InfiniteLoop => write!(f, "infloop"),
             Abort => write!(f, "abort"),
-            Failure => write!(f, "fail"),
+            Failure => write!(f, "failure"),
             Segfault => write!(f, "segfault"),
             DivZero => write!(f, "div-by-zero"),
             Return(None) => write!(f, "return *"),
             Return(Some(x)) => write!(f, "return {}", x),
+            ReturnIn(ReturnRange::Between(min, max)) => write!(f, "return in [{}, {}]", min, max),
+            ReturnIn(ReturnRange::Except(value)) => write!(f, "return != {}", value),
+            Exit(code) => write!(f, "exit {}", code),
+            UnknownExit(code) => write!(f, "unknown-exit {}", code),
+            UnknownSignal(signal) => write!(f, "unknown-signal {}", signal),
+            Failed(RuntimeFailure::Assert, _) => write!(f, "assert"),
+            Failed(RuntimeFailure::Contract, _) => write!(f, "contract"),
+            Failed(RuntimeFailure::ErrorCall, _) => write!(f, "error()"),
             
             Skipped => write!(f, "<skipped>")
         }
     }
-}
\ No newline at end of file
+}
+impl Display for RawStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RawStatus::Exited(code) => write!(f, "exited with code {}", code),
+            RawStatus::Signaled(signal) => write!(f, "was killed by {}", signal)
+        }
+    }
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use Assertion::*;
+        match self {
+            Warns(text) => write!(f, "warns {:?}", text),
+            NoWarnings => write!(f, "no-warnings"),
+            OutputSize(limit) => write!(f, "output < {}", limit),
+            RawExit(code) => write!(f, "raw-exit {}", code),
+            RawSignal(signal) => write!(f, "raw-signal {}", signal),
+            Checks(patterns) => {
+                write!(f, "checks")?;
+                for pattern in patterns.iter() {
+                    write!(f, " {:?}", pattern)?;
+                }
+                Ok(())
+            },
+            Output { matcher, expected, tolerance } => {
+                write!(f, "output {} {:?}", matcher, expected)?;
+                match tolerance {
+                    Some(tolerance) => write!(f, " within {}%", tolerance),
+                    None => Ok(())
+                }
+            },
+            Expects(file) => write!(f, "expects {:?}", file),
+            Prints { pattern, regex: None } => write!(f, "prints {:?}", pattern),
+            Prints { pattern, regex: Some(_) } => write!(f, "prints /{}/", pattern.replace('/', "\\/")),
+            Creates { file, golden, matcher, tolerance } => {
+                write!(f, "creates {:?} matching {:?}", file, golden)?;
+                if let Some(matcher) = matcher {
+                    write!(f, " using {}", matcher)?;
+                }
+                match tolerance {
+                    Some(tolerance) => write!(f, " within {}%", tolerance),
+                    None => Ok(())
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+
+use crate::spec::*;
+
+/// Counts of what a test corpus contains, for planning where more tests are needed
+#[derive(Default)]
+pub struct CorpusStats {
+    pub tests: usize,
+    pub behaviors: HashMap<String, usize>,
+    pub assertions: HashMap<String, usize>,
+    /// How many specs mention each predicate
+    pub predicates: HashMap<String, usize>,
+    pub directories: HashMap<String, usize>,
+    /// Tests are C1 if any of their sources are
+    pub languages: HashMap<String, usize>,
+    /// Total number of predicates, behaviors, and assertions in all specs
+    pub spec_size: usize
+}
+
+impl CorpusStats {
+    pub fn new(tests: &[TestInfo]) -> CorpusStats {
+        let mut stats = CorpusStats { tests: tests.len(), ..CorpusStats::default() };
+
+        for test in tests {
+            let directory = test.execution.directory.file_name().unwrap_or_default();
+            *stats.directories.entry(directory.to_string_lossy().into_owned()).or_default() += 1;
+
+            let is_c1 = test.execution.sources.iter()
+                .any(|source| matches!(source.extension().and_then(OsStr::to_str), Some("c1") | Some("h1")));
+            *stats.languages.entry(String::from(if is_c1 { "c1" } else { "c0" })).or_default() += 1;
+
+            for spec in test.specs.iter() {
+                stats.add_spec(spec);
+            }
+        }
+
+        stats
+    }
+
+    fn add_spec(&mut self, spec: &Spec) {
+        match spec {
+            Spec::Behavior(behavior) => {
+                self.spec_size += 1;
+                *self.behaviors.entry(behavior_kind(behavior)).or_default() += 1;
+            },
+            Spec::Assertion(assertion) => {
+                self.spec_size += 1;
+                *self.assertions.entry(assertion_kind(assertion)).or_default() += 1;
+            },
+            Spec::Skip(_) => {
+                self.spec_size += 1;
+                *self.behaviors.entry(String::from("skip")).or_default() += 1;
+            },
+            Spec::ExpectedFailure(spec) => {
+                *self.behaviors.entry(String::from("xfail")).or_default() += 1;
+                self.add_spec(spec);
+            },
+            Spec::Implication(predicate, consequent) => {
+                let mut atoms = Vec::new();
+                predicate_atoms(predicate, &mut atoms);
+
+                // Count each predicate once per spec, even if it is mentioned twice
+                atoms.sort();
+                atoms.dedup();
+                self.spec_size += atoms.len();
+                for atom in atoms {
+                    *self.predicates.entry(atom).or_default() += 1;
+                }
+
+                self.add_spec(consequent);
+            }
+        }
+    }
+
+    /// Average number of predicates, behaviors, and assertions per test
+    pub fn average_spec_size(&self) -> f64 {
+        if self.tests == 0 { 0. } else { self.spec_size as f64 / self.tests as f64 }
+    }
+
+    pub fn print(&self) {
+        println!("Tests: {}", self.tests);
+        println!("Average spec size: {:.2} predicates, behaviors, and assertions per test", self.average_spec_size());
+
+        print_counts("By behavior", &self.behaviors, self.tests);
+        print_counts("By assertion", &self.assertions, self.tests);
+        print_counts("By predicate", &self.predicates, self.tests);
+        print_counts("By language", &self.languages, self.tests);
+        print_counts("By directory", &self.directories, self.tests);
+    }
+}
+
+/// Prints counts from most to least common, with the percentage of tests
+fn print_counts(title: &str, counts: &HashMap<String, usize>, tests: usize) {
+    if counts.is_empty() {
+        return
+    }
+
+    let mut counts: Vec<(&String, &usize)> = counts.iter().collect();
+    counts.sort_by(|(name1, count1), (name2, count2)| count2.cmp(count1).then(name1.cmp(name2)));
+
+    let name_width = counts.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let count_width = counts.iter().map(|(_, count)| count.to_string().len()).max().unwrap_or(0);
+
+    println!("\n{}:\n", title);
+    for (name, count) in counts {
+        let percent = *count as f64 / tests.max(1) as f64 * 100.;
+        println!("  {:name_width$}  {:>count_width$}  {:>5.1}%", name, count, percent, 
+            name_width = name_width, count_width = count_width);
+    }
+}
+
+/// The kind of a behavior, without any return value
+fn behavior_kind(behavior: &Behavior) -> String {
+    match behavior {
+        Behavior::Return(_) | Behavior::ReturnIn(_) => String::from("return"),
+        Behavior::Exit(_) => String::from("exit"),
+        behavior => behavior.to_string()
+    }
+}
+
+/// The kind of an assertion, without its text
+fn assertion_kind(assertion: &Assertion) -> String {
+    let kind = match assertion {
+        Assertion::Warns(_) => "warns",
+        Assertion::NoWarnings => "no-warnings",
+        Assertion::Checks(_) => "checks",
+        Assertion::Output { .. } => "output",
+        Assertion::OutputSize(_) => "output-size",
+        Assertion::Creates { .. } => "creates",
+        Assertion::Expects(_) => "expects",
+        Assertion::Prints { .. } => "prints",
+        Assertion::RawExit(_) => "raw-exit",
+        Assertion::RawSignal(_) => "raw-signal"
+    };
+    String::from(kind)
+}
+
+/// Collects the atoms of a predicate. Negated atoms are kept
+/// distinct, since e.g. '!safe' tests are about unsafe implementations
+fn predicate_atoms(predicate: &ImplementationPredicate, atoms: &mut Vec<String>) {
+    use ImplementationPredicate::*;
+    match predicate {
+        And(p1, p2) | Or(p1, p2) => {
+            predicate_atoms(p1, atoms);
+            predicate_atoms(p2, atoms);
+        },
+        Not(p) => {
+            let mut negated = Vec::new();
+            predicate_atoms(p, &mut negated);
+            atoms.extend(negated.into_iter().map(|atom| format!("!{}", atom)));
+        },
+        atom => atoms.push(atom.to_string())
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+    use crate::parse_spec::{self, ParseOptions};
+
+    fn test_info(source: &str, spec: &str) -> TestInfo {
+        let (_, specs) = parse_spec::parse(spec, ParseOptions { require_test_marker: false }).unwrap();
+        TestInfo::new(TestExecutionInfo::for_source(format!("/tests/suite/{}", source)), specs)
+    }
+
+    #[test]
+    fn test() {
+        let stats = CorpusStats::new(&[
+            test_info("a.c0", "return 0"),
+            test_info("b.c1", "safe, !gc => error; safe or safe => return 1; no-warnings"),
+            test_info("c.c0", "cc0 => infloop")
+        ]);
+
+        assert_eq!(stats.tests, 3);
+        assert_eq!(stats.behaviors["return"], 2);
+        assert_eq!(stats.behaviors["error"], 1);
+        assert_eq!(stats.assertions["no-warnings"], 1);
+        assert_eq!(stats.predicates["safe"], 2);
+        assert_eq!(stats.predicates["!gc"], 1);
+        assert_eq!(stats.predicates["cc0"], 1);
+        assert_eq!(stats.languages["c0"], 2);
+        assert_eq!(stats.languages["c1"], 1);
+        assert_eq!(stats.directories["suite"], 3);
+        // a: 1, b: 3 + 2 + 1, c: 2
+        assert_eq!(stats.spec_size, 9);
+    }
+}
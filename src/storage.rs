@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use anyhow::{bail, Context, Result};
+
+/// Somewhere outside the machine which a run's files can be pushed to and fetched
+/// from (see --storage), e.g. so CI runners can share caches and keep failed runs.
+/// Files are named by keys like 'runs/<run>/log.txt', which are paths under the storage
+pub trait Storage: Send + Sync {
+    /// Copies a local file to the key, replacing what was there
+    fn put(&self, local: &Path, key: &str) -> Result<()>;
+
+    /// Copies the key to a local file. Returns false if there's nothing under the key
+    fn get(&self, key: &str, local: &Path) -> Result<bool>;
+
+    /// Where a key is, for messages
+    fn url(&self, key: &str) -> String;
+}
+
+/// Opens storage from its URL: 's3://<bucket>/<prefix>', 'gs://<bucket>/<prefix>',
+/// or a directory, which can also be written as 'file://<dir>'
+pub fn open(url: &str) -> Result<Arc<dyn Storage>> {
+    for service in [ObjectService::S3, ObjectService::Gcs].iter() {
+        if let Some(rest) = url.strip_prefix(service.scheme()) {
+            if rest.split('/').next().unwrap_or_default().is_empty() {
+                bail!("'{}' doesn't name a bucket", url)
+            }
+            return Ok(Arc::new(ObjectStore { service: *service, base: url.trim_end_matches('/').to_string() }))
+        }
+    }
+
+    let dir = url.strip_prefix("file://").unwrap_or(url);
+    if dir.contains("://") {
+        bail!("Unknown storage '{}', which should be a directory, s3://<bucket> or gs://<bucket>", url)
+    }
+    Ok(Arc::new(LocalDir { root: PathBuf::from(dir) }))
+}
+
+/// Pushes every file in a directory and its subdirectories to keys under 'prefix'.
+/// Symlinks aren't followed. Returns how many files were pushed
+pub fn put_dir(storage: &dyn Storage, dir: &Path, prefix: &str) -> Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir).context(format!("Couldn't read '{}'", dir.display()))? {
+        let entry = entry?;
+        let key = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            count += put_dir(storage, &entry.path(), &key)?;
+        }
+        else if kind.is_file() {
+            storage.put(&entry.path(), &key)?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// A directory, e.g. on a shared network drive
+struct LocalDir {
+    root: PathBuf
+}
+
+impl Storage for LocalDir {
+    fn put(&self, local: &Path, key: &str) -> Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!("Couldn't create '{}'", parent.display()))?;
+        }
+
+        // Copied under a temporary name and then renamed, so other runners never fetch half of it
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{}-{}-{}", name, std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        if let Err(error) = fs::copy(local, &temp) {
+            let _ = fs::remove_file(&temp);
+            return Err(error).context(format!("Couldn't copy '{}' to '{}'", local.display(), temp.display()))
+        }
+        fs::rename(&temp, &path).context(format!("Couldn't move it to '{}'", path.display()))?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str, local: &Path) -> Result<bool> {
+        let path = self.root.join(key);
+        if !path.is_file() {
+            return Ok(false)
+        }
+        fs::copy(&path, local).context(format!("Couldn't copy '{}' to '{}'", path.display(), local.display()))?;
+        Ok(true)
+    }
+
+    fn url(&self, key: &str) -> String {
+        self.root.join(key).display().to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjectService {
+    S3,
+    Gcs
+}
+
+impl ObjectService {
+    fn scheme(self) -> &'static str {
+        match self {
+            ObjectService::S3 => "s3://",
+            ObjectService::Gcs => "gs://"
+        }
+    }
+
+    /// The command which copies between local files and objects. It has to be
+    /// in $PATH, and takes its credentials from the environment as usual
+    fn copy_command(self) -> Command {
+        match self {
+            ObjectService::S3 => {
+                let mut command = Command::new("aws");
+                command.args(["s3", "cp", "--only-show-errors"].iter());
+                command
+            },
+            ObjectService::Gcs => {
+                let mut command = Command::new("gsutil");
+                command.args(["-q", "cp"].iter());
+                command
+            }
+        }
+    }
+}
+
+/// A bucket in S3 or Google Cloud Storage, used through their command line tools
+/// rather than their APIs, so that c0check doesn't need their SDKs
+struct ObjectStore {
+    service: ObjectService,
+    /// e.g. 's3://bucket/prefix', without a trailing slash
+    base: String
+}
+
+impl ObjectStore {
+    /// Copies between a local file and an object, returning the tool's errors if it fails
+    fn copy(&self, from: &str, to: &str) -> Result<Result<(), String>> {
+        let output = self.service.copy_command().arg(from).arg(to).output()
+            .context(format!("Couldn't run the command line tool for {}", self.service.scheme()))?;
+        if output.status.success() {
+            Ok(Ok(()))
+        }
+        else {
+            Ok(Err(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+        }
+    }
+}
+
+impl Storage for ObjectStore {
+    fn put(&self, local: &Path, key: &str) -> Result<()> {
+        match self.copy(&local.to_string_lossy(), &self.url(key))? {
+            Ok(()) => Ok(()),
+            Err(error) => bail!("Couldn't upload '{}' to '{}': {}", local.display(), self.url(key), error)
+        }
+    }
+
+    fn get(&self, key: &str, local: &Path) -> Result<bool> {
+        match self.copy(&self.url(key), &local.to_string_lossy())? {
+            Ok(()) => Ok(true),
+            Err(error) if is_missing(&error) => Ok(false),
+            Err(error) => bail!("Couldn't download '{}': {}", self.url(key), error)
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base, key)
+    }
+}
+
+/// Whether a copy failed because the object doesn't exist,
+/// going by what aws and gsutil say when it doesn't
+fn is_missing(error: &str) -> bool {
+    ["(404)", "NoSuchKey", "No URLs matched"].iter().any(|message| error.contains(message))
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_open() {
+        assert_eq!(open("s3://ci-results/c0check").unwrap().url("cache/results.json"), "s3://ci-results/c0check/cache/results.json");
+        assert_eq!(open("gs://ci-results/").unwrap().url("runs/a/log.txt"), "gs://ci-results/runs/a/log.txt");
+        assert_eq!(open("file:///mnt/shared").unwrap().url("a.txt"), "/mnt/shared/a.txt");
+        assert!(open("s3://").is_err());
+        assert!(open("ftp://example.com/").is_err());
+        assert!(is_missing("fatal error: An error occurred (404) when calling the HeadObject operation: Key \"a\" does not exist"));
+    }
+
+    #[test]
+    fn test_local_dir() -> Result<()> {
+        let base = TempDir::new("storage");
+        let run = base.join("run");
+        fs::create_dir_all(run.join("coverage"))?;
+        fs::write(run.join("log.txt"), "log")?;
+        fs::write(run.join("coverage").join("a.gcda"), "coverage")?;
+
+        let storage = open(&base.join("storage").to_string_lossy())?;
+        assert_eq!(put_dir(&*storage, &run, "runs/1")?, 2);
+        assert_eq!(fs::read_to_string(base.join("storage/runs/1/coverage/a.gcda"))?, "coverage");
+        // Nothing is left under a temporary name
+        assert_eq!(fs::read_dir(base.join("storage/runs/1"))?.count(), 2);
+        fs::write(run.join("log.txt"), "newer log")?;
+        storage.put(&run.join("log.txt"), "runs/1/log.txt")?;
+        assert_eq!(fs::read_to_string(base.join("storage/runs/1/log.txt"))?, "newer log");
+
+        let fetched = base.join("fetched.txt");
+        assert!(storage.get("runs/1/log.txt", &fetched)?);
+        assert_eq!(fs::read_to_string(&fetched)?, "newer log");
+        assert!(!storage.get("runs/2/log.txt", &fetched)?);
+
+        Ok(())
+    }
+}
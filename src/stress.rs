@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+
+use crate::checker::{Checker, TestOutcome, TestResult};
+use crate::executer::Executer;
+use crate::hooks::HookRunner;
+use crate::spec::TestInfo;
+
+/// How often each outcome happened when running a test over and over
+#[derive(Default)]
+pub struct StressStats {
+    pub runs: usize,
+    pub passes: usize,
+    /// Keyed by the first line of each failure or error, since it
+    /// usually tells them apart. Holds how many times it happened, and the
+    /// whole message from the first time
+    pub failures: BTreeMap<String, (usize, String)>
+}
+
+/// Runs a test (or each test in turn) from 'instances' threads until 'duration' is up
+pub fn stress(
+    checker: &Checker,
+    hooks: &HookRunner,
+    executer: &dyn Executer,
+    tests: &[TestInfo],
+    instances: usize,
+    duration: Duration) -> Result<BTreeMap<String, StressStats>>
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(instances)
+        .build()
+        .context("Couldn't start the stress threads")?;
+
+    let stats: Mutex<BTreeMap<String, StressStats>> = Mutex::new(BTreeMap::new());
+    let deadline = Instant::now() + duration;
+
+    pool.scope(|scope| {
+        for instance in 0..instances {
+            let stats = &stats;
+            scope.spawn(move |_| {
+                let mut i = instance;
+                while Instant::now() < deadline {
+                    let test = &tests[i % tests.len()];
+                    i += 1;
+
+                    let (outcome, _) = hooks.around_test(test, || checker.run_test(executer, test));
+                    let failure = match outcome {
+                        Some(Ok(TestOutcome { result: TestResult::Success, .. }))
+                            | Some(Ok(TestOutcome { result: TestResult::ExpectedFailure(_), .. })) => None,
+                        Some(Ok(TestOutcome { result: TestResult::UnexpectedPass, .. })) => Some(String::from("❗ Passed, but it's expected to fail")),
+                        Some(Ok(TestOutcome { result: TestResult::Skipped(_, reason), .. })) => Some(format!("⏭️ Skipped: {}", reason)),
+                        Some(Ok(TestOutcome { result: TestResult::Mismatch(failure), .. })) => Some(format!("❌ {}", failure)),
+                        Some(Err(error)) => Some(format!("⛔ {:#}", error)),
+                        None => Some(String::from("🪝 Its before hook failed"))
+                    };
+
+                    let mut stats = stats.lock().unwrap();
+                    let stats = stats.entry(test.name()).or_default();
+                    stats.runs += 1;
+                    match failure {
+                        None => stats.passes += 1,
+                        Some(failure) => {
+                            let key = failure.lines().next().unwrap_or_default().to_string();
+                            stats.failures.entry(key).or_insert((0, failure)).0 += 1;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(stats.into_inner().unwrap())
+}
+
+pub fn print_report(stats: &BTreeMap<String, StressStats>, instances: usize, duration: Duration) {
+    for (test, stats) in stats.iter() {
+        let percent = |count: usize| count as f64 / stats.runs as f64 * 100.;
+        let failures = stats.runs - stats.passes;
+
+        println!("\n{}: {} runs in {}s with {} instances ({:.1} runs/s)",
+            test, stats.runs, duration.as_secs(), instances, stats.runs as f64 / duration.as_secs_f64());
+        println!("✅ Passed: {} ({:.2}%)", stats.passes, percent(stats.passes));
+
+        for (count, message) in stats.failures.values() {
+            println!("{} time(s) ({:.2}%):\n{}", count, percent(*count), message);
+        }
+
+        println!("Failure rate: {}/{} ({:.2}%)", failures, stats.runs, percent(failures));
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use anyhow::{Context, Result};
+
+use crate::spec::{ExitCodes, ResourceLimits};
+use crate::options::parse_size;
+use crate::hooks::Hooks;
+use crate::parse_spec;
+
+/// Settings shared by every test in a directory, read from 'suite.toml'
+///
+///```toml
+/// allowed_failures = 5
+/// owners = ["@alice"]
+/// c0_home = "/opt/c0-2019"
+///
+/// [compilation]
+/// time = 60
+/// memory = "8 GB"
+///
+/// [hooks]
+/// before = "./generate-inputs.sh"
+/// after = "rm -f *.out"
+///
+/// [exit_codes.coin]
+/// 3 = "abort"
+///
+/// [libraries.points]
+/// header = "points.h0"
+/// sources = ["points.c"]
+///```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuiteConfig {
+    /// How many tests can fail before the suite counts as failed,
+    /// e.g. for experimental suites with known failures
+    allowed_failures: usize,
+    /// Who to ask about the suite's failures, e.g. GitHub handles
+    owners: Vec<String>,
+    /// The C0 distribution the suite's tests use instead of --c0-home,
+    /// e.g. to pin old tests to an old release. Relative to the suite's directory
+    c0_home: Option<PathBuf>,
+    /// Resource limits for CC0/GCC which override the global ones
+    compilation: LimitsConfig,
+    /// Shell commands run from the suite's directory before
+    /// its first test and after all tests have finished
+    hooks: Hooks,
+    /// What runtime exit codes mean, by executer name,
+    /// e.g. for libraries which exit with their own codes
+    exit_codes: BTreeMap<String, BTreeMap<String, String>>,
+    /// Native libraries, by name, which are compiled once per
+    /// run and linked into every test in the suite
+    libraries: BTreeMap<String, LibraryConfig>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsConfig {
+    time: Option<u64>,
+    memory: Option<String>
+}
+
+/// A C0 library written in C, as in the C0 distribution's 'lib' directory
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    /// The C0 declarations of the library's functions
+    pub header: String,
+    /// C files, relative to the suite's directory
+    pub sources: Vec<String>,
+    /// Extra arguments for GCC, e.g. "-lm"
+    pub cflags: Vec<String>
+}
+
+impl SuiteConfig {
+    /// Reads 'suite.toml' from the given directory. Directories
+    /// without one get the default configuration
+    pub fn load(dir: &Path) -> Result<SuiteConfig> {
+        let path = dir.join("suite.toml");
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(SuiteConfig::default()),
+            Err(e) => return Err(e).context(format!("Couldn't read '{}'", path.display()))
+        };
+
+        toml::from_str(&contents).context(format!("Invalid suite configuration '{}'", path.display()))
+    }
+
+    pub fn compilation_limits(&self) -> Result<ResourceLimits> {
+        self.compilation.to_limits().context("in [compilation]")
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub fn allowed_failures(&self) -> usize {
+        self.allowed_failures
+    }
+
+    pub fn owners(&self) -> &[String] {
+        &self.owners
+    }
+
+    /// The suite's own C0 distribution, if it has one. 'dir' is the suite's directory
+    pub fn c0_home(&self, dir: &Path) -> Option<PathBuf> {
+        self.c0_home.as_ref().map(|c0_home| dir.join(c0_home))
+    }
+
+    pub fn libraries(&self) -> &BTreeMap<String, LibraryConfig> {
+        &self.libraries
+    }
+
+    pub fn exit_codes(&self) -> Result<ExitCodes> {
+        self.exit_codes.iter().map(|(executer, codes)| {
+            let codes = codes.iter().map(|(code, behavior)| {
+                let code: i32 = code.parse().context(format!("'{}' isn't an exit code", code))?;
+                let behavior = parse_spec::parse_behavior(behavior)
+                    .context(format!("Invalid behavior for exit code {}", code))?;
+                Ok((code, behavior))
+            }).collect::<Result<_>>();
+
+            Ok((executer.clone(), codes.context(format!("in [exit_codes.{}]", executer))?))
+        }).collect()
+    }
+}
+
+impl LimitsConfig {
+    fn to_limits(&self) -> Result<ResourceLimits> {
+        let memory = match &self.memory {
+            Some(memory) => Some(parse_size(memory)?),
+            None => None
+        };
+
+        Ok(ResourceLimits { time: self.time, memory })
+    }
+}
+
+#[cfg(test)]
+mod suite_config_tests {
+    use super::*;
+    use crate::spec::Behavior;
+
+    #[test]
+    fn test_compilation_limits() -> Result<()> {
+        let config: SuiteConfig = toml::from_str("[compilation]\ntime = 60\nmemory = \"8 GB\"")?;
+        assert_eq!(config.compilation_limits()?, ResourceLimits {
+            time: Some(60),
+            memory: Some(8 * 1024 * 1024 * 1024)
+        });
+
+        let config: SuiteConfig = toml::from_str("")?;
+        assert_eq!(config.compilation_limits()?, ResourceLimits::default());
+        assert_eq!(config.allowed_failures(), 0);
+
+        let config: SuiteConfig = toml::from_str("allowed_failures = 5\n[compilation]\ntime = 60")?;
+        assert_eq!(config.allowed_failures(), 5);
+        assert!(config.owners().is_empty());
+
+        let config: SuiteConfig = toml::from_str("owners = [\"@alice\", \"@bob\"]")?;
+        assert_eq!(config.owners(), &[String::from("@alice"), String::from("@bob")]);
+
+        assert!(toml::from_str::<SuiteConfig>("[compilation]\ntimeout = 60").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hooks() -> Result<()> {
+        let config: SuiteConfig = toml::from_str("[hooks]\nbefore = \"make inputs\"")?;
+        assert_eq!(config.hooks(), &Hooks { before: Some(String::from("make inputs")), after: None });
+
+        assert!(toml::from_str::<SuiteConfig>("[hooks]\nbefore_each = \"true\"").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_codes() -> Result<()> {
+        let config: SuiteConfig = toml::from_str("[exit_codes.coin]\n3 = \"abort\"\n-1 = \"return 5\"")?;
+        let exit_codes = config.exit_codes()?;
+        assert_eq!(exit_codes["coin"][&3], Behavior::Abort);
+        assert!(matches!(exit_codes["coin"][&-1], Behavior::Return(Some(5))));
+
+        let config: SuiteConfig = toml::from_str("[exit_codes.coin]\nthree = \"abort\"")?;
+        assert!(config.exit_codes().is_err());
+
+        let config: SuiteConfig = toml::from_str("[exit_codes.coin]\n3 = \"aborts\"")?;
+        assert!(config.exit_codes().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_libraries() -> Result<()> {
+        let config: SuiteConfig = toml::from_str("[libraries.points]\nheader = \"points.h0\"\nsources = [\"points.c\"]")?;
+        assert_eq!(config.libraries()["points"], LibraryConfig {
+            header: String::from("points.h0"),
+            sources: vec![String::from("points.c")],
+            cflags: Vec::new()
+        });
+
+        assert!(toml::from_str::<SuiteConfig>("[libraries.points]\nsource = \"points.c\"").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_c0_home() -> Result<()> {
+        let dir = Path::new("/tests/legacy");
+        assert_eq!(SuiteConfig::default().c0_home(dir), None);
+
+        let config: SuiteConfig = toml::from_str("c0_home = \"/opt/c0-2019\"")?;
+        assert_eq!(config.c0_home(dir), Some(PathBuf::from("/opt/c0-2019")));
+        let config: SuiteConfig = toml::from_str("c0_home = \"../c0\"")?;
+        assert_eq!(config.c0_home(dir), Some(PathBuf::from("/tests/legacy/../c0")));
+        Ok(())
+    }
+}
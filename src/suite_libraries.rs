@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use sha2::{Digest, Sha256};
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::fingerprint;
+use crate::launcher::run_tool;
+use crate::options::Options;
+use crate::spec::TestInfo;
+use crate::suite_config::{LibraryConfig, SuiteConfig};
+
+/// Compiles the libraries each suite declares in its suite.toml, once per run,
+/// and links them into the suite's tests. Returns how many were built
+pub fn build(options: &Options, tests: &mut [TestInfo]) -> Result<usize> {
+    let mut suites: HashMap<Arc<Path>, Vec<String>> = HashMap::new();
+    for test in tests.iter() {
+        let dir = &test.execution.directory;
+        if !suites.contains_key(dir) {
+            let config = SuiteConfig::load(dir)?;
+            let mut link_options = Vec::new();
+            for (name, library) in config.libraries() {
+                let lib_dir = build_library(options, dir, name, library)
+                    .context(format!("Couldn't build the library '{}' of '{}'", name, dir.display()))?;
+                link_options.push(String::from("-L"));
+                link_options.push(lib_dir.to_string_lossy().into_owned());
+                link_options.push(format!("-l{}", name));
+            }
+            suites.insert(dir.clone(), link_options);
+        }
+    }
+
+    for test in tests.iter_mut() {
+        test.execution.link_options = suites[&test.execution.directory].clone();
+    }
+
+    Ok(suites.values().map(|link_options| link_options.len() / 3).sum())
+}
+
+/// Compiles a library into a directory laid out like the C0 distribution's
+/// 'lib', with '<name>.h0' and 'lib<name>.so'. The directory is named after
+/// the library's contents, so cached results don't outlive changes to it
+fn build_library(options: &Options, dir: &Path, name: &str, library: &LibraryConfig) -> Result<PathBuf> {
+    if library.header.is_empty() || library.sources.is_empty() {
+        bail!("Libraries need a 'header' and 'sources'")
+    }
+
+    let lib_dir = options.cache_dir().join("libraries").join(library_key(dir, name, library)?);
+    fs::create_dir_all(&lib_dir).context(format!("Couldn't create '{}'", lib_dir.display()))?;
+
+    let header = dir.join(&library.header);
+    fs::copy(&header, lib_dir.join(format!("{}.h0", name)))
+        .context(format!("Couldn't copy '{}'", header.display()))?;
+
+    let mut args = vec![String::from("-shared"), String::from("-fPIC"), String::from("-o")];
+    args.push(lib_dir.join(format!("lib{}.so", name)).to_string_lossy().into_owned());
+    args.extend(library.sources.iter().map(|source| dir.join(source).to_string_lossy().into_owned()));
+    args.extend(library.cflags.iter().cloned());
+
+    let args = args.into_iter().map(CString::new).collect::<Result<Vec<_>, _>>()
+        .context("Invalid character in the GCC arguments")?;
+    let (success, output) = run_tool(CString::new("gcc").unwrap(), &args, options.compilation_time, options.compilation_mem)?;
+    if !success {
+        return Err(anyhow!("{}", output)).context("GCC failed")
+    }
+
+    Ok(lib_dir)
+}
+
+/// Hashes everything a library's build depends on
+fn library_key(dir: &Path, name: &str, library: &LibraryConfig) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{} {:?}", name, library.cflags));
+    hasher.update(fingerprint::file_hash(&dir.join(&library.header))?);
+    for source in library.sources.iter() {
+        hasher.update(format!("{} {}", source, fingerprint::file_hash(&dir.join(source))?));
+    }
+
+    Ok(format!("{}-{:.16x}", name, hasher.finalize()))
+}
+
+#[cfg(test)]
+mod suite_libraries_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_library_key() {
+        let dir = TempDir::new("libraries");
+        fs::write(dir.join("points.h0"), "int origin();\n").unwrap();
+        fs::write(dir.join("points.c"), "int origin() { return 0; }\n").unwrap();
+
+        let library = LibraryConfig {
+            header: String::from("points.h0"),
+            sources: vec![String::from("points.c")],
+            cflags: Vec::new()
+        };
+        let key = library_key(&dir, "points", &library).unwrap();
+        assert!(key.starts_with("points-"));
+        assert_eq!(key, library_key(&dir, "points", &library).unwrap());
+
+        // Changing a source changes where the library goes
+        fs::write(dir.join("points.c"), "int origin() { return 1; }\n").unwrap();
+        let changed = library_key(&dir, "points", &library).unwrap();
+        assert_ne!(key, changed);
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::Result;
+
+use crate::executer::{Artifact, Compilation, Executer, ExecuterProperties, TestRun};
+use crate::options::Options;
+use crate::spec::*;
+use crate::suite_config::SuiteConfig;
+
+/// The C0 distribution of each suite which doesn't use --c0-home, by suite
+/// directory, from --suite-c0-home or else 'c0_home' in its suite.toml
+pub fn c0_homes(options: &Options, tests: &[TestInfo]) -> Result<BTreeMap<Arc<Path>, PathBuf>> {
+    let mut c0_homes = BTreeMap::new();
+    let mut checked = Vec::new();
+    for dir in tests.iter().map(|test| &test.execution.directory) {
+        if checked.contains(&dir) {
+            continue
+        }
+        checked.push(dir);
+
+        let name = suite_name(dir);
+        let c0_home = match options.suite_c0_home.iter().rev().find(|(suite, _)| *suite == name) {
+            Some((_, c0_home)) => Some(c0_home.clone()),
+            None => SuiteConfig::load(dir)?.c0_home(dir)
+        };
+        match c0_home {
+            Some(c0_home) if options.c0_home.as_ref() != Some(&c0_home) => { c0_homes.insert(dir.clone(), c0_home); },
+            _ => ()
+        }
+    }
+
+    for (suite, _) in options.suite_c0_home.iter() {
+        if !checked.iter().any(|dir| suite_name(dir) == *suite) {
+            eprintln!("⚠: --suite-c0-home names '{}', but no suite being run has that name", suite);
+        }
+    }
+
+    Ok(c0_homes)
+}
+
+/// The C0 distribution of each suite which doesn't use --c0-home, by suite name, for reports
+pub fn by_name(c0_homes: &BTreeMap<Arc<Path>, PathBuf>) -> BTreeMap<String, String> {
+    c0_homes.iter().map(|(dir, c0_home)| (suite_name(dir), c0_home.display().to_string())).collect()
+}
+
+fn suite_name(dir: &Path) -> String {
+    dir.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+/// An executer which runs each suite's tests with its own C0 distribution,
+/// if it has one, and every other test with the default executer
+pub struct SuiteToolchains {
+    default: Box<dyn Executer>,
+    executers: Vec<Box<dyn Executer>>,
+    /// Which of 'executers' each suite with its own distribution uses
+    suites: HashMap<Arc<Path>, usize>
+}
+
+impl SuiteToolchains {
+    /// 'create' makes an executer which uses the given C0 distribution.
+    /// Suites which use the same distribution share an executer
+    pub fn new(
+        default: Box<dyn Executer>,
+        c0_homes: &BTreeMap<Arc<Path>, PathBuf>,
+        create: impl Fn(&Path) -> Result<Box<dyn Executer>>) -> Result<SuiteToolchains>
+    {
+        let mut executers = Vec::new();
+        let mut created: Vec<&Path> = Vec::new();
+        let mut suites = HashMap::new();
+        for (dir, c0_home) in c0_homes.iter() {
+            let index = match created.iter().position(|created| created == c0_home) {
+                Some(index) => index,
+                None => {
+                    executers.push(create(c0_home)?);
+                    created.push(c0_home);
+                    executers.len() - 1
+                }
+            };
+            suites.insert(dir.clone(), index);
+        }
+
+        Ok(SuiteToolchains { default, executers, suites })
+    }
+
+    fn executer(&self, test: &TestExecutionInfo) -> &dyn Executer {
+        match self.suites.get(&test.directory) {
+            Some(&index) => &*self.executers[index],
+            None => &*self.default
+        }
+    }
+}
+
+impl Executer for SuiteToolchains {
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
+        self.executer(test).compile(test)
+    }
+
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun> {
+        self.executer(test).execute(test, artifact)
+    }
+
+    fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        self.executer(test).compile_command(test)
+    }
+
+    fn exit_code(&self, code: i32) -> Option<Behavior> {
+        self.default.exit_code(code)
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        let mut toolchain = self.default.toolchain();
+        toolchain.extend(self.executers.iter().flat_map(|executer| executer.toolchain()));
+        toolchain
+    }
+
+    fn properties(&self) -> ExecuterProperties {
+        self.default.properties()
+    }
+}
+
+#[cfg(test)]
+mod suite_toolchains_tests {
+    use super::*;
+
+    /// Says which C0 distribution it uses in the output of every run
+    struct HomeExecuter(PathBuf);
+
+    impl Executer for HomeExecuter {
+        fn compile(&self, _test: &TestExecutionInfo) -> Result<Compilation> {
+            Ok(Compilation::NotNeeded)
+        }
+
+        fn execute(&self, _test: &TestExecutionInfo, _artifact: Option<&Artifact>) -> Result<TestRun> {
+            Ok(TestRun::new(self.0.display().to_string(), None, Behavior::Return(Some(0))))
+        }
+
+        fn toolchain(&self) -> Vec<PathBuf> {
+            vec![self.0.join("bin/cc0")]
+        }
+
+        fn properties(&self) -> ExecuterProperties {
+            ExecuterProperties { libraries: true, typechecked: true, garbage_collected: true, safe: true, name: "home" }
+        }
+    }
+
+    fn test_in(dir: &str) -> TestExecutionInfo {
+        TestExecutionInfo::new(Vec::new(), Arc::from(Path::new(dir)))
+    }
+
+    #[test]
+    fn test() -> Result<()> {
+        let c0_homes: BTreeMap<Arc<Path>, PathBuf> = vec![
+            (Arc::from(Path::new("/tests/legacy")), PathBuf::from("/opt/c0-2019")),
+            (Arc::from(Path::new("/tests/old-gc")), PathBuf::from("/opt/c0-2019")),
+            (Arc::from(Path::new("/tests/next")), PathBuf::from("/opt/c0-dev"))
+        ].into_iter().collect();
+
+        let toolchains = SuiteToolchains::new(Box::new(HomeExecuter(PathBuf::from("/opt/c0"))), &c0_homes,
+            |c0_home| Ok(Box::new(HomeExecuter(c0_home.to_path_buf()))))?;
+        assert_eq!(toolchains.executers.len(), 2);
+
+        let output = |dir| toolchains.execute(&test_in(dir), None).unwrap().output;
+        assert_eq!(output("/tests/legacy"), "/opt/c0-2019");
+        assert_eq!(output("/tests/old-gc"), "/opt/c0-2019");
+        assert_eq!(output("/tests/next"), "/opt/c0-dev");
+        assert_eq!(output("/tests/basic"), "/opt/c0");
+        assert_eq!(toolchains.toolchain().len(), 3);
+
+        assert_eq!(by_name(&c0_homes)["legacy"], "/opt/c0-2019");
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+use regex::Regex;
+use anyhow::{Context, Result};
+
+/// Patterns for known-noisy output lines (e.g. GC diagnostics or
+/// linker warnings) which are removed before checking test output
+#[derive(Default)]
+pub struct Suppressions {
+    patterns: Vec<Regex>
+}
+
+impl Suppressions {
+    /// Reads a suppressions file, which has one regex per line.
+    /// Blank lines and lines starting with '#' are ignored
+    pub fn load(path: &Path) -> Result<Suppressions> {
+        let contents = fs::read_to_string(path)
+            .context(format!("Couldn't read suppressions file '{}'", path.display()))?;
+
+        Suppressions::parse(&contents)
+            .context(format!("in suppressions file '{}'", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Suppressions> {
+        let patterns = contents.lines()
+            .zip(1usize..)
+            .filter(|(line, _)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(line, lineno)| Regex::new(line.trim()).context(format!("Invalid regex on line {}", lineno)))
+            .collect::<Result<_>>()?;
+
+        Ok(Suppressions { patterns })
+    }
+
+    /// Removes every line matching a suppression from the output
+    pub fn apply(&self, output: &str) -> String {
+        if self.patterns.is_empty() {
+            return output.to_string()
+        }
+
+        output.split_inclusive('\n')
+            .filter(|line| !self.patterns.iter().any(|pattern| pattern.is_match(line.trim_end_matches('\n'))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod suppressions_tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let suppressions = Suppressions::parse("# GC noise\n^GC Warning:\n\nld: warning: .*\n")?;
+
+        assert_eq!(suppressions.apply("GC Warning: repeated allocation\nhello\n"), "hello\n");
+        assert_eq!(suppressions.apply("ld: warning: object file was built for newer macOS"), "");
+        assert_eq!(suppressions.apply("say GC Warning: no\n"), "say GC Warning: no\n");
+        assert!(Suppressions::parse("(unclosed").is_err());
+
+        Ok(())
+    }
+}
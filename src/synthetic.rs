@@ -0,0 +1,163 @@
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{anyhow, Context, Result};
+use nix::libc;
+use nix::sys::signal::Signal;
+
+use crate::launcher::{self, Execution};
+use crate::spec::*;
+
+/// A small C program which ends however its arguments say, the way a test program might.
+/// It writes its result file like the C0 runtime does when main returns
+const CHILD_SOURCE: &str = r#"
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static void write_result(int value) {
+    const char *path = getenv("C0_RESULT_FILE");
+    FILE *file = path == NULL ? NULL : fopen(path, "wb");
+    if (file == NULL) {
+        perror("C0_RESULT_FILE");
+        abort();
+    }
+    fputc(0, file);
+    fwrite(&value, sizeof value, 1, file);
+    fclose(file);
+}
+
+int main(int argc, char **argv) {
+    const char *action = argc > 1 ? argv[1] : "";
+    int arg = argc > 2 ? atoi(argv[2]) : 0;
+
+    if (strcmp(action, "return") == 0) {
+        write_result(arg);
+        return 0;
+    }
+    if (strcmp(action, "exit") == 0) {
+        exit(arg);
+    }
+    if (strcmp(action, "signal") == 0) {
+        raise(arg);
+    }
+    if (strcmp(action, "spin") == 0) {
+        volatile unsigned long count = 0;
+        for (;;) count++;
+    }
+    if (strcmp(action, "allocate") == 0) {
+        size_t size = (size_t)arg * 1024 * 1024;
+        char *memory = malloc(size);
+        if (memory == NULL) {
+            fputs("out of memory\n", stderr);
+            abort();
+        }
+        memset(memory, 1, size);
+        write_result(0);
+        return 0;
+    }
+
+    fprintf(stderr, "unknown action '%s'\n", action);
+    return 64;
+}
+"#;
+
+const MB: u64 = 1024 * 1024;
+
+/// A way for a test program to end, and how the launcher should classify it
+pub struct Case {
+    pub name: String,
+    pub args: Vec<String>,
+    pub expected: Behavior,
+    /// CPU time in seconds
+    pub time: u64,
+    /// Memory in bytes
+    pub memory: u64
+}
+
+impl Case {
+    fn new(name: &str, args: &[String], expected: Behavior) -> Case {
+        Case { name: name.to_string(), args: args.to_vec(), expected, time: 5, memory: 512 * MB }
+    }
+}
+
+/// Every way of ending which the launcher tells apart. Signal numbers
+/// come from the platform, since they differ between systems
+pub fn cases() -> Vec<Case> {
+    let args = |action: &str, arg: i32| vec![action.to_string(), arg.to_string()];
+    let signal = |signal: libc::c_int| args("signal", signal);
+
+    vec![
+        Case::new("main returns 0", &args("return", 0), Behavior::Return(Some(0))),
+        Case::new("main returns -7", &args("return", -7), Behavior::Return(Some(-7))),
+        Case::new("exits with 1", &args("exit", 1), Behavior::Failure),
+        Case::new("exits with 42", &args("exit", 42), Behavior::UnknownExit(42)),
+        Case::new("SIGSEGV", &signal(libc::SIGSEGV), Behavior::Segfault),
+        Case::new("SIGFPE", &signal(libc::SIGFPE), Behavior::DivZero),
+        Case::new("SIGABRT", &signal(libc::SIGABRT), Behavior::Abort),
+        Case::new("SIGUSR1", &signal(libc::SIGUSR1), Behavior::UnknownSignal(Signal::SIGUSR1)),
+        Case { time: 1, ..Case::new("runs out of CPU time", &[String::from("spin")], Behavior::InfiniteLoop) },
+        Case { memory: 64 * MB, ..Case::new("runs out of memory", &args("allocate", 256), Behavior::Abort) },
+        Case { memory: 256 * MB, ..Case::new("stays within its memory", &args("allocate", 16), Behavior::Return(Some(0))) }
+    ]
+}
+
+/// The compiled helper program, which runs from the directory it was built in
+pub struct Child {
+    path: PathBuf,
+    dir: Arc<Path>
+}
+
+impl Child {
+    /// Compiles the helper into 'dir' with $CC, or 'cc' if it isn't set
+    pub fn build(dir: &Path) -> Result<Child> {
+        fs::create_dir_all(dir).context(format!("Couldn't create '{}'", dir.display()))?;
+        let source = dir.join("synthetic_child.c");
+        fs::write(&source, CHILD_SOURCE).context(format!("Couldn't write '{}'", source.display()))?;
+
+        let path = dir.join("synthetic_child");
+        let compiler = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+        let args = [CString::new("-o")?, CString::new(path.to_string_lossy().as_bytes())?, CString::new(source.to_string_lossy().as_bytes())?];
+        let (success, output) = launcher::run_tool(CString::new(compiler.as_str())?, &args, 60, 1024 * MB)?;
+        if !success {
+            return Err(anyhow!("{}", output)).context(format!("Couldn't compile the helper program with '{}'", compiler))
+        }
+
+        Ok(Child { path, dir: Arc::from(dir) })
+    }
+
+    /// Runs the helper the way a test program is run
+    pub fn run(&self, args: &[String], time: u64, memory: u64) -> Result<Execution> {
+        let info = TestExecutionInfo::new(Vec::new(), self.dir.clone());
+
+        let args = args.iter().map(|arg| CString::new(arg.as_str())).collect::<Result<Vec<_>, _>>()?;
+        launcher::execute_with_args(&info, CString::new(self.path.to_string_lossy().as_bytes())?, &args, &[], &self.dir, time, memory)
+    }
+
+    /// Runs every case, returning how each one was classified
+    pub fn check(&self, cases: &[Case]) -> Vec<Result<Behavior>> {
+        cases.iter().map(|case| self.run(&case.args, case.time, case.memory).map(|execution| execution.behavior)).collect()
+    }
+}
+
+#[cfg(test)]
+mod synthetic_tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test() -> Result<()> {
+        let dir = TempDir::new("synthetic");
+        let child = Child::build(&dir)?;
+
+        let cases = cases();
+        for (case, behavior) in cases.iter().zip(child.check(&cases)) {
+            assert_eq!(behavior?, case.expected, "{}", case.name);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::str::FromStr;
+use regex::Regex;
+use anyhow::{Context, Error, Result};
+
+use crate::spec::TestInfo;
+
+/// A --filter pattern: a glob, or a regex if it starts with 're:'.
+/// Globs have to match all of a name, but regexes only part of it
+#[derive(Debug, Clone)]
+pub struct TestFilter {
+    regex: Regex
+}
+
+impl FromStr for TestFilter {
+    type Err = Error;
+
+    fn from_str(pattern: &str) -> Result<TestFilter> {
+        match pattern.strip_prefix("re:") {
+            Some(regex) => Ok(TestFilter {
+                regex: Regex::new(regex).context(format!("Invalid regex '{}'", regex))?
+            }),
+            None => Ok(TestFilter {
+                regex: Regex::new(&glob_to_regex(pattern)).context(format!("Invalid glob '{}'", pattern))?
+            })
+        }
+    }
+}
+
+impl TestFilter {
+    /// Checks the test's name (e.g. 'basic/ok.c0'), each of its
+    /// sources as they appear in the name, and their full paths
+    pub fn matches(&self, test: &TestInfo) -> bool {
+        let name = test.name();
+        let sources = test.execution.sources.iter().map(|source| source.to_string_lossy().into_owned());
+
+        let mut candidates = std::iter::once(name.clone())
+            .chain(name.split(' ').map(String::from))
+            .chain(sources);
+        candidates.any(|candidate| self.regex.is_match(&candidate))
+    }
+}
+
+/// Keeps the tests which match any of the filters
+pub fn filter(filters: &[TestFilter], tests: Vec<TestInfo>) -> Vec<TestInfo> {
+    tests.into_iter().filter(|test| filters.iter().any(|filter| filter.matches(test))).collect()
+}
+
+/// Keeps the tests which have any of 'tags', if there are any,
+/// and none of 'skip_tags'
+pub fn filter_tags(tags: &[String], skip_tags: &[String], tests: Vec<TestInfo>) -> Vec<TestInfo> {
+    tests.into_iter()
+        .filter(|test| tags.is_empty() || tags.iter().any(|tag| test.has_tag(tag)))
+        .filter(|test| !skip_tags.iter().any(|tag| test.has_tag(tag)))
+        .collect()
+}
+
+/// Turns a glob into an anchored regex. '*' and '?' don't match
+/// across directories, but '**' does
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            },
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string()))
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod test_filter_tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use crate::spec::*;
+
+    fn test_with_sources(sources: &[&str]) -> TestInfo {
+        let sources = sources.iter().map(|source| PathBuf::from("/tests/basic").join(source)).collect();
+        TestInfo::new(TestExecutionInfo::new(sources, Arc::from(Path::new("/tests/basic"))), Vec::new())
+    }
+
+    fn matches(pattern: &str, sources: &[&str]) -> bool {
+        pattern.parse::<TestFilter>().unwrap().matches(&test_with_sources(sources))
+    }
+
+    #[test]
+    fn test() {
+        assert!(matches("basic/*", &["ok.c0"]));
+        assert!(matches("basic/*.c0", &["lib.c0", "main.c0"]));
+        assert!(matches("*/main.c0", &["lib.c0", "main.c0"]));
+        assert!(!matches("*.c0", &["ok.c0"]));
+        assert!(matches("**.c0", &["ok.c0"]));
+        assert!(matches("/tests/**/ok.c?", &["ok.c0"]));
+        assert!(!matches("basic/ok", &["ok.c0"]));
+
+        assert!(matches("re:ok", &["ok.c0"]));
+        assert!(matches("re:^basic/(ok|err)\\.c0$", &["err.c0"]));
+        assert!(!matches("re:^ok", &["ok.c0"]));
+        assert!("re:(".parse::<TestFilter>().is_err());
+
+        let tests = vec![test_with_sources(&["a.c0"]), test_with_sources(&["b.c0"]), test_with_sources(&["c.c0"])];
+        let filters: Vec<TestFilter> = vec!["basic/a.c0".parse().unwrap(), "re:/c".parse().unwrap()];
+        let names: Vec<String> = filter(&filters, tests).iter().map(TestInfo::name).collect();
+        assert_eq!(names, vec!["basic/a.c0", "basic/c.c0"]);
+    }
+
+    #[test]
+    fn test_tags() {
+        let tagged = |source: &str, tags: &[&str], slow: bool| {
+            let mut test = test_with_sources(&[source]);
+            test.tags = tags.iter().map(|tag| tag.to_string()).collect();
+            test.execution.slow = slow;
+            test
+        };
+        let tests = vec![tagged("a.c0", &["gc"], false), tagged("b.c0", &["gc", "quick"], false), tagged("c.c0", &[], true)];
+        let names = |tags: &[&str], skip_tags: &[&str]| -> Vec<String> {
+            let strings = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+            filter_tags(&strings(tags), &strings(skip_tags), tests.clone()).iter().map(TestInfo::name).collect()
+        };
+
+        assert_eq!(names(&[], &[]).len(), 3);
+        assert_eq!(names(&["gc"], &[]), vec!["basic/a.c0", "basic/b.c0"]);
+        assert_eq!(names(&["gc"], &["quick"]), vec!["basic/a.c0"]);
+        // @slow counts as the 'slow' tag
+        assert_eq!(names(&["slow", "quick"], &[]), vec!["basic/b.c0", "basic/c.c0"]);
+        assert_eq!(names(&[], &["slow"]), vec!["basic/a.c0", "basic/b.c0"]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Display;
+use std::fs;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::Result;
+
+use crate::launcher;
+
+/// How long to wait before the first retry. Each retry waits twice as long
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often to check whether the machine is still overloaded
+const HOST_POLL: Duration = Duration::from_secs(1);
+
+/// Limits how many tests run at once. There's no limit until the system runs
+/// out of processes, file descriptors, or memory (see 'launcher::Contention'),
+/// then each time it does the limit is halved, and the test is retried
+/// after a while instead of being recorded as an error.
+///
+/// Tests also wait to start while the machine is overloaded (see 'HostLimits'),
+/// e.g. by other people's jobs on a shared machine, so they don't time out
+/// spuriously. One test can always run, so the run keeps going meanwhile
+pub struct Throttle {
+    /// How many times a test is retried before its error is kept
+    retries: u32,
+    host: HostLimits,
+    state: Mutex<ThrottleState>,
+    freed: Condvar
+}
+
+struct ThrottleState {
+    limit: usize,
+    running: usize,
+    retries: usize,
+    /// How many tests had to wait because the machine was overloaded
+    held: usize,
+    /// When the machine was last checked, and whether it was overloaded
+    last_check: Option<(Instant, bool)>
+}
+
+/// How busy the machine can be before tests wait to start (see --max-load
+/// and --min-free-memory). There are no limits by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostLimits {
+    /// The highest 1-minute load average
+    pub max_load: Option<f64>,
+    /// The least memory which has to be available, in bytes
+    pub min_free_memory: Option<u64>
+}
+
+impl HostLimits {
+    /// Whether the machine is past either limit. Machines which
+    /// don't have /proc (or limits which aren't set) never are
+    fn exceeded(&self) -> bool {
+        let overloaded = match self.max_load {
+            Some(max_load) => matches!(fs::read_to_string("/proc/loadavg").ok().and_then(|text| parse_load(&text)),
+                Some(load) if load > max_load),
+            None => false
+        };
+
+        overloaded || match self.min_free_memory {
+            Some(min_free) => matches!(fs::read_to_string("/proc/meminfo").ok().and_then(|text| parse_available_memory(&text)),
+                Some(available) if available < min_free),
+            None => false
+        }
+    }
+}
+
+/// How much a run was slowed down to get through contention
+pub struct Degradation {
+    pub retries: usize,
+    /// The most tests which were run at once afterwards
+    pub limit: usize,
+    /// How many tests waited to start because the machine was overloaded
+    pub held: usize
+}
+
+impl Throttle {
+    pub fn new(retries: u32, host: HostLimits) -> Throttle {
+        let state = ThrottleState { limit: usize::MAX, running: 0, retries: 0, held: 0, last_check: None };
+        Throttle { retries, host, state: Mutex::new(state), freed: Condvar::new() }
+    }
+
+    /// Runs part of a test once there is room for it, retrying it if the system was too busy
+    pub fn run<T>(&self, test: &dyn Display, run: impl Fn() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            self.acquire();
+            let result = run();
+            self.release();
+
+            match result {
+                Err(error) if attempt < self.retries && launcher::is_contention(&error) => {
+                    let delay = INITIAL_BACKOFF * 2u32.pow(attempt);
+                    let limit = self.back_off();
+                    eprintln!("🐢 {}: {:#}. Retrying in {}ms, with at most {} tests at once",
+                        test, error, delay.as_millis(), limit);
+
+                    thread::sleep(delay);
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+
+    /// 'None' if nothing was ever retried or held back
+    pub fn degradation(&self) -> Option<Degradation> {
+        let state = self.state.lock().unwrap();
+        if state.retries == 0 && state.held == 0 {
+            return None
+        }
+
+        Some(Degradation { retries: state.retries, limit: state.limit, held: state.held })
+    }
+
+    fn acquire(&self) {
+        let mut state = self.state.lock().unwrap();
+        let mut held = false;
+        loop {
+            if state.running >= state.limit {
+                state = self.freed.wait(state).unwrap();
+            }
+            else if state.running > 0 && self.overloaded(&mut state) {
+                // Nothing wakes this up when the load goes down, so check again in a while
+                if !held {
+                    held = true;
+                    state.held += 1;
+                }
+                state = self.freed.wait_timeout(state, HOST_POLL).unwrap().0;
+            }
+            else {
+                break
+            }
+        }
+        state.running += 1;
+    }
+
+    /// Whether the machine is overloaded, checking at most once every HOST_POLL
+    fn overloaded(&self, state: &mut ThrottleState) -> bool {
+        match state.last_check {
+            Some((checked, overloaded)) if checked.elapsed() < HOST_POLL => overloaded,
+            _ => {
+                let overloaded = self.host.exceeded();
+                state.last_check = Some((Instant::now(), overloaded));
+                overloaded
+            }
+        }
+    }
+
+    fn release(&self) {
+        self.state.lock().unwrap().running -= 1;
+        self.freed.notify_one();
+    }
+
+    /// Halves the limit (counting from however many tests were running, if that's
+    /// fewer), returning the new one. At least one test can always run
+    fn back_off(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.limit = (state.limit.min(state.running + 1) / 2).max(1);
+        state.retries += 1;
+        state.limit
+    }
+}
+
+/// Reads the 1-minute load average from /proc/loadavg
+fn parse_load(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Reads how much memory is available, in bytes, from /proc/meminfo
+fn parse_available_memory(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod throttle_tests {
+    use super::*;
+    use std::cell::Cell;
+    use anyhow::anyhow;
+    use nix::errno::Errno;
+
+    #[test]
+    fn test() {
+        let throttle = Throttle::new(2, HostLimits::default());
+        let attempts = Cell::new(0);
+        let result = throttle.run(&"test", || {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(anyhow!(nix::Error::Sys(Errno::EAGAIN))),
+                _ => Ok(attempts.get())
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        let degradation = throttle.degradation().unwrap();
+        assert_eq!((degradation.retries, degradation.limit), (1, 1));
+
+        // Other errors aren't retried, and contention only is until the retries run out
+        attempts.set(0);
+        assert!(throttle.run(&"test", || -> Result<()> { attempts.set(attempts.get() + 1); Err(anyhow!("failed")) }).is_err());
+        assert_eq!(attempts.get(), 1);
+
+        attempts.set(0);
+        let result = throttle.run(&"test", || -> Result<()> {
+            attempts.set(attempts.get() + 1);
+            Err(anyhow!(launcher::Contention))
+        });
+        assert!(launcher::is_contention(&result.unwrap_err()));
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn test_host_limits() {
+        assert_eq!(parse_load("3.52 2.10 1.05 2/512 12345\n"), Some(3.52));
+        assert_eq!(parse_load(""), None);
+        assert_eq!(parse_available_memory("MemTotal: 16 kB\nMemFree: 1 kB\nMemAvailable: 8 kB\n"), Some(8 * 1024));
+        assert_eq!(parse_available_memory("MemTotal: 16 kB\n"), None);
+
+        // A lone test runs however overloaded the machine is
+        let throttle = Throttle::new(0, HostLimits { max_load: Some(-1.0), min_free_memory: Some(u64::MAX) });
+        assert_eq!(throttle.run(&"test", || Ok(1)).unwrap(), 1);
+        assert!(throttle.degradation().is_none());
+    }
+}
@@ -0,0 +1,209 @@
+use std::cell::Cell;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+use serde::Serialize;
+use anyhow::{Context, Result};
+
+use crate::executer::{Artifact, Compilation, Executer, ExecuterProperties, TestRun};
+use crate::spec::*;
+
+/// When each test and each of its phases ran, and on which thread, for --timeline
+pub struct Timeline {
+    start: Instant,
+    spans: Mutex<Vec<Span>>
+}
+
+struct Span {
+    name: String,
+    /// e.g. 'test' or 'compile'
+    category: &'static str,
+    thread: usize,
+    thread_name: Option<String>,
+    /// Microseconds since the start of the timeline
+    start: u128,
+    duration: u128
+}
+
+/// An event in the Chrome tracing format, which Perfetto and chrome://tracing open
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    cat: &'a str,
+    /// 'X' for spans, 'M' for metadata such as thread names
+    ph: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ts: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<u128>,
+    pid: u32,
+    tid: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    args: Option<serde_json::Value>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: Vec<TraceEvent<'a>>,
+    display_time_unit: &'static str
+}
+
+/// Numbers threads in the order they first record a span. Thread IDs from
+/// rayon start over in each pool, so they'd put compile threads on test lanes
+fn thread_number() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    thread_local! {
+        static NUMBER: Cell<usize> = const { Cell::new(0) };
+    }
+
+    NUMBER.with(|number| {
+        if number.get() == 0 {
+            number.set(NEXT.fetch_add(1, Ordering::Relaxed));
+        }
+        number.get()
+    })
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline { start: Instant::now(), spans: Mutex::new(Vec::new()) }
+    }
+
+    /// Records something which ran on this thread from 'start' until now
+    pub fn record(&self, name: String, category: &'static str, start: Instant) {
+        let micros = |instant: Instant| instant.saturating_duration_since(self.start).as_micros();
+        let end = Instant::now();
+        let span = Span {
+            name,
+            category,
+            thread: thread_number(),
+            thread_name: thread::current().name().map(String::from),
+            start: micros(start),
+            duration: micros(end) - micros(start)
+        };
+        self.spans.lock().unwrap().push(span);
+    }
+
+    /// Writes the timeline as Chrome tracing JSON, with a lane for each thread
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let spans = self.spans.lock().unwrap();
+        let mut threads: Vec<(usize, &Option<String>)> = spans.iter().map(|span| (span.thread, &span.thread_name)).collect();
+        threads.sort_by_key(|(thread, _)| *thread);
+        threads.dedup_by_key(|(thread, _)| *thread);
+
+        let names: Vec<(usize, String)> = threads.iter()
+            .map(|(thread, name)| (*thread, name.as_ref().map_or_else(|| format!("thread {}", thread), String::clone)))
+            .collect();
+        let metadata = names.iter().map(|(thread, name)| TraceEvent {
+            name: "thread_name",
+            cat: "",
+            ph: "M",
+            ts: None,
+            dur: None,
+            pid: 1,
+            tid: *thread,
+            args: Some(serde_json::json!({ "name": name }))
+        });
+        let events = spans.iter().map(|span| TraceEvent {
+            name: &span.name,
+            cat: span.category,
+            ph: "X",
+            ts: Some(span.start),
+            dur: Some(span.duration),
+            pid: 1,
+            tid: span.thread,
+            args: None
+        });
+
+        let trace = Trace { trace_events: metadata.chain(events).collect(), display_time_unit: "ms" };
+        let file = File::create(path).context(format!("Couldn't create '{}'", path.display()))?;
+        serde_json::to_writer(BufWriter::new(file), &trace)?;
+        Ok(())
+    }
+}
+
+/// An executer which records when each test compiles and runs in a timeline
+pub struct TimelineExecuter {
+    executer: Box<dyn Executer>,
+    timeline: Arc<Timeline>
+}
+
+impl TimelineExecuter {
+    pub fn new(executer: Box<dyn Executer>, timeline: Arc<Timeline>) -> TimelineExecuter {
+        TimelineExecuter { executer, timeline }
+    }
+}
+
+impl Executer for TimelineExecuter {
+    fn compile(&self, test: &TestExecutionInfo) -> Result<Compilation> {
+        let start = Instant::now();
+        let compilation = self.executer.compile(test);
+        self.timeline.record(test.name(), "compile", start);
+        compilation
+    }
+
+    fn execute(&self, test: &TestExecutionInfo, artifact: Option<&Artifact>) -> Result<TestRun> {
+        let start = Instant::now();
+        let run = self.executer.execute(test, artifact);
+        self.timeline.record(test.name(), "run", start);
+        run
+    }
+
+    fn compile_command(&self, test: &TestExecutionInfo) -> Option<Vec<CString>> {
+        self.executer.compile_command(test)
+    }
+
+    fn exit_code(&self, code: i32) -> Option<Behavior> {
+        self.executer.exit_code(code)
+    }
+
+    fn toolchain(&self) -> Vec<PathBuf> {
+        self.executer.toolchain()
+    }
+
+    fn properties(&self) -> ExecuterProperties {
+        self.executer.properties()
+    }
+}
+
+#[cfg(test)]
+mod timeline_tests {
+    use super::*;
+    use std::fs;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test() -> Result<()> {
+        let timeline = Timeline::new();
+        let start = Instant::now();
+        timeline.record(String::from("basic/a.c0"), "compile", start);
+        timeline.record(String::from("basic/a.c0"), "test", start);
+        thread::scope(|scope| {
+            scope.spawn(|| timeline.record(String::from("basic/b.c0"), "test", Instant::now()));
+        });
+
+        let dir = TempDir::new("timeline");
+        let path = dir.join("timeline.json");
+        timeline.write(&path)?;
+        let trace: serde_json::Value = serde_json::from_slice(&fs::read(&path)?)?;
+
+        let events = trace["traceEvents"].as_array().unwrap();
+        let spans: Vec<&serde_json::Value> = events.iter().filter(|event| event["ph"] == "X").collect();
+        assert_eq!(spans.len(), 3);
+        assert_eq!((&spans[0]["name"], &spans[0]["cat"]), (&serde_json::json!("basic/a.c0"), &serde_json::json!("compile")));
+        assert!(spans[0]["dur"].as_u64().unwrap() <= spans[1]["dur"].as_u64().unwrap());
+        assert_eq!(spans[0]["tid"], spans[1]["tid"]);
+        assert_ne!(spans[0]["tid"], spans[2]["tid"]);
+
+        // Each thread gets a named lane
+        assert_eq!(events.iter().filter(|event| event["ph"] == "M").count(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::cmp::max;
+
+/// How many unchanged lines are shown around each change
+const CONTEXT: usize = 3;
+
+/// Outputs longer than this (in lines of one times lines of the other) aren't
+/// worth finding the smallest diff for, so their differing middles are replaced wholesale
+const MAX_TABLE: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    /// Lines of both sides, by index
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize)
+}
+
+/// Describes how 'actual' differs from 'expected' like 'diff -u', naming
+/// them with the given names in the header
+pub fn diff(expected_name: &str, actual_name: &str, expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    let edits = edits(&old, &new);
+
+    let changes: Vec<usize> = (0..edits.len()).filter(|&i| !matches!(edits[i], Edit::Same(..))).collect();
+    if changes.is_empty() {
+        return format!("{} differs from {} only in line endings or a trailing newline", actual_name, expected_name)
+    }
+
+    // Where each edit starts on both sides
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for edit in edits.iter() {
+        positions.push((old_pos, new_pos));
+        match edit {
+            Edit::Same(..) => { old_pos += 1; new_pos += 1 },
+            Edit::Removed(_) => old_pos += 1,
+            Edit::Added(_) => new_pos += 1
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let mut result = format!("--- {}\n+++ {}", expected_name, actual_name);
+    let mut rest = changes.as_slice();
+    while let Some(&first) = rest.first() {
+        // Changes close enough to share context go in the same hunk
+        let mut last = first;
+        while let Some(&next) = rest.get(1) {
+            if next - last > 2 * CONTEXT {
+                break
+            }
+            last = next;
+            rest = &rest[1..];
+        }
+        rest = &rest[1..];
+
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(edits.len());
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        result += &format!("\n@@ -{} +{} @@", range(old_start, old_end), range(new_start, new_end));
+
+        for edit in edits[start..end].iter() {
+            match *edit {
+                Edit::Same(i, _) => result += &format!("\n {}", old[i]),
+                Edit::Removed(i) => result += &format!("\n-{}", old[i]),
+                Edit::Added(j) => result += &format!("\n+{}", new[j])
+            }
+        }
+    }
+
+    result
+}
+
+/// A hunk's range of lines as 'diff -u' writes it. Empty ranges start at the line before them
+fn range(start: usize, end: usize) -> String {
+    match end - start {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        len => format!("{},{}", start + 1, len)
+    }
+}
+
+/// The fewest lines to remove and add to turn 'old' into 'new', found with the
+/// longest common subsequence of the lines between their common prefix and suffix
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let (n, m) = (old_middle.len(), new_middle.len());
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Same(i, i)).collect();
+    if n * m <= MAX_TABLE {
+        // lcs[i][j] is the length of the longest common subsequence of old_middle[i..] and new_middle[j..]
+        let width = m + 1;
+        let mut lcs = vec![0u32; (n + 1) * width];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * width + j] = if old_middle[i] == new_middle[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                }
+                else {
+                    max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_middle[i] == new_middle[j] {
+                edits.push(Edit::Same(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            }
+            else if j == m || (i < n && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                edits.push(Edit::Removed(prefix + i));
+                i += 1;
+            }
+            else {
+                edits.push(Edit::Added(prefix + j));
+                j += 1;
+            }
+        }
+    }
+    else {
+        edits.extend((0..n).map(|i| Edit::Removed(prefix + i)));
+        edits.extend((0..m).map(|j| Edit::Added(prefix + j)));
+    }
+
+    edits.extend((0..suffix).map(|k| Edit::Same(old.len() - suffix + k, new.len() - suffix + k)));
+    edits
+}
+
+#[cfg(test)]
+mod unified_diff_tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(diff("a.expect", "the output", "1\n2\n3\n", "1\n2\n3"),
+            "the output differs from a.expect only in line endings or a trailing newline");
+
+        assert_eq!(diff("a.expect", "the output", "1\n2\n3\n", "1\ntwo\n3\n"),
+            "--- a.expect\n+++ the output\n@@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3");
+
+        // Far apart changes get their own hunks, with at most 3 lines of context
+        let expected: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+        let mut actual = expected.clone();
+        actual.remove(1);
+        actual.insert(15, String::from("new"));
+        assert_eq!(diff("a", "b", &expected.join("\n"), &actual.join("\n")),
+            "--- a\n+++ b\n@@ -1,5 +1,4 @@\n 1\n-2\n 3\n 4\n 5\n@@ -14,6 +13,7 @@\n 14\n 15\n 16\n+new\n 17\n 18\n 19");
+
+        assert_eq!(diff("a", "b", "", "hi\n"), "--- a\n+++ b\n@@ -0,0 +1 @@\n+hi");
+    }
+}
@@ -0,0 +1,113 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+
+use crate::options::Options;
+
+/// The directory compiled tests and result files are written to.
+/// This is the current directory, unless --tmpfs was given, in
+/// which case it is a RAM-backed directory which is removed afterwards
+pub struct WorkDir {
+    path: PathBuf,
+    kind: WorkDirKind
+}
+
+enum WorkDirKind {
+    CurrentDir,
+    /// A directory in an existing tmpfs (i.e. /dev/shm)
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    Shared,
+    /// A tmpfs we mounted ourselves
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    Mounted
+}
+
+impl WorkDir {
+    pub fn new(options: &Options) -> Result<WorkDir> {
+        match options.tmpfs {
+            Some(size) => WorkDir::tmpfs(size),
+            None => Ok(WorkDir {
+                path: env::current_dir().context("Couldn't get the current directory")?,
+                kind: WorkDirKind::CurrentDir
+            })
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Mounts a tmpfs of the given size if we are root, otherwise
+    /// makes a directory in /dev/shm as long as it has enough space
+    #[cfg(target_os = "linux")]
+    fn tmpfs(size: u64) -> Result<WorkDir> {
+        use anyhow::bail;
+        use nix::mount::{self, MsFlags};
+        use nix::sys::{statfs, statvfs};
+        use nix::unistd;
+
+        let name = format!("c0check-{}", std::process::id());
+
+        if unistd::geteuid().is_root() {
+            let path = env::temp_dir().join(name);
+            fs::create_dir_all(&path).context(format!("Couldn't create '{}'", path.display()))?;
+
+            let data = format!("size={}", size);
+            mount::mount(Some("tmpfs"), &path, Some("tmpfs"), MsFlags::empty(), Some(data.as_str()))
+                .context(format!("Couldn't mount a tmpfs at '{}'", path.display()))?;
+
+            return Ok(WorkDir { path, kind: WorkDirKind::Mounted })
+        }
+
+        let shm = Path::new("/dev/shm");
+        let is_tmpfs = statfs::statfs(shm)
+            .map(|stats| stats.filesystem_type() == statfs::TMPFS_MAGIC)
+            .unwrap_or(false);
+        if !is_tmpfs {
+            bail!("/dev/shm is not a tmpfs, and mounting one requires root")
+        }
+
+        let stats = statvfs::statvfs(shm).context("Couldn't check the free space in /dev/shm")?;
+        let available = stats.blocks_available() as u64 * stats.fragment_size() as u64;
+        if available < size {
+            bail!("/dev/shm only has {} bytes free, but --tmpfs asked for {}", available, size)
+        }
+
+        let path = shm.join(name);
+        fs::create_dir_all(&path).context(format!("Couldn't create '{}'", path.display()))?;
+
+        Ok(WorkDir { path, kind: WorkDirKind::Shared })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn tmpfs(_size: u64) -> Result<WorkDir> {
+        anyhow::bail!("--tmpfs is only supported on Linux")
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let result = match self.kind {
+            WorkDirKind::CurrentDir => return,
+            WorkDirKind::Shared => fs::remove_dir_all(&self.path).map_err(anyhow::Error::from),
+            WorkDirKind::Mounted => unmount(&self.path)
+        };
+
+        if let Err(e) = result {
+            eprintln!("❗ Couldn't clean up '{}': {:#}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn unmount(path: &Path) -> Result<()> {
+    nix::mount::umount(path)?;
+    fs::remove_dir(path)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unmount(_path: &Path) -> Result<()> {
+    unreachable!()
+}